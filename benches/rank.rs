@@ -0,0 +1,74 @@
+//! Benchmarks of ranking queries against a feature index, comparing the
+//! exhaustive `rank` with the pruned `rank_above`, on low complexity queries
+//! (whose k-mers are shared by many features) as well as ordinary ones.
+//!
+//! Run with `cargo bench --bench rank`.
+
+use std::io::Write;
+
+use criterion::{Criterion, criterion_group, criterion_main};
+use datafusion::prelude::SessionContext;
+use rand::{Rng, SeedableRng, rngs::StdRng};
+use svelt::{features::FeatureIndex, options::IndexingOptions};
+
+fn random_sequence(rng: &mut StdRng, len: usize) -> String {
+    (0..len)
+        .map(|_| ['A', 'C', 'G', 'T'][rng.random_range(0..4)])
+        .collect()
+}
+
+/// An index of features like repeat elements: most end in a poly-A tail,
+/// and a third are interrupted by a simple repeat.
+fn make_index(rng: &mut StdRng) -> (FeatureIndex, Vec<String>) {
+    let path = std::env::temp_dir().join(format!("svelt-bench-rank-{}.fa", std::process::id()));
+    let mut features = Vec::new();
+    let mut file = std::fs::File::create(&path).unwrap();
+    for i in 0..5000 {
+        let len = rng.random_range(200..2000);
+        let mut sequence = random_sequence(rng, len);
+        if i % 3 == 0 {
+            sequence.insert_str(len / 2, &"CA".repeat(rng.random_range(10..40)));
+        }
+        if i % 4 != 0 {
+            sequence.push_str(&"A".repeat(rng.random_range(10..60)));
+        }
+        writeln!(file, ">family/class/f{}\n{}", i, sequence).unwrap();
+        features.push(sequence);
+    }
+    drop(file);
+
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+    let options = IndexingOptions::default();
+    let ctx = SessionContext::new();
+    let idx = runtime
+        .block_on(FeatureIndex::build(path.to_str().unwrap(), &options, &ctx))
+        .unwrap();
+    std::fs::remove_file(&path).unwrap();
+    (idx, features)
+}
+
+fn bench_rank(c: &mut Criterion) {
+    let mut rng = StdRng::seed_from_u64(1038);
+    let (idx, features) = make_index(&mut rng);
+
+    let feature = &features[17];
+    let queries = [
+        ("poly-a", "A".repeat(300)),
+        ("dinucleotide", "CA".repeat(150)),
+        (
+            "tail",
+            format!("{}{}", &feature[feature.len() - 200..], "A".repeat(40)),
+        ),
+        ("random", random_sequence(&mut rng, 300)),
+    ];
+
+    for (name, query) in queries.iter() {
+        let mut group = c.benchmark_group(*name);
+        group.bench_function("rank", |b| b.iter(|| idx.rank(query)));
+        group.bench_function("rank_above", |b| b.iter(|| idx.rank_above(query, 0.5)));
+        group.finish();
+    }
+}
+
+criterion_group!(benches, bench_rank);
+criterion_main!(benches);
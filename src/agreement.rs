@@ -0,0 +1,151 @@
+use std::{
+    collections::BTreeMap,
+    io::{BufWriter, Write},
+    rc::Rc,
+};
+
+use autocompress::{CompressionLevel, autodetect_create};
+use noodles::vcf::{Header, Record, variant::record::Record as _};
+use serde::Serialize;
+
+use crate::options::AgreementMatrixFormat;
+
+/// Pairwise agreement between the inputs of a merge (`--agreement-matrix`).
+///
+/// For each pair of inputs, this counts the groups with records from both,
+/// the Jaccard index of the sets of groups each contributes to, and the
+/// median breakpoint offset between their records in the shared groups (the
+/// larger of the differences in POS and END). The diagonal describes each
+/// input on its own.
+#[derive(Debug, Serialize)]
+pub struct AgreementMatrix {
+    pub inputs: Vec<String>,
+    pub shared_groups: Vec<Vec<usize>>,
+    pub jaccard: Vec<Vec<f64>>,
+    pub median_offset: Vec<Vec<Option<f64>>>,
+    #[serde(skip)]
+    offsets: Vec<Vec<BTreeMap<usize, usize>>>,
+}
+
+impl AgreementMatrix {
+    pub fn new(paths: &[String]) -> AgreementMatrix {
+        let n = paths.len();
+        AgreementMatrix {
+            inputs: paths.to_vec(),
+            shared_groups: vec![vec![0; n]; n],
+            jaccard: vec![vec![0.0; n]; n],
+            median_offset: vec![vec![None; n]; n],
+            offsets: vec![vec![BTreeMap::new(); n]; n],
+        }
+    }
+
+    /// Add the records making up one merged group (indexed by input).
+    pub fn add_group(&mut self, recs: &[Option<(Rc<Header>, Record)>]) -> std::io::Result<()> {
+        let mut extents = Vec::new();
+        for (vix, hnr) in recs.iter().enumerate() {
+            let Some((header, rec)) = hnr else {
+                continue;
+            };
+            let start = rec.variant_start().transpose()?.map_or(0, |p| p.get());
+            let end = rec.variant_end(header).map_or(start, |p| p.get());
+            extents.push((vix, start, end));
+        }
+        for (i, &(vix, start, end)) in extents.iter().enumerate() {
+            for &(wix, start2, end2) in extents[i..].iter() {
+                let offset = start.abs_diff(start2).max(end.abs_diff(end2));
+                self.shared_groups[vix][wix] += 1;
+                *self.offsets[vix][wix].entry(offset).or_default() += 1;
+                if wix != vix {
+                    self.shared_groups[wix][vix] += 1;
+                    *self.offsets[wix][vix].entry(offset).or_default() += 1;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Compute the derived statistics and write the matrix.
+    pub fn finish(&mut self, out: &str, format: AgreementMatrixFormat) -> std::io::Result<()> {
+        let n = self.inputs.len();
+        for vix in 0..n {
+            for wix in 0..n {
+                let shared = self.shared_groups[vix][wix];
+                let either = self.shared_groups[vix][vix] + self.shared_groups[wix][wix] - shared;
+                if either > 0 {
+                    self.jaccard[vix][wix] = shared as f64 / either as f64;
+                }
+                self.median_offset[vix][wix] = median(&self.offsets[vix][wix]);
+            }
+        }
+
+        let writer = autodetect_create(out, CompressionLevel::Default)?;
+        let mut writer = BufWriter::new(writer);
+        match format {
+            AgreementMatrixFormat::Tsv => {
+                writeln!(
+                    writer,
+                    "input\tother\tshared_groups\tjaccard\tmedian_offset"
+                )?;
+                for vix in 0..n {
+                    for wix in 0..n {
+                        let median_offset = match self.median_offset[vix][wix] {
+                            Some(m) => format!("{:.1}", m),
+                            None => String::from("."),
+                        };
+                        writeln!(
+                            writer,
+                            "{}\t{}\t{}\t{:.4}\t{}",
+                            self.inputs[vix],
+                            self.inputs[wix],
+                            self.shared_groups[vix][wix],
+                            self.jaccard[vix][wix],
+                            median_offset
+                        )?;
+                    }
+                }
+            }
+            AgreementMatrixFormat::Json => {
+                serde_json::to_writer_pretty(&mut writer, self)?;
+                writeln!(writer)?;
+            }
+        }
+        writer.flush()
+    }
+}
+
+/// The median of the values counted in a histogram.
+fn median(counts: &BTreeMap<usize, usize>) -> Option<f64> {
+    let total: usize = counts.values().sum();
+    if total == 0 {
+        return None;
+    }
+    // The (0-based) ranks of the middle value(s).
+    let (lo, hi) = ((total - 1) / 2, total / 2);
+    let mut lo_value = None;
+    let mut seen = 0;
+    for (value, count) in counts.iter() {
+        seen += count;
+        if lo_value.is_none() && seen > lo {
+            lo_value = Some(*value);
+        }
+        if seen > hi {
+            return Some((lo_value.unwrap() + value) as f64 / 2.0);
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_median() {
+        let counts = |xs: &[(usize, usize)]| xs.iter().cloned().collect::<BTreeMap<_, _>>();
+        assert_eq!(median(&counts(&[])), None);
+        assert_eq!(median(&counts(&[(7, 1)])), Some(7.0));
+        assert_eq!(median(&counts(&[(1, 1), (4, 1)])), Some(2.5));
+        assert_eq!(median(&counts(&[(0, 2), (3, 1)])), Some(0.0));
+        assert_eq!(median(&counts(&[(0, 2), (3, 2), (10, 1)])), Some(3.0));
+    }
+}
@@ -0,0 +1,190 @@
+use std::{
+    collections::{HashMap, HashSet},
+    io::BufRead,
+    sync::Arc,
+};
+
+use datafusion::{
+    arrow::{
+        array::{GenericStringBuilder, RecordBatch, StringArray},
+        datatypes::{DataType, Field, Schema},
+    },
+    prelude::SessionContext,
+};
+use noodles::vcf::{
+    self, Header, Record,
+    variant::{
+        RecordBuf, io::Write as _, record::AlternateBases as _,
+        record_buf::info::field::Value as InfoValue,
+    },
+};
+
+use crate::{
+    construct::OutputWriter,
+    errors::{Context, FileContext},
+    inputs::{get_svtype, sv_kind},
+    merge::classify::find_classifications,
+    output_format::SVELT_INFO_FIELDS,
+    tables::{digest, is_seq},
+};
+
+/// The INFO field the classifications are written to.
+const ALT_CLASS: &str = "SVELT_ALT_CLASS";
+
+/// Classify the inserted sequences of a single VCF against a feature index,
+/// as `svelt merge --annotate-insertions` does, writing the VCF to `out` with
+/// `SVELT_ALT_CLASS` added.
+///
+/// Any `SVELT_ALT_CLASS` the records already have is replaced (or removed,
+/// if the sequence no longer matches a feature), and the other records are
+/// written as they are.
+pub async fn annotate_vcf(
+    vcf: &str,
+    out: &str,
+    features: &str,
+    batch_size: usize,
+    ctx: &SessionContext,
+) -> std::io::Result<()> {
+    let sequences = FileContext::new(vcf).with(|| {
+        let (mut reader, header) = open_vcf(vcf)?;
+        let mut sequences = Vec::new();
+        let mut seen = HashSet::new();
+        let mut rec = Record::default();
+        while reader.read_record(&mut rec)? > 0 {
+            if let Some(seq) = inserted_sequence(&rec, &header)? {
+                let key = sequence_key(&seq);
+                if seen.insert(key.clone()) {
+                    sequences.push((key, seq));
+                }
+            }
+        }
+        Ok(sequences)
+    })?;
+    log::info!(
+        "{} distinct insertion sequences in '{}'",
+        sequences.len(),
+        vcf
+    );
+
+    let classes = classify(sequences, features, batch_size, ctx).await?;
+
+    FileContext::new(vcf).with(|| {
+        let (mut reader, mut header) = open_vcf(vcf)?;
+        if let Some(def) = SVELT_INFO_FIELDS.iter().find(|def| def.id == ALT_CLASS) {
+            header
+                .infos_mut()
+                .insert(String::from(ALT_CLASS), def.declaration(""));
+        }
+
+        let mut writer = vcf::io::Writer::new(OutputWriter::create(out, None, 1)?);
+        writer.write_header(&header)?;
+
+        let mut classified: usize = 0;
+        let mut rec = Record::default();
+        while reader.read_record(&mut rec)? > 0 {
+            let class =
+                inserted_sequence(&rec, &header)?.and_then(|seq| classes.get(&sequence_key(&seq)));
+            if class.is_none() && rec.info().get(&header, ALT_CLASS).is_none() {
+                writer.write_record(&header, &rec)?;
+                continue;
+            }
+            let mut rec = RecordBuf::try_from_variant_record(&header, &rec)?;
+            match class {
+                Some(class) => {
+                    classified += 1;
+                    rec.info_mut().insert(
+                        String::from(ALT_CLASS),
+                        Some(InfoValue::String(class.clone())),
+                    );
+                }
+                None => {
+                    rec.info_mut().as_mut().shift_remove(ALT_CLASS);
+                }
+            }
+            writer.write_variant_record(&header, &rec)?;
+        }
+        writer.get_mut().finish()?;
+        log::info!("{} insertions classified", classified);
+        Ok(())
+    })
+}
+
+fn open_vcf(path: &str) -> std::io::Result<(vcf::io::Reader<Box<dyn BufRead>>, Header)> {
+    let reader = autocompress::autodetect_open(path)?;
+    let mut reader: vcf::io::Reader<Box<dyn BufRead>> =
+        vcf::io::reader::Builder::default().build_from_reader(reader)?;
+    let header = reader.read_header()?;
+    Ok((reader, header))
+}
+
+/// The sequence inserted by an insertion (with a sequence ALT), without the
+/// padding base, as it is taken for merging (see `tables::load_vcf_core`).
+fn inserted_sequence(rec: &Record, header: &Header) -> std::io::Result<Option<String>> {
+    if sv_kind(&get_svtype(rec, header)?) != Some("INS") {
+        return Ok(None);
+    }
+    match rec.alternate_bases().iter().next() {
+        Some(alt) => {
+            let alt = alt?;
+            Ok((is_seq(alt) && alt.len() > 1).then(|| String::from(&alt[1..])))
+        }
+        None => Ok(None),
+    }
+}
+
+fn sequence_key(seq: &str) -> String {
+    format!("{:016x}", digest(seq))
+}
+
+/// The class (with the strand) of each of the `sequences` that matches a
+/// feature, by key.
+async fn classify(
+    sequences: Vec<(String, String)>,
+    features: &str,
+    batch_size: usize,
+    ctx: &SessionContext,
+) -> std::io::Result<HashMap<String, String>> {
+    let mut key_builder = GenericStringBuilder::<i32>::new();
+    let mut seq_builder = GenericStringBuilder::<i64>::new();
+    for (key, seq) in sequences {
+        key_builder.append_value(key);
+        seq_builder.append_value(seq);
+    }
+    let schema = Arc::new(Schema::new(vec![
+        Field::new("seq_hash", DataType::Utf8, false),
+        Field::new("alt_seq", DataType::LargeUtf8, false),
+    ]));
+    let recs = RecordBatch::try_new(
+        schema,
+        vec![
+            Arc::new(key_builder.finish()),
+            Arc::new(seq_builder.finish()),
+        ],
+    )
+    .map_err(std::io::Error::other)?;
+
+    let classifications =
+        find_classifications(ctx.read_batch(recs)?, features, batch_size, ctx).await?;
+
+    let mut classes = HashMap::new();
+    for recs in classifications.collect().await? {
+        let names = get_array::<StringArray>(&recs, "query_name");
+        let class = get_array::<StringArray>(&recs, "class");
+        let strand = get_array::<StringArray>(&recs, "strand");
+        for i in 0..recs.num_rows() {
+            classes.insert(
+                String::from(names.value(i)),
+                format!("{}{}", class.value(i), strand.value(i)),
+            );
+        }
+    }
+    Ok(classes)
+}
+
+fn get_array<'a, Type: 'static>(recs: &'a RecordBatch, name: &str) -> &'a Type {
+    recs.column_by_name(name)
+        .unwrap()
+        .as_any()
+        .downcast_ref::<Type>()
+        .unwrap()
+}
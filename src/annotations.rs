@@ -0,0 +1,211 @@
+use std::{collections::HashSet, rc::Rc};
+
+use noodles::vcf::{
+    Header, Record,
+    variant::{
+        record::info::field::{Value, value::Array},
+        record_buf::info::field::{Value as InfoValue, value::Array as InfoArray},
+    },
+};
+
+use crate::tag_patterns::TagPatterns;
+
+/// The INFO fields holding consequence annotations: `CSQ` (from VEP) and
+/// `ANN` (from SnpEff).
+pub const ANNOTATION_FIELDS: &[&str] = &["CSQ", "ANN"];
+
+/// A consequence annotation field, as it is to be written.
+struct AnnotationField {
+    id: String,
+
+    /// For each input, `None` if it doesn't declare the field, otherwise the
+    /// `|` separated subfields of its entries to write, in the order of the
+    /// output declaration (or `None` if its entries are written as they are).
+    mappings: Vec<Option<Option<Vec<Option<usize>>>>>,
+}
+
+/// Reconciles the consequence annotations of the records in a merged group
+/// (`--reconcile-annotations`), taking the union of their entries, without
+/// duplicates.
+///
+/// Each field is written in the layout declared by the first input that
+/// declares it, and the entries from inputs declaring a different layout are
+/// rearranged to match (by subfield name), leaving out subfields the first
+/// doesn't have and leaving empty those it has that they don't.
+pub struct AnnotationReconciler {
+    fields: Vec<AnnotationField>,
+}
+
+impl AnnotationReconciler {
+    /// Work out how to reconcile the annotation fields declared by the inputs
+    /// with the given `headers`, and declare them in the `output` header.
+    /// Fields matching `unwanted` are left alone.
+    pub fn new(
+        headers: &[Header],
+        output: &mut Header,
+        unwanted: &TagPatterns,
+    ) -> AnnotationReconciler {
+        let mut fields = Vec::new();
+        for id in ANNOTATION_FIELDS.iter() {
+            if unwanted.matches(id) {
+                continue;
+            }
+            let Some(declaration) = headers.iter().find_map(|h| h.infos().get(*id)) else {
+                continue;
+            };
+            let layout = parse_layout(declaration.description());
+            if !output.infos().contains_key(*id) {
+                output
+                    .infos_mut()
+                    .insert(String::from(*id), declaration.clone());
+            }
+
+            let mut mappings = Vec::new();
+            for (vix, header) in headers.iter().enumerate() {
+                let Some(info) = header.infos().get(*id) else {
+                    mappings.push(None);
+                    continue;
+                };
+                let mapping = match (&layout, parse_layout(info.description())) {
+                    (Some(layout), Some(theirs)) if theirs != *layout => Some(
+                        layout
+                            .iter()
+                            .map(|name| theirs.iter().position(|n| n == name))
+                            .collect(),
+                    ),
+                    (Some(_), Some(_)) => None,
+                    _ => {
+                        if info.description() != declaration.description() {
+                            log::warn!(
+                                "can't work out the layout of {} in input {}, so its entries are used as they are",
+                                id,
+                                vix + 1
+                            );
+                        }
+                        None
+                    }
+                };
+                mappings.push(Some(mapping));
+            }
+            fields.push(AnnotationField {
+                id: String::from(*id),
+                mappings,
+            });
+        }
+        AnnotationReconciler { fields }
+    }
+
+    /// The reconciled annotations of a merged group (indexed by input), for
+    /// each field that any of the records has.
+    pub fn reconcile(
+        &self,
+        recs: &[Option<(Rc<Header>, Record)>],
+    ) -> std::io::Result<Vec<(String, InfoValue)>> {
+        let mut res = Vec::new();
+        for field in self.fields.iter() {
+            let mut seen = HashSet::new();
+            let mut entries = Vec::new();
+            for (vix, hnr) in recs.iter().enumerate() {
+                let (Some((header, rec)), Some(mapping)) = (hnr, &field.mappings[vix]) else {
+                    continue;
+                };
+                let mut add = |entry: &str| {
+                    let entry = match mapping {
+                        Some(mapping) => rearrange(entry, mapping),
+                        None => String::from(entry),
+                    };
+                    if seen.insert(entry.clone()) {
+                        entries.push(Some(entry));
+                    }
+                };
+                match rec.info().get(header, &field.id).transpose()? {
+                    Some(Some(Value::String(entry))) => add(&entry),
+                    Some(Some(Value::Array(Array::String(values)))) => {
+                        for entry in values.iter() {
+                            if let Some(entry) = entry? {
+                                add(&entry);
+                            }
+                        }
+                    }
+                    _ => {}
+                }
+            }
+            if !entries.is_empty() {
+                res.push((
+                    field.id.clone(),
+                    InfoValue::Array(InfoArray::String(entries)),
+                ));
+            }
+        }
+        Ok(res)
+    }
+}
+
+/// The names of the subfields of a consequence annotation field, from the
+/// description in its declaration: after "Format:" for VEP, or in quotes for
+/// SnpEff.
+fn parse_layout(description: &str) -> Option<Vec<String>> {
+    let layout = if let Some((_, layout)) = description.split_once("Format:") {
+        layout
+    } else {
+        let start = description.find('\'')?;
+        let end = description.rfind('\'')?;
+        if end <= start {
+            return None;
+        }
+        &description[start + 1..end]
+    };
+    let names: Vec<String> = layout
+        .split('|')
+        .map(|name| String::from(name.trim().trim_matches(|c| c == '\'' || c == '"').trim()))
+        .collect();
+    if names.len() < 2 {
+        return None;
+    }
+    Some(names)
+}
+
+/// Put the subfields of `entry` in the order given by `mapping`.
+fn rearrange(entry: &str, mapping: &[Option<usize>]) -> String {
+    let subfields: Vec<&str> = entry.split('|').collect();
+    let rearranged: Vec<&str> = mapping
+        .iter()
+        .map(|ix| ix.and_then(|ix| subfields.get(ix).copied()).unwrap_or(""))
+        .collect();
+    rearranged.join("|")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_layout() {
+        assert_eq!(
+            parse_layout(
+                "Consequence annotations from Ensembl VEP. Format: Allele|Consequence|SYMBOL"
+            ),
+            Some(vec![
+                String::from("Allele"),
+                String::from("Consequence"),
+                String::from("SYMBOL")
+            ])
+        );
+        assert_eq!(
+            parse_layout("Functional annotations: 'Allele | Annotation | Gene_Name '"),
+            Some(vec![
+                String::from("Allele"),
+                String::from("Annotation"),
+                String::from("Gene_Name")
+            ])
+        );
+        assert_eq!(parse_layout("Consequence annotations"), None);
+    }
+
+    #[test]
+    fn test_rearrange() {
+        let mapping = [Some(0), Some(2), None, Some(1)];
+        assert_eq!(rearrange("A|GENE|del", &mapping), "A|del||GENE");
+        assert_eq!(rearrange("A", &mapping), "A|||");
+    }
+}
@@ -0,0 +1,211 @@
+use std::io::Write;
+
+use noodles::vcf::{
+    Header,
+    header::record::value::map::{format::Number as FormatNumber, info::Number as InfoNumber},
+};
+
+use crate::tag_patterns::TagPatterns;
+
+/// What becomes of a field declared by an input.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FieldFate {
+    /// Declared in the output header, so its values are carried through.
+    Propagated,
+    /// Dropped with `--unwanted-info`.
+    Unwanted,
+    /// Not declared in the output header, so its values are written (from
+    /// records that lead their group) without a declaration.
+    Undeclared,
+}
+
+impl FieldFate {
+    pub fn name(&self) -> &'static str {
+        match self {
+            FieldFate::Propagated => "propagated",
+            FieldFate::Unwanted => "unwanted",
+            FieldFate::Undeclared => "undeclared",
+        }
+    }
+}
+
+/// An INFO or FORMAT field declared by one of the inputs.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FieldAudit {
+    pub input: String,
+    pub scope: &'static str,
+    pub id: String,
+    pub number: String,
+    pub ty: String,
+    pub fate: FieldFate,
+    /// The other inputs declaring the field with a different Number or Type.
+    pub conflicts: Vec<String>,
+}
+
+/// List the INFO and FORMAT fields declared by each input, with what becomes
+/// of them given the `output` header.
+///
+/// The INFO fields of a merged record are those of the first input (in the
+/// order given) contributing to it, so a field is only carried through where
+/// an input declaring it has the leading record.
+pub fn audit_fields(
+    names: &[String],
+    headers: &[Header],
+    output: &Header,
+    unwanted: &TagPatterns,
+) -> Vec<FieldAudit> {
+    let mut res = Vec::new();
+    for (name, header) in names.iter().zip(headers.iter()) {
+        for (id, info) in header.infos().iter() {
+            let fate = if unwanted.matches(id) {
+                FieldFate::Unwanted
+            } else if output.infos().contains_key(id) {
+                FieldFate::Propagated
+            } else {
+                FieldFate::Undeclared
+            };
+            res.push(FieldAudit {
+                input: name.clone(),
+                scope: "INFO",
+                id: id.clone(),
+                number: info_number(info.number()),
+                ty: info.ty().to_string(),
+                fate,
+                conflicts: Vec::new(),
+            });
+        }
+        for (id, format) in header.formats().iter() {
+            let fate = if output.formats().contains_key(id) {
+                FieldFate::Propagated
+            } else {
+                FieldFate::Undeclared
+            };
+            res.push(FieldAudit {
+                input: name.clone(),
+                scope: "FORMAT",
+                id: id.clone(),
+                number: format_number(format.number()),
+                ty: format.ty().to_string(),
+                fate,
+                conflicts: Vec::new(),
+            });
+        }
+    }
+
+    for i in 0..res.len() {
+        let conflicts: Vec<String> = res
+            .iter()
+            .filter(|other| {
+                other.input != res[i].input
+                    && other.scope == res[i].scope
+                    && other.id == res[i].id
+                    && (other.number != res[i].number || other.ty != res[i].ty)
+            })
+            .map(|other| other.input.clone())
+            .collect();
+        res[i].conflicts = conflicts;
+    }
+    res
+}
+
+/// Write the field audit as tab separated values.
+pub fn write_field_audit<W: Write>(audit: &[FieldAudit], out: &mut W) -> std::io::Result<()> {
+    writeln!(out, "input\tscope\tid\tnumber\ttype\tfate\tconflicts")?;
+    for field in audit.iter() {
+        let conflicts = if field.conflicts.is_empty() {
+            String::from(".")
+        } else {
+            field.conflicts.join(",")
+        };
+        writeln!(
+            out,
+            "{}\t{}\t{}\t{}\t{}\t{}\t{}",
+            field.input,
+            field.scope,
+            field.id,
+            field.number,
+            field.ty,
+            field.fate.name(),
+            conflicts
+        )?;
+    }
+    Ok(())
+}
+
+fn info_number(number: InfoNumber) -> String {
+    match number {
+        InfoNumber::Count(n) => n.to_string(),
+        InfoNumber::AlternateBases => String::from("A"),
+        InfoNumber::ReferenceAlternateBases => String::from("R"),
+        InfoNumber::Samples => String::from("G"),
+        InfoNumber::Unknown => String::from("."),
+    }
+}
+
+fn format_number(number: FormatNumber) -> String {
+    match number {
+        FormatNumber::Count(n) => n.to_string(),
+        FormatNumber::AlternateBases => String::from("A"),
+        FormatNumber::ReferenceAlternateBases => String::from("R"),
+        FormatNumber::Samples => String::from("G"),
+        FormatNumber::LocalAlternateBases => String::from("LA"),
+        FormatNumber::LocalReferenceAlternateBases => String::from("LR"),
+        FormatNumber::LocalSamples => String::from("LG"),
+        FormatNumber::Ploidy => String::from("P"),
+        FormatNumber::BaseModifications => String::from("M"),
+        FormatNumber::Unknown => String::from("."),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn header(lines: &[&str]) -> Header {
+        let mut text = String::from("##fileformat=VCFv4.2\n");
+        for line in lines {
+            text.push_str(line);
+            text.push('\n');
+        }
+        text.push_str("#CHROM\tPOS\tID\tREF\tALT\tQUAL\tFILTER\tINFO\tFORMAT\tS\n");
+        text.parse().unwrap()
+    }
+
+    #[test]
+    fn test_audit_fields() {
+        let a = header(&[
+            "##INFO=<ID=SVLEN,Number=1,Type=Integer,Description=\"Length\">",
+            "##INFO=<ID=RE,Number=1,Type=Integer,Description=\"Reads\">",
+            "##FORMAT=<ID=GT,Number=1,Type=String,Description=\"Genotype\">",
+        ]);
+        let b = header(&[
+            "##INFO=<ID=SVLEN,Number=.,Type=Integer,Description=\"Length\">",
+            "##INFO=<ID=CIPOS,Number=2,Type=Integer,Description=\"Interval\">",
+            "##FORMAT=<ID=GT,Number=1,Type=String,Description=\"Genotype\">",
+            "##FORMAT=<ID=DV,Number=1,Type=Integer,Description=\"Variant reads\">",
+        ]);
+        let names = vec![String::from("a.vcf"), String::from("b.vcf")];
+        let headers = vec![a.clone(), b];
+        let audit = audit_fields(
+            &names,
+            &headers,
+            &a,
+            &TagPatterns::new(&[String::from("RE")]),
+        );
+
+        let find = |input: &str, scope: &str, id: &str| {
+            audit
+                .iter()
+                .find(|f| f.input == input && f.scope == scope && f.id == id)
+                .unwrap()
+        };
+        assert_eq!(audit.len(), 7);
+        assert_eq!(find("a.vcf", "INFO", "RE").fate, FieldFate::Unwanted);
+        assert_eq!(find("b.vcf", "INFO", "CIPOS").fate, FieldFate::Undeclared);
+        assert_eq!(find("b.vcf", "FORMAT", "DV").fate, FieldFate::Undeclared);
+        assert_eq!(find("b.vcf", "FORMAT", "GT").fate, FieldFate::Propagated);
+        assert_eq!(find("a.vcf", "INFO", "SVLEN").conflicts, vec!["b.vcf"]);
+        assert_eq!(find("b.vcf", "INFO", "SVLEN").number, ".");
+        assert!(find("a.vcf", "FORMAT", "GT").conflicts.is_empty());
+    }
+}
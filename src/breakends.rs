@@ -1,13 +1,29 @@
-use std::cmp::max;
+use std::{cmp::max, collections::HashSet, io::Error, sync::Arc};
 
 use datafusion::{
+    arrow::{
+        array::{
+            Array, BooleanBuilder, GenericStringBuilder, Int32Array, Int64Array, PrimitiveBuilder,
+            RecordBatch, StringArray, UInt16Array, UInt32Array, UInt64Array,
+        },
+        datatypes::{DataType, Field, Int64Type, Schema, UInt32Type},
+    },
     common::JoinType,
-    prelude::{DataFrame, col, concat_ws, lit},
+    functions_aggregate::expr_fn::bool_or,
+    prelude::{DataFrame, SessionContext, cast, col, lit, nullif},
 };
 use noodles::{core::Position, fasta::Repository};
 use regex::Regex;
 
-use crate::{errors::SveltError, expressions::prefix_cols};
+use crate::{
+    errors::SveltError,
+    options::{BndPairing, MergeOptions},
+    window::Window,
+};
+
+mod mates;
+
+pub use mates::{MateEnd, MateIndex, MatePair};
 
 #[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Copy)]
 pub enum BreakEndSide {
@@ -15,6 +31,31 @@ pub enum BreakEndSide {
     After,
 }
 
+impl BreakEndSide {
+    pub fn opposite(&self) -> BreakEndSide {
+        match self {
+            BreakEndSide::Before => BreakEndSide::After,
+            BreakEndSide::After => BreakEndSide::Before,
+        }
+    }
+
+    /// A single character code for the side: 'A' for after, 'B' for before.
+    pub fn code(&self) -> char {
+        match self {
+            BreakEndSide::Before => 'B',
+            BreakEndSide::After => 'A',
+        }
+    }
+
+    pub fn from_code(c: char) -> Option<BreakEndSide> {
+        match c {
+            'B' => Some(BreakEndSide::Before),
+            'A' => Some(BreakEndSide::After),
+            _ => None,
+        }
+    }
+}
+
 pub fn parse_breakend(
     alt: &str,
 ) -> std::result::Result<(String, usize, BreakEndSide, BreakEndSide), SveltError> {
@@ -47,6 +88,15 @@ pub fn parse_breakend(
     Err(SveltError::BadBreakEnd(String::from(alt)))
 }
 
+/// The length of the sequence inserted at the junction of a breakend ALT,
+/// e.g. 3 for `tAGC[p[` or `]p]AGCt`, not counting the reference base.
+pub fn breakend_insertion(alt: &str) -> Option<usize> {
+    parse_breakend(alt).ok()?;
+    let first = alt.find(['[', ']'])?;
+    let last = alt.rfind(['[', ']'])?;
+    Some((first + alt.len() - last - 1).saturating_sub(1))
+}
+
 #[derive(Debug)]
 pub struct BreakEnd {
     pub chrom: String,
@@ -70,22 +120,32 @@ impl BreakEnd {
         })
     }
 
+    /// The breakend as the pair of ends it joins (see `MatePair`).
+    pub fn pair(&self) -> MatePair<String> {
+        MatePair::new(
+            self.chrom.clone(),
+            self.end as i32,
+            self.chrom2.clone(),
+            self.end2 as i32,
+            Some((self.side, self.side2)),
+        )
+    }
+
+    /// Construct the mate breakend, as seen from the other side of the join.
+    ///
+    /// The join is on the opposite side of each position when viewed from
+    /// the mate, so `t[p[` pairs with `]t]p`, `t]p]` with `p]t]`, and `[p[t`
+    /// with `[t[p`.
     pub fn flip(&self) -> BreakEnd {
-        let BreakEnd {
-            chrom,
-            end,
+        let mate = self.pair().flip();
+        let (side, side2) = mate.sides().unwrap();
+        BreakEnd {
+            chrom: mate.here.chrom,
+            end: mate.here.pos as usize,
             side,
-            chrom2,
-            end2,
+            chrom2: mate.there.chrom,
+            end2: mate.there.pos as usize,
             side2,
-        } = self;
-        BreakEnd {
-            chrom: chrom2.clone(),
-            end: *end2,
-            side: *side2,
-            chrom2: chrom.clone(),
-            end2: *end,
-            side2: *side,
         }
     }
 
@@ -121,44 +181,406 @@ impl BreakEnd {
     }
 }
 
-pub(crate) async fn unpaired_breakend_check(tbl: DataFrame) -> std::io::Result<DataFrame> {
-    let df = tbl
+/// Add a `paired_bnd` column saying whether each breakend has a reciprocal mate.
+///
+/// With `--bnd-pairing exact` the mate must point back at exactly this breakend,
+/// with `--bnd-pairing window` both positions may differ by less than
+/// `--bnd-pairing-window`. Where the sides of the join are known for both, the
+/// mate must face back the same way (see `MatePair`). Unless
+/// `--bnd-pairing-across-inputs` is given, the mate must come from the same
+/// input VCF.
+pub(crate) async fn unpaired_breakend_check(
+    tbl: DataFrame,
+    options: &MergeOptions,
+    ctx: &SessionContext,
+) -> std::io::Result<DataFrame> {
+    let bnds = tbl
         .clone()
         .filter(col("kind").eq(lit("BND")))?
-        .with_column(
-            "here_there",
-            concat_ws(
-                lit("_"),
-                vec![col("chrom"), col("end"), col("chrom2"), col("end2")],
-            ),
-        )?
-        .with_column(
-            "there_here",
-            concat_ws(
-                lit("_"),
-                vec![col("chrom2"), col("end2"), col("chrom"), col("end")],
-            ),
-        )?;
-
-    let lhs = prefix_cols(df.clone(), "lhs")?;
-    let rhs = prefix_cols(df.clone(), "rhs")?;
-
-    let paired = lhs
+        .select(vec![
+            col("row_id"),
+            col("vix"),
+            cast(col("chrom"), DataType::Utf8).alias("chrom"),
+            col("end"),
+            cast(col("chrom2"), DataType::Utf8).alias("chrom2"),
+            col("end2"),
+            col("bnd_sides"),
+        ])?
+        .collect()
+        .await?;
+
+    let mut row_ids = Vec::new();
+    let mut pairs = Vec::new();
+    for recs in bnds.iter() {
+        let row_id = get_array::<Int64Array>(recs, "row_id");
+        let vix = get_array::<UInt32Array>(recs, "vix");
+        let chrom = get_array::<StringArray>(recs, "chrom");
+        let end = get_array::<Int32Array>(recs, "end");
+        let chrom2 = get_array::<StringArray>(recs, "chrom2");
+        let end2 = get_array::<Int32Array>(recs, "end2");
+        let sides = get_array::<StringArray>(recs, "bnd_sides");
+        for i in 0..recs.num_rows() {
+            row_ids.push(row_id.value(i));
+            if chrom2.is_null(i) || end2.is_null(i) {
+                continue;
+            }
+            let sides = sides.is_valid(i).then(|| {
+                let mut codes = sides.value(i).chars().filter_map(BreakEndSide::from_code);
+                codes.next().zip(codes.next())
+            });
+            let pair = MatePair::new(
+                String::from(chrom.value(i)),
+                end.value(i),
+                String::from(chrom2.value(i)),
+                end2.value(i),
+                sides.flatten(),
+            );
+            pairs.push((pair, (row_id.value(i), vix.value(i))));
+        }
+    }
+
+    // The window is exclusive.
+    let w = match options.bnd_pairing {
+        BndPairing::Exact => Window::new(0),
+        BndPairing::Window => Window::new(options.bnd_pairing_window.saturating_sub(1)),
+    };
+    let index = MateIndex::new(pairs.iter().cloned(), w);
+    let mut paired: HashSet<i64> = HashSet::new();
+    for (pair, (row_id, vix)) in pairs.iter() {
+        let mut mates = index.mates(pair);
+        if mates.any(|(mate_row_id, mate_vix)| {
+            mate_row_id != row_id && (options.bnd_pairing_across_inputs || mate_vix == vix)
+        }) {
+            paired.insert(*row_id);
+        }
+    }
+
+    let mut row_id_builder = PrimitiveBuilder::<Int64Type>::new();
+    let mut paired_builder = BooleanBuilder::new();
+    for row_id in row_ids {
+        row_id_builder.append_value(row_id);
+        paired_builder.append_value(paired.contains(&row_id));
+    }
+    let schema = Arc::new(Schema::new(vec![
+        Field::new("paired_row_id", DataType::Int64, false),
+        Field::new("paired_bnd", DataType::Boolean, false),
+    ]));
+    let recs = RecordBatch::try_new(
+        schema,
+        vec![
+            Arc::new(row_id_builder.finish()),
+            Arc::new(paired_builder.finish()),
+        ],
+    )
+    .map_err(Error::other)?;
+    let paired = ctx.read_batch(recs)?;
+
+    let tbl = tbl
         .join(
-            rhs,
+            paired,
             JoinType::Left,
-            &["lhs_vix", "lhs_here_there"],
-            &["rhs_vix", "rhs_there_here"],
+            &["row_id"],
+            &["paired_row_id"],
             None,
         )?
-        .with_column("paired_bnd", col("rhs_row_id").is_not_null())?
-        .select_columns(&["lhs_row_id", "paired_bnd"])?;
+        .drop_columns(&["paired_row_id"])?;
+
+    Ok(tbl)
+}
+
+/// Find the unpaired breakend groups whose synthetic mate would sort before the
+/// group itself in the output.
+///
+/// Returns the `row_key` of each such group along with the (contig number, position)
+/// of the mate. The table is expected to have the primary columns added.
+pub(crate) async fn unpaired_breakend_mates(
+    tbl: DataFrame,
+) -> std::io::Result<Vec<(u32, (usize, usize))>> {
+    let df = tbl
+        .filter(col("kind").eq(lit("BND")).and(col("chrom2_id").is_not_null()))?
+        .aggregate(
+            vec![
+                col("row_key"),
+                col("chrom_id"),
+                col("primary_start"),
+                col("chrom2_id"),
+                col("primary_end2"),
+            ],
+            vec![bool_or(col("paired_bnd")).alias("paired")],
+        )?
+        .filter(col("paired").is_not_true())?
+        .filter(
+            col("chrom2_id")
+                .lt(col("chrom_id"))
+                .or(col("chrom2_id")
+                    .eq(col("chrom_id"))
+                    .and(col("primary_end2").lt(col("primary_start")))),
+        )?
+        .select(vec![
+            col("row_key"),
+            cast(col("chrom2_id"), DataType::UInt64).alias("chrom2_id"),
+            col("primary_end2"),
+        ])?;
+
+    let mut res = Vec::new();
+    for recs in df.collect().await? {
+        let row_keys = get_array::<UInt32Array>(&recs, "row_key");
+        let chrom2_ids = get_array::<UInt64Array>(&recs, "chrom2_id");
+        let end2s = get_array::<Int32Array>(&recs, "primary_end2");
+        for i in 0..recs.num_rows() {
+            let pos = std::cmp::max(1, end2s.value(i)) as usize;
+            res.push((row_keys.value(i), (chrom2_ids.value(i) as usize, pos)));
+        }
+    }
+    Ok(res)
+}
+
+/// The coordinates and orientation of a group of merged breakends.
+#[derive(Debug, Clone, Copy)]
+struct BreakEndGroup {
+    row_key: u32,
+    chrom_id: u16,
+    end: i32,
+    chrom2_id: u16,
+    end2: i32,
+    side: BreakEndSide,
+    side2: BreakEndSide,
+}
+
+impl BreakEndGroup {
+    /// Each pair of mates has one member in canonical order, with its own
+    /// position coming before that of its mate.
+    fn is_canonical(&self) -> bool {
+        (self.chrom_id, self.end) < (self.chrom2_id, self.end2)
+    }
+
+    fn pair(&self) -> MatePair<u16> {
+        MatePair::new(
+            self.chrom_id,
+            self.end,
+            self.chrom2_id,
+            self.end2,
+            Some((self.side, self.side2)),
+        )
+    }
+
+    /// Two breakends form the two sides of a balanced rearrangement if they join
+    /// nearby positions on the same pair of contigs, but facing the opposite way.
+    fn is_reciprocal_of(&self, other: &BreakEndGroup, w: Window) -> bool {
+        self.chrom_id == other.chrom_id
+            && self.chrom2_id == other.chrom2_id
+            && w.contains(self.end, other.end)
+            && w.contains(self.end2, other.end2)
+            && self.side != other.side
+            && self.side2 != other.side2
+    }
+}
+
+/// Recognise balanced rearrangements (e.g. reciprocal translocations), which
+/// appear as a quadruple of breakends: two reciprocal pairs of mates.
+///
+/// Adds a column `event_id` which gives the four groups of a quadruple
+/// a common identifier, and is null for all other rows (and for all rows,
+/// without `--match-bnd-orientation`). The table is expected to have its
+/// merging completed.
+pub(crate) async fn breakend_events(
+    tbl: DataFrame,
+    options: &MergeOptions,
+    ctx: &SessionContext,
+) -> std::io::Result<DataFrame> {
+    let w = Window::new(options.position_window.get("BND"));
+
+    // Without --match-bnd-orientation, breakends of opposite orientation may
+    // have been merged, breaking up the quadruples, so none are looked for.
+    if !options.match_bnd_orientation {
+        return Ok(tbl.with_column("event_id", nullif(lit(""), lit("")))?);
+    }
+
+    let bnds = tbl
+        .clone()
+        .filter(
+            col("kind")
+                .eq(lit("BND"))
+                .and(col("chrom2_id").is_not_null())
+                .and(col("bnd_sides").is_not_null()),
+        )?
+        .select_columns(&["row_key", "row_id", "chrom_id", "end", "chrom2_id", "end2", "bnd_sides"])?
+        .sort_by(vec![col("row_key"), col("row_id")])?
+        .collect()
+        .await?;
+
+    // Use the first member of each group to represent it.
+    let mut groups: Vec<BreakEndGroup> = Vec::new();
+    for recs in bnds.iter() {
+        let row_keys = get_array::<UInt32Array>(recs, "row_key");
+        let chrom_ids = get_array::<UInt16Array>(recs, "chrom_id");
+        let ends = get_array::<Int32Array>(recs, "end");
+        let chrom2_ids = get_array::<UInt16Array>(recs, "chrom2_id");
+        let end2s = get_array::<Int32Array>(recs, "end2");
+        let sides = get_array::<StringArray>(recs, "bnd_sides");
+        for i in 0..recs.num_rows() {
+            let row_key = row_keys.value(i);
+            if groups.last().is_some_and(|g| g.row_key == row_key) {
+                continue;
+            }
+            let mut codes = sides.value(i).chars().filter_map(BreakEndSide::from_code);
+            if let (Some(side), Some(side2)) = (codes.next(), codes.next()) {
+                groups.push(BreakEndGroup {
+                    row_key,
+                    chrom_id: chrom_ids.value(i),
+                    end: ends.value(i),
+                    chrom2_id: chrom2_ids.value(i),
+                    end2: end2s.value(i),
+                    side,
+                    side2,
+                });
+            }
+        }
+    }
+
+    let (mut canonical, others): (Vec<BreakEndGroup>, Vec<BreakEndGroup>) =
+        groups.into_iter().partition(|g| g.is_canonical());
+    canonical.sort_by_key(|g| (g.chrom_id, g.chrom2_id, g.end, g.end2, g.row_key));
+
+    let mates = MateIndex::new(others.iter().map(|g| (g.pair(), g.row_key)), w);
+
+    let mut used: HashSet<u32> = HashSet::new();
+    let find_mate = |g: &BreakEndGroup, used: &HashSet<u32>| {
+        let pair = g.pair();
+        mates
+            .mates(&pair)
+            .find(|row_key| !used.contains(row_key))
+            .copied()
+    };
+
+    let mut row_key_builder = PrimitiveBuilder::<UInt32Type>::new();
+    let mut event_id_builder = GenericStringBuilder::<i32>::new();
+    let mut event_count = 0;
+    for i in 0..canonical.len() {
+        let g = canonical[i];
+        if used.contains(&g.row_key) {
+            continue;
+        }
+        for h in canonical[i + 1..].iter() {
+            if h.chrom_id != g.chrom_id || h.chrom2_id != g.chrom2_id || w.passed(g.end, h.end) {
+                break;
+            }
+            if used.contains(&h.row_key) || !g.is_reciprocal_of(h, w) {
+                continue;
+            }
+            let Some(g_mate) = find_mate(&g, &used) else {
+                continue;
+            };
+            let mut tentative = used.clone();
+            tentative.insert(g_mate);
+            let Some(h_mate) = find_mate(h, &tentative) else {
+                continue;
+            };
 
-    // paired.sort_by(vec![col("lhs_row_id")])?.show().await?;
+            event_count += 1;
+            let event_id = format!("SVELT_EVENT_{}", event_count);
+            for row_key in [g.row_key, g_mate, h.row_key, h_mate] {
+                used.insert(row_key);
+                row_key_builder.append_value(row_key);
+                event_id_builder.append_value(&event_id);
+            }
+            break;
+        }
+    }
+    log::info!("found {} balanced breakend events", event_count);
+
+    let schema = Arc::new(Schema::new(vec![
+        Field::new("event_row_key", DataType::UInt32, false),
+        Field::new("event_id", DataType::Utf8, false),
+    ]));
+    let recs = RecordBatch::try_new(
+        schema,
+        vec![
+            Arc::new(row_key_builder.finish()),
+            Arc::new(event_id_builder.finish()),
+        ],
+    )
+    .map_err(Error::other)?;
+    let events = ctx.read_batch(recs)?;
 
     let tbl = tbl
-        .join(paired, JoinType::Left, &["row_id"], &["lhs_row_id"], None)?
-        .drop_columns(&["lhs_row_id"])?;
+        .join(events, JoinType::Left, &["row_key"], &["event_row_key"], None)?
+        .drop_columns(&["event_row_key"])?;
 
     Ok(tbl)
 }
+
+fn get_array<'a, Type: 'static>(recs: &'a RecordBatch, name: &str) -> &'a Type {
+    recs.column_by_name(name)
+        .unwrap()
+        .as_any()
+        .downcast_ref::<Type>()
+        .unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn mate_alt(chrom: &str, end: usize, alt: &str) -> (BreakEndSide, BreakEndSide) {
+        let bnd = BreakEnd::new(chrom, end, alt).unwrap();
+        let mate = bnd.flip();
+        assert_eq!(mate.chrom, bnd.chrom2);
+        assert_eq!(mate.end, bnd.end2);
+        assert_eq!(mate.chrom2, bnd.chrom);
+        assert_eq!(mate.end2, bnd.end);
+        (mate.side, mate.side2)
+    }
+
+    #[test]
+    fn test_flip_mates() {
+        // Mate pairs from the VCF specification.
+        let sides = |alt: &str| parse_breakend(alt).map(|x| (x.2, x.3)).unwrap();
+        assert_eq!(mate_alt("13", 123456, "C[2:321682["), sides("]13:123456]T"));
+        assert_eq!(mate_alt("2", 321682, "]13:123456]T"), sides("C[2:321682["));
+        assert_eq!(mate_alt("2", 321681, "G]17:198982]"), sides("A]2:321681]"));
+        assert_eq!(mate_alt("13", 123457, "[17:198983[A"), sides("[13:123457[C"));
+    }
+
+    #[test]
+    fn test_flip_twice() {
+        for alt in ["A[2:10[", "A]2:10]", "]2:10]A", "[2:10[A"] {
+            let bnd = BreakEnd::new("1", 5, alt).unwrap();
+            let back = bnd.flip().flip();
+            assert_eq!((back.side, back.side2), (bnd.side, bnd.side2));
+            assert!(bnd.flip().pair().is_mate_of(&bnd.pair(), Window::new(0)));
+        }
+    }
+    #[test]
+    fn test_breakend_insertion() {
+        assert_eq!(breakend_insertion("C[2:321682["), Some(0));
+        assert_eq!(breakend_insertion("]13:123456]T"), Some(0));
+        assert_eq!(breakend_insertion("CAGT[2:321682["), Some(3));
+        assert_eq!(breakend_insertion("]13:123456]AGTNNNNNCAT"), Some(10));
+        assert_eq!(breakend_insertion("<DEL>"), None);
+    }
+
+    #[test]
+    fn test_mates_at_extremes() {
+        let group = |end: i32, end2: i32, side: BreakEndSide, side2: BreakEndSide| BreakEndGroup {
+            row_key: 0,
+            chrom_id: 0,
+            end,
+            chrom2_id: 0,
+            end2,
+            side,
+            side2,
+        };
+        let (before, after) = (BreakEndSide::Before, BreakEndSide::After);
+        let g = group(1, i32::MAX, after, before);
+        let h = group(i32::MAX - 25, 26, after, before);
+        assert!(g.pair().is_mate_of(&h.pair(), Window::new(25)));
+        assert!(!g.pair().is_mate_of(&h.pair(), Window::new(24)));
+        assert!(!g.pair().is_mate_of(&h.pair(), Window::new(0)));
+
+        // Windows too large for an i32 accept everything.
+        let h = group(0, i32::MAX, after, before);
+        assert!(g.pair().is_mate_of(&h.pair(), Window::new(u32::MAX)));
+        assert!(g.is_reciprocal_of(&group(i32::MAX, 0, before, after), Window::new(u32::MAX)));
+    }
+}
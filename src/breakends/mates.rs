@@ -0,0 +1,209 @@
+use std::{collections::HashMap, hash::Hash};
+
+use crate::{breakends::BreakEndSide, window::Window};
+
+/// One end of the join a breakend makes: a contig, a position on it, and
+/// the side of the position that is joined, where that is known.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MateEnd<C> {
+    pub chrom: C,
+    pub pos: i32,
+    pub side: Option<BreakEndSide>,
+}
+
+impl<C: PartialEq> MateEnd<C> {
+    /// Whether `other` is the same end, to within `w`. Sides that aren't
+    /// known (e.g. from breakends given in the INFO fields) match either.
+    fn meets(&self, other: &MateEnd<C>, w: Window) -> bool {
+        self.chrom == other.chrom
+            && w.contains(self.pos, other.pos)
+            && match (self.side, other.side) {
+                (Some(lhs), Some(rhs)) => lhs == rhs,
+                _ => true,
+            }
+    }
+}
+
+/// A breakend as the pair of ends it joins, seen from `here`.
+///
+/// The side of `there` is the side of the mate's own position that is
+/// joined, i.e. the opposite of the side given in the ALT (see
+/// `BreakEnd::flip`), so that a breakend and its mate are the same pair of
+/// ends, swapped.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MatePair<C> {
+    pub here: MateEnd<C>,
+    pub there: MateEnd<C>,
+}
+
+impl<C: Clone + Ord> MatePair<C> {
+    pub fn new(
+        chrom: C,
+        end: i32,
+        chrom2: C,
+        end2: i32,
+        sides: Option<(BreakEndSide, BreakEndSide)>,
+    ) -> MatePair<C> {
+        MatePair {
+            here: MateEnd {
+                chrom,
+                pos: end,
+                side: sides.map(|(side, _)| side),
+            },
+            there: MateEnd {
+                chrom: chrom2,
+                pos: end2,
+                side: sides.map(|(_, side2)| side2.opposite()),
+            },
+        }
+    }
+
+    /// The sides of the join as the ALT gives them, where both are known.
+    pub fn sides(&self) -> Option<(BreakEndSide, BreakEndSide)> {
+        Some((self.here.side?, self.there.side?.opposite()))
+    }
+
+    /// The mate, as seen from the other end of the join.
+    pub fn flip(&self) -> MatePair<C> {
+        MatePair {
+            here: self.there.clone(),
+            there: self.here.clone(),
+        }
+    }
+
+    /// Whether `other` is the mate of this breakend, with positions that
+    /// differ by no more than `w`.
+    pub fn is_mate_of(&self, other: &MatePair<C>, w: Window) -> bool {
+        self.here.meets(&other.there, w) && self.there.meets(&other.here, w)
+    }
+
+    /// The canonical form of the pair, shared by a breakend and its mate: the
+    /// two contigs in order, and the position of the end that comes first.
+    fn key(&self) -> ((C, C), i32) {
+        let (first, second) =
+            if (&self.here.chrom, self.here.pos) <= (&self.there.chrom, self.there.pos) {
+                (&self.here, &self.there)
+            } else {
+                (&self.there, &self.here)
+            };
+        ((first.chrom.clone(), second.chrom.clone()), first.pos)
+    }
+}
+
+/// An index of breakends by their canonical pair of ends, for finding the
+/// mates of breakends within a window.
+///
+/// The breakends joining each pair of contigs are kept sorted by the
+/// position of their first end, which is within the window of that of each
+/// of their mates, so a lookup is a hash and a binary search.
+#[derive(Debug)]
+pub struct MateIndex<C, T> {
+    window: Window,
+    pairs: HashMap<(C, C), Bucket<C, T>>,
+}
+
+/// The breakends joining a pair of contigs, with the position of their first
+/// end.
+type Bucket<C, T> = Vec<(i32, MatePair<C>, T)>;
+
+impl<C: Clone + Ord + Hash, T> MateIndex<C, T> {
+    pub fn new<I: IntoIterator<Item = (MatePair<C>, T)>>(items: I, window: Window) -> Self {
+        let mut pairs: HashMap<(C, C), Bucket<C, T>> = HashMap::new();
+        for (pair, value) in items {
+            let (contigs, pos) = pair.key();
+            pairs.entry(contigs).or_default().push((pos, pair, value));
+        }
+        // Stable, so mates at the same position are found in the order given.
+        for items in pairs.values_mut() {
+            items.sort_by_key(|item| item.0);
+        }
+        MateIndex { window, pairs }
+    }
+
+    /// The values of the breakends in the index that are mates of `pair`.
+    pub fn mates<'a>(&'a self, pair: &'a MatePair<C>) -> impl Iterator<Item = &'a T> + 'a {
+        let (contigs, pos) = pair.key();
+        let w = self.window;
+        let items = self
+            .pairs
+            .get(&contigs)
+            .map_or(&[][..], |items| items.as_slice());
+        let first = items.partition_point(|item| w.passed(item.0, pos));
+        items[first..]
+            .iter()
+            .take_while(move |item| !w.passed(pos, item.0))
+            .filter(move |item| item.1.is_mate_of(pair, w))
+            .map(|item| &item.2)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SIDES: [BreakEndSide; 2] = [BreakEndSide::Before, BreakEndSide::After];
+
+    #[test]
+    fn test_mate_orientations() {
+        for side in SIDES {
+            for side2 in SIDES {
+                let pair = MatePair::new("chr1", 1000, "chr2", 500, Some((side, side2)));
+                let mate = pair.flip();
+                assert_eq!(mate.flip(), pair);
+                assert!(mate.is_mate_of(&pair, Window::new(0)));
+                assert!(pair.is_mate_of(&mate, Window::new(0)));
+                assert_eq!(mate.key(), pair.key());
+
+                // A breakend is not its own mate, nor is one facing any other way.
+                assert!(!pair.is_mate_of(&pair, Window::new(0)));
+                for other in SIDES {
+                    for other2 in SIDES {
+                        let other = MatePair::new("chr2", 500, "chr1", 1000, Some((other, other2)));
+                        assert_eq!(
+                            other.is_mate_of(&pair, Window::new(0)),
+                            other == mate,
+                            "{:?} {:?}",
+                            pair,
+                            other
+                        );
+                    }
+                }
+
+                // Unknown sides match any.
+                let unknown = MatePair::new("chr2", 500, "chr1", 1000, None);
+                assert!(unknown.is_mate_of(&pair, Window::new(0)));
+            }
+        }
+    }
+
+    #[test]
+    fn test_mate_index() {
+        let (before, after) = (BreakEndSide::Before, BreakEndSide::After);
+        let pairs = vec![
+            (MatePair::new(1, 1000, 2, 500, Some((after, after))), 'a'),
+            (MatePair::new(2, 510, 1, 990, Some((before, before))), 'b'),
+            (MatePair::new(2, 500, 1, 1000, Some((before, after))), 'c'),
+            (MatePair::new(2, 530, 1, 1000, None), 'd'),
+            (MatePair::new(1, 200, 1, 100, Some((before, after))), 'e'),
+            (MatePair::new(1, 110, 1, 190, Some((before, after))), 'f'),
+        ];
+
+        let exact = MateIndex::new(pairs.clone(), Window::new(0));
+        let mates = |index: &MateIndex<u16, char>, ix: usize| -> Vec<char> {
+            index.mates(&pairs[ix].0).copied().collect()
+        };
+        assert_eq!(mates(&exact, 0), Vec::<char>::new());
+        assert_eq!(mates(&exact, 1), Vec::<char>::new());
+        assert_eq!(mates(&exact, 4), Vec::<char>::new());
+
+        let near = MateIndex::new(pairs.clone(), Window::new(10));
+        assert_eq!(mates(&near, 0), vec!['b']);
+        assert_eq!(mates(&near, 1), vec!['a']);
+        assert_eq!(mates(&near, 2), Vec::<char>::new());
+        assert_eq!(mates(&near, 4), vec!['f']);
+        assert_eq!(mates(&near, 5), vec!['e']);
+
+        let far = MateIndex::new(pairs.clone(), Window::new(30));
+        assert_eq!(mates(&far, 0), vec!['b', 'd']);
+    }
+}
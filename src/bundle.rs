@@ -0,0 +1,106 @@
+use std::{
+    io::{BufWriter, Write},
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use datafusion::{
+    dataframe::DataFrameWriteOptions, parquet::arrow::ArrowWriter, prelude::DataFrame,
+};
+use serde::Serialize;
+
+use crate::{options::MergeOptions, report::is_empty};
+
+/// The merge table, as written with `--write-merge-table`.
+pub const BUNDLE_MERGE_TABLE: &str = "merge-table.parquet";
+
+/// The per-input and per-sample QC report, as written with `--qc-report-format json`.
+pub const BUNDLE_SUMMARY: &str = "summary.json";
+
+/// The version of svelt, the command line, the inputs, and the merge options.
+pub const BUNDLE_CONFIG: &str = "config.json";
+
+/// A directory holding the merged VCF along with everything needed to see how
+/// it was made (`--bundle-output`).
+pub struct Bundle {
+    dir: PathBuf,
+}
+
+#[derive(Serialize)]
+struct BundleConfig<'a> {
+    svelt_version: &'a str,
+    command_line: Vec<String>,
+    output: &'a str,
+    inputs: &'a [String],
+    options: &'a MergeOptions,
+}
+
+impl Bundle {
+    /// Create the directory (and its parents) if need be.
+    pub fn create(dir: &str) -> std::io::Result<Bundle> {
+        std::fs::create_dir_all(dir)?;
+        Ok(Bundle {
+            dir: PathBuf::from(dir),
+        })
+    }
+
+    /// The path of a file in the bundle.
+    pub fn path(&self, name: &str) -> String {
+        self.dir.join(name).to_string_lossy().into_owned()
+    }
+
+    /// Record the configuration of the merge of `inputs` into `out`.
+    pub fn write_config(
+        &self,
+        out: &str,
+        inputs: &[String],
+        options: &MergeOptions,
+    ) -> std::io::Result<()> {
+        let config = BundleConfig {
+            svelt_version: env!("CARGO_PKG_VERSION"),
+            command_line: std::env::args().collect(),
+            output: out,
+            inputs,
+            options,
+        };
+        let mut writer = BufWriter::new(std::fs::File::create(self.path(BUNDLE_CONFIG))?);
+        serde_json::to_writer_pretty(&mut writer, &config)?;
+        writeln!(writer)?;
+        writer.flush()
+    }
+
+    /// Write the merge table as parquet.
+    pub async fn write_merge_table(&self, table: DataFrame) -> std::io::Result<()> {
+        if is_empty(&table).await? {
+            // DataFusion writes no file at all for an empty table.
+            let schema = Arc::new(table.schema().as_arrow().clone());
+            let file = std::fs::File::create(self.path(BUNDLE_MERGE_TABLE))?;
+            ArrowWriter::try_new(file, schema, None)
+                .and_then(|writer| writer.close())
+                .map_err(std::io::Error::other)?;
+            return Ok(());
+        }
+        table
+            .write_parquet(
+                &self.path(BUNDLE_MERGE_TABLE),
+                DataFrameWriteOptions::default(),
+                None,
+            )
+            .await?;
+        Ok(())
+    }
+
+    /// Copy the merged VCF into the bundle, under the same name (unless it
+    /// was written there in the first place).
+    pub fn add_vcf(&self, out: &str) -> std::io::Result<()> {
+        let name = Path::new(out)
+            .file_name()
+            .unwrap_or_else(|| std::ffi::OsStr::new("merged.vcf"));
+        let dest = self.dir.join(name);
+        if dest.exists() && std::fs::canonicalize(&dest)? == std::fs::canonicalize(out)? {
+            return Ok(());
+        }
+        std::fs::copy(out, dest)?;
+        Ok(())
+    }
+}
@@ -1,8 +1,12 @@
 use std::collections::HashMap;
 
+use regex::Regex;
+
 pub struct ChromSet {
     names: Vec<String>,
     index: HashMap<String, usize>,
+    aliases: HashMap<String, usize>,
+    partial: bool,
 }
 
 impl ChromSet {
@@ -10,6 +14,8 @@ impl ChromSet {
         ChromSet {
             names: Vec::new(),
             index: HashMap::new(),
+            aliases: HashMap::new(),
+            partial: false,
         }
     }
 
@@ -35,6 +41,44 @@ impl ChromSet {
     pub fn index(&self, name: &str) -> Option<usize> {
         self.index.get(name).map(|x| *x)
     }
+
+    /// Also recognise names that differ from the contigs only by a `chr`
+    /// prefix (e.g. `1` for `chr1`, and `MT` for `chrM`), for callers that
+    /// name mate contigs differently from the primary ones.
+    pub fn add_soft_aliases(&mut self) {
+        for (ix, name) in self.names.iter().enumerate() {
+            let mut aliases = vec![match name.strip_prefix("chr") {
+                Some(rest) => String::from(rest),
+                None => format!("chr{}", name),
+            }];
+            match name.as_str() {
+                "chrM" => aliases.push(String::from("MT")),
+                "MT" => aliases.push(String::from("chrM")),
+                _ => {}
+            }
+            for alias in aliases {
+                if !self.index.contains_key(&alias) {
+                    self.aliases.entry(alias).or_insert(ix);
+                }
+            }
+        }
+    }
+
+    /// The index of a contig, allowing for any aliases.
+    pub fn resolve(&self, name: &str) -> Option<usize> {
+        self.index(name).or_else(|| self.aliases.get(name).copied())
+    }
+
+    /// Accept headers declaring only some of the contigs, in any order (see
+    /// `--reconcile-contigs`), for a set made from the contigs of all the
+    /// inputs.
+    pub fn accept_partial_headers(&mut self) {
+        self.partial = true;
+    }
+
+    pub fn accepts_partial_headers(&self) -> bool {
+        self.partial
+    }
 }
 
 impl From<&[&str]> for ChromSet {
@@ -45,6 +89,70 @@ impl From<&[&str]> for ChromSet {
             .enumerate()
             .map(|(ix, chrom)| (String::from(*chrom), ix))
             .collect();
-        ChromSet { names, index }
+        ChromSet {
+            names,
+            index,
+            aliases: HashMap::new(),
+            partial: false,
+        }
+    }
+}
+
+/// Select the contigs whose records take part in a merge.
+///
+/// A contig is kept if it matches the include pattern (when given) and does
+/// not match the exclude pattern (when given). Patterns are unanchored, so
+/// use `^...$` to match whole names.
+pub struct ContigFilter {
+    include: Option<Regex>,
+    exclude: Option<Regex>,
+}
+
+impl ContigFilter {
+    pub fn new(include: Option<Regex>, exclude: Option<Regex>) -> Self {
+        ContigFilter { include, exclude }
+    }
+
+    /// Are records on this contig kept?
+    pub fn keeps(&self, name: &str) -> bool {
+        self.include.as_ref().is_none_or(|re| re.is_match(name))
+            && !self.exclude.as_ref().is_some_and(|re| re.is_match(name))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_contig_filter() {
+        let filter = ContigFilter::new(
+            Some(Regex::new(r"^chr").unwrap()),
+            Some(Regex::new(r"_alt$|_decoy$|^chrUn_").unwrap()),
+        );
+        assert!(filter.keeps("chr1"));
+        assert!(filter.keeps("chrX"));
+        assert!(!filter.keeps("chr6_GL000250v2_alt"));
+        assert!(!filter.keeps("chrUn_KI270302v1"));
+        assert!(!filter.keeps("hs38d1_decoy"));
+        assert!(!filter.keeps("HLA-A*01:01:01:01"));
+
+        let filter = ContigFilter::new(None, None);
+        assert!(filter.keeps("chr6_GL000250v2_alt"));
+    }
+
+    #[test]
+    fn test_soft_aliases() {
+        let mut chroms = ChromSet::from(["chr1", "chr2", "chrM", "3"].as_ref());
+        assert_eq!(chroms.resolve("1"), None);
+        chroms.add_soft_aliases();
+        assert_eq!(chroms.resolve("chr1"), Some(0));
+        assert_eq!(chroms.resolve("1"), Some(0));
+        assert_eq!(chroms.resolve("2"), Some(1));
+        assert_eq!(chroms.resolve("MT"), Some(2));
+        assert_eq!(chroms.resolve("M"), Some(2));
+        assert_eq!(chroms.resolve("chr3"), Some(3));
+        assert_eq!(chroms.resolve("chr4"), None);
+        assert_eq!(chroms.index("1"), None);
     }
 }
@@ -1,16 +1,22 @@
-use std::collections::HashSet;
-use std::io::{BufWriter, Error, ErrorKind};
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs::File;
+use std::io::{BufWriter, Error, ErrorKind, Write as _};
+use std::num::NonZeroUsize;
 use std::rc::Rc;
 use std::str::FromStr;
 
 use autocompress::io::ProcessorWriter;
 use autocompress::{CompressionLevel, Processor, autodetect_create};
+use noodles::bcf;
+use noodles::bgzf;
 use noodles::core::Position;
 use noodles::fasta::Repository;
 use noodles::vcf;
 use noodles::vcf::header::record::value::Map;
 use noodles::vcf::header::record::value::map::info::{Number, Type};
-use noodles::vcf::header::record::value::map::{Builder, Filter};
+use noodles::vcf::header::record::value::map::{Filter, Info as InfoMap};
+use noodles::vcf::variant::Record as VariantRecord;
 use noodles::vcf::variant::io::Write;
 use noodles::vcf::variant::record::{
     AlternateBases as AlternateBases_, Filters as Filters_, Ids as Ids_,
@@ -24,18 +30,211 @@ use noodles::vcf::variant::record_buf::{AlternateBases, Filters, Ids, Info, Samp
 use noodles::vcf::{Header, Record, variant::RecordBuf};
 use vcf::variant::record_buf::info::field::value::Array as InfoArray;
 
+use crate::annotations::AnnotationReconciler;
 use crate::breakends::{BreakEnd, parse_breakend};
-use crate::options::MergeOptions;
-use crate::tables::is_seq;
+use crate::errors::{Context, VariantContext, as_io_error, error_chain};
+use crate::inputs::{get_svtype, get_translocation, sv_kind};
+use crate::export::{EventWriter, mate_position};
+use crate::format_map::{FormatMap, SampleFields};
+use crate::info_rules::InfoRules;
+use crate::samples::{SampleLayout, consensus_genotype};
+use crate::homology::reverse_complement;
+use crate::igv::IgvBatch;
+use crate::observer::MergeObserver;
+use crate::options::{
+    ContigLengthPolicy, IndexFormat, MergeOptions, OutputFormat, RecordErrorAction,
+    UnpairedBndAction,
+};
+use crate::output_format::{
+    MERGE_CRITERIA, SHARED_INFO_FIELDS, STANDARD_INFO_FIELDS, SVELT_FORMAT_KEY,
+    SVELT_FORMAT_VERSION, SVELT_INFO_FIELDS, input_svelt_field, is_svelt_owned,
+};
+use crate::output_index::OutputIndexer;
+use crate::quarantine::Quarantine;
+use crate::repeats::RepeatMask;
+use crate::tables::{compatible_symbols, is_seq, symbolic_alt};
+use crate::tag_patterns::TagPatterns;
 
 pub type InnerWriter =
     BufWriter<ProcessorWriter<Box<dyn Processor + Send + Unpin + 'static>, std::fs::File>>;
+
+/// The destination of the merged VCF.
+///
+/// Outputs named `.gz`, `.bgz` or `.bcf` are written as BGZF, at the
+/// requested compression level, with blocks compressed in parallel by the
+/// given number of threads. Anything else is left to `autocompress` to
+/// recognise.
+///
+/// For BGZF, the number of (uncompressed) bytes written so far is kept, so
+/// that the output can be indexed.
+pub enum OutputWriter {
+    Bgzf(bgzf::io::MultithreadedWriter<File>, u64),
+    Other(InnerWriter),
+}
+
+impl OutputWriter {
+    pub fn create(out: &str, level: Option<u8>, threads: usize) -> std::io::Result<OutputWriter> {
+        if !(out.ends_with(".gz") || out.ends_with(".bgz") || out.ends_with(".bcf")) {
+            let writer = autodetect_create(out, CompressionLevel::Default)?;
+            return Ok(OutputWriter::Other(BufWriter::new(writer)));
+        }
+        OutputWriter::create_bgzf(out, level, threads)
+    }
+
+    /// BGZF output, whatever it's called (e.g. for BCF).
+    pub fn create_bgzf(
+        out: &str,
+        level: Option<u8>,
+        threads: usize,
+    ) -> std::io::Result<OutputWriter> {
+        let level = match level {
+            Some(level) => bgzf::io::writer::CompressionLevel::new(level).ok_or_else(|| {
+                Error::new(
+                    ErrorKind::InvalidInput,
+                    format!("invalid compression level {}", level),
+                )
+            })?,
+            None => bgzf::io::writer::CompressionLevel::default(),
+        };
+        let workers = NonZeroUsize::new(threads).unwrap_or(NonZeroUsize::MIN);
+        let file = File::create(out)?;
+        Ok(OutputWriter::Bgzf(
+            bgzf::io::multithreaded_writer::Builder::default()
+                .set_compression_level(level)
+                .set_worker_count(workers)
+                .build_from_writer(file),
+            0,
+        ))
+    }
+
+    /// The uncompressed offset of the next byte written to BGZF output.
+    pub fn position(&self) -> Option<u64> {
+        match self {
+            OutputWriter::Bgzf(_, position) => Some(*position),
+            OutputWriter::Other(_) => None,
+        }
+    }
+
+    /// Flush everything, and write the BGZF EOF marker if there is one.
+    pub fn finish(&mut self) -> std::io::Result<()> {
+        match self {
+            OutputWriter::Bgzf(writer, _) => writer.finish().map(|_| ()),
+            OutputWriter::Other(writer) => writer.flush(),
+        }
+    }
+}
+
+impl std::io::Write for OutputWriter {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        match self {
+            OutputWriter::Bgzf(writer, position) => {
+                let n = writer.write(buf)?;
+                *position += n as u64;
+                Ok(n)
+            }
+            OutputWriter::Other(writer) => writer.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        match self {
+            OutputWriter::Bgzf(writer, _) => writer.flush(),
+            OutputWriter::Other(writer) => writer.flush(),
+        }
+    }
+}
+
+/// The writer of the merged records, as VCF or BCF (see `--output-format`).
+pub enum VariantWriter {
+    Vcf(vcf::io::Writer<OutputWriter>),
+    Bcf(bcf::io::Writer<OutputWriter>),
+}
+
+impl VariantWriter {
+    pub fn write_header(&mut self, header: &Header) -> std::io::Result<()> {
+        match self {
+            VariantWriter::Vcf(writer) => writer.write_header(header),
+            VariantWriter::Bcf(writer) => writer.write_variant_header(header),
+        }
+    }
+
+    pub fn write_record(&mut self, header: &Header, rec: &RecordBuf) -> std::io::Result<()> {
+        match self {
+            VariantWriter::Vcf(writer) => writer.write_variant_record(header, rec),
+            VariantWriter::Bcf(writer) => writer.write_variant_record(header, rec),
+        }
+    }
+
+    pub fn get_ref(&self) -> &OutputWriter {
+        match self {
+            VariantWriter::Vcf(writer) => writer.get_ref(),
+            VariantWriter::Bcf(writer) => writer.get_ref(),
+        }
+    }
+
+    pub fn get_mut(&mut self) -> &mut OutputWriter {
+        match self {
+            VariantWriter::Vcf(writer) => writer.get_mut(),
+            VariantWriter::Bcf(writer) => writer.get_mut(),
+        }
+    }
+}
+
+/// Put the criteria from a merge into the order they are applied, without
+/// duplicates.
+pub fn canonical_criteria(criteria: &str) -> Vec<&str> {
+    let mut res: Vec<&str> = MERGE_CRITERIA
+        .iter()
+        .map(|(name, _, _)| *name)
+        .filter(|name| criteria.split(',').any(|c| c == *name))
+        .collect();
+    for c in criteria.split(',') {
+        if !c.is_empty() && !res.contains(&c) {
+            res.push(c);
+        }
+    }
+    res
+}
+
+/// Records are ordered in the output by (contig number, position, sequence number).
+type OutputKey = (usize, usize, u64);
+
+/// The ID and coordinates of the record emitted for a merged group.
+#[derive(Debug, Clone)]
+pub struct EmittedRecord {
+    pub row_key: u32,
+    pub id: String,
+    pub start: i32,
+    pub end: i32,
+    pub end2: Option<i32>,
+}
+
 pub struct MergeBuilder {
-    writer: vcf::io::Writer<InnerWriter>,
+    out: String,
+    writer: VariantWriter,
+    indexer: Option<OutputIndexer>,
+    events: Option<EventWriter>,
+    igv: Option<IgvBatch>,
     options: Rc<MergeOptions>,
     header: Header,
     reference: Option<Rc<Repository>>,
     current_chrom: String,
+    pending: BTreeMap<OutputKey, RecordBuf>,
+    sequence_number: u64,
+    outstanding_mates: HashMap<u32, (usize, usize)>,
+    outstanding_positions: BTreeMap<(usize, usize), usize>,
+    force_kept: HashSet<u32>,
+    format_map: FormatMap,
+    info_rules: InfoRules,
+    emitted: Option<Vec<EmittedRecord>>,
+    quarantine: Option<Quarantine>,
+    annotations: Option<AnnotationReconciler>,
+    observer: Option<Rc<RefCell<dyn MergeObserver>>>,
+    rejected: usize,
+    unwanted_info: TagPatterns,
+    unwanted_format: TagPatterns,
+    removed: BTreeMap<(&'static str, String), usize>,
+    repeats: Option<RepeatMask>,
 }
 
 impl MergeBuilder {
@@ -45,157 +244,741 @@ impl MergeBuilder {
         header: Header,
         reference: Option<Rc<Repository>>,
     ) -> std::io::Result<MergeBuilder> {
-        let writer = autodetect_create(out, CompressionLevel::Default)?;
-        let writer = BufWriter::new(writer);
-        let mut writer = vcf::io::Writer::new(writer);
+        let bcf = options.output_format(out) == OutputFormat::Bcf;
+        let writer = if bcf {
+            OutputWriter::create_bgzf(
+                out,
+                options.output_compression_level,
+                options.output_threads,
+            )?
+        } else {
+            OutputWriter::create(
+                out,
+                options.output_compression_level,
+                options.output_threads,
+            )?
+        };
+        let indexer = match options.write_index {
+            Some(_) if writer.position().is_none() => {
+                return Err(Error::new(
+                    ErrorKind::InvalidInput,
+                    format!("--write-index needs .gz, .bgz or BCF output, not {}", out),
+                ));
+            }
+            Some(IndexFormat::Tbi) if bcf => {
+                return Err(Error::new(
+                    ErrorKind::InvalidInput,
+                    "BCF output can't have a tabix index (use --write-index csi)",
+                ));
+            }
+            Some(_) if bcf => Some(OutputIndexer::bcf(&header)),
+            Some(format) => Some(OutputIndexer::new(format)),
+            None => None,
+        };
+        let mut writer = if bcf {
+            VariantWriter::Bcf(bcf::io::Writer::from(writer))
+        } else {
+            VariantWriter::Vcf(vcf::io::Writer::new(writer))
+        };
         writer.write_header(&header)?;
+        let format_map = FormatMap::parse(&options.format_map).map_err(as_io_error)?;
+        let info_rules = InfoRules::parse(&options.info_rule).map_err(as_io_error)?;
+
+        let events = match &options.export_events {
+            Some(events_out) => Some(EventWriter::new(events_out)?),
+            None => None,
+        };
+        let igv = match &options.igv_batch {
+            Some(igv_out) => Some(IgvBatch::new(
+                igv_out,
+                options.igv_top,
+                options.igv_padding,
+                options.igv_min_support,
+            )?),
+            None => None,
+        };
+
+        let unwanted_info = options.unwanted_info_tags();
+        let unwanted_format = options.unwanted_format_tags();
+        let repeats = match &options.repeat_context {
+            Some(path) => Some(RepeatMask::load(path)?),
+            None => None,
+        };
 
         Ok(MergeBuilder {
+            out: String::from(out),
             writer,
+            indexer,
+            events,
+            igv,
             options,
             header,
             reference,
             current_chrom: String::new(),
+            pending: BTreeMap::new(),
+            sequence_number: 0,
+            outstanding_mates: HashMap::new(),
+            outstanding_positions: BTreeMap::new(),
+            force_kept: HashSet::new(),
+            format_map,
+            info_rules,
+            emitted: None,
+            quarantine: None,
+            annotations: None,
+            observer: None,
+            rejected: 0,
+            unwanted_info,
+            unwanted_format,
+            removed: BTreeMap::new(),
+            repeats,
         })
     }
 
+    /// Register the synthetic mates that will be created at positions earlier than
+    /// the record they are derived from.
+    ///
+    /// Each item is the `row_key` of an unpaired breakend group along with the
+    /// (contig number, position) of its mate. Output is held back from the earliest
+    /// such position until the corresponding group has been constructed, so that the
+    /// output remains sorted.
+    pub fn expect_mates(&mut self, mates: Vec<(u32, (usize, usize))>) {
+        for (row_key, pos) in mates {
+            self.outstanding_mates.insert(row_key, pos);
+            *self.outstanding_positions.entry(pos).or_default() += 1;
+        }
+    }
+
+    /// Pass the records with the given `row_key`s (see `--force-keep`) through
+    /// to the output as they were in their input, rather than constructing a
+    /// merged record: only their samples are laid out across all the inputs.
+    pub fn force_keep(&mut self, row_keys: HashSet<u32>) {
+        self.force_kept = row_keys;
+    }
+
+    /// Write records that can't be processed to `quarantine` (as well as skipping them).
+    pub fn quarantine(&mut self, quarantine: Quarantine) {
+        self.quarantine = Some(quarantine);
+    }
+
+    /// Replace the consequence annotations of each record with those reconciled
+    /// from the whole group by `annotations`.
+    pub fn reconcile_annotations(&mut self, annotations: AnnotationReconciler) {
+        self.annotations = Some(annotations);
+    }
+
+    /// Pass each record to `observer`, which may amend it or leave it out, before it is written.
+    pub fn observe(&mut self, observer: Rc<RefCell<dyn MergeObserver>>) {
+        self.observer = Some(observer);
+    }
+
+    /// Account for the lines of records rejected when the inputs were loaded.
+    pub fn reject_lines(&mut self, lines: &[Vec<u8>]) -> std::io::Result<()> {
+        self.rejected += lines.len();
+        if let Some(quarantine) = self.quarantine.as_mut() {
+            for line in lines.iter() {
+                quarantine.write_line(line)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Keep a note of the ID and coordinates of the record emitted for each
+    /// group, for cross-referencing the output with the merge table.
+    pub fn track_emitted(&mut self) {
+        self.emitted = Some(Vec::new());
+    }
+
+    /// Take the records noted since `track_emitted` was called.
+    pub fn take_emitted(&mut self) -> Vec<EmittedRecord> {
+        self.emitted.take().unwrap_or_default()
+    }
+
     pub fn construct(
         &mut self,
+        row_key: u32,
         recs: Vec<Option<(Rc<Header>, Record)>>,
-        vix_samples: &Vec<usize>,
+        layout: &SampleLayout,
         vids: &Vec<String>,
+        collapsed_ids: &[Vec<String>],
         alts: &Vec<Option<String>>,
         paired_bnd: bool,
         criteria: &str,
+        merge_score: Option<f64>,
+        event_id: &str,
         feature: &str,
+        spread: &GroupSpread,
     ) -> std::io::Result<()> {
-        let rec = construct_record(
-            &self.header,
-            recs,
-            &vix_samples,
-            vids,
-            &alts,
-            paired_bnd,
-            &criteria,
-            feature,
-            self.options.as_ref(),
-            &self.reference,
-        )?;
-        self.writer.write_variant_record(&self.header, &rec)?;
+        // Errors are reported at the first record of the group.
+        let (chrom, position) = match recs.iter().flatten().next() {
+            Some((_, first)) => (
+                String::from(first.reference_sequence_name()),
+                first.variant_start().transpose()?.map_or(0, |p| p.get()),
+            ),
+            None => (String::new(), 0),
+        };
+        let context = VariantContext::new(&chrom, position);
+
+        let converted = self.options.tra_to_bnd && context.with(|| is_translocation(&recs))?;
+        let original = if self.force_kept.contains(&row_key) {
+            recs.iter()
+                .enumerate()
+                .find_map(|(vix, rec)| rec.clone().map(|rec| (vix, rec)))
+        } else {
+            None
+        };
+        let group = match self.options.on_record_error {
+            RecordErrorAction::Abort => None,
+            RecordErrorAction::Skip | RecordErrorAction::Quarantine => Some(recs.clone()),
+        };
+        let annotations = self
+            .annotations
+            .as_ref()
+            .map(|annotations| annotations.reconcile(&recs))
+            .transpose();
+        let rec = context.with(|| {
+            let annotations = annotations?;
+            let mut rec = construct_record(
+                &self.header,
+                recs,
+                layout,
+                vids,
+                collapsed_ids,
+                alts,
+                paired_bnd,
+                criteria,
+                merge_score,
+                event_id,
+                feature,
+                spread,
+                self.options.as_ref(),
+                &self.format_map,
+                &self.info_rules,
+                &self.reference,
+            )?;
+            if let Some((vix, original)) = &original {
+                restore_original(&mut rec, *vix, original, self.options.as_ref())?;
+                return Ok(rec);
+            }
+            for (name, value) in annotations.into_iter().flatten() {
+                rec.info_mut().insert(name, Some(value));
+            }
+            Ok(rec)
+        });
+        let rec = match rec {
+            Ok(rec) => rec,
+            Err(e) => match group {
+                Some(group) => return self.reject_group(group, e),
+                None => return Err(e),
+            },
+        };
+
+        if let Some(emitted) = self.emitted.as_mut() {
+            emitted.push(emitted_record(&self.header, row_key, &rec)?);
+        }
+
+        if !(self.options.synthesize_bnd_mates || self.options.tra_to_bnd) {
+            return self.write(rec);
+        }
+
+        if let Some(pos) = self.outstanding_mates.remove(&row_key)
+            && let Some(count) = self.outstanding_positions.get_mut(&pos)
+        {
+            *count -= 1;
+            if *count == 0 {
+                self.outstanding_positions.remove(&pos);
+            }
+        }
+
+        let mut rec = rec;
+        let key = self.output_key(&rec);
+        if !paired_bnd
+            && original.is_none()
+            && (self.options.synthesize_bnd_mates || converted)
+            && let Some(mate) =
+                synthesize_mate(&self.header, &mut rec, self.reference.as_ref().unwrap())?
+        {
+            let mate_key = self.output_key(&mate);
+            self.pending.insert(mate_key, mate);
+        }
+        self.pending.insert(key, rec);
+
+        // Everything strictly before the current record and before any mate
+        // we're still expecting is safe to write.
+        let mut limit = (key.0, key.1);
+        if let Some((pos, _)) = self.outstanding_positions.first_key_value() {
+            limit = std::cmp::min(limit, *pos);
+        }
+        while let Some(entry) = self.pending.first_entry() {
+            let (chrom_ix, pos, _) = *entry.key();
+            if (chrom_ix, pos) >= limit {
+                break;
+            }
+            let rec = entry.remove();
+            self.write(rec)?;
+        }
+
+        Ok(())
+    }
+
+    /// Write out any records that are still being held and flush the output.
+    pub fn finish(&mut self) -> std::io::Result<()> {
+        while let Some((_, rec)) = self.pending.pop_first() {
+            self.write(rec)?;
+        }
+        self.writer.get_mut().finish()?;
+        if let Some(indexer) = self.indexer.as_mut() {
+            indexer.finish(&self.out)?;
+        }
+        if let Some(events) = self.events.as_mut() {
+            events.finish()?;
+        }
+        if let Some(igv) = self.igv.as_mut() {
+            igv.finish(&self.out, self.options.reference.as_deref())?;
+        }
+        if self.rejected > 0 {
+            log::warn!("{} malformed records were left out", self.rejected);
+        }
+        for ((kind, id), count) in self.removed.iter() {
+            log::info!("removed {} tag '{}' from {} records", kind, id, count);
+        }
+        if let Some(quarantine) = self.quarantine.as_mut() {
+            quarantine.finish()?;
+        }
+        Ok(())
+    }
+
+    /// Leave out a group whose record couldn't be constructed.
+    fn reject_group(
+        &mut self,
+        group: Vec<Option<(Rc<Header>, Record)>>,
+        error: std::io::Error,
+    ) -> std::io::Result<()> {
+        let group: Vec<(Rc<Header>, Record)> = group.into_iter().flatten().collect();
+        log::warn!("skipping {} record(s): {}", group.len(), error_chain(&error));
+
+        self.rejected += group.len();
+        if let Some(quarantine) = self.quarantine.as_mut() {
+            for (header, rec) in group.iter() {
+                quarantine.write_record(header, rec)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn write(&mut self, rec: RecordBuf) -> std::io::Result<()> {
+        let mut rec = rec;
+        if let Some(repeats) = &self.repeats {
+            add_repeat_context(&mut rec, repeats);
+        }
+        for tag in remove_unwanted_fields(&mut rec, &self.unwanted_info, &self.unwanted_format) {
+            *self.removed.entry(tag).or_default() += 1;
+        }
+        if let Some(observer) = &self.observer
+            && !observer.borrow_mut().on_record(&self.header, &mut rec)?
+        {
+            return Ok(());
+        }
+        let begin = self.writer.get_ref().position();
+        self.writer.write_record(&self.header, &rec)?;
+        if let Some(indexer) = self.indexer.as_mut() {
+            let offsets = (begin.unwrap(), self.writer.get_ref().position().unwrap());
+            let start = rec.variant_start().ok_or_else(|| {
+                Error::new(
+                    ErrorKind::InvalidData,
+                    "can't index a record without a position",
+                )
+            })?;
+            let end = VariantRecord::variant_end(&rec, &self.header)?;
+            indexer.add_record(rec.reference_sequence_name(), start, end, offsets)?;
+        }
+        if let Some(events) = self.events.as_mut() {
+            events.write(&self.header, &rec)?;
+        }
+        if let Some(igv) = self.igv.as_mut() {
+            igv.add(&self.header, &rec);
+        }
         if rec.reference_sequence_name() != &self.current_chrom {
             self.current_chrom = String::from(rec.reference_sequence_name());
             log::info!("writing variants for {}", self.current_chrom);
         }
         Ok(())
     }
+
+    fn output_key(&mut self, rec: &RecordBuf) -> OutputKey {
+        let chrom_ix = self
+            .header
+            .contigs()
+            .get_index_of(rec.reference_sequence_name())
+            .unwrap_or(usize::MAX);
+        let pos = rec.variant_start().map(|p| p.get()).unwrap_or(0);
+        let n = self.sequence_number;
+        self.sequence_number += 1;
+        (chrom_ix, pos, n)
+    }
 }
 
-pub fn add_svelt_header_fields(
-    header: &mut Header,
-    unwanted_info: &Vec<String>,
-) -> std::io::Result<()> {
-    let filters = header.filters_mut();
+fn emitted_record(header: &Header, row_key: u32, rec: &RecordBuf) -> std::io::Result<EmittedRecord> {
+    let id = rec.ids().as_ref().iter().next().cloned().unwrap_or_default();
+    let start = rec.variant_start().map(|p| p.get()).unwrap_or(0) as i32;
+    let end = match rec.info().get("END") {
+        Some(Some(InfoValue::Integer(end))) => *end,
+        _ => VariantRecord::variant_end(rec, header)?.get() as i32,
+    };
+    let end2 = match rec.info().get("END2") {
+        Some(Some(InfoValue::Integer(end2))) => Some(*end2),
+        // As some callers declare it.
+        Some(Some(InfoValue::String(end2))) => end2.trim().parse().ok(),
+        Some(Some(InfoValue::Array(InfoArray::Integer(end2)))) if end2.len() == 1 => end2[0],
+        _ => None,
+    };
+    Ok(EmittedRecord {
+        row_key,
+        id,
+        start,
+        end,
+        end2,
+    })
+}
+
+/// Is the group made of legacy SVTYPE=TRA records?
+fn is_translocation(recs: &[Option<(Rc<Header>, Record)>]) -> std::io::Result<bool> {
+    match recs.iter().flatten().next() {
+        Some((header, rec)) => Ok(get_translocation(rec, header)?.is_some()),
+        None => Ok(false),
+    }
+}
 
-    if filters.get("UNPAIRED_BND").is_none() {
-        filters.insert(
+/// Create the reciprocal breakend record for an unpaired breakend.
+///
+/// The mate is given the ID of the original with a `_MATE` suffix, the two
+/// records are linked with MATEID, and the mate is flagged with SVELT_SYNTHETIC_MATE.
+/// The samples, QUAL and FILTER (and ORIGINAL_IDS, SUPP and SUPP_VEC) are
+/// copied from the original.
+fn synthesize_mate(
+    header: &Header,
+    rec: &mut RecordBuf,
+    reference: &Repository,
+) -> std::io::Result<Option<RecordBuf>> {
+    let chrom = String::from(rec.reference_sequence_name());
+    let pos = rec.variant_start().map(|p| p.get()).unwrap_or(0);
+    let bnd = if let Some(alt) = rec.alternate_bases().as_ref().first() {
+        match BreakEnd::new(&chrom, pos, alt) {
+            Ok(bnd) => bnd,
+            Err(_) => return Ok(None),
+        }
+    } else {
+        return Ok(None);
+    };
+
+    if header.contigs().get(&bnd.chrom2).is_none() {
+        log::warn!(
+            "cannot synthesize mate for breakend at {}:{} - undeclared contig '{}'",
+            chrom,
+            pos,
+            bnd.chrom2
+        );
+        return Ok(None);
+    }
+
+    let id = rec
+        .ids()
+        .as_ref()
+        .iter()
+        .next()
+        .cloned()
+        .unwrap_or_else(|| format!("{}_{}", chrom, pos));
+    let mate_id = format!("{}_MATE", id);
+
+    let (mate_chrom, mate_pos, mate_base, mate_alt) = bnd.flip().format(reference)?;
+
+    rec.info_mut().insert(
+        String::from("MATEID"),
+        Some(InfoValue::String(mate_id.clone())),
+    );
+
+    let mut info: Vec<(String, Option<InfoValue>)> = vec![
+        (String::from("SVTYPE"), Some(InfoValue::String(String::from("BND")))),
+        (String::from("CHR2"), Some(InfoValue::String(chrom.clone()))),
+        (String::from("END2"), Some(InfoValue::Integer(pos as i32))),
+        (String::from("MATEID"), Some(InfoValue::String(id))),
+    ];
+    for name in std::iter::once("ORIGINAL_IDS").chain(SHARED_INFO_FIELDS.iter().copied()) {
+        if let Some(Some(value)) = rec.info().get(name) {
+            info.push((String::from(name), Some(value.clone())));
+        }
+    }
+    info.push((String::from("SVELT_SYNTHETIC_MATE"), Some(InfoValue::Flag)));
+    let info = Info::from_iter(info);
+
+    let mut bldr = RecordBuf::builder()
+        .set_reference_sequence_name(mate_chrom)
+        .set_variant_start(Position::try_from(mate_pos).unwrap())
+        .set_ids(Ids::from_iter(vec![mate_id]))
+        .set_reference_bases(String::from(mate_base))
+        .set_alternate_bases(AlternateBases::from(vec![mate_alt]))
+        .set_filters(rec.filters().clone())
+        .set_info(info)
+        .set_samples(rec.samples().clone());
+    if let Some(quality_score) = rec.quality_score() {
+        bldr = bldr.set_quality_score(quality_score);
+    }
+
+    Ok(Some(bldr.build()))
+}
+
+pub fn add_svelt_header_fields(header: &mut Header, options: &MergeOptions) -> std::io::Result<()> {
+    let unpaired_bnd_description = options.unpaired_bnd_description();
+
+    if options.unpaired_bnd_action == UnpairedBndAction::Filter {
+        header.filters_mut().insert(
             String::from("UNPAIRED_BND"),
-            Map::<Filter>::new("Breakend variant does not have a symmetric pair"),
+            Map::<Filter>::new(unpaired_bnd_description.as_str()),
         );
     }
 
+    header.other_records_mut().shift_remove(SVELT_FORMAT_KEY);
+    header.insert(
+        SVELT_FORMAT_KEY
+            .parse()
+            .map_err(Error::other)?,
+        vcf::header::record::Value::from(SVELT_FORMAT_VERSION.to_string()),
+    )
+    .map_err(Error::other)?;
+
     let infos = header.infos_mut();
 
-    if infos.get("CHR2").is_none() {
-        infos.insert(
-            String::from("CHR2"),
-            Builder::default()
-                .set_number(Number::Unknown)
-                .set_type(Type::String)
-                .set_description("Chromosome for end coordinate in case of a translocation")
-                .build()
-                .map_err(|e| Error::new(ErrorKind::Other, e))?,
-        );
+    for def in SVELT_INFO_FIELDS.iter() {
+        let wanted = match def.id {
+            "UNPAIRED_BND" => options.unpaired_bnd_action == UnpairedBndAction::Annotate,
+            // Keep the inputs' declarations of the standard fields.
+            id if STANDARD_INFO_FIELDS.contains(&id) => infos.get(def.id).is_none(),
+            "SVELT_CALLERS" | "SVELT_SAMPLES" => !options.input_labels.is_empty(),
+            "SUPP" | "SUPP_VEC" => options.support_fields,
+            "SVELT_MERGE_SCORE" => options.merge_scoring(),
+            "SVELT_ALT_REVERSED" => options.orient_insertions,
+            "SVELT_DEL_CLASS" => options.annotate_deletions,
+            "SVELT_REPEATS" | "SVELT_REPEAT_ENDS" => options.repeat_context.is_some(),
+            _ => true,
+        };
+        if wanted {
+            infos.insert(
+                String::from(def.id),
+                def.declaration(&unpaired_bnd_description),
+            );
+        }
+
+        // The flags for the criteria follow SVELT_CRITERIA.
+        if def.id == "SVELT_CRITERIA" {
+            for (name, flag, description) in MERGE_CRITERIA.iter() {
+                // Only declared when the criterion is in use.
+                if *name == "similar" && options.min_insertion_identity.is_none() {
+                    continue;
+                }
+                if *name == "overlap" && options.min_reciprocal_overlap.is_none() {
+                    continue;
+                }
+                if (*name == "breakpoints" || *name == "copies") && !options.kind_specific_criteria
+                {
+                    continue;
+                }
+                infos.insert(
+                    String::from(*flag),
+                    Map::<InfoMap>::new(Number::Count(0), Type::Flag, *description),
+                );
+            }
+        }
     }
 
-    if infos.get("END2").is_none() {
-        infos.insert(
-            String::from("END2"),
-            Builder::default()
-                .set_number(Number::Unknown)
-                .set_type(Type::Integer)
-                .set_description("End position of the structural variant on CHR2")
-                .build()
-                .map_err(|e| Error::new(ErrorKind::Other, e))?,
+    Ok(())
+}
+
+/// Remove the declarations of the INFO and FORMAT fields given by
+/// `--unwanted-info` and `--unwanted-format` from the output header.
+pub fn remove_unwanted_header_fields(header: &mut Header, options: &MergeOptions) {
+    let unwanted = options.unwanted_info_tags();
+    for pattern in unwanted.unmatched(header.infos().keys().map(|id| id.as_str())) {
+        log::info!("--unwanted-info '{}' matches no declared INFO tag", pattern);
+    }
+    header.infos_mut().retain(|id, _| {
+        let wanted = !unwanted.matches(id);
+        if !wanted {
+            log::info!("removing INFO tag '{}'", id);
+        }
+        wanted
+    });
+
+    let unwanted = options.unwanted_format_tags();
+    for pattern in unwanted.unmatched(header.formats().keys().map(|id| id.as_str())) {
+        log::info!(
+            "--unwanted-format '{}' matches no declared FORMAT tag",
+            pattern
         );
     }
+    header.formats_mut().retain(|id, _| {
+        let wanted = !unwanted.matches(id);
+        if !wanted {
+            log::info!("removing FORMAT tag '{}'", id);
+        }
+        wanted
+    });
+}
 
-    infos.insert(
-        String::from("ORIGINAL_IDS"),
-        Builder::default()
-            .set_number(Number::Unknown)
-            .set_type(Type::String)
-            .set_description("The variant IDs from the original VCFs")
-            .build()
-            .map_err(|e| Error::new(ErrorKind::Other, e))?,
-    );
+/// Remove the INFO and FORMAT fields matching `info` and `format` from
+/// `rec`, giving the kind and name of each field removed.
+fn remove_unwanted_fields(
+    rec: &mut RecordBuf,
+    info: &TagPatterns,
+    format: &TagPatterns,
+) -> Vec<(&'static str, String)> {
+    let mut removed = Vec::new();
+    rec.info_mut().as_mut().retain(|id, _| {
+        let wanted = !info.matches(id);
+        if !wanted {
+            removed.push(("INFO", id.clone()));
+        }
+        wanted
+    });
 
-    infos.insert(
-        String::from("SVELT_CRITERIA"),
-        Builder::default()
-            .set_number(Number::Unknown)
-            .set_type(Type::String)
-            .set_description("The list of criteria that resulted in the merging of these variants.")
-            .build()
-            .map_err(|e| Error::new(ErrorKind::Other, e))?,
-    );
+    let samples = rec.samples();
+    let kept: Vec<usize> = (0..samples.keys().as_ref().len())
+        .filter(|ix| {
+            let id = &samples.keys().as_ref()[*ix];
+            let wanted = !format.matches(id);
+            if !wanted {
+                removed.push(("FORMAT", id.clone()));
+            }
+            wanted
+        })
+        .collect();
+    if kept.len() < samples.keys().as_ref().len() {
+        let keys = Keys::from_iter(kept.iter().map(|ix| samples.keys().as_ref()[*ix].clone()));
+        let values = samples
+            .values()
+            .map(|sample| {
+                let values = sample.values();
+                kept.iter()
+                    .map(|ix| values.get(*ix).cloned().flatten())
+                    .collect()
+            })
+            .collect();
+        *rec.samples_mut() = Samples::new(keys, values);
+    }
+    removed
+}
 
-    infos.insert(
-        String::from("SVELT_ALT_SEQ"),
-        Builder::default()
-            .set_number(Number::Unknown)
-            .set_type(Type::String)
-            .set_description("The list of alt sequences that were replaced with the ALT tag.")
-            .build()
-            .map_err(|e| Error::new(ErrorKind::Other, e))?,
+/// Note the repeats the breakpoints of `rec` (its start, and its END or
+/// breakend mate) lie in, if any, in SVELT_REPEATS and SVELT_REPEAT_ENDS.
+fn add_repeat_context(rec: &mut RecordBuf, repeats: &RepeatMask) {
+    let Some(start) = rec.variant_start().map(|pos| pos.get()) else {
+        return;
+    };
+    let chrom = rec.reference_sequence_name();
+    let (chrom2, end2) = match mate_position(rec) {
+        (Some(chrom2), Some(end2)) => (chrom2, end2),
+        _ => {
+            let end = match rec.info().get("END") {
+                Some(Some(InfoValue::Integer(end))) => *end as usize,
+                _ => start,
+            };
+            (String::from(chrom), end)
+        }
+    };
+    let names: Vec<Option<String>> = [repeats.at(chrom, start), repeats.at(&chrom2, end2)]
+        .into_iter()
+        .map(|name| name.map(String::from))
+        .collect();
+    let ends = match names.iter().flatten().count() {
+        0 => return,
+        1 => "one",
+        _ => "both",
+    };
+    let info = rec.info_mut();
+    info.insert(
+        String::from("SVELT_REPEATS"),
+        Some(InfoValue::Array(InfoArray::String(names))),
     );
-
-    infos.insert(
-        String::from("SVELT_ALT_CLASS"),
-        Builder::default()
-            .set_number(Number::Unknown)
-            .set_type(Type::String)
-            .set_description("Classification of the inserted sequence.")
-            .build()
-            .map_err(|e| Error::new(ErrorKind::Other, e))?,
+    info.insert(
+        String::from("SVELT_REPEAT_ENDS"),
+        Some(InfoValue::String(String::from(ends))),
     );
+}
+
+/// How far the records merged into a group are from its primary record (the
+/// first, from which the merged record is made), for SVELT_MAX_OFFSET and
+/// SVELT_MIN_RATIO.
+#[derive(Debug, Clone, Default)]
+pub struct GroupSpread {
+    /// The number of records in the group.
+    pub records: usize,
+
+    /// The largest distance of a start from that of the primary record.
+    pub max_start_offset: i64,
+
+    /// The largest distance of an end (for breakends, a mate position) from
+    /// that of the primary record.
+    pub max_end_offset: i64,
 
-    for unwanted in unwanted_info.iter() {
-        log::info!("removing INFO tag '{}'", unwanted);
-        infos.shift_remove(unwanted);
+    /// The smallest ratio of a length to that of the primary record (the
+    /// shorter over the longer), where both are known.
+    pub min_length_ratio: Option<f64>,
+}
+
+impl GroupSpread {
+    pub fn add(&mut self, start_offset: Option<i64>, end_offset: Option<i64>, ratio: Option<f64>) {
+        self.records += 1;
+        self.max_start_offset = self.max_start_offset.max(start_offset.unwrap_or(0));
+        self.max_end_offset = self.max_end_offset.max(end_offset.unwrap_or(0));
+        if let Some(ratio) = ratio {
+            self.min_length_ratio = Some(self.min_length_ratio.map_or(ratio, |r| r.min(ratio)));
+        }
     }
+}
 
-    Ok(())
+/// The numbers of distinct callers and distinct samples among the labelled
+/// inputs contributing a record to a group.
+fn distinct_support(
+    recs: &[Option<(Rc<Header>, Record)>],
+    options: &MergeOptions,
+) -> (usize, usize) {
+    let mut callers = HashSet::new();
+    let mut samples = HashSet::new();
+    for (vix, rec) in recs.iter().enumerate() {
+        if rec.is_some()
+            && let Some((caller, sample)) = options.input_label(vix)
+        {
+            callers.insert(caller);
+            samples.insert(sample);
+        }
+    }
+    (callers.len(), samples.len())
 }
 
 pub fn construct_record(
     header: &Header,
     recs: Vec<Option<(Rc<Header>, Record)>>,
-    vix_samples: &Vec<usize>,
+    layout: &SampleLayout,
     vids: &Vec<String>,
+    collapsed_ids: &[Vec<String>],
     alts: &Vec<Option<String>>,
     paired_bnd: bool,
     criteria: &str,
+    merge_score: Option<f64>,
+    event_id: &str,
     feature: &str,
+    spread: &GroupSpread,
     options: &MergeOptions,
+    format_map: &FormatMap,
+    info_rules: &InfoRules,
     reference: &Option<Rc<Repository>>,
 ) -> std::io::Result<RecordBuf> {
-    let _ = header;
     let mut the_variant_id = String::new();
     let mut the_record = None;
+    let mut the_vix = 0;
     for vix in 0..recs.len() {
         if let Some(hnr) = &recs[vix] {
             the_variant_id = vids[vix].clone();
             the_record = Some(hnr);
+            the_vix = vix;
             break;
         }
     }
@@ -212,16 +995,64 @@ pub fn construct_record(
     let ids = vec![the_variant_id];
     let ids = Ids::from_iter(ids.into_iter());
 
-    let (reference_bases, alternate_bases) = make_ref_and_alt(&the_record, options.force_alt_tags)?;
+    let (reference_bases, alternate_bases) =
+        make_ref_and_alt(the_record, the_header, options.force_alt_tags)?;
     let mut reference_bases = reference_bases;
     let mut alternate_bases = alternate_bases;
 
+    // Keep the most specific of the compatible symbolic ALTs in the group, so
+    // that a subtype (e.g. <INS:ME:ALU>) isn't lost to a plainer ALT.
+    if let Some(symbol) = symbolic_alt(&alternate_bases[0]) {
+        let mut best = String::from(symbol);
+        for (_, rec) in recs.iter().flatten() {
+            for alt in rec.alternate_bases().iter() {
+                if let Some(other) = symbolic_alt(alt?)
+                    && other.len() > best.len()
+                    && compatible_symbols(&best, other)
+                {
+                    best = String::from(other);
+                }
+            }
+        }
+        alternate_bases[0] = format!("<{}>", best);
+    }
+
+    // Out of range coordinates have already been reported when loading.
+    let clamp = |chrom: &str, pos: usize| match options.contig_length_policy {
+        ContigLengthPolicy::Clamp => header
+            .contigs()
+            .get(chrom)
+            .and_then(|c| c.length())
+            .map_or(pos, |length| pos.min(length)),
+        ContigLengthPolicy::Error => pos,
+    };
+
+    let variant_start = clamp(&chrom, variant_start);
     let variant_start = if variant_start > 0 {
         Position::try_from(variant_start).unwrap()
     } else {
         Position::try_from(1).unwrap()
     };
 
+    // Legacy translocations are rewritten in breakend notation.
+    let mut converted = false;
+    if options.tra_to_bnd
+        && let Some((chrom2, end2, side, side2)) = get_translocation(the_record, the_header)?
+    {
+        let bnd = BreakEnd {
+            chrom: chrom.clone(),
+            end: variant_start.get(),
+            side,
+            chrom2,
+            end2,
+            side2,
+        };
+        let (_, _, base, alt) = bnd.format(reference.as_ref().unwrap())?;
+        reference_bases = String::from(base);
+        alternate_bases = vec![alt];
+        converted = true;
+    }
+
     let mut chrom2 = None;
     let mut end2 = None;
     if let Ok(bnd) = BreakEnd::new(&chrom, variant_start.get(), &alternate_bases[0]) {
@@ -272,6 +1103,23 @@ pub fn construct_record(
         }
     }
 
+    // Deletions are classified by the reference sequence they remove (see
+    // `--annotate-deletions`).
+    let deletion =
+        !feature.is_empty() && sv_kind(&get_svtype(the_record, the_header)?) == Some("DEL");
+
+    // Insertions matching the reverse strand of a feature are given in the
+    // orientation of the feature (see `--orient-insertions`).
+    let reversed = options.orient_insertions && !deletion && feature.ends_with('-');
+    if reversed
+        && reference_bases.len() == 1
+        && alternate_bases[0].len() > 1
+        && is_seq(&alternate_bases[0])
+    {
+        let alt = &alternate_bases[0];
+        alternate_bases[0] = format!("{}{}", &alt[..1], reverse_complement(&alt[1..]));
+    }
+
     let alternate_bases = AlternateBases::from(alternate_bases);
 
     let mut quality_score: f32 = 0.0;
@@ -295,30 +1143,64 @@ pub fn construct_record(
             }
         }
     }
-    if chrom2.is_some() && !paired_bnd {
+    // Converted translocations are given a synthetic mate.
+    let unpaired = chrom2.is_some() && !paired_bnd && !converted;
+    if unpaired && options.unpaired_bnd_action == UnpairedBndAction::Filter {
         filters.insert(String::from("UNPAIRED_BND"));
     }
     let mut filters: Vec<String> = filters.into_iter().collect();
     filters.sort();
     let filters = Filters::from_iter(filters.into_iter());
 
-    // Gather up the original IDs
+    // Gather up the original IDs, along with those of any duplicates
+    // collapsed within the inputs (see `--dedup-within-inputs`).
     let mut original_ids = Vec::new();
     for vix in 0..recs.len() {
         if let Some(hnr) = &recs[vix] {
             for id in hnr.1.ids().iter() {
                 original_ids.push(Some(String::from(id)));
             }
+            for id in collapsed_ids[vix].iter() {
+                original_ids.push(Some(id.clone()));
+            }
         }
     }
 
     let mut info: Vec<(String, Option<InfoValue>)> = Vec::new();
     for item in the_record.info().iter(&the_header) {
         let (name, value) = item.unwrap();
-        let name = String::from(name);
-        let value = value.map(make_info_value);
+        let name = input_info_name(name, the_vix, options);
+        let mut value = value.map(make_info_value);
+        if converted {
+            match name.as_str() {
+                "SVTYPE" => value = Some(InfoValue::String(String::from("BND"))),
+                "END" | "POS2" | "CT" => continue,
+                _ => {}
+            }
+        }
+        if name == "END"
+            && let Some(InfoValue::Integer(end)) = value
+        {
+            value = Some(InfoValue::Integer(clamp(&chrom, end.max(0) as usize) as i32));
+        }
         info.push((name, value));
     }
+    // Fields with a rule (see `--info-rule`) are combined across the group,
+    // including those the first record doesn't have.
+    for (name, value) in info_rules.combine(&recs)? {
+        let value = match (name.as_str(), value) {
+            ("SVTYPE" | "END" | "POS2" | "CT", _) if converted => continue,
+            ("END", InfoValue::Integer(end)) => {
+                InfoValue::Integer(clamp(&chrom, end.max(0) as usize) as i32)
+            }
+            (_, value) => value,
+        };
+        let name = input_info_name(&name, the_vix, options);
+        match info.iter_mut().find(|item| item.0 == name) {
+            Some(item) => item.1 = Some(value),
+            None => info.push((name, Some(value))),
+        }
+    }
     if original_ids.len() > 0 {
         info.push((
             String::from("ORIGINAL_IDS"),
@@ -334,20 +1216,91 @@ pub fn construct_record(
             String::from("CHR2"),
             Some(InfoValue::String(chrom2.clone())),
         ));
-        info.push((String::from("END2"), Some(InfoValue::Integer(*end2 as i32))));
+        let end2 = clamp(chrom2, *end2);
+        info.push((String::from("END2"), Some(InfoValue::Integer(end2 as i32))));
+    }
+    if unpaired && options.unpaired_bnd_action == UnpairedBndAction::Annotate {
+        info.push((String::from("UNPAIRED_BND"), Some(InfoValue::Flag)));
     }
     if criteria.len() > 0 {
-        let criteria: Vec<Option<String>> =
-            criteria.split(',').map(|s| Some(String::from(s))).collect();
+        let criteria = canonical_criteria(criteria);
         info.push((
             String::from("SVELT_CRITERIA"),
-            Some(InfoValue::Array(InfoArray::String(criteria))),
+            Some(InfoValue::Array(InfoArray::String(
+                criteria.iter().map(|s| Some(String::from(*s))).collect(),
+            ))),
+        ));
+        for (name, flag, _) in MERGE_CRITERIA.iter() {
+            if criteria.contains(name) {
+                info.push((String::from(*flag), Some(InfoValue::Flag)));
+            }
+        }
+    }
+    if !options.input_labels.is_empty() {
+        let (callers, samples) = distinct_support(&recs, options);
+        info.push((
+            String::from("SVELT_CALLERS"),
+            Some(InfoValue::Integer(callers as i32)),
+        ));
+        info.push((
+            String::from("SVELT_SAMPLES"),
+            Some(InfoValue::Integer(samples as i32)),
+        ));
+    }
+    if options.support_fields {
+        // Any the input has (e.g. from SURVIVOR) are replaced.
+        info.retain(|item| !SHARED_INFO_FIELDS.contains(&item.0.as_str()));
+        let support: String = recs
+            .iter()
+            .map(|rec| if rec.is_some() { '1' } else { '0' })
+            .collect();
+        info.push((
+            String::from("SUPP"),
+            Some(InfoValue::Integer(recs.iter().flatten().count() as i32)),
         ));
+        info.push((String::from("SUPP_VEC"), Some(InfoValue::String(support))));
+    }
+    if !event_id.is_empty() {
+        info.retain(|item| item.0 != "EVENT");
+        info.push((
+            String::from("EVENT"),
+            Some(InfoValue::String(String::from(event_id))),
+        ));
+    }
+    if options.merge_scoring()
+        && let Some(merge_score) = merge_score
+    {
+        info.push((
+            String::from("SVELT_MERGE_SCORE"),
+            Some(InfoValue::Float(merge_score as f32)),
+        ));
+    }
+    if spread.records > 1 {
+        info.push((
+            String::from("SVELT_MAX_OFFSET"),
+            Some(InfoValue::Array(InfoArray::Integer(vec![
+                Some(spread.max_start_offset as i32),
+                Some(spread.max_end_offset as i32),
+            ]))),
+        ));
+        if let Some(ratio) = spread.min_length_ratio {
+            // Rounded down, so that filtering on it never lets through a
+            // group whose ratio is really below the threshold.
+            let ratio = (ratio * 1000.0).floor() / 1000.0;
+            info.push((
+                String::from("SVELT_MIN_RATIO"),
+                Some(InfoValue::Float(ratio as f32)),
+            ));
+        }
     }
     let mut alt_sequences = Vec::new();
     for vix in 0..alts.len() {
         if let Some(alt) = &alts[vix] {
-            alt_sequences.push(Some(String::from(alt)));
+            if reversed {
+                alt_sequences.push(Some(reverse_complement(alt)));
+            } else {
+                alt_sequences.push(Some(String::from(alt)));
+            }
         }
     }
     if alt_sequences.len() > 0 {
@@ -357,57 +1310,101 @@ pub fn construct_record(
         ));
     }
     if feature.len() > 0 {
+        let tag = if deletion {
+            "SVELT_DEL_CLASS"
+        } else {
+            "SVELT_ALT_CLASS"
+        };
         info.push((
-            String::from("SVELT_ALT_CLASS"),
+            String::from(tag),
             Some(InfoValue::String(String::from(feature))),
         ));
     }
-    let info: Vec<(String, Option<InfoValue>)> = info
-        .into_iter()
-        .filter(|item| {
-            options
-                .unwanted_info
-                .iter()
-                .all(|unwanted| &item.0 != unwanted)
-        })
-        .collect();
+    if reversed {
+        info.push((String::from("SVELT_ALT_REVERSED"), Some(InfoValue::Flag)));
+    }
     let info = Info::from_iter(info.into_iter());
 
-    let keys: Vec<String> = the_record
-        .samples()
-        .keys()
-        .iter()
-        .map(|k| String::from(k))
-        .collect();
-    let mut samples = Vec::new();
+    // The samples of each input are laid out by key, as the inputs (or the
+    // FORMAT fields made by --format-map) needn't agree on them. Each output
+    // sample is made from those of the inputs with the variant that go in
+    // its column (see `SampleLayout`).
+    let mut keys: Vec<String> = Vec::new();
+    let mut sample_fields: Vec<Vec<SampleFields>> = vec![Vec::new(); layout.len()];
     for vix in 0..recs.len() {
-        match &recs[vix] {
-            Some((header, record)) => {
-                for sample in record.samples().iter() {
-                    let mut fields: Vec<Option<Value>> = Vec::new();
-                    for value in sample.values(header) {
-                        if let Some(value) = value {
-                            let value = value?;
-                            let value = make_sample_value(value);
-                            fields.push(Some(value));
-                        } else {
-                            fields.push(None);
-                        }
-                    }
-                    samples.push(fields);
+        if let Some((header, record)) = &recs[vix] {
+            let caller = options.input_label(vix).map(|(caller, _)| caller);
+            let sample_keys: Vec<String> = record
+                .samples()
+                .keys()
+                .iter()
+                .map(String::from)
+                .collect();
+            for (sample, column) in record.samples().iter().zip(layout.columns(vix)) {
+                let mut fields = Vec::new();
+                for (key, value) in sample_keys.iter().zip(sample.values(header)) {
+                    let value = value.transpose()?.map(make_sample_value);
+                    fields.push((key.clone(), value));
                 }
-            }
-            None => {
-                for _ in 0..vix_samples[vix] {
-                    let fields: Vec<Option<Value>> = keys
-                        .iter()
-                        .map(|k| make_empty_fmt_value(options, k))
-                        .collect();
-                    samples.push(fields);
+                format_map.apply(caller, &mut fields);
+                for (key, _) in fields.iter() {
+                    if !keys.contains(key) {
+                        keys.push(key.clone());
+                    }
                 }
+                sample_fields[*column].push(fields);
             }
         }
     }
+    // GT must come first.
+    if let Some(ix) = keys.iter().position(|k| k == "GT") {
+        let gt = keys.remove(ix);
+        keys.insert(0, gt);
+    }
+    let samples: Vec<Vec<Option<Value>>> = sample_fields
+        .into_iter()
+        .map(|fields| {
+            if fields.is_empty() {
+                return keys.iter().map(|k| make_empty_fmt_value(options, k)).collect();
+            }
+            let value = |fields: &[(String, Option<Value>)], k: &str| {
+                fields
+                    .iter()
+                    .find(|(key, _)| key == k)
+                    .and_then(|(_, value)| value.clone())
+            };
+            // With more than one input for the sample, the other fields are
+            // taken from the input whose genotype is chosen, and left missing
+            // if none is (so values from different calls aren't mixed).
+            let mut consensus = None;
+            if fields.len() > 1 {
+                let genotypes: Vec<Option<Genotype>> = fields
+                    .iter()
+                    .map(|fields| match value(fields, "GT") {
+                        Some(Value::Genotype(gt)) => Some(gt),
+                        _ => None,
+                    })
+                    .collect();
+                if genotypes.iter().any(|gt| gt.is_some()) {
+                    let called: Vec<Genotype> = genotypes.iter().flatten().cloned().collect();
+                    let gt = consensus_genotype(&called, options.genotype_conflict);
+                    let source = gt
+                        .as_ref()
+                        .and_then(|gt| genotypes.iter().position(|other| other.as_ref() == Some(gt)));
+                    consensus = Some((gt.map(Value::Genotype), source));
+                }
+            }
+            keys.iter()
+                .map(|k| match &consensus {
+                    Some((gt, _)) if k == "GT" => gt.clone(),
+                    Some((_, Some(ix))) => value(&fields[*ix], k),
+                    Some((_, None)) => None,
+                    // Otherwise fields are taken from the first input with them.
+                    None => fields.iter().find_map(|fields| value(fields, k)),
+                })
+                .collect()
+        })
+        .collect();
     let keys = Keys::from_iter(keys.into_iter());
     let samples = Samples::new(keys, samples);
 
@@ -429,7 +1426,58 @@ pub fn construct_record(
     Ok(res)
 }
 
-fn make_ref_and_alt(rec: &Record, force_alt_tags: bool) -> std::io::Result<(String, Vec<String>)> {
+/// Put back the ID, REF, ALT, QUAL, FILTER and INFO of the record `original`
+/// in `rec`, constructed from it alone, so that it is passed through as it was
+/// (but for any `--unwanted-info`, which is removed as the record is written,
+/// and svelt's own fields, which are renamed).
+fn restore_original(
+    rec: &mut RecordBuf,
+    vix: usize,
+    original: &(Rc<Header>, Record),
+    options: &MergeOptions,
+) -> std::io::Result<()> {
+    let (header, original) = original;
+    *rec.ids_mut() = original.ids().iter().map(String::from).collect();
+    *rec.reference_bases_mut() = String::from(original.reference_bases());
+    let mut alternate_bases = Vec::new();
+    for alt in original.alternate_bases().iter() {
+        alternate_bases.push(String::from(alt?));
+    }
+    *rec.alternate_bases_mut() = AlternateBases::from(alternate_bases);
+    *rec.quality_score_mut() = original.quality_score().transpose()?;
+    let mut filters = Vec::new();
+    for filter in original.filters().iter(header) {
+        filters.push(String::from(filter?));
+    }
+    *rec.filters_mut() = Filters::from_iter(filters);
+    let mut info = Vec::new();
+    for item in original.info().iter(header) {
+        let (name, value) = item?;
+        info.push((
+            input_info_name(name, vix, options),
+            value.map(make_info_value),
+        ));
+    }
+    *rec.info_mut() = Info::from_iter(info);
+    Ok(())
+}
+
+/// The name in the output of the INFO field `name` of a record from input
+/// `vix`: with `--allow-svelt-inputs`, svelt's own fields are renamed, so
+/// they can't be taken for this run's.
+fn input_info_name(name: &str, vix: usize, options: &MergeOptions) -> String {
+    if options.allow_svelt_inputs && is_svelt_owned(name) {
+        input_svelt_field(vix, name)
+    } else {
+        String::from(name)
+    }
+}
+
+fn make_ref_and_alt(
+    rec: &Record,
+    header: &Header,
+    force_alt_tags: bool,
+) -> std::io::Result<(String, Vec<String>)> {
     let mut reference_bases = String::from(rec.reference_bases());
 
     let mut alternate_bases = Vec::new();
@@ -437,6 +1485,10 @@ fn make_ref_and_alt(rec: &Record, force_alt_tags: bool) -> std::io::Result<(Stri
         let alt = alt?;
         alternate_bases.push(String::from(alt));
     }
+    // Breakends given by INFO fields (see `get_translocation`) may have no ALT.
+    if alternate_bases.is_empty() {
+        alternate_bases.push(format!("<{}>", get_svtype(rec, header)?));
+    }
     assert_eq!(alternate_bases.len(), 1);
 
     if force_alt_tags && is_seq(&reference_bases) && alternate_bases.iter().all(|s| is_seq(s)) {
@@ -0,0 +1,404 @@
+use std::{
+    fs::{File, create_dir_all},
+    io::{BufWriter, Write},
+    path::Path,
+    rc::Rc,
+};
+
+use noodles::fasta;
+
+use crate::{
+    features::FeatureIndex,
+    homology::reverse_complement,
+    merge::merge_vcfs,
+    options::{CommonOptions, IndexingOptions, MergeOptions, make_session_context},
+};
+
+/// The contigs of the made up genome, and their lengths.
+const CONTIGS: &[(&str, usize)] = &[("chr1", 20000), ("chr2", 10000)];
+
+/// The made up repeat families of the feature index, and their lengths.
+const FAMILIES: &[(&str, usize)] = &[("SINE/Alu/DemoAlu", 300), ("LINE/L1/DemoL1", 900)];
+
+const CALLERS: &[&str] = &["manta", "cutesv"];
+const SAMPLES: &[&str] = &["S1", "S2"];
+
+/// The FORMAT fields the made up callers use for read support, and the
+/// `--format-map` putting Manta's in cuteSV's terms.
+const MANTA_FORMATS: &str = "\
+##FORMAT=<ID=PR,Number=.,Type=Integer,Description=\"Spanning paired-read support for the ref and alt alleles\">
+##FORMAT=<ID=SR,Number=.,Type=Integer,Description=\"Split reads for the ref and alt alleles\">
+";
+const CUTESV_FORMATS: &str = "\
+##FORMAT=<ID=DR,Number=1,Type=Integer,Description=\"Reads supporting the reference allele\">
+##FORMAT=<ID=DV,Number=1,Type=Integer,Description=\"Reads supporting the variant allele\">
+";
+const FORMAT_MAP: &[&str] = &["manta:DR=PR[0]+SR[0]", "manta:DV=PR[1]+SR[1]"];
+
+/// Bases drawn from a fixed seed, so that every run of the demo makes the
+/// same files.
+struct Bases(u64);
+
+impl Bases {
+    fn take(&mut self, n: usize) -> String {
+        (0..n)
+            .map(|_| {
+                self.0 = self
+                    .0
+                    .wrapping_mul(6364136223846793005)
+                    .wrapping_add(1442695040888963407);
+                b"ACGT"[(self.0 >> 62) as usize] as char
+            })
+            .collect()
+    }
+}
+
+/// Change every `step`th base of a sequence, as a caller's rendering of an
+/// insertion might differ from another's.
+fn perturb(seq: &str, step: usize) -> String {
+    seq.chars()
+        .enumerate()
+        .map(|(i, c)| match (i % step == step - 1, c) {
+            (false, c) => c,
+            (true, 'A') => 'C',
+            (true, 'C') => 'G',
+            (true, 'G') => 'T',
+            (true, _) => 'A',
+        })
+        .collect()
+}
+
+fn write_fasta(path: &str, sequences: &[(&str, String)]) -> std::io::Result<()> {
+    let mut out = BufWriter::new(File::create(path)?);
+    for (name, seq) in sequences.iter() {
+        writeln!(out, ">{}", name)?;
+        for line in seq.as_bytes().chunks(60) {
+            out.write_all(line)?;
+            writeln!(out)?;
+        }
+    }
+    out.flush()
+}
+
+/// A record of one of the made up callsets.
+struct Call {
+    chrom: &'static str,
+    pos: usize,
+    event: &'static str,
+    alt: String,
+    info: String,
+    genotype: &'static str,
+}
+
+/// The records `caller` makes for `sample`. The events, and which calls
+/// show what, are described in the demo's README.txt.
+fn calls(caller: &str, sample: &str, alu: &str, l1: &str) -> Vec<Call> {
+    let manta = caller == "manta";
+    let s1 = sample == "S1";
+    let mut res = Vec::new();
+
+    // Deletion: every call; cuteSV's breakpoints a few bases off Manta's.
+    let (pos, end) = if manta { (2000, 2600) } else { (2008, 2606) };
+    res.push(Call {
+        chrom: "chr1",
+        pos,
+        event: "del",
+        alt: String::from("<DEL>"),
+        info: format!("SVTYPE=DEL;SVLEN=-{};END={}", end - pos, end),
+        genotype: if s1 { "0/1" } else { "1/1" },
+    });
+
+    // Insertion of an Alu: S1 only; cuteSV's sequence differs a little.
+    if s1 {
+        let (pos, seq) = if manta {
+            (6000, String::from(alu))
+        } else {
+            (6004, perturb(alu, 50))
+        };
+        res.push(Call {
+            chrom: "chr1",
+            pos,
+            event: "alu_ins",
+            alt: seq.clone(),
+            info: format!("SVTYPE=INS;SVLEN={}", seq.len()),
+            genotype: "0/1",
+        });
+    }
+
+    // Duplication: Manta only, in S2.
+    if manta && !s1 {
+        res.push(Call {
+            chrom: "chr1",
+            pos: 10000,
+            event: "dup",
+            alt: String::from("<DUP>"),
+            info: String::from("SVTYPE=DUP;SVLEN=1000;END=11000"),
+            genotype: "0/1",
+        });
+    }
+
+    // Translocation: Manta only, as a pair of breakends.
+    if manta {
+        res.push(Call {
+            chrom: "chr1",
+            pos: 15000,
+            event: "tra_1",
+            alt: String::from("[chr2:7000["),
+            info: format!("SVTYPE=BND;MATEID=manta_{}_tra_2", sample),
+            genotype: "0/1",
+        });
+        res.push(Call {
+            chrom: "chr2",
+            pos: 7000,
+            event: "tra_2",
+            alt: String::from("]chr1:15000]"),
+            info: format!("SVTYPE=BND;MATEID=manta_{}_tra_1", sample),
+            genotype: "0/1",
+        });
+    }
+
+    // Insertion of part of an L1, on the reverse strand: S2 only.
+    if !s1 {
+        let fragment = reverse_complement(&l1[200..700]);
+        let (pos, seq) = if manta {
+            (3000, fragment)
+        } else {
+            (2995, perturb(&fragment, 40))
+        };
+        res.push(Call {
+            chrom: "chr2",
+            pos,
+            event: "l1_ins",
+            alt: seq.clone(),
+            info: format!("SVTYPE=INS;SVLEN={}", seq.len()),
+            genotype: "1/1",
+        });
+    }
+
+    // Inversion: cuteSV only.
+    if !manta {
+        res.push(Call {
+            chrom: "chr2",
+            pos: 5000,
+            event: "inv",
+            alt: String::from("<INV>"),
+            info: String::from("SVTYPE=INV;SVLEN=800;END=5800"),
+            genotype: "0/1",
+        });
+    }
+
+    res.sort_by_key(|call| (call.chrom, call.pos));
+    res
+}
+
+/// Write the callset `caller` made for `sample`, with REF bases (and the
+/// bases anchoring sequence and breakend ALTs) from `genome`.
+fn write_vcf(
+    path: &str,
+    caller: &str,
+    sample: &str,
+    genome: &[(&str, String)],
+    alu: &str,
+    l1: &str,
+) -> std::io::Result<()> {
+    let mut out = BufWriter::new(File::create(path)?);
+    writeln!(out, "##fileformat=VCFv4.2")?;
+    writeln!(out, "##source=svelt demo ({})", caller)?;
+    for (name, len) in CONTIGS.iter() {
+        writeln!(out, "##contig=<ID={},length={}>", name, len)?;
+    }
+    for (id, description) in [
+        ("DEL", "Deletion"),
+        ("DUP", "Duplication"),
+        ("INV", "Inversion"),
+    ] {
+        writeln!(out, "##ALT=<ID={},Description=\"{}\">", id, description)?;
+    }
+    write!(
+        out,
+        "\
+##INFO=<ID=SVTYPE,Number=1,Type=String,Description=\"Type of structural variant\">
+##INFO=<ID=SVLEN,Number=1,Type=Integer,Description=\"Length of structural variant\">
+##INFO=<ID=END,Number=1,Type=Integer,Description=\"End position of structural variant\">
+##INFO=<ID=MATEID,Number=1,Type=String,Description=\"ID of the mate breakend\">
+##FORMAT=<ID=GT,Number=1,Type=String,Description=\"Genotype\">
+"
+    )?;
+    let manta = caller == "manta";
+    write!(
+        out,
+        "{}",
+        if manta { MANTA_FORMATS } else { CUTESV_FORMATS }
+    )?;
+    writeln!(
+        out,
+        "#CHROM\tPOS\tID\tREF\tALT\tQUAL\tFILTER\tINFO\tFORMAT\t{}_{}",
+        sample, caller
+    )?;
+
+    for call in calls(caller, sample, alu, l1) {
+        let seq = &genome
+            .iter()
+            .find(|(name, _)| *name == call.chrom)
+            .unwrap()
+            .1;
+        let base = &seq[call.pos - 1..call.pos];
+        let alt = if call.alt.starts_with('<') {
+            call.alt
+        } else if call.alt.starts_with('[') {
+            format!("{}{}", base, call.alt)
+        } else if call.alt.starts_with(']') {
+            format!("{}{}", call.alt, base)
+        } else {
+            format!("{}{}", base, call.alt)
+        };
+        let (ref_reads, alt_reads) = if call.genotype == "1/1" {
+            (0, 24)
+        } else {
+            (14, 12)
+        };
+        let (format, values) = if manta {
+            (
+                "GT:PR:SR",
+                format!(
+                    "{}:{},{}:{},{}",
+                    call.genotype,
+                    ref_reads / 2,
+                    alt_reads / 2,
+                    ref_reads - ref_reads / 2,
+                    alt_reads - alt_reads / 2
+                ),
+            )
+        } else {
+            (
+                "GT:DR:DV",
+                format!("{}:{}:{}", call.genotype, ref_reads, alt_reads),
+            )
+        };
+        writeln!(
+            out,
+            "{}\t{}\t{}_{}_{}\t{}\t{}\t.\tPASS\t{}\t{}\t{}",
+            call.chrom, call.pos, caller, sample, call.event, base, alt, call.info, format, values
+        )?;
+    }
+    out.flush()
+}
+
+const README: &str = "\
+svelt demo
+==========
+
+A made up cohort of two samples (S1 and S2), each called by two callers
+(Manta and cuteSV), merged with svelt, classifying the inserted sequences
+against an index of two made up repeat families.
+
+Inputs
+------
+
+reference.fa      The genome (chr1 and chr2, of random bases), with its .fai.
+features.fa       The repeat families, DemoAlu (SINE/Alu) and DemoL1 (LINE/L1).
+features-*        The index of features.fa (svelt index-features).
+manta_S1.vcf      The calls, one VCF for each caller and sample. Manta counts
+manta_S2.vcf      supporting reads in PR and SR, cuteSV in DR and DV.
+cutesv_S1.vcf
+cutesv_S2.vcf
+
+The calls (IDs are CALLER_SAMPLE_EVENT) show these events:
+
+del      chr1:2000-2600, called in both samples by both callers, cuteSV's
+         breakpoints a few bases off Manta's: merged, exactly between the
+         samples and as near between the callers.
+alu_ins  chr1:6000, an insertion of DemoAlu in S1; cuteSV's is placed 4
+         bases on, with a few bases different: merged, and classified as
+         SINE/Alu/DemoAlu on the + strand.
+l1_ins   chr2:3000, an insertion of part of DemoL1, reverse complemented, in
+         S2: merged, and classified as LINE/L1/DemoL1 on the - strand.
+dup      chr1:10000-11000, called by Manta in S2 only: left as it is.
+tra_1/2  chr1:15000 joined to chr2:7000, a pair of breakends called by Manta
+         in both samples: merged as breakends.
+inv      chr2:5000-5800, called by cuteSV in both samples: merged.
+
+Outputs
+-------
+
+merged.vcf        The merged calls, with a sample column for each input.
+                  Manta's PR and SR are summed into DR and DV, so every
+                  sample has the same fields (--format-map).
+merge-table.tsv   What became of each input record (--write-merge-table).
+qc-report.tsv     Summaries of the records, merging and genotypes of each
+                  input (--qc-report).
+
+The same can be done with:
+
+svelt index-features -f features.fa -o features
+svelt merge -r reference.fa --annotate-insertions features \\
+    --input-labels manta:S1,manta:S2,cutesv:S1,cutesv:S2 \\
+    --format-map manta:DR=PR[0]+SR[0],manta:DV=PR[1]+SR[1] \\
+    --write-merge-table merge-table.tsv --qc-report qc-report.tsv \\
+    -o merged.vcf manta_S1.vcf manta_S2.vcf cutesv_S1.vcf cutesv_S2.vcf
+";
+
+/// Make a small cohort in `dir`: a genome, an index of repeat families, and
+/// the calls of two callers for two samples, and merge them, as a worked
+/// example (described in the README.txt written alongside) and a check that
+/// an installed svelt works from end to end.
+pub async fn demo(dir: &str, common: &CommonOptions) -> std::io::Result<()> {
+    create_dir_all(dir)?;
+    let path = |name: &str| Path::new(dir).join(name).to_string_lossy().into_owned();
+
+    let mut bases = Bases(1024);
+    let genome: Vec<(&str, String)> = CONTIGS
+        .iter()
+        .map(|(name, len)| (*name, bases.take(*len)))
+        .collect();
+    let families: Vec<(&str, String)> = FAMILIES
+        .iter()
+        .map(|(name, len)| (*name, bases.take(*len)))
+        .collect();
+
+    let reference = path("reference.fa");
+    write_fasta(&reference, &genome)?;
+    fasta::fai::fs::write(format!("{}.fai", reference), &fasta::fs::index(&reference)?)?;
+
+    log::info!("indexing the demo's features");
+    let ctx = make_session_context(common)?;
+    let features = path("features.fa");
+    write_fasta(&features, &families)?;
+    let idx = FeatureIndex::build(&features, &IndexingOptions::default(), &ctx).await?;
+    let index = path("features");
+    idx.save(&index, &ctx).await?;
+
+    let mut vcfs = Vec::new();
+    let mut labels = Vec::new();
+    for caller in CALLERS.iter() {
+        for sample in SAMPLES.iter() {
+            let vcf = path(&format!("{}_{}.vcf", caller, sample));
+            write_vcf(
+                &vcf,
+                caller,
+                sample,
+                &genome,
+                &families[0].1,
+                &families[1].1,
+            )?;
+            vcfs.push(vcf);
+            labels.push(format!("{}:{}", caller, sample));
+        }
+    }
+    std::fs::write(path("README.txt"), README)?;
+
+    log::info!("merging the demo's calls");
+    let options = MergeOptions {
+        reference: Some(reference),
+        annotate_insertions: Some(index),
+        input_labels: labels,
+        format_map: FORMAT_MAP.iter().map(|m| String::from(*m)).collect(),
+        write_merge_table: Some(path("merge-table.tsv")),
+        qc_report: Some(path("qc-report.tsv")),
+        ..MergeOptions::default()
+    };
+    merge_vcfs(&path("merged.vcf"), &vcfs, Rc::new(options), common).await?;
+
+    log::info!("wrote the demo to '{}' (see README.txt there)", dir);
+    Ok(())
+}
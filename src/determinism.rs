@@ -0,0 +1,461 @@
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap},
+    io::{BufRead, BufReader, Write},
+    path::{Path, PathBuf},
+    rc::Rc,
+};
+
+use autocompress::{CompressionLevel, autodetect_create};
+
+use crate::{
+    errors::{Context, FileContext, SveltError, as_io_error},
+    merge::merge_vcfs,
+    options::{CommonOptions, MergeOptions, RecordErrorAction},
+    output_format::MERGE_CRITERIA,
+    report::{MergeTableRow, read_merge_table},
+    row_key::RowKey,
+    tune::read_groups,
+};
+
+/// A run of the merge to compare with the first.
+struct Run {
+    name: String,
+    /// The input (in the order given) taken as each input of the run.
+    order: Vec<usize>,
+    threads: Option<usize>,
+}
+
+impl Run {
+    fn permuted(&self) -> bool {
+        self.order.iter().enumerate().any(|(i, vix)| i != *vix)
+    }
+}
+
+/// Where a run first diverged from the first run.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Divergence {
+    /// The stage of the merge responsible: `load`, one of the criteria
+    /// (`exact`, `near`, etc.), `grouping`, `scoring`, `construct` or `header`.
+    pub stage: String,
+    pub detail: String,
+}
+
+/// What a run made of an input record: the records it was merged with
+/// (including itself, as (input, record number)), by which criteria, and the
+/// coordinates of the record emitted for them.
+#[derive(Debug, Clone, PartialEq)]
+struct Fate {
+    group: Vec<(u32, u32)>,
+    criteria: BTreeSet<String>,
+    merge_score: Option<f64>,
+    emitted: (Option<i32>, Option<i32>, Option<i32>),
+}
+
+/// Run the merge several times on the same inputs, within this process, and
+/// check that each run gives the same output as the first: once more as it
+/// is, once with each of `thread_counts` threads, and (with `permute_inputs`)
+/// once with the inputs in reverse order.
+///
+/// The merge tables of the runs are compared first, so that a divergence can
+/// be put down to the stage of the merge that caused it, then the merged
+/// VCFs, byte for byte, or (when the inputs were permuted, which changes the
+/// leading record of each group, and the order of the samples) by the
+/// `ORIGINAL_IDS` of each record. A line is written for each run, and the
+/// first divergence is returned as an error.
+pub async fn audit_determinism(
+    out: Option<&str>,
+    vcf: &[String],
+    permute_inputs: bool,
+    thread_counts: &[usize],
+    options: &MergeOptions,
+    common: &CommonOptions,
+) -> std::io::Result<()> {
+    let n = vcf.len();
+    let identity: Vec<usize> = (0..n).collect();
+    let mut runs = vec![Run {
+        name: String::from("repeat"),
+        order: identity.clone(),
+        threads: common.threads,
+    }];
+    for threads in thread_counts.iter() {
+        runs.push(Run {
+            name: format!("threads={}", threads),
+            order: identity.clone(),
+            threads: Some(*threads),
+        });
+    }
+    if permute_inputs {
+        runs.push(Run {
+            name: String::from("permuted"),
+            order: identity.iter().rev().cloned().collect(),
+            threads: common.threads,
+        });
+    }
+
+    let writer: Box<dyn Write> = match out {
+        Some(out) => Box::new(autodetect_create(out, CompressionLevel::Default)?),
+        None => Box::new(std::io::stdout()),
+    };
+    let mut writer = std::io::BufWriter::new(writer);
+    writeln!(writer, "run\tinput_order\tthreads\tresult\tstage\tdetail")?;
+
+    let tmp_dir = match &common.temp_dir {
+        Some(dir) => PathBuf::from(dir),
+        None => std::env::temp_dir(),
+    };
+    let names: Vec<String> = vcf.iter().map(|name| file_name(name)).collect();
+
+    let baseline = Run {
+        name: String::from("baseline"),
+        order: identity,
+        threads: common.threads,
+    };
+    log::info!("running the baseline merge");
+    let (baseline_vcf, baseline_table) =
+        run_merge(&tmp_dir, &baseline, vcf, options, common).await?;
+    let baseline_fates = fates(&baseline_table, &baseline.order);
+
+    let mut first = None;
+    for run in runs.iter() {
+        log::info!("running the {} merge", run.name);
+        let (run_vcf, run_table) = run_merge(&tmp_dir, run, vcf, options, common).await?;
+        let divergence = match compare_fates(
+            &baseline_fates,
+            &fates(&run_table, &run.order),
+            &names,
+            run.permuted(),
+        ) {
+            Some(divergence) => Some(divergence),
+            None => compare_outputs(&baseline_vcf, &run_vcf, run.permuted())?,
+        };
+        std::fs::remove_file(&run_vcf)?;
+
+        let order: Vec<String> = run.order.iter().map(|vix| vix.to_string()).collect();
+        let threads = run
+            .threads
+            .map_or(String::from("default"), |threads| threads.to_string());
+        match &divergence {
+            Some(divergence) => {
+                log::warn!(
+                    "the {} run diverged at the {} stage: {}",
+                    run.name,
+                    divergence.stage,
+                    divergence.detail
+                );
+                writeln!(
+                    writer,
+                    "{}\t{}\t{}\tdiverged\t{}\t{}",
+                    run.name,
+                    order.join(","),
+                    threads,
+                    divergence.stage,
+                    divergence.detail
+                )?;
+            }
+            None => {
+                writeln!(
+                    writer,
+                    "{}\t{}\t{}\tsame\t.\t.",
+                    run.name,
+                    order.join(","),
+                    threads
+                )?;
+            }
+        }
+        if first.is_none() {
+            first = divergence.map(|divergence| (run.name.clone(), divergence.stage));
+        }
+    }
+    std::fs::remove_file(&baseline_vcf)?;
+    writer.flush()?;
+
+    match first {
+        Some((run, stage)) => Err(as_io_error(SveltError::Nondeterministic(run, stage))),
+        None => {
+            log::info!("all {} runs agreed with the baseline", runs.len());
+            Ok(())
+        }
+    }
+}
+
+/// Merge the inputs in the order of the run, giving the merged VCF and the
+/// merge table.
+async fn run_merge(
+    tmp_dir: &Path,
+    run: &Run,
+    vcf: &[String],
+    options: &MergeOptions,
+    common: &CommonOptions,
+) -> std::io::Result<(PathBuf, Vec<MergeTableRow>)> {
+    let stem = format!(
+        "svelt-determinism-{}-{}",
+        std::process::id(),
+        run.name.replace('=', "-")
+    );
+    let merged = tmp_dir.join(format!("{}.vcf", stem));
+    let table = tmp_dir.join(format!("{}.tsv", stem));
+
+    let mut options = options.clone();
+    options.write_merge_table = Some(table.to_string_lossy().into_owned());
+    // Only the merged VCF and the merge table are needed.
+    options.write_index = None;
+    options.export_events = None;
+    options.bundle_output = None;
+    options.qc_report = None;
+    options.agreement_matrix = None;
+    options.igv_batch = None;
+    options.audit_fields = false;
+    if options.on_record_error == RecordErrorAction::Quarantine {
+        options.on_record_error = RecordErrorAction::Skip;
+    }
+    options.quarantine = None;
+    if !options.input_labels.is_empty() {
+        options.input_labels = run
+            .order
+            .iter()
+            .map(|vix| options.input_labels[*vix].clone())
+            .collect();
+    }
+    let mut common = common.clone();
+    common.threads = run.threads;
+    let inputs: Vec<String> = run.order.iter().map(|vix| vcf[*vix].clone()).collect();
+
+    merge_vcfs(merged.to_str().unwrap(), &inputs, Rc::new(options), &common).await?;
+    let rows = read_merge_table(table.to_str().unwrap()).await?;
+    std::fs::remove_file(&table)?;
+    Ok((merged, rows))
+}
+
+/// The fate of each input record, by (input in the order given, record number).
+fn fates(table: &[MergeTableRow], order: &[usize]) -> BTreeMap<(u32, u32), Fate> {
+    let keys = RowKey::new(order.len());
+    let record = |row: &MergeTableRow| {
+        let (vix, rn) = keys.decode(row.row_id as u32);
+        (order[vix as usize] as u32, rn)
+    };
+    let mut groups: HashMap<u32, Vec<(u32, u32)>> = HashMap::new();
+    for row in table.iter() {
+        groups.entry(row.row_key).or_default().push(record(row));
+    }
+    for group in groups.values_mut() {
+        group.sort();
+    }
+    table
+        .iter()
+        .map(|row| {
+            let fate = Fate {
+                group: groups[&row.row_key].clone(),
+                criteria: row
+                    .criteria
+                    .iter()
+                    .flat_map(|criteria| criteria.split(','))
+                    .map(String::from)
+                    .collect(),
+                merge_score: row.merge_score,
+                emitted: (row.emitted_start, row.emitted_end, row.emitted_end2),
+            };
+            (record(row), fate)
+        })
+        .collect()
+}
+
+/// The first input record whose fate differs between two runs. Where it was
+/// merged with different records, the stage is the first criterion used by
+/// only one of them, if there is one.
+fn compare_fates(
+    baseline: &BTreeMap<(u32, u32), Fate>,
+    other: &BTreeMap<(u32, u32), Fate>,
+    names: &[String],
+    permuted: bool,
+) -> Option<Divergence> {
+    let describe = |(vix, rn): &(u32, u32)| format!("{}#{}", names[*vix as usize], rn);
+    let describe_group = |group: &Vec<(u32, u32)>| {
+        group
+            .iter()
+            .map(describe)
+            .collect::<Vec<String>>()
+            .join(",")
+    };
+    let records: BTreeSet<&(u32, u32)> = baseline.keys().chain(other.keys()).collect();
+    for record in records {
+        let (lhs, rhs) = match (baseline.get(record), other.get(record)) {
+            (Some(lhs), Some(rhs)) => (lhs, rhs),
+            (lhs, _) => {
+                let which = if lhs.is_some() { "baseline" } else { "other" };
+                return Some(Divergence {
+                    stage: String::from("load"),
+                    detail: format!("{} is only in the {} run", describe(record), which),
+                });
+            }
+        };
+        if lhs.group != rhs.group {
+            let stage = MERGE_CRITERIA
+                .iter()
+                .map(|(name, _, _)| String::from(*name))
+                .find(|name| lhs.criteria.contains(name) != rhs.criteria.contains(name))
+                .unwrap_or(String::from("grouping"));
+            return Some(Divergence {
+                stage,
+                detail: format!(
+                    "{} was merged with {} in the baseline run, but {}",
+                    describe(record),
+                    describe_group(&lhs.group),
+                    describe_group(&rhs.group)
+                ),
+            });
+        }
+        if lhs.merge_score != rhs.merge_score {
+            return Some(Divergence {
+                stage: String::from("scoring"),
+                detail: format!(
+                    "{} has merge score {:?} in the baseline run, but {:?}",
+                    describe(record),
+                    lhs.merge_score,
+                    rhs.merge_score
+                ),
+            });
+        }
+        // The leading record of a group depends on the order of the inputs.
+        if !permuted && lhs.emitted != rhs.emitted {
+            return Some(Divergence {
+                stage: String::from("construct"),
+                detail: format!(
+                    "{} was emitted at {:?} in the baseline run, but {:?}",
+                    describe(record),
+                    lhs.emitted,
+                    rhs.emitted
+                ),
+            });
+        }
+    }
+    None
+}
+
+/// Compare the merged VCFs of two runs which made the same merge table.
+fn compare_outputs(
+    baseline: &Path,
+    other: &Path,
+    permuted: bool,
+) -> std::io::Result<Option<Divergence>> {
+    let (lhs, rhs, what) = if permuted {
+        let lhs: Vec<String> = partition(baseline)?;
+        let rhs: Vec<String> = partition(other)?;
+        (lhs, rhs, "record (by ORIGINAL_IDS)")
+    } else {
+        (read_lines(baseline)?, read_lines(other)?, "line")
+    };
+    for (ix, (l, r)) in lhs.iter().zip(rhs.iter()).enumerate() {
+        if l != r {
+            let stage = if l.starts_with('#') {
+                "header"
+            } else {
+                "construct"
+            };
+            return Ok(Some(Divergence {
+                stage: String::from(stage),
+                detail: format!("{} {} of the output differs", what, ix + 1),
+            }));
+        }
+    }
+    if lhs.len() != rhs.len() {
+        return Ok(Some(Divergence {
+            stage: String::from("construct"),
+            detail: format!(
+                "{} of the output in the baseline run, but {}",
+                lhs.len(),
+                rhs.len()
+            ),
+        }));
+    }
+    Ok(None)
+}
+
+/// The `ORIGINAL_IDS` of each record of a merged VCF, sorted.
+fn partition(path: &Path) -> std::io::Result<Vec<String>> {
+    let groups = read_groups(path.to_str().unwrap())?;
+    let mut records: BTreeMap<Option<usize>, Vec<String>> = BTreeMap::new();
+    for (id, record) in groups.into_iter() {
+        records.entry(record).or_default().push(id);
+    }
+    let mut res: Vec<String> = records
+        .into_values()
+        .map(|mut ids| {
+            ids.sort();
+            ids.join(",")
+        })
+        .collect();
+    res.sort();
+    Ok(res)
+}
+
+fn read_lines(path: &Path) -> std::io::Result<Vec<String>> {
+    let name = path.to_string_lossy();
+    FileContext::new(&name).with(|| {
+        let reader = BufReader::new(std::fs::File::open(path)?);
+        reader.lines().collect()
+    })
+}
+
+fn file_name(path: &str) -> String {
+    Path::new(path)
+        .file_name()
+        .map_or(String::from(path), |name| {
+            name.to_string_lossy().into_owned()
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fate(group: &[(u32, u32)], criteria: &[&str]) -> Fate {
+        Fate {
+            group: group.to_vec(),
+            criteria: criteria.iter().map(|c| String::from(*c)).collect(),
+            merge_score: None,
+            emitted: (Some(1000), Some(1500), None),
+        }
+    }
+
+    #[test]
+    fn test_compare_fates() {
+        let names = vec![String::from("a.vcf"), String::from("b.vcf")];
+        let baseline: BTreeMap<(u32, u32), Fate> = vec![
+            ((0, 0), fate(&[(0, 0), (1, 0)], &["near"])),
+            ((1, 0), fate(&[(0, 0), (1, 0)], &["near"])),
+            ((1, 1), fate(&[(1, 1)], &[])),
+        ]
+        .into_iter()
+        .collect();
+        assert_eq!(compare_fates(&baseline, &baseline, &names, false), None);
+
+        let mut other = baseline.clone();
+        other.insert((0, 0), fate(&[(0, 0)], &[]));
+        other.insert((1, 0), fate(&[(1, 0)], &[]));
+        let divergence = compare_fates(&baseline, &other, &names, false).unwrap();
+        assert_eq!(divergence.stage, "near");
+        assert_eq!(
+            divergence.detail,
+            "a.vcf#0 was merged with a.vcf#0,b.vcf#0 in the baseline run, but a.vcf#0"
+        );
+
+        let mut other = baseline.clone();
+        other.get_mut(&(1, 1)).unwrap().emitted = (Some(1010), Some(1500), None);
+        assert_eq!(
+            compare_fates(&baseline, &other, &names, false)
+                .unwrap()
+                .stage,
+            "construct"
+        );
+        assert_eq!(compare_fates(&baseline, &other, &names, true), None);
+
+        let mut other = baseline.clone();
+        other.remove(&(1, 1));
+        assert_eq!(
+            compare_fates(&baseline, &other, &names, false)
+                .unwrap()
+                .stage,
+            "load"
+        );
+    }
+}
@@ -0,0 +1,31 @@
+use std::{
+    io::Error,
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+use datafusion::{arrow::util::pretty::pretty_format_batches, prelude::DataFrame};
+
+static DEBUG_TABLES: AtomicBool = AtomicBool::new(false);
+
+/// Turn the logging of intermediate tables on or off (`--debug-tables`).
+pub fn set_debug_tables(on: bool) {
+    DEBUG_TABLES.store(on, Ordering::Relaxed);
+}
+
+/// Is the logging of intermediate tables turned on?
+pub fn debug_tables() -> bool {
+    DEBUG_TABLES.load(Ordering::Relaxed)
+}
+
+/// Log the contents of an intermediate table, if `--debug-tables` was given.
+///
+/// This executes the query behind `tbl`, so it can be very slow for large inputs.
+pub async fn show_table(label: &str, tbl: DataFrame) -> std::io::Result<()> {
+    if !debug_tables() {
+        return Ok(());
+    }
+    let batches = tbl.collect().await?;
+    let table = pretty_format_batches(&batches).map_err(Error::other)?;
+    log::info!("{}:\n{}", label, table);
+    Ok(())
+}
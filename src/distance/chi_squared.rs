@@ -4,7 +4,7 @@ use datafusion::{
     prelude::{DataFrame, coalesce, col, lit},
 };
 
-use crate::expressions::prefix_cols;
+use crate::{diagnostics::show_table, expressions::prefix_cols};
 
 /// Compute the chi-squared distance metric on a set of queries and subjects.
 ///
@@ -150,12 +150,7 @@ pub async fn chi_squared(query: DataFrame, subject: DataFrame) -> std::io::Resul
         ])?
         .with_column("distance", col("chi_squared"))?;
 
-    if true {
-        tbl.clone()
-            .sort_by(vec![col("chi_squared")])?
-            .show()
-            .await?;
-    }
+    show_table("chi-squared distances", tbl.clone().sort_by(vec![col("chi_squared")])?).await?;
 
     Ok(tbl)
 }
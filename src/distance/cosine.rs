@@ -4,7 +4,7 @@ use datafusion::{
     prelude::{DataFrame, col, lit, sqrt},
 };
 
-use crate::expressions::prefix_cols;
+use crate::{diagnostics::show_table, expressions::prefix_cols};
 
 /// Compute the cosine distance metric on a set of queries and subjects.
 ///
@@ -66,12 +66,7 @@ pub async fn cosine(query: DataFrame, subject: DataFrame) -> std::io::Result<Dat
                 - col("distance_numerator") * lit(1.0) / (col("query_mag") * col("subject_mag")),
         )?;
 
-    if false {
-        tbl.clone()
-            .sort_by(vec![col("distance")])?
-            .show()
-            .await?;
-    }
+    show_table("cosine distances", tbl.clone().sort_by(vec![col("distance")])?).await?;
 
     let tbl = tbl.drop_columns(&[
         "query_mag_name",
@@ -80,12 +75,12 @@ pub async fn cosine(query: DataFrame, subject: DataFrame) -> std::io::Result<Dat
         "subject_mag",
     ])?;
 
-    if false {
+    show_table(
+        "cosine distances by name",
         tbl.clone()
-            .sort_by(vec![col("query_name"), col("subject_name")])?
-            .show()
-            .await?;
-    }
+            .sort_by(vec![col("query_name"), col("subject_name")])?,
+    )
+    .await?;
 
     Ok(tbl)
 }
@@ -68,24 +68,26 @@ impl NeedlemanWunsch {
     }
 
     pub fn align(&self, lhs: &str, rhs: &str) -> i32 {
-        let n = lhs.len();
         let m = rhs.len();
 
-        let mut s: Vec<Vec<i32>> = (0..=n).map(|_| vec![0; m + 1]).collect();
-        let mut ix: Vec<Vec<i32>> = (0..=n).map(|_| vec![0; m + 1]).collect();
-        let mut iy: Vec<Vec<i32>> = (0..=n).map(|_| vec![0; m + 1]).collect();
-
-        for i in 1..=n {
-            s[i][0] = self.gap_open + (i as i32 - 1) * self.gap_extend;
-            ix[i][0] = self.gap_open + (i as i32 - 1) * self.gap_extend;
-        }
+        // Only the previous row of each matrix is needed, so long sequences
+        // can be aligned in linear space.
+        let mut s: Vec<i32> = vec![0; m + 1];
+        let mut ix: Vec<i32> = vec![0; m + 1];
+        let mut iy: Vec<i32> = vec![0; m + 1];
         for j in 1..=m {
-            s[0][j] = self.gap_open + (j as i32 - 1) * self.gap_extend;
-            iy[0][j] = self.gap_open + (j as i32 - 1) * self.gap_extend;
+            s[j] = self.gap_open + (j as i32 - 1) * self.gap_extend;
+            iy[j] = self.gap_open + (j as i32 - 1) * self.gap_extend;
         }
 
+        let mut s_next: Vec<i32> = vec![0; m + 1];
+        let mut ix_next: Vec<i32> = vec![0; m + 1];
+        let mut iy_next: Vec<i32> = vec![0; m + 1];
         for (i, x) in lhs.chars().enumerate() {
             let i = i + 1;
+            s_next[0] = self.gap_open + (i as i32 - 1) * self.gap_extend;
+            ix_next[0] = self.gap_open + (i as i32 - 1) * self.gap_extend;
+            iy_next[0] = 0;
             for (j, y) in rhs.chars().enumerate() {
                 let j = j + 1;
 
@@ -94,26 +96,36 @@ impl NeedlemanWunsch {
                 } else {
                     self.symbol_mismatch
                 };
-                let s_m = s[i - 1][j - 1] + v;
-                let s_ix = ix[i - 1][j - 1] + v;
-                let s_iy = iy[i - 1][j - 1] + v;
-                s[i][j] = std::cmp::max(s_m, std::cmp::max(s_ix, s_iy));
-
-                let ix_o = s[i - 1][j] + self.gap_open;
-                let ix_e = ix[i - 1][j] + self.gap_extend;
-                ix[i][j] = std::cmp::max(ix_o, ix_e);
-
-                let iy_o = s[i][j - 1] + self.gap_open;
-                let iy_e = iy[i][j - 1] + self.gap_extend;
-                iy[i][j] = std::cmp::max(iy_o, iy_e);
+                let s_m = s[j - 1] + v;
+                let s_ix = ix[j - 1] + v;
+                let s_iy = iy[j - 1] + v;
+                s_next[j] = std::cmp::max(s_m, std::cmp::max(s_ix, s_iy));
+
+                let ix_o = s[j] + self.gap_open;
+                let ix_e = ix[j] + self.gap_extend;
+                ix_next[j] = std::cmp::max(ix_o, ix_e);
+
+                let iy_o = s_next[j - 1] + self.gap_open;
+                let iy_e = iy_next[j - 1] + self.gap_extend;
+                iy_next[j] = std::cmp::max(iy_o, iy_e);
             }
+            std::mem::swap(&mut s, &mut s_next);
+            std::mem::swap(&mut ix, &mut ix_next);
+            std::mem::swap(&mut iy, &mut iy_next);
         }
 
-        //for row in s.iter() {
-        //    println!("{:?}", row);
-        //}
+        s[m]
+    }
 
-        s[n][m]
+    /// The alignment score of two sequences as a fraction of the score of
+    /// the longer one aligned with itself (so at most the ratio of their
+    /// lengths), or 0 if it is negative.
+    pub fn identity(&self, lhs: &str, rhs: &str) -> f64 {
+        let perfect = self.symbol_match as f64 * lhs.len().max(rhs.len()) as f64;
+        if perfect <= 0.0 {
+            return 0.0;
+        }
+        (self.align(lhs, rhs) as f64 / perfect).max(0.0)
     }
 }
 
@@ -181,4 +193,14 @@ mod tests {
 
         assert_eq!(a.align(x, y), 254);
     }
+
+    #[test]
+    fn identity_1() {
+        let a = NeedlemanWunsch::default();
+
+        assert_eq!(a.identity("ACCGTTGA", "ACCGTTGA"), 1.0);
+        assert_eq!(a.identity("ACCGTTGA", "ACCATTGA"), 11.0 / 16.0);
+        assert_eq!(a.identity("AAAA", "TTTT"), 0.0);
+        assert_eq!(a.identity("", ""), 0.0);
+    }
 }
@@ -4,30 +4,53 @@ use std::{
     io::{Error as IoError, ErrorKind},
 };
 
+#[cfg(feature = "datafusion")]
+use datafusion::error::DataFusionError;
+
 #[derive(Debug)]
 pub enum SveltError {
     BadBreakEnd(String),
+    /// The mate contig given by CHR2, and the one given by the breakend ALT.
     BadChr2(String, String),
     BadChrom(String),
     BadFormatField(String, Box<dyn Error + Send + Sync + 'static>),
+    BadFormatType(String, String),
     BadInfoField(String, Box<dyn Error + Send + Sync + 'static>),
     BadInfoType(String, String),
+    BadInputSchema(String),
     BadKind(String),
+    BadOptionValue(String, String),
+    BadPortableIndex(String, String),
+    BadRegion(String),
     BadSample(String, Box<dyn Error + Send + Sync + 'static>),
+    BadTruthPair(String),
     BadVariant(String, usize, Box<dyn Error + Send + Sync + 'static>),
     Contigs(usize, usize),
+    ContigLength(String, usize, usize),
     ContigMissing(String, usize),
     ContigOrder(String, usize, usize),
+    CoordinateBeyondContig(String, String, usize, usize),
+    DuplicateFeatureName(String),
     FileError(String, Box<dyn Error + Send + Sync + 'static>),
+    IndexWithoutSequences(String),
     MissingAlt,
     MissingChr2,
     MissingInfo(String),
     MissingK(String),
     MissingType,
     NeardexDuplicate(u32),
+    Nondeterministic(String, String),
     OptionReferenceRequired(String),
-    TooManyVcfs(usize),
+    /// The input, and svelt's own INFO fields it has.
+    SveltOwnedFields(String, String),
+    /// The input, its number of records, and the most that can be merged.
+    TooManyRecords(String, usize, usize),
     UnexpectedNull(String),
+    UnknownAmbiguityMode(String, String),
+    UnknownKmerScheme(String, String),
+    UnsortedInput(String, String),
+    UnsupportedK(String, String),
+    UnsupportedIndexVersion(String, String),
 }
 
 impl Display for SveltError {
@@ -36,11 +59,11 @@ impl Display for SveltError {
             SveltError::BadBreakEnd(alt) => {
                 write!(f, "Badly formed breakend '{}'", alt)
             }
-            SveltError::BadChr2(chrom2, chr2) => {
+            SveltError::BadChr2(chr2, bnd_chrom) => {
                 write!(
                     f,
                     "Inconsistent BND INFO - BND had {}, CHR2 had {}",
-                    chr2, chrom2
+                    bnd_chrom, chr2
                 )
             }
             SveltError::BadChrom(chrom) => {
@@ -49,6 +72,13 @@ impl Display for SveltError {
             SveltError::BadFormatField(name, _error) => {
                 write!(f, "Problem with parsing FORMAT field '{}'", name)
             }
+            SveltError::BadFormatType(tag, exp) => {
+                write!(
+                    f,
+                    "Unexpected type of FORMAT - tag was {}, expected type was {}",
+                    tag, exp
+                )
+            }
             SveltError::BadInfoField(name, _error) => {
                 write!(f, "Problem with parsing INFO field '{}'", name)
             }
@@ -59,12 +89,39 @@ impl Display for SveltError {
                     tag, exp
                 )
             }
+            SveltError::BadInputSchema(name) => {
+                write!(f, "input '{}' does not conform to the core VCF schema", name)
+            }
             SveltError::BadKind(kind) => {
                 write!(f, "Unexpected SVTYPE: '{}'", kind)
             }
+            SveltError::BadOptionValue(opt, value) => {
+                write!(f, "Bad value for option '{}': {}", opt, value)
+            }
+            SveltError::BadPortableIndex(name, reason) => {
+                write!(
+                    f,
+                    "index '{}' is not a usable portable index: {}",
+                    name, reason
+                )
+            }
+            SveltError::BadRegion(line) => {
+                write!(
+                    f,
+                    "Badly formed BED region '{}' (expected 'CHROM START END')",
+                    line
+                )
+            }
             SveltError::BadSample(name, _error) => {
                 write!(f, "Problem with parsing sample field '{}'", name)
             }
+            SveltError::BadTruthPair(line) => {
+                write!(
+                    f,
+                    "Badly formed truth pair '{}' (expected 'ID1 ID2 1|0')",
+                    line
+                )
+            }
             SveltError::BadVariant(chrom, position, _error) => {
                 write!(f, "Problem with variant at {}:{}", chrom, position)
             }
@@ -73,6 +130,13 @@ impl Display for SveltError {
                 "Unexpected number of contigs - expected {}, got {}",
                 exp, got
             ),
+            SveltError::ContigLength(chrom, exp, got) => {
+                write!(
+                    f,
+                    "Contig '{}' has a different length - expected {}, got {}",
+                    chrom, exp, got
+                )
+            }
             SveltError::ContigMissing(chrom, got) => {
                 write!(f, "Unexpected contig '{}' (number {}", chrom, got)
             }
@@ -83,9 +147,26 @@ impl Display for SveltError {
                     chrom, exp, got
                 )
             }
+            SveltError::CoordinateBeyondContig(field, chrom, pos, length) => {
+                write!(
+                    f,
+                    "{} ({}) is beyond the end of contig '{}' (length {})",
+                    field, pos, chrom, length
+                )
+            }
+            SveltError::DuplicateFeatureName(name) => {
+                write!(
+                    f,
+                    "feature name '{}' occurs more than once (use --duplicate-names rename to make the names unique)",
+                    name
+                )
+            }
             SveltError::FileError(filename, _error) => {
                 write!(f, "Problem processing file '{}'", filename)
             }
+            SveltError::IndexWithoutSequences(name) => {
+                write!(f, "index '{}' was built without sequences (--omit-sequences)", name)
+            }
             SveltError::MissingAlt => {
                 write!(f, "Missing ALT")
             }
@@ -104,6 +185,13 @@ impl Display for SveltError {
             SveltError::NeardexDuplicate(key) => {
                 write!(f, "Cannot construct Neardex with duplicate key {}", key)
             }
+            SveltError::Nondeterministic(run, stage) => {
+                write!(
+                    f,
+                    "The {} run of the merge diverged at the {} stage",
+                    run, stage
+                )
+            }
             SveltError::OptionReferenceRequired(opt) => {
                 write!(
                     f,
@@ -111,12 +199,46 @@ impl Display for SveltError {
                     opt
                 )
             }
-            SveltError::TooManyVcfs(n) => {
-                write!(f, "Too many VCFs (max 64) ({} given)", n)
+            SveltError::SveltOwnedFields(name, fields) => {
+                write!(
+                    f,
+                    "input '{}' has svelt's own INFO fields ({}), so may be svelt's output (use --allow-svelt-inputs to merge it, renaming them)",
+                    name, fields
+                )
+            }
+            SveltError::TooManyRecords(name, count, limit) => {
+                write!(
+                    f,
+                    "input '{}' has {} records, but at most {} records per input can be merged with this many inputs",
+                    name, count, limit
+                )
             }
             SveltError::UnexpectedNull(src) => {
                 write!(f, "unexpected null value at {}", src)
             }
+            SveltError::UnknownAmbiguityMode(name, mode) => {
+                write!(f, "index '{}' has unknown ambiguity mode '{}'", name, mode)
+            }
+            SveltError::UnknownKmerScheme(name, scheme) => {
+                write!(f, "index '{}' has unknown k-mer scheme '{}'", name, scheme)
+            }
+            SveltError::UnsortedInput(here, previous) => {
+                write!(
+                    f,
+                    "Record at {} is out of order, coming after {} (sort the input, e.g. with 'bcftools sort', or use --sort-inputs)",
+                    here, previous
+                )
+            }
+            SveltError::UnsupportedK(name, k) => {
+                write!(f, "index '{}' has an unsupported k-mer length: {}", name, k)
+            }
+            SveltError::UnsupportedIndexVersion(name, version) => {
+                write!(
+                    f,
+                    "index '{}' has version {}, which is newer than this version of svelt supports",
+                    name, version
+                )
+            }
         }
     }
 }
@@ -134,10 +256,175 @@ impl std::error::Error for SveltError {
     }
 }
 
+/// Broad classes of failure, distinguished by the process exit status so that
+/// workflow engines can decide whether a failed run is worth retrying.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FailureClass {
+    /// Anything not covered below, including I/O errors and bugs.
+    Internal,
+    /// The command line, or an input taken as a whole, is unusable
+    /// (e.g. a missing file, or an incompatible index).
+    InputValidation,
+    /// The inputs don't have the same contigs, in the same order.
+    ContigMismatch,
+    /// A record (or other input data) couldn't be parsed or makes no sense.
+    MalformedRecord,
+    /// The memory or disk available was exceeded.
+    ResourceExhausted,
+}
+
+impl FailureClass {
+    /// The exit status for this class (2 is also used by the argument parser
+    /// for errors on the command line).
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            FailureClass::Internal => 1,
+            FailureClass::InputValidation => 2,
+            FailureClass::ContigMismatch => 3,
+            FailureClass::MalformedRecord => 4,
+            FailureClass::ResourceExhausted => 5,
+        }
+    }
+
+    /// Classify an error by looking through the chain of causes for the first
+    /// one that tells us what went wrong.
+    pub fn of(error: &IoError) -> FailureClass {
+        if let Some(class) = FailureClass::of_kind(error.kind()) {
+            return class;
+        }
+        let mut current = error.get_ref().map(|e| e as &(dyn Error + 'static));
+        while let Some(e) = current {
+            if let Some(e) = e.downcast_ref::<SveltError>() {
+                match e {
+                    SveltError::FileError(_filename, _error) => {}
+                    _ => return e.failure_class(),
+                }
+            } else if let Some(class) = FailureClass::of_datafusion(e) {
+                return class;
+            } else if let Some(e) = e.downcast_ref::<IoError>() {
+                if let Some(class) = FailureClass::of_kind(e.kind()) {
+                    return class;
+                }
+                // The source of an io::Error skips over the error it wraps.
+                current = e.get_ref().map(|e| e as &(dyn Error + 'static));
+                continue;
+            }
+            current = e.source();
+        }
+        FailureClass::Internal
+    }
+
+    #[cfg(feature = "datafusion")]
+    fn of_datafusion(e: &(dyn Error + 'static)) -> Option<FailureClass> {
+        let e = e.downcast_ref::<DataFusionError>()?;
+        if let DataFusionError::ResourcesExhausted(_) = e.find_root() {
+            return Some(FailureClass::ResourceExhausted);
+        }
+        None
+    }
+
+    #[cfg(not(feature = "datafusion"))]
+    fn of_datafusion(_e: &(dyn Error + 'static)) -> Option<FailureClass> {
+        None
+    }
+
+    fn of_kind(kind: ErrorKind) -> Option<FailureClass> {
+        match kind {
+            ErrorKind::NotFound | ErrorKind::PermissionDenied => {
+                Some(FailureClass::InputValidation)
+            }
+            ErrorKind::InvalidData | ErrorKind::UnexpectedEof => {
+                Some(FailureClass::MalformedRecord)
+            }
+            ErrorKind::OutOfMemory | ErrorKind::StorageFull | ErrorKind::QuotaExceeded => {
+                Some(FailureClass::ResourceExhausted)
+            }
+            _ => None,
+        }
+    }
+}
+
+impl SveltError {
+    /// The first `SveltError` in the chain of causes of `error`, other than
+    /// those that just say which file it was in.
+    pub fn cause_of(error: &IoError) -> Option<&SveltError> {
+        let mut current = error.get_ref().map(|e| e as &(dyn Error + 'static));
+        while let Some(e) = current {
+            if let Some(e) = e.downcast_ref::<SveltError>()
+                && !matches!(e, SveltError::FileError(_, _))
+            {
+                return Some(e);
+            }
+            current = match e.downcast_ref::<IoError>() {
+                // The source of an io::Error skips over the error it wraps.
+                Some(e) => e.get_ref().map(|e| e as &(dyn Error + 'static)),
+                None => e.source(),
+            };
+        }
+        None
+    }
+
+    pub fn failure_class(&self) -> FailureClass {
+        match self {
+            SveltError::BadBreakEnd(_)
+            | SveltError::BadChr2(_, _)
+            | SveltError::BadChrom(_)
+            | SveltError::BadFormatField(_, _)
+            | SveltError::BadFormatType(_, _)
+            | SveltError::BadInfoField(_, _)
+            | SveltError::BadInfoType(_, _)
+            | SveltError::BadKind(_)
+            | SveltError::BadSample(_, _)
+            | SveltError::BadVariant(_, _, _)
+            | SveltError::CoordinateBeyondContig(_, _, _, _)
+            | SveltError::MissingAlt
+            | SveltError::MissingChr2
+            | SveltError::MissingInfo(_)
+            | SveltError::MissingType => FailureClass::MalformedRecord,
+            SveltError::Contigs(_, _)
+            | SveltError::ContigLength(_, _, _)
+            | SveltError::ContigMissing(_, _)
+            | SveltError::ContigOrder(_, _, _) => FailureClass::ContigMismatch,
+            SveltError::BadInputSchema(_)
+            | SveltError::BadOptionValue(_, _)
+            | SveltError::BadPortableIndex(_, _)
+            | SveltError::BadRegion(_)
+            | SveltError::BadTruthPair(_)
+            | SveltError::DuplicateFeatureName(_)
+            | SveltError::IndexWithoutSequences(_)
+            | SveltError::MissingK(_)
+            | SveltError::OptionReferenceRequired(_)
+            | SveltError::SveltOwnedFields(_, _)
+            | SveltError::TooManyRecords(_, _, _)
+            | SveltError::UnknownAmbiguityMode(_, _)
+            | SveltError::UnknownKmerScheme(_, _)
+            | SveltError::UnsortedInput(_, _)
+            | SveltError::UnsupportedK(_, _)
+            | SveltError::UnsupportedIndexVersion(_, _) => FailureClass::InputValidation,
+            SveltError::FileError(_, _)
+            | SveltError::NeardexDuplicate(_)
+            | SveltError::Nondeterministic(_, _)
+            | SveltError::UnexpectedNull(_) => FailureClass::Internal,
+        }
+    }
+}
+
 pub fn as_io_error(error: SveltError) -> std::io::Error {
     IoError::new(ErrorKind::Other, error)
 }
 
+/// Describe an error along with its chain of causes, on one line (for warnings).
+pub fn error_chain(error: &dyn Error) -> String {
+    let mut res = error.to_string();
+    let mut current_source = error.source();
+    while let Some(source) = current_source {
+        res.push_str(": ");
+        res.push_str(&source.to_string());
+        current_source = source.source();
+    }
+    res
+}
+
 pub fn wrap_file_error<E: std::error::Error + Send + Sync + 'static>(
     e: E,
     filename: &str,
@@ -187,3 +474,64 @@ impl<'a> Context for VariantContext<'a> {
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_failure_class() {
+        let e = IoError::other("oops");
+        assert_eq!(FailureClass::of(&e), FailureClass::Internal);
+
+        let e = SveltError::ContigOrder(String::from("chr2"), 1, 2);
+        let e = wrap_file_error(as_io_error(e), "a.vcf");
+        assert_eq!(FailureClass::of(&e), FailureClass::ContigMismatch);
+
+        let e = VariantContext::new("chr1", 1000)
+            .with(|| Err::<(), _>(IoError::other("bad SVLEN")))
+            .unwrap_err();
+        let e = wrap_file_error(e, "a.vcf");
+        assert_eq!(FailureClass::of(&e), FailureClass::MalformedRecord);
+
+        let e = wrap_file_error(IoError::new(ErrorKind::NotFound, "no such file"), "a.vcf");
+        assert_eq!(FailureClass::of(&e), FailureClass::InputValidation);
+
+        #[cfg(feature = "datafusion")]
+        {
+            let e = IoError::other(DataFusionError::ResourcesExhausted(String::from("memory")));
+            assert_eq!(FailureClass::of(&e), FailureClass::ResourceExhausted);
+        }
+        assert_eq!(FailureClass::ResourceExhausted.exit_code(), 5);
+    }
+
+    #[test]
+    fn test_variant_messages() {
+        let e = SveltError::BadChr2(String::from("chr2"), String::from("chr3"));
+        assert_eq!(
+            e.to_string(),
+            "Inconsistent BND INFO - BND had chr3, CHR2 had chr2"
+        );
+        assert_eq!(
+            SveltError::MissingChr2.to_string(),
+            "Missing CHR2 INFO field"
+        );
+        assert_eq!(SveltError::MissingAlt.to_string(), "Missing ALT");
+        assert_eq!(SveltError::MissingType.to_string(), "Missing SVTYPE");
+
+        for e in [
+            SveltError::BadChr2(String::from("chr2"), String::from("chr3")),
+            SveltError::MissingChr2,
+        ] {
+            let message = e.to_string();
+            let e = VariantContext::new("chr1", 1000)
+                .with(|| Err::<(), _>(as_io_error(e)))
+                .unwrap_err();
+            assert_eq!(
+                error_chain(&e),
+                format!("Problem with variant at chr1:1000: {}", message)
+            );
+            assert_eq!(FailureClass::of(&e), FailureClass::MalformedRecord);
+        }
+    }
+}
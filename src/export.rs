@@ -0,0 +1,218 @@
+use std::io::{BufWriter, Write};
+
+use autocompress::{CompressionLevel, autodetect_create};
+use noodles::vcf::variant::record::samples::series::value::genotype::Phasing;
+use noodles::vcf::{
+    Header,
+    variant::{
+        RecordBuf,
+        record::samples::keys::key,
+        record_buf::{
+            info::field::{Value as InfoValue, value::Array as InfoArray},
+            samples::sample::value::{Genotype, Value, genotype::Allele},
+        },
+    },
+};
+use serde::Serialize;
+
+use crate::{breakends::BreakEnd, construct::InnerWriter};
+
+/// A merged variant, as exported for loading into downstream databases.
+///
+/// This is deliberately independent of the VCF representation, so that the
+/// exported documents remain stable as the VCF output evolves.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct MergedEvent {
+    pub id: String,
+    pub chrom: String,
+    pub start: usize,
+    pub end: Option<usize>,
+    pub kind: Option<String>,
+    pub length: Option<i64>,
+    pub chrom2: Option<String>,
+    pub end2: Option<usize>,
+    pub quality: Option<f32>,
+    pub filters: Vec<String>,
+    pub support: usize,
+    pub genotypes: Vec<SampleGenotype>,
+    pub classification: Option<String>,
+    pub provenance: Provenance,
+}
+
+/// The genotype call for one sample.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct SampleGenotype {
+    pub sample: String,
+    pub genotype: Option<String>,
+}
+
+/// Where a merged event came from.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Provenance {
+    pub original_ids: Vec<String>,
+    pub criteria: Vec<String>,
+    pub synthetic: bool,
+}
+
+impl MergedEvent {
+    pub fn from_record(header: &Header, rec: &RecordBuf) -> MergedEvent {
+        let chrom = String::from(rec.reference_sequence_name());
+        let start = rec.variant_start().map(|p| p.get()).unwrap_or(0);
+
+        let info = rec.info();
+        let kind = info_string(info.get("SVTYPE"));
+        let end = info_integer(info.get("END")).map(|e| e as usize);
+        let length = info_integer(info.get("SVLEN")).map(|l| l as i64);
+
+        let (chrom2, end2) = mate_position(rec);
+
+        let mut genotypes = Vec::new();
+        let mut support = 0;
+        let gt_index = rec.samples().keys().as_ref().get_index_of(key::GENOTYPE);
+        for (sample, values) in header.sample_names().iter().zip(rec.samples().values()) {
+            let genotype = gt_index
+                .and_then(|i| values.values().get(i))
+                .and_then(|v| match v {
+                    Some(Value::Genotype(gt)) => Some(gt),
+                    _ => None,
+                });
+            if let Some(gt) = genotype
+                && gt.as_ref().iter().any(|a| a.position().unwrap_or(0) > 0)
+            {
+                support += 1;
+            }
+            genotypes.push(SampleGenotype {
+                sample: sample.clone(),
+                genotype: genotype.map(format_genotype),
+            });
+        }
+
+        MergedEvent {
+            id: rec
+                .ids()
+                .as_ref()
+                .iter()
+                .next()
+                .cloned()
+                .unwrap_or_default(),
+            chrom,
+            start,
+            end,
+            kind,
+            length,
+            chrom2,
+            end2,
+            quality: rec.quality_score(),
+            filters: rec.filters().as_ref().iter().cloned().collect(),
+            support,
+            genotypes,
+            classification: info_string(info.get("SVELT_ALT_CLASS"))
+                .or_else(|| info_string(info.get("SVELT_DEL_CLASS"))),
+            provenance: Provenance {
+                original_ids: info_strings(info.get("ORIGINAL_IDS")),
+                criteria: info_strings(info.get("SVELT_CRITERIA")),
+                synthetic: info.get("SVELT_SYNTHETIC_MATE").is_some(),
+            },
+        }
+    }
+}
+
+/// The contig and position of the other end of a record with a CHR2 (or of
+/// a breakend, given by its ALT).
+pub fn mate_position(rec: &RecordBuf) -> (Option<String>, Option<usize>) {
+    let info = rec.info();
+    let chrom2 = info_string(info.get("CHR2"));
+    let end2 = info_integer(info.get("END2")).map(|e| e as usize);
+    if chrom2.is_none()
+        && let Some(alt) = rec.alternate_bases().as_ref().first()
+        && let Some(start) = rec.variant_start()
+        && let Ok(bnd) = BreakEnd::new(rec.reference_sequence_name(), start.get(), alt)
+    {
+        return (Some(bnd.chrom2), Some(bnd.end2));
+    }
+    (chrom2, end2)
+}
+
+/// Write merged events as JSON Lines (one document per line).
+pub struct EventWriter {
+    writer: InnerWriter,
+}
+
+impl EventWriter {
+    pub fn new(out: &str) -> std::io::Result<EventWriter> {
+        let writer = autodetect_create(out, CompressionLevel::Default)?;
+        let writer = BufWriter::new(writer);
+        Ok(EventWriter { writer })
+    }
+
+    pub fn write(&mut self, header: &Header, rec: &RecordBuf) -> std::io::Result<()> {
+        let event = MergedEvent::from_record(header, rec);
+        serde_json::to_writer(&mut self.writer, &event)?;
+        self.writer.write_all(b"\n")
+    }
+
+    pub fn finish(&mut self) -> std::io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+fn format_genotype(gt: &Genotype) -> String {
+    let mut res = String::new();
+    for (i, allele) in gt.as_ref().iter().enumerate() {
+        if i > 0 {
+            res.push(match allele.phasing() {
+                Phasing::Phased => '|',
+                Phasing::Unphased => '/',
+            });
+        }
+        res.push_str(&format_allele(allele));
+    }
+    res
+}
+
+fn format_allele(allele: &Allele) -> String {
+    match allele.position() {
+        Some(p) => p.to_string(),
+        None => String::from("."),
+    }
+}
+
+fn info_string(value: Option<Option<&InfoValue>>) -> Option<String> {
+    match value {
+        Some(Some(InfoValue::String(s))) => Some(s.clone()),
+        _ => None,
+    }
+}
+
+fn info_integer(value: Option<Option<&InfoValue>>) -> Option<i32> {
+    match value {
+        Some(Some(InfoValue::Integer(x))) => Some(*x),
+        Some(Some(InfoValue::Array(InfoArray::Integer(xs)))) => xs.first().cloned().flatten(),
+        _ => None,
+    }
+}
+
+fn info_strings(value: Option<Option<&InfoValue>>) -> Vec<String> {
+    match value {
+        Some(Some(InfoValue::String(s))) => vec![s.clone()],
+        Some(Some(InfoValue::Array(InfoArray::String(xs)))) => {
+            xs.iter().flatten().cloned().collect()
+        }
+        _ => Vec::new(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use super::*;
+
+    #[test]
+    fn test_format_genotype() {
+        for s in ["0/1", "1|1", "./.", "0"] {
+            let gt = Genotype::from_str(s).unwrap();
+            assert_eq!(format_genotype(&gt), s);
+        }
+    }
+}
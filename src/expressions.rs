@@ -1,6 +1,18 @@
-use datafusion::prelude::{case, lit, DataFrame, Expr};
+//! Helpers for building the DataFusion expressions and tables the merge
+//! criteria are made of, for use by new criteria, in this crate or outside it.
+//!
+//! A criterion typically takes the table of records, makes two copies of it
+//! with `prefix_cols` (`lhs_...` and `rhs_...`), joins them on the columns
+//! that must agree, and filters the pairs with predicates such as
+//! `window_match`.
 
+use std::io::{Error, ErrorKind};
 
+use datafusion::arrow::datatypes::DataType;
+use datafusion::prelude::{DataFrame, Expr, abs, case, cast, col, lit};
+
+/// `then` where `cond` is true, and `otherwise` where it is false (and null
+/// where it is null).
 pub fn ifelse(cond: Expr, then: Expr, otherwise: Expr) -> datafusion::common::Result<Expr> {
     case(cond)
         .when(lit(true), then)
@@ -8,14 +20,30 @@ pub fn ifelse(cond: Expr, then: Expr, otherwise: Expr) -> datafusion::common::Re
         .end()
 }
 
+/// The lesser of `lhs` and `rhs`, row by row.
 pub fn pmin(lhs: Expr, rhs: Expr) -> datafusion::common::Result<Expr> {
     ifelse(lhs.clone().lt_eq(rhs.clone()), lhs, rhs)
 }
 
+/// The greater of `lhs` and `rhs`, row by row.
 pub fn pmax(lhs: Expr, rhs: Expr) -> datafusion::common::Result<Expr> {
     ifelse(lhs.clone().gt_eq(rhs.clone()), lhs, rhs)
 }
 
+/// The difference between two (32 bit) position columns, worked out in 64
+/// bits so it can't overflow (see `window::Window`).
+pub fn difference(lhs: &str, rhs: &str) -> Expr {
+    cast(col(lhs), DataType::Int64) - cast(col(rhs), DataType::Int64)
+}
+
+/// Whether the positions in columns `lhs` and `rhs` are no further apart than
+/// `w`: the test of `Window::contains`, as an expression.
+pub fn window_match(lhs: &str, rhs: &str, w: u32) -> Expr {
+    abs(difference(lhs, rhs)).lt_eq(lit(w as i64))
+}
+
+/// Rename every column of `df` from `name` to `prefix_name`, so that two
+/// copies of a table can be joined without their columns clashing.
 pub fn prefix_cols(df: DataFrame, prefix: &str) -> std::io::Result<DataFrame> {
     let mut df = df;
     for column in df.schema().columns().iter() {
@@ -24,4 +52,116 @@ pub fn prefix_cols(df: DataFrame, prefix: &str) -> std::io::Result<DataFrame> {
         df = df.with_column_renamed(old_name, &new_name)?;
     }
     Ok(df)
-}
\ No newline at end of file
+}
+
+/// Rename the columns of `df` given as `(old, new)` pairs. Unlike
+/// `DataFrame::with_column_renamed`, which quietly does nothing when there is
+/// no column `old`, this is an error, as is a `new` name that is already taken.
+pub fn rename_cols(df: DataFrame, renames: &[(&str, &str)]) -> std::io::Result<DataFrame> {
+    let mut df = df;
+    for (old_name, new_name) in renames.iter() {
+        let schema = df.schema();
+        if !schema.has_column_with_unqualified_name(old_name) {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!("no column '{}' to rename", old_name),
+            ));
+        }
+        if schema.has_column_with_unqualified_name(new_name) {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!(
+                    "can't rename '{}': there is already a column '{}'",
+                    old_name, new_name
+                ),
+            ));
+        }
+        df = df.with_column_renamed(*old_name, new_name)?;
+    }
+    Ok(df)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use datafusion::{
+        arrow::{
+            array::{Array, BooleanArray, Int32Array, Int64Array, RecordBatch},
+            datatypes::{Field, Schema},
+        },
+        prelude::SessionContext,
+    };
+
+    use super::*;
+
+    fn positions(ctx: &SessionContext) -> DataFrame {
+        let schema = Arc::new(Schema::new(vec![
+            Field::new("start", DataType::Int32, false),
+            Field::new("end", DataType::Int32, false),
+        ]));
+        let recs = RecordBatch::try_new(
+            schema,
+            vec![
+                Arc::new(Int32Array::from(vec![100, 100, i32::MIN, 200])),
+                Arc::new(Int32Array::from(vec![125, 126, i32::MAX, 175])),
+            ],
+        )
+        .unwrap();
+        ctx.read_batch(recs).unwrap()
+    }
+
+    async fn column<T: Array + Clone + 'static>(df: DataFrame, name: &str) -> T {
+        let recs = df.select_columns(&[name]).unwrap().collect().await.unwrap();
+        recs[0]
+            .column(0)
+            .as_any()
+            .downcast_ref::<T>()
+            .unwrap()
+            .clone()
+    }
+
+    #[tokio::test]
+    async fn test_expressions() {
+        let ctx = SessionContext::new();
+        let df = positions(&ctx)
+            .with_column("diff", difference("end", "start"))
+            .unwrap()
+            .with_column("near", window_match("start", "end", 25))
+            .unwrap()
+            .with_column("lo", pmin(col("start"), col("end")).unwrap())
+            .unwrap()
+            .with_column("hi", pmax(col("start"), col("end")).unwrap())
+            .unwrap();
+
+        let diff = column::<Int64Array>(df.clone(), "diff").await;
+        assert_eq!(diff.values().to_vec(), vec![25, 26, u32::MAX as i64, -25]);
+        let near = column::<BooleanArray>(df.clone(), "near").await;
+        assert_eq!(
+            near.iter().collect::<Vec<_>>(),
+            vec![Some(true), Some(false), Some(false), Some(true)]
+        );
+        let lo = column::<Int32Array>(df.clone(), "lo").await;
+        assert_eq!(lo.values().to_vec(), vec![100, 100, i32::MIN, 175]);
+        let hi = column::<Int32Array>(df, "hi").await;
+        assert_eq!(hi.values().to_vec(), vec![125, 126, i32::MAX, 200]);
+    }
+
+    #[tokio::test]
+    async fn test_renaming() {
+        let ctx = SessionContext::new();
+        let df = prefix_cols(positions(&ctx), "lhs").unwrap();
+        let names: Vec<String> = df
+            .schema()
+            .fields()
+            .iter()
+            .map(|f| f.name().clone())
+            .collect();
+        assert_eq!(names, vec!["lhs_start", "lhs_end"]);
+
+        let df = rename_cols(df, &[("lhs_start", "pos")]).unwrap();
+        assert!(df.schema().has_column_with_unqualified_name("pos"));
+        assert!(rename_cols(df.clone(), &[("start", "begin")]).is_err());
+        assert!(rename_cols(df, &[("pos", "lhs_end")]).is_err());
+    }
+}
@@ -1,15 +1,18 @@
+#[cfg(feature = "datafusion")]
 use std::{
-    collections::HashMap,
+    collections::{HashMap, HashSet},
     io::{BufReader, Error, ErrorKind},
     iter::zip,
     sync::Arc,
 };
 
+#[cfg(feature = "datafusion")]
 use autocompress::autodetect_open;
+#[cfg(feature = "datafusion")]
 use datafusion::{
     arrow::{
         array::{
-            Float64Array, GenericStringBuilder, PrimitiveBuilder, RecordBatch, StringViewArray,
+            ArrayRef, Float64Array, GenericStringBuilder, PrimitiveBuilder, RecordBatch, StringViewArray,
             UInt32Array, UInt64Array,
         },
         datatypes::{DataType, Field, Float64Type, Schema, UInt32Type, UInt64Type},
@@ -18,75 +21,129 @@ use datafusion::{
     dataframe::DataFrameWriteOptions,
     prelude::{ParquetReadOptions, SessionContext},
 };
+#[cfg(feature = "datafusion")]
 use noodles::fasta;
 
-use crate::{errors::wrap_file_error, kmers::KmerIterator, options::IndexingOptions};
+use crate::kmers::{Ambiguity, KmerIterator, minimizer::sample_kmers};
+#[cfg(feature = "datafusion")]
+use crate::{
+    errors::{SveltError, as_io_error, wrap_file_error},
+    kmers::{INDEX_K, Kmer, check_k},
+    options::{DuplicateNames, IndexingOptions},
+    threads::WorkerPool,
+};
+#[cfg(feature = "datafusion")]
+use rayon::prelude::*;
 
+mod portable;
 mod vector;
 
+/// The position of a window of a reference genome, 1-based and inclusive.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Locus {
+    pub chrom: String,
+    pub start: u64,
+    pub end: u64,
+}
+
+impl std::fmt::Display for Locus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}:{}-{}", self.chrom, self.start, self.end)
+    }
+}
+
+/// The version of the parquet layout written by `save`, recorded in the
+/// metadata of the k-mers table. Indexes without a version predate it, and are
+/// still loaded (as far as their metadata allows), but should be upgraded with
+/// `svelt index-upgrade`.
+pub const INDEX_VERSION: u32 = 2;
+
+/// The number of sequences (or genome windows) whose k-mers are counted at
+/// once when building an index.
+#[cfg(feature = "datafusion")]
+const BUILD_BATCH_SIZE: usize = 1024;
+
 pub struct FeatureIndex {
     pub(crate) k: usize,
+    /// The minimizer window (0 means every k-mer is indexed).
+    pub(crate) w: usize,
+    /// How ambiguity codes were treated when building the index.
+    pub(crate) ambiguity: Ambiguity,
     pub(crate) kmers: vector::MergeVector,
     pub(crate) names: Vec<String>,
-    pub(crate) sequences: Vec<String>,
+    /// The feature sequences, which are only needed for alignment, so are
+    /// only loaded on request (see `load_sequences`).
+    pub(crate) sequences: Option<Vec<String>>,
     pub(crate) mags: Vec<f64>,
+    /// The base name the index was loaded from, if any.
+    pub(crate) source: Option<String>,
+    /// For an index of a reference genome, the position of each window.
+    pub(crate) loci: Option<Vec<Locus>>,
+    /// The number of features given a suffix (#2, #3, ...) when the index
+    /// was built, as their names duplicated an earlier feature's.
+    pub(crate) renamed: usize,
 }
 
 impl FeatureIndex {
-    pub async fn build(source: &str, options: &IndexingOptions) -> std::io::Result<FeatureIndex> {
+    #[cfg(feature = "datafusion")]
+    pub async fn build(
+        source: &str,
+        options: &IndexingOptions,
+        ctx: &SessionContext,
+    ) -> std::io::Result<FeatureIndex> {
+        options.check().map_err(as_io_error)?;
         let reader = autodetect_open(source).map_err(|e| wrap_file_error(e, source))?;
         let reader = BufReader::new(reader);
         let mut reader = fasta::io::reader::Builder::default().build_from_reader(reader)?;
 
-        let k = options.k;
+        let window = options.genome_window;
+        let step = options.genome_step.unwrap_or((window / 2).max(1));
+        if window > 0 && (step == 0 || step > window) {
+            return Err(as_io_error(SveltError::BadOptionValue(
+                String::from("--genome-step"),
+                format!("must be between 1 and the genome window ({})", window),
+            )));
+        }
 
-        let mut sequence_number: u32 = 0;
+        let mut builder = IndexBuilder::new(options);
+        let pool = WorkerPool::of(ctx)?;
+        let mut pending = Vec::new();
 
         log::info!("reading sequences from '{}'", source);
 
-        let mut names = Vec::new();
-        let mut sequences = Vec::new();
-        let mut mags = Vec::new();
-        let mut kmers: HashMap<u64, Vec<(u32, u32)>> = HashMap::new();
-
         for rec in reader.records() {
             let rec = rec?;
-            let nix = sequence_number;
-            let name = rec.definition().to_string().split_off(1);
-            let sequence = String::from_utf8(rec.sequence().as_ref().to_vec()).unwrap();
-
-            let mut tmp: HashMap<u64, u32> = HashMap::new();
-            for (x, _) in KmerIterator::new(k, sequence.as_bytes().iter()) {
-                *tmp.entry(x.0).or_default() += 1;
-            }
-
-            let mut mag = 0;
-            for (x, count) in tmp.into_iter() {
-                mag += count * count;
-                kmers.entry(x).or_default().push((nix, count));
+            if window > 0 {
+                let chrom = String::from_utf8_lossy(rec.name()).to_string();
+                let sequence = rec.sequence().as_ref();
+                log::info!("indexing '{}' ({} bases)", chrom, sequence.len());
+                for (begin, end) in genome_windows(sequence.len(), window, step) {
+                    let locus = Locus {
+                        chrom: chrom.clone(),
+                        start: begin as u64 + 1,
+                        end: end as u64,
+                    };
+                    let sequence = String::from_utf8(sequence[begin..end].to_vec()).unwrap();
+                    pending.push((locus.to_string(), sequence, Some(locus)));
+                    if pending.len() == BUILD_BATCH_SIZE {
+                        builder.add_batch(std::mem::take(&mut pending), &pool)?;
+                    }
+                }
+            } else {
+                let name = rec.definition().to_string().split_off(1);
+                let sequence = String::from_utf8(rec.sequence().as_ref().to_vec()).unwrap();
+                pending.push((name, sequence, None));
+                if pending.len() == BUILD_BATCH_SIZE {
+                    builder.add_batch(std::mem::take(&mut pending), &pool)?;
+                }
             }
-
-            names.push(name);
-            sequences.push(sequence);
-            mags.push((mag as f64).sqrt());
-
-            sequence_number += 1;
         }
+        builder.add_batch(pending, &pool)?;
 
-        let kmers: Vec<(u64, Vec<(u32, u32)>)> = kmers.into_iter().collect();
-        let kmers = vector::MergeVector::new(k, kmers);
-
-        log::info!("index construction complete");
-
-        Ok(FeatureIndex {
-            k: options.k,
-            kmers,
-            names,
-            sequences,
-            mags,
-        })
+        Ok(builder.finish())
     }
 
+    #[cfg(feature = "datafusion")]
     pub async fn save(&self, out: &str, ctx: &SessionContext) -> std::io::Result<()> {
         log::info!("saving sequences table.");
 
@@ -96,33 +153,43 @@ impl FeatureIndex {
         }
         let name_array = name_builder.finish();
 
-        let mut sequence_builder = GenericStringBuilder::<i32>::new();
-        for sequence in self.sequences.iter() {
-            sequence_builder.append_value(sequence);
-        }
-        let sequence_array = sequence_builder.finish();
-
         let mut mags_builder = PrimitiveBuilder::<Float64Type>::new();
         for mag in self.mags.iter() {
             mags_builder.append_value(*mag);
         }
         let mags_array = mags_builder.finish();
 
-        let name_schema = Arc::new(Schema::new(vec![
-            Field::new("name", DataType::Utf8, false),
-            Field::new("sequence", DataType::Utf8, false),
-            Field::new("mags", DataType::Float64, false),
-        ]));
+        let mut fields = vec![Field::new("name", DataType::Utf8, false)];
+        let mut columns: Vec<ArrayRef> = vec![Arc::new(name_array)];
+        if let Some(sequences) = &self.sequences {
+            let mut sequence_builder = GenericStringBuilder::<i32>::new();
+            for sequence in sequences.iter() {
+                sequence_builder.append_value(sequence);
+            }
+            fields.push(Field::new("sequence", DataType::Utf8, false));
+            columns.push(Arc::new(sequence_builder.finish()));
+        }
+        fields.push(Field::new("mags", DataType::Float64, false));
+        columns.push(Arc::new(mags_array));
+        if let Some(loci) = &self.loci {
+            let mut chrom_builder = GenericStringBuilder::<i32>::new();
+            let mut start_builder = PrimitiveBuilder::<UInt64Type>::new();
+            let mut end_builder = PrimitiveBuilder::<UInt64Type>::new();
+            for locus in loci.iter() {
+                chrom_builder.append_value(&locus.chrom);
+                start_builder.append_value(locus.start);
+                end_builder.append_value(locus.end);
+            }
+            fields.push(Field::new("chrom", DataType::Utf8, false));
+            columns.push(Arc::new(chrom_builder.finish()));
+            fields.push(Field::new("start", DataType::UInt64, false));
+            columns.push(Arc::new(start_builder.finish()));
+            fields.push(Field::new("end", DataType::UInt64, false));
+            columns.push(Arc::new(end_builder.finish()));
+        }
 
-        let recs = RecordBatch::try_new(
-            name_schema,
-            vec![
-                Arc::new(name_array),
-                Arc::new(sequence_array),
-                Arc::new(mags_array),
-            ],
-        )
-        .map_err(|e| Error::new(ErrorKind::Other, e))?;
+        let recs = RecordBatch::try_new(Arc::new(Schema::new(fields)), columns)
+            .map_err(Error::other)?;
 
         save_record_batch(recs, &format!("{}-names.parquet", out), ctx, vec![]).await?;
 
@@ -144,9 +211,17 @@ impl FeatureIndex {
         let nixs_array = nixs_builder.finish();
         let counts_array = counts_builder.finish();
 
-        let kmers_meta: HashMap<String, String> = vec![(String::from("k"), format!("{}", self.k))]
-            .into_iter()
-            .collect();
+        let mut meta = vec![
+            (String::from("version"), INDEX_VERSION.to_string()),
+            (String::from("k"), format!("{}", self.k)),
+            (String::from("scheme"), String::from(self.scheme())),
+            (String::from("w"), format!("{}", self.w)),
+            (String::from("ambiguity"), String::from(self.ambiguity.name())),
+        ];
+        if self.renamed > 0 {
+            meta.push((String::from("renamed"), self.renamed.to_string()));
+        }
+        let kmers_meta: HashMap<String, String> = meta.iter().cloned().collect();
         log::info!("saving meta: {:?}", kmers_meta);
         let kmers_schema = Arc::new(Schema::new_with_metadata(
             vec![
@@ -171,27 +246,40 @@ impl FeatureIndex {
             recs,
             &format!("{}-kmers.parquet", out),
             ctx,
-            vec![(String::from("k"), Some(self.k.to_string()))],
+            meta.into_iter()
+                .map(|(key, value)| (key, Some(value)))
+                .collect(),
         )
         .await?;
 
         Ok(())
     }
 
+    #[cfg(feature = "datafusion")]
     pub async fn load(features: &str, ctx: &SessionContext) -> std::io::Result<FeatureIndex> {
         log::info!("loading index '{}'", features);
 
         let mut names: Vec<String> = Vec::new();
-        let mut sequences: Vec<String> = Vec::new();
         let mut mags: Vec<f64> = Vec::new();
         let mut kmers: HashMap<u64, Vec<(u32, u32)>> = HashMap::new();
         let mut k: usize = 0;
+        let mut w: usize = 0;
+        let mut ambiguity = Ambiguity::Break;
+        let mut renamed = 0;
 
+        // The sequences aren't needed for ranking, so aren't read.
         let options = ParquetReadOptions::default().skip_metadata(false);
         let df = ctx
             .read_parquet(&format!("{}-names.parquet", features), options)
             .await?;
+        let genome = df.schema().has_column_with_unqualified_name("chrom");
+        let df = if genome {
+            df.select_columns(&["name", "mags", "chrom", "start", "end"])?
+        } else {
+            df.select_columns(&["name", "mags"])?
+        };
         let batches = df.collect().await?;
+        let mut loci: Option<Vec<Locus>> = if genome { Some(Vec::new()) } else { None };
 
         for recs in batches {
             let name_col = recs
@@ -199,24 +287,41 @@ impl FeatureIndex {
                 .as_any()
                 .downcast_ref::<StringViewArray>()
                 .unwrap();
-            let sequence_col = recs
-                .column(1)
-                .as_any()
-                .downcast_ref::<StringViewArray>()
-                .unwrap();
             let mags_col = recs
-                .column(2)
+                .column(1)
                 .as_any()
                 .downcast_ref::<Float64Array>()
                 .unwrap();
-            for (name, (sequence, mag)) in zip(name_col, zip(sequence_col, mags_col)) {
+            for (name, mag) in zip(name_col, mags_col) {
                 let name = name.unwrap();
-                let sequence = sequence.unwrap();
                 let mag = mag.unwrap();
                 names.push(name.to_string());
-                sequences.push(sequence.to_string());
                 mags.push(mag);
             }
+            if let Some(loci) = loci.as_mut() {
+                let chrom_col = recs
+                    .column(2)
+                    .as_any()
+                    .downcast_ref::<StringViewArray>()
+                    .unwrap();
+                let start_col = recs
+                    .column(3)
+                    .as_any()
+                    .downcast_ref::<UInt64Array>()
+                    .unwrap();
+                let end_col = recs
+                    .column(4)
+                    .as_any()
+                    .downcast_ref::<UInt64Array>()
+                    .unwrap();
+                for (chrom, (start, end)) in zip(chrom_col, zip(start_col, end_col)) {
+                    loci.push(Locus {
+                        chrom: chrom.unwrap().to_string(),
+                        start: start.unwrap(),
+                        end: end.unwrap(),
+                    });
+                }
+            }
         }
 
         let options = ParquetReadOptions::default().skip_metadata(false);
@@ -232,8 +337,65 @@ impl FeatureIndex {
                     .metadata()
                     .get("k")
                     .map(|s| s.to_string())
-                    .unwrap();
-                k = k_str.parse().unwrap();
+                    .ok_or_else(|| as_io_error(SveltError::MissingK(features.to_string())))?;
+                k = k_str.parse().map_err(|_| {
+                    as_io_error(SveltError::UnsupportedK(
+                        features.to_string(),
+                        k_str.clone(),
+                    ))
+                })?;
+                check_k(k, INDEX_K).map_err(|msg| {
+                    as_io_error(SveltError::UnsupportedK(features.to_string(), msg))
+                })?;
+
+                let meta = recs.schema().metadata().clone();
+                match meta.get("version") {
+                    None => {
+                        log::warn!(
+                            "index '{}' was written by an older version of svelt, and support for it is deprecated; upgrade it with `svelt index-upgrade`",
+                            features
+                        );
+                    }
+                    Some(version) => {
+                        if version.parse::<u32>().map_or(true, |v| v > INDEX_VERSION) {
+                            return Err(as_io_error(SveltError::UnsupportedIndexVersion(
+                                features.to_string(),
+                                version.to_string(),
+                            )));
+                        }
+                    }
+                }
+
+                // Indexes written before minimizers were supported have no
+                // scheme, and contain every k-mer.
+                match meta.get("scheme").map(|s| s.as_str()) {
+                    None | Some("all") => {}
+                    Some("minimizer") => {
+                        w = meta.get("w").and_then(|s| s.parse().ok()).unwrap_or(0);
+                    }
+                    Some(other) => {
+                        return Err(as_io_error(SveltError::UnknownKmerScheme(
+                            features.to_string(),
+                            other.to_string(),
+                        )));
+                    }
+                }
+
+                // Likewise, indexes written before ambiguity handling was
+                // configurable broke k-mers at ambiguity codes.
+                if let Some(name) = meta.get("ambiguity") {
+                    ambiguity = Ambiguity::from_name(name).ok_or_else(|| {
+                        as_io_error(SveltError::UnknownAmbiguityMode(
+                            features.to_string(),
+                            name.to_string(),
+                        ))
+                    })?;
+                }
+
+                renamed = meta
+                    .get("renamed")
+                    .and_then(|s| s.parse().ok())
+                    .unwrap_or(0);
             }
             let kmer_col = recs
                 .column(0)
@@ -262,45 +424,181 @@ impl FeatureIndex {
         let kmers: Vec<(u64, Vec<(u32, u32)>)> = kmers.into_iter().collect();
         let kmers = vector::MergeVector::new(k, kmers);
 
-        log::info!("loading index done.");
+        log::info!(
+            "loading index done (k = {}, scheme = {}, ambiguity = {}).",
+            k,
+            scheme_name(w),
+            ambiguity.name()
+        );
+        warn_duplicate_names(&names, features);
 
         Ok(FeatureIndex {
             k,
+            w,
+            ambiguity,
             kmers,
             names,
-            sequences,
+            sequences: None,
             mags,
+            source: Some(features.to_string()),
+            loci,
+            renamed,
         })
     }
 
+    /// Rewrite an index (e.g. one written by an older version of svelt) in
+    /// the current layout, keeping its sequences, if it has them.
+    #[cfg(feature = "datafusion")]
+    pub async fn upgrade(
+        features: &str,
+        out: &str,
+        ctx: &SessionContext,
+    ) -> std::io::Result<FeatureIndex> {
+        let mut idx = FeatureIndex::load(features, ctx).await?;
+
+        let options = ParquetReadOptions::default();
+        let df = ctx
+            .read_parquet(&format!("{}-names.parquet", features), options)
+            .await?;
+        if df.schema().has_column_with_unqualified_name("sequence") {
+            idx.load_sequences(ctx).await?;
+        }
+
+        log::info!("writing index '{}' as version {}", out, INDEX_VERSION);
+        idx.save(out, ctx).await?;
+        Ok(idx)
+    }
+
+    /// Load the feature sequences, if they haven't been already.
+    #[cfg(feature = "datafusion")]
+    pub async fn load_sequences(&mut self, ctx: &SessionContext) -> std::io::Result<()> {
+        if self.sequences.is_some() {
+            return Ok(());
+        }
+        let Some(features) = self.source.clone() else {
+            return Ok(());
+        };
+        log::info!("loading sequences for index '{}'", features);
+
+        let options = ParquetReadOptions::default();
+        let df = ctx
+            .read_parquet(&format!("{}-names.parquet", features), options)
+            .await?;
+        if !df.schema().has_column_with_unqualified_name("sequence") {
+            return Err(as_io_error(SveltError::IndexWithoutSequences(features)));
+        }
+        let batches = df.select_columns(&["sequence"])?.collect().await?;
+
+        let mut sequences: Vec<String> = Vec::with_capacity(self.names.len());
+        for recs in batches {
+            let sequence_col = recs
+                .column(0)
+                .as_any()
+                .downcast_ref::<StringViewArray>()
+                .unwrap();
+            for sequence in sequence_col {
+                sequences.push(sequence.unwrap().to_string());
+            }
+        }
+        self.sequences = Some(sequences);
+        Ok(())
+    }
+
+    /// The name of a feature (or, for a genome window, its position).
+    pub fn name(&self, nix: u32) -> &str {
+        &self.names[nix as usize]
+    }
+
+    /// The number of features renamed when the index was built, as their
+    /// names duplicated an earlier feature's (see `--duplicate-names`).
+    pub fn renamed(&self) -> usize {
+        self.renamed
+    }
+
+    /// The sequence of a feature, if the sequences have been loaded.
+    pub fn sequence(&self, nix: u32) -> Option<&str> {
+        self.sequences
+            .as_ref()
+            .map(|sequences| sequences[nix as usize].as_str())
+    }
+
+    /// The position of a genome window, if this is an index of a reference genome.
+    pub fn locus(&self, nix: u32) -> Option<&Locus> {
+        self.loci.as_ref().map(|loci| &loci[nix as usize])
+    }
+
     pub fn k(&self) -> usize {
         self.k
     }
 
-    pub fn rank(&self, query: &str) -> (Vec<(u32, f64)>, Vec<(u32, f64)>) {
+    /// The minimizer window used for the index (0 if every k-mer is indexed).
+    pub fn w(&self) -> usize {
+        self.w
+    }
+
+    /// How ambiguity codes are treated, for both the index and queries.
+    pub fn ambiguity(&self) -> Ambiguity {
+        self.ambiguity
+    }
+
+    /// The name of the k-mer sampling scheme, as recorded in the index metadata.
+    pub fn scheme(&self) -> &'static str {
+        scheme_name(self.w)
+    }
+
+    /// The cosine similarity of the k-mers of `query` (forwards, and reverse
+    /// complemented) with each feature sharing any k-mer with it.
+    pub fn rank(&self, query: &str) -> (Scores, Scores) {
+        let (fwd, rev) = self.query_kmers(query);
+        (self.rank_inner(fwd), self.rank_inner(rev))
+    }
+
+    /// As `rank`, but only giving the features scoring at least `min_score`.
+    ///
+    /// The query's k-mers are taken rarest first, and once those remaining
+    /// couldn't lift a feature to `min_score` (by Cauchy-Schwarz, they add at
+    /// most the magnitude of the rest of the query, relative to the whole, to
+    /// any score) no new features are considered, and the features already
+    /// seen are dropped as soon as they fall out of reach. So ubiquitous k-mers,
+    /// as in low complexity queries, are only looked up for the few features
+    /// still in contention.
+    pub fn rank_above(&self, query: &str, min_score: f64) -> (Scores, Scores) {
+        let (fwd, rev) = self.query_kmers(query);
+        (
+            self.rank_pruned(fwd, min_score),
+            self.rank_pruned(rev, min_score),
+        )
+    }
+
+    /// The distinct (sampled) k-mers of `query` with their counts, forwards
+    /// and reverse complemented.
+    fn query_kmers(&self, query: &str) -> (KmerCounts, KmerCounts) {
         let mut fwd: Vec<u64> = Vec::new();
         fwd.reserve(query.len());
         let mut rev: Vec<u64> = Vec::new();
         rev.reserve(query.len());
 
-        for (x, y) in KmerIterator::new(self.k, query.as_bytes().iter()) {
+        // Queries must treat ambiguity codes in the same way as the index.
+        for (x, y) in KmerIterator::with_ambiguity(self.k, self.ambiguity, query.as_bytes().iter()) {
             fwd.push(x.0);
             rev.push(y.0);
         }
 
+        // Queries must be sampled in the same way as the index.
+        let mut fwd = sample_kmers(fwd, self.w);
+        let mut rev = sample_kmers(rev, self.w);
+
         fwd.sort();
         let fwd: Vec<(u64, u32)> = fwd
             .chunk_by(|x, y| x == y)
             .map(|xs| (xs[0], xs.len() as u32))
             .collect();
-        let fwd = self.rank_inner(fwd);
 
         rev.sort();
         let rev: Vec<(u64, u32)> = rev
             .chunk_by(|x, y| x == y)
             .map(|xs| (xs[0], xs.len() as u32))
             .collect();
-        let rev = self.rank_inner(rev);
         (fwd, rev)
     }
 
@@ -329,8 +627,321 @@ impl FeatureIndex {
 
         d
     }
+
+    fn rank_pruned(&self, kmers: Vec<(u64, u32)>, min_score: f64) -> Vec<(u32, f64)> {
+        let mut q_mag = 0;
+        let mut hits: Vec<(u32, &[(u32, u32)])> = Vec::new();
+        let mut iter = self.kmers.iter();
+        for (x, count) in kmers {
+            q_mag += count * count;
+            iter.seek(x);
+            if let Some((x0, postings)) = iter.here()
+                && x == x0
+            {
+                hits.push((count, postings));
+            }
+        }
+        let q_mag = (q_mag as f64).sqrt();
+        // Rarest first. The order only decides what is pruned when, not the
+        // scores, which are sums of integers.
+        hits.sort_unstable_by_key(|(_, postings)| postings.len());
+
+        // The most the k-mers yet to be added could add to any score, given
+        // their squared magnitude. Rounding is allowed for, so nothing `rank`
+        // would score at `min_score` is dropped.
+        let mut rest: u32 = hits.iter().map(|(count, _)| count * count).sum();
+        let reach = |rest: u32| (rest as f64).sqrt() / q_mag + 1e-9;
+
+        let mut d: Vec<u32> = vec![0; self.names.len()];
+        let mut candidates: Vec<u32> = Vec::new();
+        let mut hits = hits.into_iter().peekable();
+
+        // While the rest of the query could make a feature score highly on
+        // its own, every feature hit is a candidate.
+        while let Some((count, postings)) = hits.next_if(|_| reach(rest) >= min_score) {
+            for (nix, hit_count) in postings.iter() {
+                if d[*nix as usize] == 0 {
+                    candidates.push(*nix);
+                }
+                d[*nix as usize] += count * *hit_count;
+            }
+            rest -= count * count;
+        }
+
+        // Then only the candidates still within reach are kept up to date,
+        // looking each up in the (feature ordered) postings when there are
+        // few enough of them. Checking which are within reach only pays for
+        // itself ahead of k-mers with more postings than there are candidates.
+        let mut alive = vec![false; self.names.len()];
+        for nix in candidates.iter() {
+            alive[*nix as usize] = true;
+        }
+        for (count, postings) in hits {
+            if postings.len() > candidates.len() {
+                let bound = reach(rest);
+                candidates.retain(|nix| {
+                    let i = *nix as usize;
+                    alive[i] = (d[i] as f64) / (q_mag * self.mags[i]) + bound >= min_score;
+                    alive[i]
+                });
+                if candidates.is_empty() {
+                    break;
+                }
+            }
+            let log_len = (usize::BITS - postings.len().leading_zeros()) as usize;
+            if candidates.len() * log_len < postings.len() {
+                for nix in candidates.iter() {
+                    if let Ok(j) = postings.binary_search_by_key(nix, |(nix, _)| *nix) {
+                        d[*nix as usize] += count * postings[j].1;
+                    }
+                }
+            } else {
+                for (nix, hit_count) in postings.iter() {
+                    if alive[*nix as usize] {
+                        d[*nix as usize] += count * *hit_count;
+                    }
+                }
+            }
+            rest -= count * count;
+        }
+
+        candidates.sort_unstable();
+        candidates
+            .into_iter()
+            .map(|nix| {
+                let i = nix as usize;
+                (nix, (d[i] as f64) / (q_mag * self.mags[i]))
+            })
+            .filter(|(_, score)| *score >= min_score)
+            .collect()
+    }
 }
 
+/// The features matching a query, with their scores.
+pub type Scores = Vec<(u32, f64)>;
+
+/// The distinct k-mers of a sequence, with their counts.
+type KmerCounts = Vec<(u64, u32)>;
+
+/// Accumulates the k-mers of features (or genome windows) as they are read.
+#[cfg(feature = "datafusion")]
+struct IndexBuilder {
+    k: usize,
+    w: usize,
+    ambiguity: Ambiguity,
+    names: Vec<String>,
+    sequences: Option<Vec<String>>,
+    mags: Vec<f64>,
+    loci: Option<Vec<Locus>>,
+    kmers: HashMap<u64, Vec<(u32, u32)>>,
+    ambiguous_sequences: usize,
+    ambiguous_bases: usize,
+    duplicate_names: DuplicateNames,
+    seen: HashSet<String>,
+    renamed: usize,
+}
+
+#[cfg(feature = "datafusion")]
+impl IndexBuilder {
+    fn new(options: &IndexingOptions) -> IndexBuilder {
+        IndexBuilder {
+            k: options.k,
+            w: options.minimizer_window,
+            ambiguity: options.ambiguity,
+            names: Vec::new(),
+            sequences: if options.omit_sequences {
+                None
+            } else {
+                Some(Vec::new())
+            },
+            mags: Vec::new(),
+            loci: if options.genome_window > 0 {
+                Some(Vec::new())
+            } else {
+                None
+            },
+            kmers: HashMap::new(),
+            ambiguous_sequences: 0,
+            ambiguous_bases: 0,
+            duplicate_names: options.duplicate_names,
+            seen: HashSet::new(),
+            renamed: 0,
+        }
+    }
+
+    /// Add a batch of sequences, counting their k-mers in parallel (but
+    /// adding them in order, so the index doesn't depend on the threads).
+    fn add_batch(
+        &mut self,
+        batch: Vec<(String, String, Option<Locus>)>,
+        pool: &WorkerPool,
+    ) -> std::io::Result<()> {
+        let (k, w, ambiguity) = (self.k, self.w, self.ambiguity);
+        let counts: Vec<HashMap<u64, u32>> = pool.install(|| {
+            batch
+                .par_iter()
+                .map(|(_, sequence, _)| count_kmers(k, w, ambiguity, sequence))
+                .collect()
+        });
+        for ((name, sequence, locus), counts) in batch.into_iter().zip(counts) {
+            self.add(name, sequence, locus, counts)?;
+        }
+        Ok(())
+    }
+
+    fn add(
+        &mut self,
+        name: String,
+        sequence: String,
+        locus: Option<Locus>,
+        tmp: HashMap<u64, u32>,
+    ) -> std::io::Result<()> {
+        let nix = self.names.len() as u32;
+
+        if self.ambiguity == Ambiguity::Report {
+            let count = Kmer::count_ambiguous(&sequence);
+            if count > 0 {
+                // Genomes have many runs of Ns, so windows aren't reported individually.
+                if locus.is_none() {
+                    log::warn!("feature '{}' has {} ambiguity codes", name, count);
+                }
+                self.ambiguous_sequences += 1;
+                self.ambiguous_bases += count;
+            }
+        }
+
+        // Genome windows with no k-mers (e.g. in gaps) can never match.
+        if tmp.is_empty() && locus.is_some() {
+            return Ok(());
+        }
+
+        let name = if self.seen.contains(&name) {
+            if self.duplicate_names == DuplicateNames::Error {
+                return Err(as_io_error(SveltError::DuplicateFeatureName(name)));
+            }
+            let unique = (2..)
+                .map(|i| format!("{}#{}", name, i))
+                .find(|unique| !self.seen.contains(unique))
+                .unwrap();
+            log::warn!(
+                "feature name '{}' occurs more than once, so this one is named '{}'",
+                name,
+                unique
+            );
+            self.renamed += 1;
+            unique
+        } else {
+            name
+        };
+        self.seen.insert(name.clone());
+
+        let mut mag = 0;
+        for (x, count) in tmp.into_iter() {
+            mag += count * count;
+            self.kmers.entry(x).or_default().push((nix, count));
+        }
+
+        self.names.push(name);
+        if let Some(sequences) = self.sequences.as_mut() {
+            sequences.push(sequence);
+        }
+        if let (Some(loci), Some(locus)) = (self.loci.as_mut(), locus) {
+            loci.push(locus);
+        }
+        self.mags.push((mag as f64).sqrt());
+        Ok(())
+    }
+
+    fn finish(self) -> FeatureIndex {
+        let kmers: Vec<(u64, Vec<(u32, u32)>)> = self.kmers.into_iter().collect();
+        let kmers = vector::MergeVector::new(self.k, kmers);
+
+        if self.ambiguous_sequences > 0 {
+            log::warn!(
+                "{} ambiguity codes found in {} {}",
+                self.ambiguous_bases,
+                self.ambiguous_sequences,
+                if self.loci.is_some() {
+                    "genome windows"
+                } else {
+                    "feature sequences"
+                }
+            );
+        }
+
+        log::info!("index construction complete");
+
+        FeatureIndex {
+            k: self.k,
+            w: self.w,
+            ambiguity: self.ambiguity,
+            kmers,
+            names: self.names,
+            sequences: self.sequences,
+            mags: self.mags,
+            source: None,
+            loci: self.loci,
+            renamed: self.renamed,
+        }
+    }
+}
+
+/// The number of times each (sampled) k-mer occurs in a sequence.
+#[cfg(feature = "datafusion")]
+fn count_kmers(k: usize, w: usize, ambiguity: Ambiguity, sequence: &str) -> HashMap<u64, u32> {
+    let fwd: Vec<u64> = KmerIterator::with_ambiguity(k, ambiguity, sequence.as_bytes().iter())
+        .map(|(x, _)| x.0)
+        .collect();
+    let fwd = sample_kmers(fwd, w);
+
+    let mut res: HashMap<u64, u32> = HashMap::new();
+    for x in fwd {
+        *res.entry(x).or_default() += 1;
+    }
+    res
+}
+
+/// The windows (0-based, half open) of a sequence of length `len`, starting
+/// every `step` bases. The last window finishes at the end of the sequence.
+#[cfg(feature = "datafusion")]
+fn genome_windows(len: usize, window: usize, step: usize) -> Vec<(usize, usize)> {
+    let mut res = Vec::new();
+    let mut begin = 0;
+    while begin < len {
+        let end = (begin + window).min(len);
+        res.push((begin, end));
+        if end == len {
+            break;
+        }
+        begin += step;
+    }
+    res
+}
+
+/// Warn if an index (e.g. one built before duplicate names were dealt with)
+/// has features with the same name, as classifications by them are ambiguous.
+fn warn_duplicate_names(names: &[String], source: &str) {
+    let mut seen = std::collections::HashSet::new();
+    let duplicates: Vec<&str> = names
+        .iter()
+        .filter(|name| !seen.insert(name.as_str()))
+        .map(|name| name.as_str())
+        .collect();
+    if let Some(first) = duplicates.first() {
+        log::warn!(
+            "index '{}' has {} features whose names duplicate another's (e.g. '{}'), so classifications by them are ambiguous; rebuild it with `svelt index-features` to make the names unique",
+            source,
+            duplicates.len(),
+            first
+        );
+    }
+}
+
+fn scheme_name(w: usize) -> &'static str {
+    if w == 0 { "all" } else { "minimizer" }
+}
+
+#[cfg(feature = "datafusion")]
 async fn save_record_batch(
     recs: RecordBatch,
     path: &str,
@@ -346,3 +957,81 @@ async fn save_record_batch(
         .await?;
     Ok(())
 }
+
+#[cfg(all(test, feature = "datafusion"))]
+mod tests {
+    use rand::{Rng, SeedableRng, rngs::StdRng};
+
+    use super::*;
+
+    fn random_sequence(rng: &mut StdRng, len: usize) -> String {
+        (0..len)
+            .map(|_| ['A', 'C', 'G', 'T'][rng.random_range(0..4)])
+            .collect()
+    }
+
+    #[test]
+    fn test_rank_above() {
+        let mut rng = StdRng::seed_from_u64(1038);
+        let options = IndexingOptions::default();
+        let mut builder = IndexBuilder::new(&options);
+        let mut features = Vec::new();
+        for i in 0..200 {
+            // Some features end in a poly-A tail, or are interrupted by a
+            // simple repeat, as many repeat elements are.
+            let len = rng.random_range(50..400);
+            let mut sequence = random_sequence(&mut rng, len);
+            match i % 3 {
+                0 => sequence.push_str(&"A".repeat(rng.random_range(10..40))),
+                1 => sequence.insert_str(sequence.len() / 2, &"CA".repeat(20)),
+                _ => {}
+            }
+            let counts = count_kmers(options.k, 0, options.ambiguity, &sequence);
+            builder
+                .add(format!("f{}", i), sequence.clone(), None, counts)
+                .unwrap();
+            features.push(sequence);
+        }
+        let idx = builder.finish();
+
+        let mut queries = vec!["A".repeat(100), "CA".repeat(50), "AAAAAAAAAAAAG".repeat(8)];
+        for _ in 0..50 {
+            let feature = &features[rng.random_range(0..features.len())];
+            let begin = rng.random_range(0..feature.len() / 2);
+            let end = rng.random_range(begin + 20..=feature.len());
+            let mut query: Vec<char> = feature[begin..end].chars().collect();
+            for _ in 0..rng.random_range(0..5) {
+                let j = rng.random_range(0..query.len());
+                query[j] = ['A', 'C', 'G', 'T'][rng.random_range(0..4)];
+            }
+            queries.push(query.into_iter().collect());
+        }
+
+        for query in queries.iter() {
+            let (fwd, rev) = idx.rank(query);
+            for min_score in [0.0, 0.1, 0.5, 0.8] {
+                let above = |scores: &Vec<(u32, f64)>| -> Vec<(u32, f64)> {
+                    scores
+                        .iter()
+                        .copied()
+                        .filter(|(_, score)| *score >= min_score)
+                        .collect()
+                };
+                let (pruned_fwd, pruned_rev) = idx.rank_above(query, min_score);
+                assert_eq!(pruned_fwd, above(&fwd));
+                assert_eq!(pruned_rev, above(&rev));
+            }
+        }
+    }
+
+    #[test]
+    fn test_genome_windows() {
+        assert_eq!(
+            genome_windows(10, 4, 2),
+            vec![(0, 4), (2, 6), (4, 8), (6, 10)]
+        );
+        assert_eq!(genome_windows(11, 4, 4), vec![(0, 4), (4, 8), (8, 11)]);
+        assert_eq!(genome_windows(3, 4, 2), vec![(0, 3)]);
+        assert_eq!(genome_windows(0, 4, 2), vec![]);
+    }
+}
@@ -0,0 +1,274 @@
+//! A self-contained serialization of a `FeatureIndex`, which can be written
+//! and read without DataFusion (or an async runtime), so that lightweight
+//! consumers can classify sequences with an index built by `index-features`.
+//!
+//! The file is a little-endian binary format: a magic number and version,
+//! the parameters of the index, the features (name, magnitude, and optionally
+//! sequence and locus), and finally the k-mers with their postings.
+
+use std::{
+    fs::File,
+    io::{BufReader, BufWriter, Read, Write},
+};
+
+use crate::{
+    errors::{SveltError, as_io_error, wrap_file_error},
+    kmers::{Ambiguity, INDEX_K, check_k},
+};
+
+use super::{FeatureIndex, Locus, scheme_name, vector, warn_duplicate_names};
+
+const MAGIC: &[u8; 8] = b"SVELTIDX";
+const VERSION: u32 = 1;
+
+const HAS_SEQUENCES: u8 = 1;
+const HAS_LOCI: u8 = 2;
+
+impl FeatureIndex {
+    /// Write the index as a single portable file.
+    pub fn save_portable(&self, out: &str) -> std::io::Result<()> {
+        log::info!("saving portable index '{}'", out);
+        let file = File::create(out).map_err(|e| wrap_file_error(e, out))?;
+        let mut writer = BufWriter::new(file);
+        self.write_portable(&mut writer)
+            .and_then(|_| writer.flush())
+            .map_err(|e| wrap_file_error(e, out))
+    }
+
+    /// Load an index written by `save_portable`.
+    pub fn load_portable(features: &str) -> std::io::Result<FeatureIndex> {
+        log::info!("loading portable index '{}'", features);
+        let file = File::open(features).map_err(|e| wrap_file_error(e, features))?;
+        let mut reader = BufReader::new(file);
+        let mut idx = FeatureIndex::read_portable(&mut reader, features)?;
+        idx.source = Some(features.to_string());
+        Ok(idx)
+    }
+
+    pub fn write_portable<W: Write>(&self, writer: &mut W) -> std::io::Result<()> {
+        writer.write_all(MAGIC)?;
+        write_u32(writer, VERSION)?;
+        write_u32(writer, self.k as u32)?;
+        write_u32(writer, self.w as u32)?;
+        write_str(writer, self.ambiguity.name())?;
+
+        let mut flags = 0;
+        if self.sequences.is_some() {
+            flags |= HAS_SEQUENCES;
+        }
+        if self.loci.is_some() {
+            flags |= HAS_LOCI;
+        }
+        writer.write_all(&[flags])?;
+
+        write_u32(writer, self.names.len() as u32)?;
+        for (nix, name) in self.names.iter().enumerate() {
+            write_str(writer, name)?;
+            writer.write_all(&self.mags[nix].to_le_bytes())?;
+            if let Some(sequences) = &self.sequences {
+                write_str(writer, &sequences[nix])?;
+            }
+            if let Some(loci) = &self.loci {
+                let locus = &loci[nix];
+                write_str(writer, &locus.chrom)?;
+                write_u64(writer, locus.start)?;
+                write_u64(writer, locus.end)?;
+            }
+        }
+
+        write_u64(writer, self.kmers.iter().count() as u64)?;
+        for (x, hits) in self.kmers.iter() {
+            write_u64(writer, x)?;
+            write_u32(writer, hits.len() as u32)?;
+            for (nix, count) in hits.iter() {
+                write_u32(writer, *nix)?;
+                write_u32(writer, *count)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Read an index written by `write_portable`. The name is used for error
+    /// messages.
+    pub fn read_portable<R: Read>(reader: &mut R, name: &str) -> std::io::Result<FeatureIndex> {
+        let bad =
+            |reason: String| as_io_error(SveltError::BadPortableIndex(name.to_string(), reason));
+
+        let mut magic = [0; 8];
+        reader.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            return Err(bad(String::from("bad magic number")));
+        }
+        let version = read_u32(reader)?;
+        if version != VERSION {
+            return Err(bad(format!("unsupported version {}", version)));
+        }
+        let k = read_u32(reader)? as usize;
+        check_k(k, INDEX_K)
+            .map_err(|msg| as_io_error(SveltError::UnsupportedK(name.to_string(), msg)))?;
+        let w = read_u32(reader)? as usize;
+        let ambiguity = read_str(reader)?;
+        let ambiguity = Ambiguity::from_name(&ambiguity).ok_or_else(|| {
+            as_io_error(SveltError::UnknownAmbiguityMode(
+                name.to_string(),
+                ambiguity.to_string(),
+            ))
+        })?;
+        let flags = read_u8(reader)?;
+
+        let n = read_u32(reader)? as usize;
+        let mut names = Vec::with_capacity(n);
+        let mut mags = Vec::with_capacity(n);
+        let mut sequences = (flags & HAS_SEQUENCES != 0).then(|| Vec::with_capacity(n));
+        let mut loci = (flags & HAS_LOCI != 0).then(|| Vec::with_capacity(n));
+        for _ in 0..n {
+            names.push(read_str(reader)?);
+            mags.push(f64::from_le_bytes(read_array(reader)?));
+            if let Some(sequences) = sequences.as_mut() {
+                sequences.push(read_str(reader)?);
+            }
+            if let Some(loci) = loci.as_mut() {
+                let chrom = read_str(reader)?;
+                let start = read_u64(reader)?;
+                let end = read_u64(reader)?;
+                loci.push(Locus { chrom, start, end });
+            }
+        }
+
+        let m = read_u64(reader)? as usize;
+        let mut kmers: Vec<(u64, Vec<(u32, u32)>)> = Vec::with_capacity(m);
+        for _ in 0..m {
+            let x = read_u64(reader)?;
+            let h = read_u32(reader)? as usize;
+            let mut hits = Vec::with_capacity(h);
+            for _ in 0..h {
+                let nix = read_u32(reader)?;
+                if nix as usize >= n {
+                    return Err(bad(format!("k-mer refers to feature {} of {}", nix, n)));
+                }
+                let count = read_u32(reader)?;
+                hits.push((nix, count));
+            }
+            kmers.push((x, hits));
+        }
+        let kmers = vector::MergeVector::new(k, kmers);
+
+        log::info!(
+            "loading index done (k = {}, scheme = {}, ambiguity = {}).",
+            k,
+            scheme_name(w),
+            ambiguity.name()
+        );
+        warn_duplicate_names(&names, name);
+
+        Ok(FeatureIndex {
+            k,
+            w,
+            ambiguity,
+            kmers,
+            names,
+            sequences,
+            mags,
+            source: None,
+            loci,
+            renamed: 0,
+        })
+    }
+}
+
+fn write_u32<W: Write>(writer: &mut W, x: u32) -> std::io::Result<()> {
+    writer.write_all(&x.to_le_bytes())
+}
+
+fn write_u64<W: Write>(writer: &mut W, x: u64) -> std::io::Result<()> {
+    writer.write_all(&x.to_le_bytes())
+}
+
+fn write_str<W: Write>(writer: &mut W, s: &str) -> std::io::Result<()> {
+    write_u32(writer, s.len() as u32)?;
+    writer.write_all(s.as_bytes())
+}
+
+fn read_array<R: Read, const N: usize>(reader: &mut R) -> std::io::Result<[u8; N]> {
+    let mut buf = [0; N];
+    reader.read_exact(&mut buf)?;
+    Ok(buf)
+}
+
+fn read_u8<R: Read>(reader: &mut R) -> std::io::Result<u8> {
+    Ok(read_array::<R, 1>(reader)?[0])
+}
+
+fn read_u32<R: Read>(reader: &mut R) -> std::io::Result<u32> {
+    Ok(u32::from_le_bytes(read_array(reader)?))
+}
+
+fn read_u64<R: Read>(reader: &mut R) -> std::io::Result<u64> {
+    Ok(u64::from_le_bytes(read_array(reader)?))
+}
+
+fn read_str<R: Read>(reader: &mut R) -> std::io::Result<String> {
+    let n = read_u32(reader)? as usize;
+    let mut buf = vec![0; n];
+    reader.read_exact(&mut buf)?;
+    String::from_utf8(buf).map_err(std::io::Error::other)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn small_index() -> FeatureIndex {
+        let kmers = vec![(3, vec![(0, 2), (1, 1)]), (17, vec![(1, 3)])];
+        FeatureIndex {
+            k: 4,
+            w: 0,
+            ambiguity: Ambiguity::Canonical,
+            kmers: vector::MergeVector::new(4, kmers),
+            names: vec![String::from("chr1:1-8"), String::from("chr1:5-12")],
+            sequences: Some(vec![String::from("ACGTACGT"), String::from("ACGTTTTT")]),
+            mags: vec![2.0, 10f64.sqrt()],
+            source: None,
+            renamed: 0,
+            loci: Some(vec![
+                Locus {
+                    chrom: String::from("chr1"),
+                    start: 1,
+                    end: 8,
+                },
+                Locus {
+                    chrom: String::from("chr1"),
+                    start: 5,
+                    end: 12,
+                },
+            ]),
+        }
+    }
+
+    #[test]
+    fn test_portable_round_trip() {
+        let idx = small_index();
+        let mut buf = Vec::new();
+        idx.write_portable(&mut buf).unwrap();
+
+        let res = FeatureIndex::read_portable(&mut buf.as_slice(), "test").unwrap();
+        assert_eq!(res.k(), 4);
+        assert_eq!(res.w(), 0);
+        assert_eq!(res.ambiguity(), Ambiguity::Canonical);
+        assert_eq!(res.names, idx.names);
+        assert_eq!(res.mags, idx.mags);
+        assert_eq!(res.sequence(1), Some("ACGTTTTT"));
+        assert_eq!(res.locus(1), idx.locus(1));
+        let kmers: Vec<(u64, Vec<(u32, u32)>)> = res
+            .kmers
+            .iter()
+            .map(|(x, hits)| (x, hits.to_vec()))
+            .collect();
+        assert_eq!(kmers, vec![(3, vec![(0, 2), (1, 1)]), (17, vec![(1, 3)])]);
+
+        buf[0] = b'X';
+        let res = FeatureIndex::read_portable(&mut buf.as_slice(), "test");
+        assert!(res.is_err());
+    }
+}
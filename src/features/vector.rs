@@ -23,6 +23,9 @@ impl MergeVector {
 
         toc.push(0);
         for (i, (kmer, mut hits)) in items.into_iter().enumerate() {
+            // The postings of each k-mer are kept in feature order, so that
+            // ranking can look features up in them.
+            hits.sort_unstable();
             kmers.push(kmer);
             index[1 + (kmer >> shift) as usize] = i;
             postings.append(&mut hits);
@@ -104,7 +107,7 @@ impl<'a> MergeVectorCursor<'a> {
         self.i = first;
     }
 
-    pub fn here(&self) -> Option<(u64, &[(u32, u32)])> {
+    pub fn here(&self) -> Option<(u64, &'a [(u32, u32)])> {
         if self.i < self.kmers.len() {
             let begin = self.toc[self.i];
             let end = self.toc[self.i + 1];
@@ -123,10 +126,39 @@ impl<'a> Iterator for MergeVectorCursor<'a> {
             let i = self.i;
             self.i += 1;
             let begin = self.toc[i];
-            let end = self.toc[i];
+            let end = self.toc[i + 1];
             Some((self.kmers[i], &self.postings[begin..end]))
         } else {
             None
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_iter_postings() {
+        let vector = MergeVector::new(
+            3,
+            vec![
+                (7, vec![(1, 2)]),
+                (2, vec![(0, 1), (3, 1)]),
+                (40, vec![(2, 5)]),
+            ],
+        );
+        let items: Vec<(u64, Vec<(u32, u32)>)> = vector
+            .iter()
+            .map(|(kmer, postings)| (kmer, postings.to_vec()))
+            .collect();
+        assert_eq!(
+            items,
+            vec![
+                (2, vec![(0, 1), (3, 1)]),
+                (7, vec![(1, 2)]),
+                (40, vec![(2, 5)]),
+            ]
+        );
+    }
+}
@@ -0,0 +1,105 @@
+use std::{
+    collections::{HashMap, HashSet},
+    io::{BufRead, BufReader},
+};
+
+use autocompress::autodetect_open;
+
+use crate::errors::{Context, FileContext, SveltError, as_io_error};
+
+/// The records which must be passed through to the output unmodified
+/// (`--force-keep`), rather than merged with others.
+///
+/// They are given either as a list of variant IDs, one per line, or as a BED
+/// file (if the name ends `.bed` or `.bed.gz`) of regions, in which case the
+/// records whose POS lies in a region are kept. Blank lines and lines starting
+/// with '#' are ignored, as are BED `track` and `browser` lines.
+#[derive(Debug, Default)]
+pub struct ForceKeep {
+    ids: HashSet<String>,
+    regions: HashMap<String, Vec<(usize, usize)>>,
+}
+
+impl ForceKeep {
+    pub fn load(path: &str) -> std::io::Result<ForceKeep> {
+        FileContext::new(path).with(|| {
+            let bed = path.ends_with(".bed") || path.ends_with(".bed.gz");
+            let reader = BufReader::new(autodetect_open(path)?);
+            let mut res = ForceKeep::default();
+            for line in reader.lines() {
+                let line = line?;
+                let line = line.trim();
+                if line.is_empty() || line.starts_with('#') {
+                    continue;
+                }
+                if !bed {
+                    res.ids.insert(String::from(line));
+                    continue;
+                }
+                if line.starts_with("track") || line.starts_with("browser") {
+                    continue;
+                }
+                let (chrom, begin, end) = parse_region(line)?;
+                res.regions.entry(chrom).or_default().push((begin, end));
+            }
+            Ok(res)
+        })
+    }
+
+    /// Whether the record with the given IDs, at `chrom`:`pos` (1-based), must
+    /// be kept.
+    pub fn keeps<'a, I: IntoIterator<Item = &'a str>>(
+        &self,
+        ids: I,
+        chrom: &str,
+        pos: usize,
+    ) -> bool {
+        if let Some(regions) = self.regions.get(chrom)
+            && regions
+                .iter()
+                .any(|&(begin, end)| begin < pos && pos <= end)
+        {
+            return true;
+        }
+        ids.into_iter().any(|id| self.ids.contains(id))
+    }
+}
+
+/// Parse a BED line, giving the (0-based, half open) region.
+pub(crate) fn parse_region(line: &str) -> std::io::Result<(String, usize, usize)> {
+    let bad = || as_io_error(SveltError::BadRegion(String::from(line)));
+    let fields: Vec<&str> = line.split('\t').collect();
+    if fields.len() < 3 {
+        return Err(bad());
+    }
+    let begin: usize = fields[1].trim().parse().map_err(|_| bad())?;
+    let end: usize = fields[2].trim().parse().map_err(|_| bad())?;
+    if end < begin {
+        return Err(bad());
+    }
+    Ok((String::from(fields[0]), begin, end))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_keeps() {
+        let mut keep = ForceKeep::default();
+        keep.ids.insert(String::from("pathogenic_1"));
+        let (chrom, begin, end) = parse_region("chr1\t99\t200").unwrap();
+        keep.regions.insert(chrom, vec![(begin, end)]);
+
+        assert!(keep.keeps(["x", "pathogenic_1"], "chr2", 5));
+        assert!(!keep.keeps(["x"], "chr1", 99));
+        assert!(keep.keeps(["x"], "chr1", 100));
+        assert!(keep.keeps(["x"], "chr1", 200));
+        assert!(!keep.keeps(["x"], "chr1", 201));
+        assert!(!keep.keeps(["x"], "chr2", 150));
+
+        assert!(parse_region("chr1\t10").is_err());
+        assert!(parse_region("chr1\tx\t20").is_err());
+        assert!(parse_region("chr1\t20\t10").is_err());
+    }
+}
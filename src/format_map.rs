@@ -0,0 +1,251 @@
+use noodles::vcf::{
+    Header,
+    header::record::value::{
+        Map,
+        map::{
+            Format,
+            format::{Number, Type},
+        },
+    },
+    variant::record_buf::samples::sample::{Value, value::Array},
+};
+
+use crate::errors::SveltError;
+
+/// The FORMAT fields of a sample, by key.
+pub type SampleFields = Vec<(String, Option<Value>)>;
+
+/// A FORMAT field of the output, made from the FORMAT fields of one caller's
+/// records (`--format-map CALLER:KEY=SOURCE`).
+///
+/// The source is either another key, whose values are copied as they are, or
+/// a sum of integer or float terms, each a key (`DV`) or an element of an
+/// array (`AD[1]`), e.g. `manta:DV=SR[1]+PR[1]`. The caller is as given with
+/// `--input-labels`, or `*` for every input.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FormatMapping {
+    caller: String,
+    key: String,
+    terms: Vec<(String, Option<usize>)>,
+    source: String,
+}
+
+impl FormatMapping {
+    pub fn parse(spec: &str) -> Result<FormatMapping, SveltError> {
+        let bad = || {
+            SveltError::BadOptionValue(
+                String::from("--format-map"),
+                format!("{} (expected CALLER:KEY=SOURCE)", spec),
+            )
+        };
+        let (caller, mapping) = spec.split_once(':').ok_or_else(bad)?;
+        let (key, source) = mapping.split_once('=').ok_or_else(bad)?;
+        if caller.is_empty() || !is_key(key) {
+            return Err(bad());
+        }
+        let mut terms = Vec::new();
+        for term in source.split('+') {
+            let term = match term.split_once('[') {
+                Some((name, index)) => {
+                    let index = index.strip_suffix(']').ok_or_else(bad)?;
+                    let index: usize = index.parse().map_err(|_| bad())?;
+                    (name, Some(index))
+                }
+                None => (term, None),
+            };
+            if !is_key(term.0) {
+                return Err(bad());
+            }
+            terms.push((String::from(term.0), term.1));
+        }
+        Ok(FormatMapping {
+            caller: String::from(caller),
+            key: String::from(key),
+            terms,
+            source: String::from(source),
+        })
+    }
+
+    fn applies_to(&self, caller: Option<&str>) -> bool {
+        self.caller == "*" || caller == Some(self.caller.as_str())
+    }
+
+    /// Is the source just another key, copied as it is?
+    fn is_copy(&self) -> bool {
+        self.terms.len() == 1 && self.terms[0].1.is_none()
+    }
+
+    fn value(&self, fields: &[(String, Option<Value>)]) -> Option<Value> {
+        let get = |name: &str| {
+            fields
+                .iter()
+                .find(|(key, _)| key == name)
+                .and_then(|(_, value)| value.as_ref())
+        };
+        if self.is_copy() {
+            return get(&self.terms[0].0).cloned();
+        }
+        let mut total = Value::Integer(0);
+        for (name, index) in self.terms.iter() {
+            let term = match (get(name)?, index) {
+                (Value::Integer(x), None) => Value::Integer(*x),
+                (Value::Float(x), None) => Value::Float(*x),
+                (Value::Array(Array::Integer(xs)), Some(i)) => Value::Integer((*xs.get(*i)?)?),
+                (Value::Array(Array::Float(xs)), Some(i)) => Value::Float((*xs.get(*i)?)?),
+                _ => return None,
+            };
+            total = match (total, term) {
+                (Value::Integer(a), Value::Integer(b)) => Value::Integer(a + b),
+                (Value::Integer(a), Value::Float(b)) => Value::Float(a as f32 + b),
+                (Value::Float(a), Value::Integer(b)) => Value::Float(a + b as f32),
+                (Value::Float(a), Value::Float(b)) => Value::Float(a + b),
+                _ => return None,
+            };
+        }
+        Some(total)
+    }
+}
+
+fn is_key(key: &str) -> bool {
+    !key.is_empty()
+        && key
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '.')
+}
+
+/// The FORMAT fields harmonised across the inputs (`--format-map`).
+#[derive(Debug, Clone, Default)]
+pub struct FormatMap {
+    mappings: Vec<FormatMapping>,
+}
+
+impl FormatMap {
+    pub fn parse(specs: &[String]) -> Result<FormatMap, SveltError> {
+        let mappings = specs
+            .iter()
+            .map(|spec| FormatMapping::parse(spec))
+            .collect::<Result<Vec<FormatMapping>, SveltError>>()?;
+        Ok(FormatMap { mappings })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.mappings.is_empty()
+    }
+
+    /// Does any of the mappings name a caller (rather than `*`)?
+    pub fn names_callers(&self) -> bool {
+        self.mappings.iter().any(|mapping| mapping.caller != "*")
+    }
+
+    /// Declare the keys made by the mappings in the `output` header, unless
+    /// it declares them already. Copied keys take the declaration of their
+    /// source, from the `headers` of the inputs (with the given `callers`);
+    /// sums are single Integer values, or Float if any term is.
+    pub fn declare(&self, headers: &[Header], callers: &[Option<&str>], output: &mut Header) {
+        for mapping in self.mappings.iter() {
+            if output.formats().contains_key(&mapping.key) {
+                continue;
+            }
+            let sources: Vec<&Map<Format>> = headers
+                .iter()
+                .zip(callers.iter())
+                .filter(|(_, caller)| mapping.applies_to(**caller))
+                .flat_map(|(header, _)| {
+                    mapping
+                        .terms
+                        .iter()
+                        .filter_map(|(name, _)| header.formats().get(name))
+                })
+                .collect();
+            let description = format!(
+                "{} (from {} for {})",
+                mapping.key, mapping.source, mapping.caller
+            );
+            let declaration = if mapping.is_copy() {
+                match sources.first() {
+                    Some(source) => Map::<Format>::new(source.number(), source.ty(), description),
+                    None => Map::<Format>::new(Number::Unknown, Type::String, description),
+                }
+            } else if sources.iter().any(|source| source.ty() == Type::Float) {
+                Map::<Format>::new(Number::Count(1), Type::Float, description)
+            } else {
+                Map::<Format>::new(Number::Count(1), Type::Integer, description)
+            };
+            output
+                .formats_mut()
+                .insert(mapping.key.clone(), declaration);
+        }
+    }
+
+    /// Add (or replace) the keys made by the mappings for `caller` to the
+    /// `fields` of a sample. The keys they are made from are left as they are.
+    pub fn apply(&self, caller: Option<&str>, fields: &mut SampleFields) {
+        for mapping in self.mappings.iter() {
+            if !mapping.applies_to(caller) {
+                continue;
+            }
+            let value = mapping.value(fields);
+            match fields.iter_mut().find(|(key, _)| key == &mapping.key) {
+                Some(field) => field.1 = value,
+                None => fields.push((mapping.key.clone(), value)),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let mapping = FormatMapping::parse("manta:DV=SR[1]+PR[1]").unwrap();
+        assert_eq!(mapping.caller, "manta");
+        assert_eq!(mapping.key, "DV");
+        assert_eq!(
+            mapping.terms,
+            vec![(String::from("SR"), Some(1)), (String::from("PR"), Some(1))]
+        );
+        assert!(FormatMapping::parse("*:DV=RE").unwrap().is_copy());
+
+        assert!(FormatMapping::parse("DV=RE").is_err());
+        assert!(FormatMapping::parse("cutesv:DV").is_err());
+        assert!(FormatMapping::parse("cutesv:DV=AD[x]").is_err());
+        assert!(FormatMapping::parse("cutesv:DV=AD[1").is_err());
+        assert!(FormatMapping::parse("cutesv:DV=AD+").is_err());
+    }
+
+    #[test]
+    fn test_apply() {
+        let map = FormatMap::parse(&[
+            String::from("manta:DV=SR[1]+PR[1]"),
+            String::from("cutesv:DR=AD[0]"),
+            String::from("*:RD=DR"),
+        ])
+        .unwrap();
+        let fields = vec![
+            (String::from("GT"), None),
+            (
+                String::from("SR"),
+                Some(Value::Array(Array::Integer(vec![Some(10), Some(3)]))),
+            ),
+            (
+                String::from("PR"),
+                Some(Value::Array(Array::Integer(vec![Some(12), Some(4)]))),
+            ),
+            (String::from("DR"), Some(Value::Integer(22))),
+        ];
+
+        let mut manta = fields.clone();
+        map.apply(Some("manta"), &mut manta);
+        assert_eq!(manta.len(), 6);
+        assert_eq!(manta[4], (String::from("DV"), Some(Value::Integer(7))));
+        assert_eq!(manta[5], (String::from("RD"), Some(Value::Integer(22))));
+
+        // A term that's missing makes the sum missing.
+        let mut cutesv = fields.clone();
+        map.apply(Some("cutesv"), &mut cutesv);
+        assert_eq!(cutesv[3], (String::from("DR"), None));
+        assert_eq!(cutesv[4], (String::from("RD"), None));
+    }
+}
@@ -13,6 +13,14 @@ impl<Item: HeapItem> Heap<Item> {
         Heap { items: Vec::new() }
     }
 
+    pub fn len(&self) -> usize {
+        self.items.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.items.is_empty()
+    }
+
     pub fn clear(&mut self) {
         self.items.clear();
     }
@@ -1,67 +1,182 @@
 use std::{cmp::Ordering, collections::BTreeMap};
 
 use datafusion::prelude::SessionContext;
+use rayon::prelude::*;
 
 use crate::{
+    distance::needleman_wunsch::NeedlemanWunsch,
     features::FeatureIndex,
+    kmers::{Ambiguity, Kmer},
     options::{CommonOptions, QueryOptions},
     sequence::{SequenceIterator, fasta::FastaSequenceIterator, vcf::VcfSequenceIterator},
+    threads::WorkerPool,
 };
 
+/// Report the features similar to each query sequence.
+///
+/// With `report_alignment`, the Needleman-Wunsch scores of the query (and
+/// its reverse complement) against each feature's sequence are appended.
+/// For an index of a reference genome, the position of each window follows
+/// its name. With `top` greater than 0, only that many of the best matches
+/// are reported for each query.
 pub async fn find_similar(
     features: &str,
     query: &QueryOptions,
+    report_alignment: bool,
+    top: usize,
     common: &CommonOptions,
     ctx:&SessionContext
 ) -> std::io::Result<()> {
     let _ = common;
+    let mut idx = FeatureIndex::load(features, ctx).await?;
+    if report_alignment {
+        idx.load_sequences(ctx).await?;
+    }
+
     if let Some(query) = &query.query {
-        return find_similar_single(features, query, ctx).await;
+        return find_similar_single(query, top, &idx);
     }
 
-    let idx = FeatureIndex::load(features, ctx).await?;
+    let pool = WorkerPool::of(ctx)?;
 
     if let Some(query_file) = &query.query_file {
         let itr = FastaSequenceIterator::new(&query_file)?;
-        find_similar_inner(itr, &idx)?;
+        find_similar_inner(itr, top, &idx, &pool)?;
     }
 
     if let Some(vcf_file) = &query.vcf {
         let itr = VcfSequenceIterator::new(&vcf_file)?;
-        find_similar_inner(itr, &idx)?;
+        find_similar_inner(itr, top, &idx, &pool)?;
     }
 
     Ok(())
 }
 
-fn find_similar_inner<Itr: SequenceIterator>(itr: Itr, idx: &FeatureIndex) -> std::io::Result<()> {
+/// The number of queries ranked at once (in parallel).
+const QUERY_BATCH_SIZE: usize = 256;
+
+fn find_similar_inner<Itr: SequenceIterator>(
+    itr: Itr,
+    top: usize,
+    idx: &FeatureIndex,
+    pool: &WorkerPool,
+) -> std::io::Result<()> {
+    let mut batch = Vec::new();
     for rec in itr {
         let (name, sequence) = rec?;
-
-        let res = find_similar_compile_results(&sequence, idx)?;
-
-        for (nix, (fwd, rev)) in res {
-            println!("{}\t{}\t{}\t{}", name, idx.names[nix as usize], fwd, rev);
+        report_ambiguity(&name, &sequence, idx);
+        batch.push((name, sequence));
+        if batch.len() == QUERY_BATCH_SIZE {
+            find_similar_batch(&batch, top, idx, pool)?;
+            batch.clear();
         }
     }
+    find_similar_batch(&batch, top, idx, pool)
+}
 
+/// Rank a batch of queries in parallel, reporting them in order.
+fn find_similar_batch(
+    batch: &[(String, String)],
+    top: usize,
+    idx: &FeatureIndex,
+    pool: &WorkerPool,
+) -> std::io::Result<()> {
+    let lines: Vec<Vec<String>> = pool.install(|| {
+        batch
+            .par_iter()
+            .map(|(name, sequence)| {
+                let res = find_similar_compile_results(sequence, top, idx)?;
+                Ok(res
+                    .into_iter()
+                    .map(|(nix, (fwd, rev))| {
+                        format!(
+                            "{}\t{}\t{}\t{}{}",
+                            name,
+                            feature_columns(nix, idx),
+                            fwd,
+                            rev,
+                            alignment_columns(sequence, nix, idx)
+                        )
+                    })
+                    .collect())
+            })
+            .collect::<std::io::Result<Vec<Vec<String>>>>()
+    })?;
+    for line in lines.iter().flatten() {
+        println!("{}", line);
+    }
     Ok(())
 }
 
-async fn find_similar_single(features: &str, query: &str, ctx: &SessionContext) -> std::io::Result<()> {
-    let idx = FeatureIndex::load(features, ctx).await?;
+fn find_similar_single(query: &str, top: usize, idx: &FeatureIndex) -> std::io::Result<()> {
+    report_ambiguity("query", query, idx);
 
-    let res = find_similar_compile_results(query, &idx)?;
+    let res = find_similar_compile_results(query, top, idx)?;
 
     for (nix, (fwd, rev)) in res {
-        println!("{}\t{}\t{}", idx.names[nix as usize], fwd, rev);
+        println!(
+            "{}\t{}\t{}{}",
+            feature_columns(nix, idx),
+            fwd,
+            rev,
+            alignment_columns(query, nix, idx)
+        );
     }
 
     Ok(())
 }
 
-fn find_similar_compile_results(sequence: &str, idx: &FeatureIndex) -> std::io::Result<Vec<(u32, (f64, f64))>> {
-    let (fwd, rev) = idx.rank(sequence);
+/// The name of a feature, and for a genome window, its position.
+fn feature_columns(nix: u32, idx: &FeatureIndex) -> String {
+    let name = &idx.names[nix as usize];
+    match idx.locus(nix) {
+        Some(locus) => format!("{}\t{}\t{}\t{}", name, locus.chrom, locus.start, locus.end),
+        None => name.clone(),
+    }
+}
+
+/// The alignment scores of the query against a feature, if its sequence is
+/// loaded, as extra columns.
+fn alignment_columns(query: &str, nix: u32, idx: &FeatureIndex) -> String {
+    match idx.sequence(nix) {
+        Some(feature) => {
+            let aligner = NeedlemanWunsch::default();
+            let fwd = aligner.align(query, feature);
+            let rev = aligner.align(&reverse_complement(query), feature);
+            format!("\t{}\t{}", fwd, rev)
+        }
+        None => String::new(),
+    }
+}
+
+pub(crate) fn reverse_complement(seq: &str) -> String {
+    seq.chars()
+        .rev()
+        .map(|c| match c {
+            'A' => 'T',
+            'C' => 'G',
+            'G' => 'C',
+            'T' => 'A',
+            'a' => 't',
+            'c' => 'g',
+            'g' => 'c',
+            't' => 'a',
+            _ => c,
+        })
+        .collect()
+}
+
+fn report_ambiguity(name: &str, sequence: &str, idx: &FeatureIndex) {
+    if idx.ambiguity() == Ambiguity::Report {
+        let count = Kmer::count_ambiguous(&sequence);
+        if count > 0 {
+            log::warn!("query '{}' has {} ambiguity codes", name, count);
+        }
+    }
+}
+
+fn find_similar_compile_results(sequence: &str, top: usize, idx: &FeatureIndex) -> std::io::Result<Vec<(u32, (f64, f64))>> {
+    let (fwd, rev) = idx.rank_above(sequence, 0.5);
 
     let mut res = BTreeMap::new();
     for (nix, score) in fwd {
@@ -79,6 +194,9 @@ fn find_similar_compile_results(sequence: &str, idx: &FeatureIndex) -> std::io::
 
     let mut res: Vec<(u32, (f64, f64))> = res.into_iter().collect();
     res.sort_by( |lhs, rhs| cmp_items(rhs, lhs));
+    if top > 0 {
+        res.truncate(top);
+    }
 
     Ok(res)
 }
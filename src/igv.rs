@@ -0,0 +1,241 @@
+use std::{
+    cmp::Reverse,
+    io::{BufWriter, Write},
+    path::Path,
+};
+
+use autocompress::{CompressionLevel, autodetect_create};
+use noodles::vcf::{Header, variant::RecordBuf};
+
+use crate::{
+    construct::InnerWriter,
+    export::MergedEvent,
+    heap::{Heap, HeapItem},
+};
+
+/// Records spanning more than this are shown with a view of each end, rather
+/// than a single view of the whole.
+const MAX_SINGLE_VIEW: usize = 50_000;
+
+/// A record chosen for review, ranked by (support, earliest first).
+struct Locus {
+    rank: (usize, Reverse<u64>),
+    id: String,
+    views: Vec<String>,
+}
+
+impl HeapItem for Locus {
+    type KeyType = (usize, Reverse<u64>);
+
+    fn key(&self) -> Self::KeyType {
+        self.rank
+    }
+}
+
+/// An IGV batch script visiting the best supported merged records
+/// (`--igv-batch`), for manual review.
+///
+/// The support of a record is the number of records merged to make it (its
+/// `ORIGINAL_IDS`). The script loads the merged VCF (and the reference, if
+/// given), then for each of the `top` records, in order of support, goes to
+/// the region around it and takes a snapshot named after its rank and ID.
+/// Breakends, and records longer than `MAX_SINGLE_VIEW`, are shown with a
+/// view of each end.
+pub struct IgvBatch {
+    out: String,
+    writer: InnerWriter,
+    top: usize,
+    padding: usize,
+    min_support: usize,
+    loci: Heap<Locus>,
+    seen: u64,
+}
+
+impl IgvBatch {
+    pub fn new(
+        out: &str,
+        top: usize,
+        padding: u32,
+        min_support: usize,
+    ) -> std::io::Result<IgvBatch> {
+        let writer = autodetect_create(out, CompressionLevel::Default)?;
+        let writer = BufWriter::new(writer);
+        Ok(IgvBatch {
+            out: String::from(out),
+            writer,
+            top,
+            padding: padding as usize,
+            min_support,
+            loci: Heap::new(),
+            seen: 0,
+        })
+    }
+
+    /// Consider an output record for review.
+    pub fn add(&mut self, header: &Header, rec: &RecordBuf) {
+        let event = MergedEvent::from_record(header, rec);
+        let support = event.provenance.original_ids.len();
+        self.seen += 1;
+        if support < self.min_support || self.top == 0 {
+            return;
+        }
+        let rank = (support, Reverse(self.seen));
+        if self.loci.len() == self.top {
+            if self.loci.front().is_some_and(|worst| worst.rank >= rank) {
+                return;
+            }
+            self.loci.change_front(Locus {
+                rank,
+                id: event.id.clone(),
+                views: views(&event, self.padding),
+            });
+        } else {
+            self.loci.push(Locus {
+                rank,
+                id: event.id.clone(),
+                views: views(&event, self.padding),
+            });
+        }
+    }
+
+    /// Write the script, to review the merged VCF `vcf` against `reference`.
+    pub fn finish(&mut self, vcf: &str, reference: Option<&str>) -> std::io::Result<()> {
+        let mut loci = Vec::new();
+        while let Some(locus) = self.loci.pop() {
+            loci.push(locus);
+        }
+        loci.reverse();
+
+        let snapshots = Path::new(&self.out)
+            .parent()
+            .filter(|dir| !dir.as_os_str().is_empty())
+            .unwrap_or(Path::new("."));
+
+        let writer = &mut self.writer;
+        writeln!(
+            writer,
+            "# The {} best supported of {} merged records",
+            loci.len(),
+            self.seen
+        )?;
+        writeln!(writer, "new")?;
+        if let Some(reference) = reference {
+            writeln!(writer, "genome {}", absolute(reference))?;
+        }
+        writeln!(writer, "load {}", absolute(vcf))?;
+        writeln!(writer, "snapshotDirectory {}", absolute(snapshots))?;
+        for (ix, locus) in loci.iter().enumerate() {
+            writeln!(writer, "goto {}", locus.views.join(" "))?;
+            writeln!(writer, "snapshot {}_{}.png", ix + 1, file_name(&locus.id))?;
+        }
+        writer.flush()
+    }
+}
+
+/// The regions of the genome to show for a record.
+fn views(event: &MergedEvent, padding: usize) -> Vec<String> {
+    let region = |chrom: &str, start: usize, end: usize| {
+        format!(
+            "{}:{}-{}",
+            chrom,
+            start.saturating_sub(padding).max(1),
+            end + padding
+        )
+    };
+    if let (Some(chrom2), Some(end2)) = (&event.chrom2, event.end2) {
+        return vec![
+            region(&event.chrom, event.start, event.start),
+            region(chrom2, end2, end2),
+        ];
+    }
+    let end = event.end.unwrap_or(event.start).max(event.start);
+    if end - event.start > MAX_SINGLE_VIEW {
+        vec![
+            region(&event.chrom, event.start, event.start),
+            region(&event.chrom, end, end),
+        ]
+    } else {
+        vec![region(&event.chrom, event.start, end)]
+    }
+}
+
+fn absolute<P: AsRef<Path>>(path: P) -> String {
+    let path = path.as_ref();
+    std::fs::canonicalize(path)
+        .unwrap_or_else(|_| path.to_path_buf())
+        .to_string_lossy()
+        .into_owned()
+}
+
+/// A variant ID, made safe to use in a file name.
+fn file_name(id: &str) -> String {
+    let name: String = id
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '-' || c == '.' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect();
+    if name.is_empty() {
+        String::from("_")
+    } else {
+        name
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::export::Provenance;
+
+    use super::*;
+
+    fn event(start: usize, end: Option<usize>, mate: Option<(&str, usize)>) -> MergedEvent {
+        MergedEvent {
+            id: String::from("x"),
+            chrom: String::from("chr1"),
+            start,
+            end,
+            kind: None,
+            length: None,
+            chrom2: mate.map(|m| String::from(m.0)),
+            end2: mate.map(|m| m.1),
+            quality: None,
+            filters: Vec::new(),
+            support: 0,
+            genotypes: Vec::new(),
+            classification: None,
+            provenance: Provenance {
+                original_ids: Vec::new(),
+                criteria: Vec::new(),
+                synthetic: false,
+            },
+        }
+    }
+
+    #[test]
+    fn test_views() {
+        assert_eq!(
+            views(&event(1000, Some(1500), None), 100),
+            vec!["chr1:900-1600"]
+        );
+        assert_eq!(views(&event(50, None, None), 100), vec!["chr1:1-150"]);
+        assert_eq!(
+            views(&event(1000, Some(1000000), None), 100),
+            vec!["chr1:900-1100", "chr1:999900-1000100"]
+        );
+        assert_eq!(
+            views(&event(1000, Some(1000), Some(("chr2", 5000))), 100),
+            vec!["chr1:900-1100", "chr2:4900-5100"]
+        );
+    }
+
+    #[test]
+    fn test_file_name() {
+        assert_eq!(file_name("SVELT_DEL_1gpqyKX"), "SVELT_DEL_1gpqyKX");
+        assert_eq!(file_name("Sniffles2.DEL/1:2"), "Sniffles2.DEL_1_2");
+        assert_eq!(file_name(""), "_");
+    }
+}
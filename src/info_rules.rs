@@ -0,0 +1,267 @@
+use std::rc::Rc;
+
+use noodles::vcf::{
+    Header, Record,
+    variant::record_buf::info::field::{Value, value::Array},
+};
+
+use crate::errors::SveltError;
+
+/// How the values of an INFO field from the records of a group are combined
+/// into the value of the merged record (`--info-rule KEY=POLICY`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InfoPolicy {
+    /// The value of the first record that has one (the default).
+    First,
+    /// The sum of the values.
+    Sum,
+    /// The mean of the values (rounded, for Integer fields).
+    Mean,
+    /// The smallest of the values.
+    Min,
+    /// The largest of the values.
+    Max,
+    /// The distinct values of all the records, for fields with several
+    /// values (such as `CSQ`), in the order they are first seen.
+    Union,
+}
+
+impl InfoPolicy {
+    fn parse(name: &str) -> Option<InfoPolicy> {
+        match name {
+            "first" => Some(InfoPolicy::First),
+            "sum" => Some(InfoPolicy::Sum),
+            "mean" => Some(InfoPolicy::Mean),
+            "min" => Some(InfoPolicy::Min),
+            "max" => Some(InfoPolicy::Max),
+            "union" => Some(InfoPolicy::Union),
+            _ => None,
+        }
+    }
+
+    /// Combine the `values` (at least one) of the records of a group, or
+    /// `None` if they can't be combined this way, in which case the value of
+    /// the first is kept.
+    fn combine(&self, values: &[Value]) -> Option<Value> {
+        match self {
+            InfoPolicy::First => values.first().cloned(),
+            InfoPolicy::Union => union(values),
+            _ => {
+                if let Some(xs) = values
+                    .iter()
+                    .map(|value| match value {
+                        Value::Integer(x) => Some(*x as i64),
+                        _ => None,
+                    })
+                    .collect::<Option<Vec<i64>>>()
+                {
+                    let x = match self {
+                        InfoPolicy::Sum => xs.iter().sum(),
+                        InfoPolicy::Mean => {
+                            (xs.iter().sum::<i64>() as f64 / xs.len() as f64).round() as i64
+                        }
+                        InfoPolicy::Min => *xs.iter().min()?,
+                        _ => *xs.iter().max()?,
+                    };
+                    return i32::try_from(x).ok().map(Value::Integer);
+                }
+                let xs = values
+                    .iter()
+                    .map(|value| match value {
+                        Value::Integer(x) => Some(*x as f32),
+                        Value::Float(x) => Some(*x),
+                        _ => None,
+                    })
+                    .collect::<Option<Vec<f32>>>()?;
+                let x = match self {
+                    InfoPolicy::Sum => xs.iter().sum(),
+                    InfoPolicy::Mean => xs.iter().sum::<f32>() / xs.len() as f32,
+                    InfoPolicy::Min => xs.iter().copied().fold(f32::INFINITY, f32::min),
+                    _ => xs.iter().copied().fold(f32::NEG_INFINITY, f32::max),
+                };
+                Some(Value::Float(x))
+            }
+        }
+    }
+}
+
+fn union(values: &[Value]) -> Option<Value> {
+    fn distinct<T: Clone + PartialEq>(xs: impl Iterator<Item = T>) -> Vec<T> {
+        let mut seen = Vec::new();
+        for x in xs {
+            if !seen.contains(&x) {
+                seen.push(x);
+            }
+        }
+        seen
+    }
+    match values.first()? {
+        Value::Array(Array::String(_)) => Some(Value::Array(Array::String(distinct(
+            values
+                .iter()
+                .map(|value| match value {
+                    Value::Array(Array::String(xs)) => Some(xs.iter().cloned()),
+                    _ => None,
+                })
+                .collect::<Option<Vec<_>>>()?
+                .into_iter()
+                .flatten(),
+        )))),
+        Value::Array(Array::Integer(_)) => Some(Value::Array(Array::Integer(distinct(
+            values
+                .iter()
+                .map(|value| match value {
+                    Value::Array(Array::Integer(xs)) => Some(xs.iter().copied()),
+                    _ => None,
+                })
+                .collect::<Option<Vec<_>>>()?
+                .into_iter()
+                .flatten(),
+        )))),
+        _ => None,
+    }
+}
+
+/// An INFO field of the merged records combined from those of every record
+/// merged into them, rather than taken from the first.
+#[derive(Debug, Clone, PartialEq)]
+pub struct InfoRule {
+    key: String,
+    policy: InfoPolicy,
+}
+
+impl InfoRule {
+    pub fn parse(spec: &str) -> Result<InfoRule, SveltError> {
+        let bad = || {
+            SveltError::BadOptionValue(
+                String::from("--info-rule"),
+                format!(
+                    "{} (expected KEY=POLICY, with POLICY one of first, sum, mean, min, max or union)",
+                    spec
+                ),
+            )
+        };
+        let (key, policy) = spec.split_once('=').ok_or_else(bad)?;
+        let policy = InfoPolicy::parse(policy).ok_or_else(bad)?;
+        if key.is_empty()
+            || !key
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '.')
+        {
+            return Err(bad());
+        }
+        Ok(InfoRule {
+            key: String::from(key),
+            policy,
+        })
+    }
+}
+
+/// The INFO fields combined across the records of each group (`--info-rule`).
+#[derive(Debug, Clone, Default)]
+pub struct InfoRules {
+    rules: Vec<InfoRule>,
+}
+
+impl InfoRules {
+    pub fn parse(specs: &[String]) -> Result<InfoRules, SveltError> {
+        let mut rules: Vec<InfoRule> = Vec::new();
+        for spec in specs.iter() {
+            let rule = InfoRule::parse(spec)?;
+            if rules.iter().any(|other| other.key == rule.key) {
+                return Err(SveltError::BadOptionValue(
+                    String::from("--info-rule"),
+                    format!("{} (more than one rule for {})", spec, rule.key),
+                ));
+            }
+            rules.push(rule);
+        }
+        Ok(InfoRules { rules })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    /// The combined value of each field with a rule, from the records of a
+    /// group (in the order of the inputs). Fields none of the records have,
+    /// or whose values can't be combined, are left out.
+    pub fn combine(
+        &self,
+        recs: &[Option<(Rc<Header>, Record)>],
+    ) -> std::io::Result<Vec<(String, Value)>> {
+        let mut combined = Vec::new();
+        for rule in self.rules.iter() {
+            let mut values = Vec::new();
+            for (header, rec) in recs.iter().flatten() {
+                if let Some(value) = rec.info().get(header, &rule.key).transpose()?.flatten() {
+                    values.push(Value::try_from(value)?);
+                }
+            }
+            if values.is_empty() {
+                continue;
+            }
+            if let Some(value) = rule.policy.combine(&values) {
+                combined.push((rule.key.clone(), value));
+            }
+        }
+        Ok(combined)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let rules =
+            InfoRules::parse(&[String::from("SVLEN=mean"), String::from("PE=sum")]).unwrap();
+        assert_eq!(
+            rules.rules[0],
+            InfoRule {
+                key: String::from("SVLEN"),
+                policy: InfoPolicy::Mean
+            }
+        );
+        assert_eq!(rules.rules[1].policy, InfoPolicy::Sum);
+
+        assert!(InfoRule::parse("SVLEN").is_err());
+        assert!(InfoRule::parse("SVLEN=average").is_err());
+        assert!(InfoRule::parse("=sum").is_err());
+        assert!(InfoRules::parse(&[String::from("PE=sum"), String::from("PE=max")]).is_err());
+    }
+
+    #[test]
+    fn test_combine() {
+        let ints = [
+            Value::Integer(-300),
+            Value::Integer(-310),
+            Value::Integer(-305),
+        ];
+        assert_eq!(InfoPolicy::First.combine(&ints), Some(Value::Integer(-300)));
+        assert_eq!(InfoPolicy::Sum.combine(&ints), Some(Value::Integer(-915)));
+        assert_eq!(InfoPolicy::Mean.combine(&ints), Some(Value::Integer(-305)));
+        assert_eq!(InfoPolicy::Min.combine(&ints), Some(Value::Integer(-310)));
+        assert_eq!(InfoPolicy::Max.combine(&ints), Some(Value::Integer(-300)));
+
+        let floats = [Value::Float(0.5), Value::Integer(1)];
+        assert_eq!(InfoPolicy::Mean.combine(&floats), Some(Value::Float(0.75)));
+        assert_eq!(InfoPolicy::Max.combine(&floats), Some(Value::Float(1.0)));
+
+        // Strings can't be summed.
+        let strings = [Value::String(String::from("x")), Value::Integer(1)];
+        assert_eq!(InfoPolicy::Sum.combine(&strings), None);
+
+        let csq = |xs: &[&str]| {
+            Value::Array(Array::String(
+                xs.iter().map(|x| Some(String::from(*x))).collect(),
+            ))
+        };
+        assert_eq!(
+            InfoPolicy::Union.combine(&[csq(&["a", "b"]), csq(&["b", "c"])]),
+            Some(csq(&["a", "b", "c"]))
+        );
+        assert_eq!(InfoPolicy::Union.combine(&ints), None);
+    }
+}
@@ -7,8 +7,43 @@ use crate::{
     breakends::{BreakEndSide, parse_breakend},
     errors::{SveltError, as_io_error},
     tables::is_seq,
+    vcf_reader::VcfReader,
 };
 
+/// The kinds of variant svelt merges, as given by SVTYPE (see `sv_kind`).
+/// Legacy translocations (TRA) are merged as breakends (see
+/// `get_translocation`).
+pub const SV_KINDS: &[&str] = &["DEL", "INS", "DUP", "INV", "CNV", "BND", "TRA"];
+
+/// Other names some callers use for the kinds in `SV_KINDS`.
+pub const SV_KIND_ALIASES: &[(&str, &str)] = &[("MEI", "INS"), ("TDUP", "DUP")];
+
+/// The kind given to records whose SVTYPE isn't in `SV_KINDS` (with
+/// `--unknown-svtype-action other`), which are only merged with each other.
+pub const OTHER_KIND: &str = "OTHER";
+
+/// The kind of variant an SVTYPE value stands for, ignoring case and any
+/// subtype (so `DUP:TANDEM` and `dup` are both `DUP`), or `None` if it isn't
+/// one svelt recognises.
+pub fn sv_kind(svtype: &str) -> Option<&'static str> {
+    let kind = svtype
+        .split(':')
+        .next()
+        .unwrap_or("")
+        .trim()
+        .to_ascii_uppercase();
+    if let Some(kind) = SV_KINDS.iter().find(|k| **k == kind) {
+        return Some(kind);
+    }
+    SV_KIND_ALIASES
+        .iter()
+        .find(|(alias, _)| *alias == kind)
+        .map(|(_, kind)| *kind)
+}
+
+/// The SVTYPE of a record (without any subtype), inferring it from the REF
+/// and ALT if it is absent. This is as the record gives it: use `sv_kind` to
+/// find the kind for merging.
 pub fn get_svtype(rec: &Record, header: &Header) -> std::io::Result<String> {
     match rec.info().get(header, "SVTYPE") {
         Some(value) => {
@@ -66,3 +101,67 @@ pub fn get_breakend(
     }
     Ok(None)
 }
+
+/// Interpret a breakend given by INFO fields rather than breakend notation:
+/// a legacy translocation record (SVTYPE=TRA, as emitted by older callers
+/// such as Delly or Sniffles), or a SVTYPE=BND record whose ALT is symbolic
+/// (e.g. `<BND>`) or missing.
+///
+/// The mate is at CHR2 and END2 or POS2 (or END, where older callers put it),
+/// and the orientation is given by CT (3to5, 5to3, 3to3, or 5to5), defaulting
+/// to 3to5.
+pub fn get_translocation(
+    rec: &Record,
+    header: &Header,
+) -> std::io::Result<Option<(String, usize, BreakEndSide, BreakEndSide)>> {
+    let kind = get_svtype(rec, header)?;
+    match sv_kind(&kind) {
+        Some("TRA") => {}
+        Some("BND") if get_breakend(rec)?.is_none() => {}
+        _ => return Ok(None),
+    }
+    let Some(chrom2) = VcfReader::info_as_str(rec, header, "CHR2")? else {
+        return Err(as_io_error(SveltError::MissingChr2));
+    };
+    let mut pos2 = None;
+    for name in ["END2", "POS2", "END"] {
+        pos2 = VcfReader::info_as_int(rec, header, name)?;
+        if pos2.is_some() {
+            break;
+        }
+    }
+    let Some(pos2) = pos2 else {
+        return Err(as_io_error(SveltError::MissingInfo(String::from("END2"))));
+    };
+    let (side, side2) = match VcfReader::info_as_str(rec, header, "CT")?.as_deref() {
+        None | Some("3to5") => (BreakEndSide::After, BreakEndSide::After),
+        Some("5to3") => (BreakEndSide::Before, BreakEndSide::Before),
+        Some("3to3") => (BreakEndSide::After, BreakEndSide::Before),
+        Some("5to5") => (BreakEndSide::Before, BreakEndSide::After),
+        Some(ct) => {
+            return Err(as_io_error(SveltError::BadBreakEnd(format!(
+                "{} with CT={}",
+                kind,
+                ct
+            ))));
+        }
+    };
+    Ok(Some((chrom2, pos2.max(0) as usize, side, side2)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sv_kind() {
+        assert_eq!(sv_kind("DEL"), Some("DEL"));
+        assert_eq!(sv_kind("del"), Some("DEL"));
+        assert_eq!(sv_kind("DUP:TANDEM"), Some("DUP"));
+        assert_eq!(sv_kind("INS:ME:ALU"), Some("INS"));
+        assert_eq!(sv_kind("MEI"), Some("INS"));
+        assert_eq!(sv_kind("TRA"), Some("TRA"));
+        assert_eq!(sv_kind("CPX"), None);
+        assert_eq!(sv_kind(""), None);
+    }
+}
@@ -1,11 +1,121 @@
 
+use std::ops::RangeInclusive;
+
+#[cfg(feature = "cli")]
+use clap::ValueEnum;
+
+#[cfg(feature = "datafusion")]
 pub mod kmerize;
+pub mod minimizer;
+
+/// How IUPAC nucleotide ambiguity codes (R, Y, S, W, K, M, B, D, H, V)
+/// are treated when extracting k-mers.
+///
+/// `N` (and anything else that isn't a nucleotide) always breaks the
+/// k-mer stream, whatever the mode.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "cli", derive(ValueEnum))]
+pub enum Ambiguity {
+    /// Treat ambiguity codes as breaks, so no k-mer spans them
+    #[default]
+    Break,
+    /// Replace each ambiguity code with the first (in A, C, G, T order) base it stands for, on each strand
+    Canonical,
+    /// Treat ambiguity codes as breaks, but count and report them
+    Report,
+}
+
+impl Ambiguity {
+    /// The name of the mode, as recorded in index metadata.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Ambiguity::Break => "break",
+            Ambiguity::Canonical => "canonical",
+            Ambiguity::Report => "report",
+        }
+    }
+
+    /// Recover a mode from its name.
+    pub fn from_name(name: &str) -> Option<Ambiguity> {
+        match name {
+            "break" => Some(Ambiguity::Break),
+            "canonical" => Some(Ambiguity::Canonical),
+            "report" => Some(Ambiguity::Report),
+            _ => None,
+        }
+    }
+
+    /// Convert an ASCII byte to a 1-mer under this mode.
+    #[inline]
+    pub fn byte(&self, c: u8) -> Option<Kmer> {
+        match self {
+            Ambiguity::Canonical => Kmer::byte(c).or_else(|| Kmer::ambiguous_byte(c)),
+            Ambiguity::Break | Ambiguity::Report => Kmer::byte(c),
+        }
+    }
+
+    /// Convert an ASCII byte to the 1-mers for the forward and reverse
+    /// strands under this mode.
+    ///
+    /// For a base, the reverse strand has its complement. For an ambiguity
+    /// code (in canonical mode), each strand has the resolution of the code
+    /// read on that strand, so the reverse strand gets the resolution of the
+    /// complementary code (e.g. R gives A forward, and C, for Y, in reverse),
+    /// and a sequence and its reverse complement have the same k-mers.
+    #[inline]
+    pub fn bases(&self, c: u8) -> Option<(Kmer, Kmer)> {
+        match Kmer::byte(c) {
+            Some(b) => {
+                let r = Kmer(3 - b.0);
+                Some((b, r))
+            }
+            None if *self == Ambiguity::Canonical => {
+                Some((Kmer::ambiguous_byte(c)?, Kmer::ambiguous_complement_byte(c)?))
+            }
+            None => None,
+        }
+    }
+}
+
+/// The k-mer lengths an index may use. k-mers are packed two bits a base
+/// into 64 bits, so can be no longer than 32, and k-mers shorter than 3
+/// occur in nearly every sequence, so can't tell features apart.
+pub const INDEX_K: RangeInclusive<usize> = 3..=32;
+
+/// The longest k for which a dense frequency vector (see
+/// `Kmer::frequency_vector`), with a count for each of the 4**k k-mers, may
+/// be made. Even at 15, that is a billion counts (8GiB).
+pub const MAX_DENSE_K: usize = 15;
+
+/// Check that `k` is in `range`, describing the problem if not.
+pub fn check_k(k: usize, range: RangeInclusive<usize>) -> Result<(), String> {
+    if range.contains(&k) {
+        Ok(())
+    } else {
+        Err(format!(
+            "{} (k must be between {} and {})",
+            k,
+            range.start(),
+            range.end()
+        ))
+    }
+}
 
 /// A k-length nucleotide sequence represented as a 64-bit integer.
 #[derive(PartialEq, Eq, PartialOrd, Ord, Hash, Clone, Debug)]
 pub struct Kmer(pub u64);
 
 impl Kmer {
+    /// The mask for the bits of a k-mer of length `k` (at most 32).
+    #[inline]
+    pub fn mask(k: usize) -> u64 {
+        if k >= 32 {
+            u64::MAX
+        } else {
+            (1 << (2 * k)) - 1
+        }
+    }
+
     /// Construct a k-mer from an unsigned integer
     pub fn from_u64(value: u64) -> Kmer {
         Kmer(value)
@@ -45,7 +155,7 @@ impl Kmer {
         F: FnMut(&Kmer),
     {
         let mut i = 0;
-        let msk: u64 = (1 << (2 * k)) - 1;
+        let msk: u64 = Kmer::mask(k);
         let mut x = Kmer(0);
         for c in seq.as_ref() {
             match Kmer::byte(*c) {
@@ -74,7 +184,7 @@ impl Kmer {
         F: FnMut(&Kmer, &Kmer),
     {
         let shift = 2 * (k - 1);
-        let msk: u64 = (1 << (2 * k)) - 1;
+        let msk: u64 = Kmer::mask(k);
         let mut x = Kmer(0);
         let mut y = Kmer(0);
         let mut i = 0;
@@ -107,7 +217,7 @@ impl Kmer {
         F: FnMut(usize, &Kmer, &Kmer),
     {
         let shift = 2 * (k - 1);
-        let msk: u64 = (1 << (2 * k)) - 1;
+        let msk: u64 = Kmer::mask(k);
         let mut x = Kmer(0);
         let mut y = Kmer(0);
         let mut i = 0;
@@ -158,6 +268,52 @@ impl Kmer {
         }
     }
 
+    /// Convert an IUPAC ambiguity code to the 1-mer for the first
+    /// (in A, C, G, T order) of the bases it stands for.
+    ///
+    /// Returns `None` for anything that isn't a two or three way
+    /// ambiguity code, including unambiguous bases and `N`.
+    #[inline]
+    pub fn ambiguous_byte(c: u8) -> Option<Kmer> {
+        match c {
+            b'R' | b'r' | b'W' | b'w' | b'M' | b'm' | b'D' | b'd' | b'H' | b'h' | b'V' | b'v' => {
+                Some(Kmer(0))
+            }
+            b'Y' | b'y' | b'S' | b's' | b'B' | b'b' => Some(Kmer(1)),
+            b'K' | b'k' => Some(Kmer(2)),
+            _ => None,
+        }
+    }
+
+    /// Convert an IUPAC ambiguity code to the 1-mer for the first (in A, C,
+    /// G, T order) of the bases its complementary code stands for (e.g. C
+    /// for R, as Y stands for C or T), which is how it reads on the reverse
+    /// strand.
+    ///
+    /// Returns `None` for the same codes as `ambiguous_byte`.
+    #[inline]
+    pub fn ambiguous_complement_byte(c: u8) -> Option<Kmer> {
+        match c {
+            b'Y' | b'y' | b'W' | b'w' | b'K' | b'k' | b'B' | b'b' | b'D' | b'd' | b'H' | b'h' => {
+                Some(Kmer(0))
+            }
+            b'R' | b'r' | b'S' | b's' | b'V' | b'v' => Some(Kmer(1)),
+            b'M' | b'm' => Some(Kmer(2)),
+            _ => None,
+        }
+    }
+
+    /// Count the IUPAC ambiguity codes (other than `N`) in a sequence.
+    pub fn count_ambiguous<S>(seq: &S) -> usize
+    where
+        S: AsRef<[u8]>,
+    {
+        seq.as_ref()
+            .iter()
+            .filter(|c| Kmer::ambiguous_byte(**c).is_some())
+            .count()
+    }
+
     /// Create a reversed k-mer.
     #[inline]
     pub fn rev(&self, k: usize) -> Kmer {
@@ -226,10 +382,13 @@ impl Kmer {
     ///
     /// Note that this is a dense representation, so the vector will have
     /// 4**k elements.
+    ///
+    /// Panics if `k` is greater than `MAX_DENSE_K`.
     pub fn frequency_vector<S>(k: usize, seq: &S) -> Vec<usize>
     where
         S: AsRef<[u8]>,
     {
+        Self::check_dense(k);
         let n: usize = 1 << (2 * k);
         let mut v: Vec<usize> = Vec::new();
         v.resize(n, 0);
@@ -246,10 +405,13 @@ impl Kmer {
     ///
     /// Note that this is a dense representation, so the vector will have
     /// 4**k elements.
+    ///
+    /// Panics if `k` is greater than `MAX_DENSE_K`.
     pub fn frequency_vector_both<S>(k: usize, seq: &S) -> Vec<usize>
     where
         S: AsRef<[u8]>,
     {
+        Self::check_dense(k);
         let n: usize = 1 << (2 * k);
         let mut v: Vec<usize> = Vec::new();
         v.resize(n, 0);
@@ -259,6 +421,12 @@ impl Kmer {
         });
         v
     }
+
+    fn check_dense(k: usize) {
+        if let Err(msg) = check_k(k, 1..=MAX_DENSE_K) {
+            panic!("k-mer frequency vector with k = {}", msg);
+        }
+    }
 }
 
 /// An iterator over the k-mers drawn from a sequence.
@@ -272,6 +440,7 @@ Src: Iterator<Item = &'a u8>
     x: Kmer,
     y: Kmer,
     i: usize,
+    ambiguity: Ambiguity,
     src: Src
 }
 
@@ -281,12 +450,17 @@ Src: Iterator<Item = &'a u8>
 {
     /// Create a new iterator yields the k-mers from both strands of a sequence.
     pub fn new(k: usize, src: Src) -> KmerIterator<'a, Src> {
+        Self::with_ambiguity(k, Ambiguity::Break, src)
+    }
+
+    /// Create a new iterator, treating ambiguity codes according to `ambiguity`.
+    pub fn with_ambiguity(k: usize, ambiguity: Ambiguity, src: Src) -> KmerIterator<'a, Src> {
         let shift: usize = 2 * (k - 1);
-        let mask: u64 = (1 << (2 * k)) - 1;
+        let mask: u64 = Kmer::mask(k);
         let x: Kmer = Kmer(0);
         let y: Kmer = Kmer(0);
         let i: usize = 0;
-        KmerIterator { k, shift, mask, x, y, i, src }
+        KmerIterator { k, shift, mask, x, y, i, ambiguity, src }
     }
 }
 
@@ -297,15 +471,15 @@ Src: Iterator<Item = &'a u8>
     type Item = (Kmer, Kmer);
     fn next(&mut self) -> Option<Self::Item> {
         while let Some(c) = self.src.next() {
-            match Kmer::byte(*c) {
+            match self.ambiguity.bases(*c) {
                 None => {
                     self.i = 0;
                     self.x.0 = 0;
                     self.y.0 = 0;
                 }
-                Some(b) => {
+                Some((b, r)) => {
                     self.x.0 = (self.x.0 << 2) | b.0;
-                    self.y.0 = (self.y.0 >> 2) | ((3 - b.0) << self.shift);
+                    self.y.0 = (self.y.0 >> 2) | (r.0 << self.shift);
                     self.i += 1;
                     if self.i == self.k {
                         self.x.0 &= self.mask;
@@ -564,4 +738,94 @@ mod tests {
         assert_eq!(itr.next(), Some((x4.clone(), x4.rev_comp(k))));
         assert_eq!(itr.next(), None);
     }
+
+    #[test]
+    fn test_8() {
+        let seq = "CTTTCTGGGGCRAGAG";
+        let k = 11;
+        assert_eq!(Kmer::count_ambiguous(&seq), 1);
+        assert_eq!(Kmer::count_ambiguous(&"ACGTN"), 0);
+
+        let xs: Vec<Kmer> = KmerIterator::new(k, seq.as_bytes().iter())
+            .map(|(x, _)| x)
+            .collect();
+        assert_eq!(xs, vec![Kmer::make("CTTTCTGGGGC").unwrap()]);
+
+        let xs: Vec<String> =
+            KmerIterator::with_ambiguity(k, Ambiguity::Canonical, seq.as_bytes().iter())
+                .map(|(x, _)| x.render(k))
+                .collect();
+        assert_eq!(xs.len(), seq.len() - k + 1);
+        assert_eq!(xs[1], "TTTCTGGGGCA");
+    }
+
+    #[test]
+    fn test_ambiguity_strand_symmetry() {
+        let k = 5;
+        let seq = "ACGRTTYSAWKGMCBDAHVTT";
+        let rc: String = seq
+            .chars()
+            .rev()
+            .map(|c| match c {
+                'A' => 'T',
+                'C' => 'G',
+                'G' => 'C',
+                'T' => 'A',
+                'R' => 'Y',
+                'Y' => 'R',
+                'K' => 'M',
+                'M' => 'K',
+                'B' => 'V',
+                'V' => 'B',
+                'D' => 'H',
+                'H' => 'D',
+                other => other,
+            })
+            .collect();
+        let canonical = |s: &str| {
+            let mut xs: Vec<Kmer> =
+                KmerIterator::with_ambiguity(k, Ambiguity::Canonical, s.as_bytes().iter())
+                    .map(|(x, y)| x.min(y))
+                    .collect();
+            xs.sort();
+            xs
+        };
+        let fwd = canonical(seq);
+        assert_eq!(fwd.len(), seq.len() - k + 1);
+        assert_eq!(fwd, canonical(&rc));
+
+        // Each ambiguity code reads the same on both strands.
+        for c in b"RYSWKMBDHV" {
+            let (b, r) = Ambiguity::Canonical.bases(*c).unwrap();
+            let comp = rc.as_bytes()[seq.len() - 1 - seq.find(*c as char).unwrap()];
+            let (cb, cr) = Ambiguity::Canonical.bases(comp).unwrap();
+            assert_eq!((b, r), (cr, cb), "{}", *c as char);
+        }
+        assert_eq!(Ambiguity::Break.bases(b'R'), None);
+        assert_eq!(Ambiguity::Break.bases(b'G'), Some((Kmer(2), Kmer(1))));
+    }
+
+    #[test]
+    fn test_9() {
+        let seq = "CTTTCTGGGGCTAGAGCAGGCAAACGTGGTACAGT";
+        let k = 32;
+        let xs: Vec<(Kmer, Kmer)> = KmerIterator::new(k, seq.as_bytes().iter()).collect();
+        assert_eq!(xs.len(), seq.len() - k + 1);
+        assert_eq!(xs[0].0.render(k), &seq[..k]);
+        assert_eq!(xs[3].0.render(k), &seq[3..]);
+        assert_eq!(xs[3].1, xs[3].0.rev_comp(k));
+
+        assert!(check_k(32, INDEX_K).is_ok());
+        assert_eq!(
+            check_k(33, INDEX_K),
+            Err(String::from("33 (k must be between 3 and 32)"))
+        );
+        assert!(check_k(2, INDEX_K).is_err());
+    }
+
+    #[test]
+    #[should_panic(expected = "k-mer frequency vector with k = 16")]
+    fn test_10() {
+        Kmer::frequency_vector(16, &"ACGT");
+    }
 }
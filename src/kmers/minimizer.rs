@@ -0,0 +1,106 @@
+use std::collections::VecDeque;
+
+/// Scramble a k-mer so that minimizers are not biased towards
+/// low complexity (e.g. poly-A) k-mers.
+#[inline]
+pub fn minimizer_order(x: u64) -> u64 {
+    let mut z = x.wrapping_add(0x9E3779B97F4A7C15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58476D1CE4E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D049BB133111EB);
+    z ^ (z >> 31)
+}
+
+/// Select the (w,k)-minimizers from a sequence of consecutive k-mers.
+///
+/// For each window of `w` consecutive k-mers, the k-mer with the smallest
+/// value of `minimizer_order` is selected (the leftmost one in the case of
+/// ties). A k-mer selected by several overlapping windows is only reported
+/// once. If there are fewer than `w` k-mers, the whole sequence is treated
+/// as a single window.
+///
+/// Applying this to the reverse complement k-mers of a sequence selects the
+/// same minimizers as applying it to the reverse complement of the sequence
+/// (up to ties), since the windows contain the same k-mers.
+///
+pub fn minimizers(xs: &[u64], w: usize) -> Vec<u64> {
+    let mut res = Vec::new();
+    if xs.is_empty() {
+        return res;
+    }
+    let w = w.clamp(1, xs.len());
+
+    let mut window: VecDeque<(u64, usize)> = VecDeque::new();
+    let mut last = usize::MAX;
+    for (i, x) in xs.iter().enumerate() {
+        let h = minimizer_order(*x);
+        while let Some((h0, _)) = window.back() {
+            if *h0 > h {
+                window.pop_back();
+            } else {
+                break;
+            }
+        }
+        window.push_back((h, i));
+        while let Some((_, j)) = window.front() {
+            if *j + w <= i {
+                window.pop_front();
+            } else {
+                break;
+            }
+        }
+        if i + 1 >= w {
+            let (_, j) = window.front().unwrap();
+            if *j != last {
+                last = *j;
+                res.push(xs[last]);
+            }
+        }
+    }
+    res
+}
+
+/// Apply a k-mer sampling scheme: keep every k-mer if `w` is 0,
+/// otherwise keep just the (w,k)-minimizers.
+pub fn sample_kmers(kmers: Vec<u64>, w: usize) -> Vec<u64> {
+    if w == 0 { kmers } else { minimizers(&kmers, w) }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::kmers::Kmer;
+
+    #[test]
+    fn test_window_1_is_identity() {
+        let xs = vec![5, 3, 9, 3, 1];
+        assert_eq!(minimizers(&xs, 1), xs);
+    }
+
+    #[test]
+    fn test_short_sequence() {
+        let xs = vec![5, 3, 9];
+        let ms = minimizers(&xs, 10);
+        assert_eq!(ms.len(), 1);
+        let best = xs
+            .iter()
+            .min_by_key(|x| minimizer_order(**x))
+            .unwrap();
+        assert_eq!(ms[0], *best);
+    }
+
+    #[test]
+    fn test_each_window_is_covered() {
+        let seq = "CTTTCTGGGGCTAGAGCAGGCAAACGTGGTACAGTCGACTCCATTCTTTCTTCCTCTGAGACCCCTTCC";
+        let xs: Vec<u64> = Kmer::make_many(11, seq).into_iter().map(|x| x.0).collect();
+        let w = 5;
+        let ms = minimizers(&xs, w);
+        assert!(ms.len() < xs.len());
+        for window in xs.windows(w) {
+            let best = window
+                .iter()
+                .min_by_key(|x| minimizer_order(**x))
+                .unwrap();
+            assert!(ms.contains(best));
+        }
+    }
+}
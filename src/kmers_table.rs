@@ -12,15 +12,20 @@ use datafusion::{
 };
 use itertools::Itertools as _;
 
-use crate::kmers::Kmer;
+use crate::kmers::{Kmer, minimizer::sample_kmers};
 
-pub async fn kmers_table(seq: &str, k: usize, ctx: &SessionContext) -> std::io::Result<(DataFrame, usize)> {
+/// Build a table of k-mer frequencies for both strands of a sequence.
+///
+/// If `w` is non-zero, only the (w,k)-minimizers are counted.
+pub async fn kmers_table(seq: &str, k: usize, w: usize, ctx: &SessionContext) -> std::io::Result<(DataFrame, usize)> {
     let mut fwd = Vec::new();
     let mut rev = Vec::new();
     Kmer::with_many_both(k, &seq, |x, y| {
         fwd.push(x.0);
         rev.push(y.0);
     });
+    let mut fwd = sample_kmers(fwd, w);
+    let mut rev = sample_kmers(rev, w);
 
     let mut kmer_count = 0;
 
@@ -1,28 +1,91 @@
+#[cfg(feature = "datafusion")]
+pub mod agreement;
+#[cfg(feature = "datafusion")]
+pub mod annotate;
+#[cfg(feature = "datafusion")]
+pub mod annotations;
+#[cfg(feature = "datafusion")]
 pub mod arrays;
+#[cfg(feature = "datafusion")]
+pub mod audit;
+#[cfg(feature = "datafusion")]
 pub mod breakends;
+#[cfg(feature = "datafusion")]
 pub mod builders;
+#[cfg(feature = "datafusion")]
+pub mod bundle;
 pub mod chroms;
+#[cfg(feature = "datafusion")]
 pub mod construct;
+#[cfg(feature = "datafusion")]
+pub mod demo;
+#[cfg(feature = "datafusion")]
+pub mod determinism;
+#[cfg(feature = "datafusion")]
+pub mod diagnostics;
 pub mod disjoint_set;
+#[cfg(feature = "datafusion")]
 pub mod distance;
 pub mod either;
 pub mod errors;
+#[cfg(feature = "datafusion")]
 pub mod expressions;
+#[cfg(feature = "datafusion")]
+pub mod export;
 pub mod features;
+pub mod force_keep;
+pub mod format_map;
 pub mod heap;
+#[cfg(feature = "datafusion")]
 pub mod homology;
+#[cfg(feature = "datafusion")]
+pub mod igv;
+pub mod info_rules;
+#[cfg(feature = "datafusion")]
 pub mod inputs;
+#[cfg(feature = "datafusion")]
 pub mod iterators;
 pub mod kmers;
+#[cfg(feature = "datafusion")]
 pub mod kmers_table;
+#[cfg(feature = "datafusion")]
 pub mod merge;
 pub mod nearest;
+#[cfg(feature = "datafusion")]
+pub mod observer;
+#[cfg(feature = "datafusion")]
 pub mod options;
+pub mod output_format;
+#[cfg(feature = "datafusion")]
+pub mod output_index;
+#[cfg(feature = "datafusion")]
+pub mod qc;
+#[cfg(feature = "datafusion")]
+pub mod quarantine;
+#[cfg(feature = "datafusion")]
 pub mod range_joins;
 pub mod record;
+pub mod repeats;
+#[cfg(feature = "datafusion")]
+pub mod report;
+#[cfg(feature = "datafusion")]
 pub mod record_seeker;
+pub mod resources;
+#[cfg(feature = "datafusion")]
 pub mod row_key;
+pub mod samples;
+#[cfg(feature = "datafusion")]
 pub mod sequence;
+pub mod sorting;
+#[cfg(feature = "datafusion")]
 pub mod tables;
+pub mod tag_patterns;
+pub mod targets;
+#[cfg(feature = "datafusion")]
+pub mod threads;
+#[cfg(feature = "datafusion")]
+pub mod tune;
 pub mod union_find;
-pub mod vcf_reader;
\ No newline at end of file
+#[cfg(feature = "datafusion")]
+pub mod vcf_reader;
+pub mod window;
\ No newline at end of file
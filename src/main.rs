@@ -1,11 +1,20 @@
 use std::{error::Error, rc::Rc};
 
-use clap::{Parser, Subcommand};
+use clap::{ArgAction, Parser, Subcommand};
+use datafusion::error::DataFusionError;
+use log::LevelFilter;
 use svelt::{
+    annotate::annotate_vcf,
+    demo::demo,
+    determinism::audit_determinism,
+    diagnostics::set_debug_tables,
+    errors::{FailureClass, SveltError, as_io_error},
     features::FeatureIndex,
     homology::find_similar,
+    kmers::{INDEX_K, check_k},
     merge::merge_vcfs,
     options::{CommonOptions, IndexingOptions, MergeOptions, QueryOptions, make_session_context},
+    tune::tune,
 };
 
 /// Structuaral Variant (SV) VCF merging
@@ -13,10 +22,37 @@ use svelt::{
 #[command(name = "svelt")]
 #[command(about = "Merge structural variants, aligning similar ones.", long_about = None)]
 struct Cli {
+    /// Log more detail (-v for debugging messages, -vv to include those of the query engine)
+    #[arg(short, long, action = ArgAction::Count, conflicts_with = "quiet")]
+    verbose: u8,
+
+    /// Only log warnings and errors
+    #[arg(short, long)]
+    quiet: bool,
+
+    /// Log the intermediate tables of the merge and classification (slow)
+    #[arg(long)]
+    debug_tables: bool,
+
     #[command(subcommand)]
     command: Commands,
 }
 
+impl Cli {
+    /// The log levels for svelt itself, and for everything else (notably DataFusion).
+    fn log_levels(&self) -> (LevelFilter, LevelFilter) {
+        if self.quiet {
+            return (LevelFilter::Warn, LevelFilter::Warn);
+        }
+        match self.verbose {
+            0 => (LevelFilter::Info, LevelFilter::Warn),
+            1 => (LevelFilter::Debug, LevelFilter::Warn),
+            2 => (LevelFilter::Trace, LevelFilter::Debug),
+            _ => (LevelFilter::Trace, LevelFilter::Trace),
+        }
+    }
+}
+
 #[derive(Debug, Subcommand)]
 enum Commands {
     /// Apply annotations from one VCF to another
@@ -37,6 +73,28 @@ enum Commands {
         common: CommonOptions,
     },
 
+    /// Classify the inserted sequences of a VCF against a feature index, without merging
+    #[command(arg_required_else_help = true)]
+    Annotate {
+        /// Base name of the feature index (made by index-features)
+        #[arg(short, long)]
+        features: String,
+
+        /// The output filename
+        #[arg(short, long)]
+        out: String,
+
+        /// SV VCF file to annotate
+        vcf: String,
+
+        /// Number of distinct insertion sequences to classify per batch
+        #[arg(long, default_value = "100000")]
+        classify_batch_size: usize,
+
+        #[command(flatten)]
+        common: CommonOptions,
+    },
+
     /// Index a set of features for annotating homology
     #[command(arg_required_else_help = true)]
     IndexFeatures {
@@ -55,6 +113,25 @@ enum Commands {
         common: CommonOptions,
     },
 
+    /// Rewrite an index made by an older version of svelt in the current layout
+    #[command(arg_required_else_help = true)]
+    IndexUpgrade {
+        /// Base name of the index to upgrade
+        #[arg(short, long)]
+        features: String,
+
+        /// Base name for the upgraded index
+        #[arg(short, long)]
+        out: String,
+
+        /// Also write the index as a single portable file (<out>.svx)
+        #[arg(long)]
+        portable: bool,
+
+        #[command(flatten)]
+        common: CommonOptions,
+    },
+
     /// Identify whether a given sequence is similar to a previously indexed one.
     #[command(arg_required_else_help = true)]
     FindSimilar {
@@ -62,21 +139,94 @@ enum Commands {
         #[arg(short, long)]
         features: String,
 
-        /// k-mer length
+        /// k-mer length (3 to 32; queries are always made with the k the index was built with)
         #[arg(short, long, required = false, default_value = "11")]
         k: usize,
 
         #[command(flatten)]
         query: QueryOptions,
 
+        /// Also report Needleman-Wunsch alignment scores against the features' sequences (slow for long sequences)
+        #[arg(long)]
+        report_alignment: bool,
+
+        /// Only report this many of the best matches for each query (0 for all)
+        #[arg(long, default_value = "0")]
+        top: usize,
+
         #[command(flatten)]
         common: CommonOptions,
     },
-}
 
-async fn main_inner() -> std::io::Result<()> {
-    let cli = Cli::parse();
+    /// Evaluate merging against a truth set over a range of merge parameters
+    #[command(arg_required_else_help = true)]
+    Tune {
+        /// The output filename (default: stdout)
+        #[arg(short, long)]
+        out: Option<String>,
+
+        /// Pairs of IDs labelled 1 if they should be merged, 0 if not
+        #[arg(short, long)]
+        truth: String,
+
+        /// Position windows to evaluate
+        #[arg(long, value_delimiter = ',', default_value = "10,25,50,100")]
+        position_windows: Vec<u32>,
+
+        /// Length ratios to evaluate
+        #[arg(long, value_delimiter = ',', default_value = "0.7,0.8,0.9,0.95")]
+        length_ratios: Vec<f64>,
+
+        /// SV VCF files to merge
+        #[arg(num_args(1..))]
+        vcf: Vec<String>,
+
+        #[command(flatten)]
+        options: MergeOptions,
+
+        #[command(flatten)]
+        common: CommonOptions,
+    },
 
+    /// Check that merging gives the same output when run again, with other numbers of threads, or with the inputs permuted
+    #[command(arg_required_else_help = true)]
+    AuditDeterminism {
+        /// The output filename for the report (default: stdout)
+        #[arg(short, long)]
+        out: Option<String>,
+
+        /// Also run the merge with the inputs in reverse order, comparing the records made (by ORIGINAL_IDS)
+        #[arg(long)]
+        permute_inputs: bool,
+
+        /// Also run the merge with each of these numbers of threads
+        #[arg(long, value_delimiter = ',')]
+        thread_counts: Vec<usize>,
+
+        /// SV VCF files to merge
+        #[arg(num_args(1..))]
+        vcf: Vec<String>,
+
+        #[command(flatten)]
+        options: MergeOptions,
+
+        #[command(flatten)]
+        common: CommonOptions,
+    },
+
+    /// Make a small example cohort, index its repeat families, and merge its calls, as a worked example and a check that svelt works from end to end
+    #[command(arg_required_else_help = true)]
+    Demo {
+        /// The directory to write the example's inputs and outputs to (created if need be)
+        #[arg(short, long)]
+        out: String,
+
+        #[command(flatten)]
+        common: CommonOptions,
+    },
+}
+
+async fn main_inner(cli: Cli) -> std::io::Result<()> {
     match cli.command {
         Commands::Merge {
             out,
@@ -87,47 +237,138 @@ async fn main_inner() -> std::io::Result<()> {
             let options = Rc::new(options);
             merge_vcfs(&out, &vcf, options, &common).await?;
         }
+        Commands::Annotate {
+            features,
+            out,
+            vcf,
+            classify_batch_size,
+            common,
+        } => {
+            let ctx = make_session_context(&common)?;
+            annotate_vcf(&vcf, &out, &features, classify_batch_size, &ctx).await?;
+        }
         Commands::IndexFeatures {
             out,
             features,
             options,
             common,
         } => {
-            let ctx = make_session_context(&common);
-            let idx = FeatureIndex::build(&features, &options).await?;
+            let ctx = make_session_context(&common)?;
+            let idx = FeatureIndex::build(&features, &options, &ctx).await?;
             idx.save(&out, &ctx).await?;
+            if options.portable {
+                idx.save_portable(&format!("{}.svx", out))?;
+            }
+        }
+        Commands::IndexUpgrade {
+            features,
+            out,
+            portable,
+            common,
+        } => {
+            let ctx = make_session_context(&common)?;
+            let idx = FeatureIndex::upgrade(&features, &out, &ctx).await?;
+            if portable {
+                idx.save_portable(&format!("{}.svx", out))?;
+            }
         }
         Commands::FindSimilar {
             features,
             query,
-            k: _,
+            k,
+            report_alignment,
+            top,
             common,
         } => {
-            let ctx = make_session_context(&common);
-            find_similar(&features, &query, &common, &ctx).await?;
+            check_k(k, INDEX_K)
+                .map_err(|msg| as_io_error(SveltError::BadOptionValue(String::from("-k"), msg)))?;
+            let ctx = make_session_context(&common)?;
+            find_similar(&features, &query, report_alignment, top, &common, &ctx).await?;
+        }
+        Commands::Tune {
+            out,
+            truth,
+            position_windows,
+            length_ratios,
+            vcf,
+            options,
+            common,
+        } => {
+            tune(
+                out.as_deref(),
+                &vcf,
+                &truth,
+                &position_windows,
+                &length_ratios,
+                &options,
+                &common,
+            )
+            .await?;
+        }
+        Commands::AuditDeterminism {
+            out,
+            permute_inputs,
+            thread_counts,
+            vcf,
+            options,
+            common,
+        } => {
+            audit_determinism(
+                out.as_deref(),
+                &vcf,
+                permute_inputs,
+                &thread_counts,
+                &options,
+                &common,
+            )
+            .await?;
+        }
+        Commands::Demo { out, common } => {
+            demo(&out, &common).await?;
         }
     }
 
     Ok(())
 }
 
+fn is_memory_exhausted(error: Option<&(dyn Error + 'static)>) -> bool {
+    matches!(
+        error
+            .and_then(|e| e.downcast_ref::<DataFusionError>())
+            .map(|e| e.find_root()),
+        Some(DataFusionError::ResourcesExhausted(_))
+    )
+}
+
 #[tokio::main]
 async fn main() -> std::io::Result<()> {
+    let cli = Cli::parse();
+    let (ours, theirs) = cli.log_levels();
     env_logger::builder()
-        .filter_level(log::LevelFilter::Info)
+        .filter_level(theirs)
+        .filter_module("svelt", ours)
+        .parse_default_env()
         .init();
+    set_debug_tables(cli.debug_tables);
 
-    let res = main_inner().await;
+    let res = main_inner(cli).await;
     match res {
         Ok(_) => {}
         Err(error) => {
             log::error!("Error: {}", error);
             let mut current_source = error.source();
+            let mut memory_exhausted = is_memory_exhausted(error.get_ref().map(|e| e as &(dyn Error + 'static)));
             while let Some(source) = current_source {
                 log::error!("Caused by: {}", source);
+                memory_exhausted |= is_memory_exhausted(Some(source));
                 current_source = source.source();
             }
-            std::process::exit(1);
+            if memory_exhausted {
+                log::error!(
+                    "The memory limit was exceeded - consider raising --memory-limit, or using fewer --threads."
+                );
+            }
+            std::process::exit(FailureClass::of(&error).exit_code());
         }
     }
 
@@ -1,50 +1,205 @@
 use std::{
+    cell::RefCell,
+    collections::{BTreeMap, HashMap, HashSet},
     io::{BufRead, Error, ErrorKind},
+    path::Path,
     rc::Rc,
+    sync::Arc,
+    time::{Duration, Instant},
 };
 
 use datafusion::{
     arrow::{
         array::{
-            Array, BooleanArray, GenericStringArray, Int64Array, RecordBatch, StringArray,
+            Array, ArrayAccessor, BinaryBuilder, BooleanArray, DictionaryArray, Float64Array,
+            GenericStringArray, Int32Array, Int64Array, PrimitiveBuilder, RecordBatch, StringArray,
             UInt32Array,
         },
-        datatypes::DataType,
+        datatypes::{DataType, Field, Int64Type, Schema, UInt16Type, UInt32Type},
     },
     common::JoinType,
-    functions_aggregate::expr_fn::first_value,
-    prelude::{DataFrame, cast, col, concat, length, lit, nullif, to_hex},
+    functions_aggregate::expr_fn::{bool_or, count, first_value},
+    prelude::{
+        DataFrame, Expr, abs, cast, coalesce, col, concat, greatest, least, length, lit, nullif,
+        to_hex, when,
+    },
 };
 use noodles::{
     fasta::{self, repository::adapters::IndexedReader},
-    vcf::{self, Header, Record, header::SampleNames},
+    vcf::{self, Header, Record, header::SampleNames, variant::record::Ids as _},
 };
 
 use crate::{
-    breakends::unpaired_breakend_check,
+    agreement::AgreementMatrix,
+    annotations::AnnotationReconciler,
+    audit::{audit_fields, write_field_audit},
+    breakends::{breakend_events, unpaired_breakend_check, unpaired_breakend_mates},
+    bundle::{BUNDLE_SUMMARY, Bundle},
     chroms::ChromSet,
-    construct::{MergeBuilder, add_svelt_header_fields},
-    errors::{Context, FileContext, SveltError, as_io_error},
+    construct::{
+        GroupSpread, MergeBuilder, add_svelt_header_fields, remove_unwanted_header_fields,
+    },
+    diagnostics::show_table,
+    errors::{Context, FileContext, SveltError, as_io_error, error_chain, wrap_file_error},
+    expressions::difference,
     merge::{
-        approx::{approx_bnd_here_there_join, approx_bnd_there_here_join, approx_near_join},
-        exact::{full_exact_bnd, full_exact_indel_join, full_exact_locus_ins_join},
-        report::produce_reporting_table,
+        approx::{
+            NearRule, approx_bnd_here_there_join, approx_bnd_there_here_join, approx_near_join,
+            reciprocal_overlap_join,
+        },
+        columns::{MATCH_COLUMNS, OUTPUT_COLUMNS, prune_columns},
+        dedup::dedup_within_inputs,
+        exact::{
+            exact_duplicates, full_exact_bnd, full_exact_indel_join, full_exact_locus_ins_join,
+        },
+        length::LengthRule,
+        pairing::Pairing,
+        report::{InputProvenance, produce_reporting_table, write_reporting_table},
+        similar::similar_insertion_join,
         union::merge_with,
         variant_id::construct_variant_ids,
+        vix_set::VixSet,
     },
-    options::{CommonOptions, MergeOptions, make_session_context},
-    record_seeker::RecordSeeker,
+    force_keep::ForceKeep,
+    format_map::FormatMap,
+    samples::SampleLayout,
+    observer::{MergeObserver, NullObserver},
+    options::{
+        CommonOptions, MergeOptions, QcReportFormat, RecordErrorAction, make_session_context,
+    },
+    output_format::{check_format_version, input_svelt_field, is_svelt_owned},
+    qc::QcReport,
+    quarantine::Quarantine,
+    record_seeker::{MemoryRecordStore, RecordSeeker, RecordStore},
     row_key::RowKey,
-    tables::load_vcf_core,
-    vcf_reader::VcfReader,
+    sorting::sort_lines,
+    tables::{load_vcf_core, vcf_core_schema},
+    targets::Targets,
+    vcf_reader::{VcfReader, check_chroms, reconcile_contigs},
 };
 
 mod approx;
-mod classify;
+pub(crate) mod classify;
+mod columns;
+mod dedup;
 mod exact;
+mod genotypes;
+mod length;
+mod merger;
+mod pairing;
 mod report;
+mod score;
+mod similar;
 mod union;
 mod variant_id;
+mod vix_set;
+
+pub use merger::{InputStatistics, MergeOutcome, MergeStatistics, Merger, MergerBuilder};
+
+/// One input to a merge, already loaded.
+///
+/// This allows VCFs to be merged from sources other than files (e.g. after
+/// conversion from BCF) by supplying the table of records directly, along
+/// with a store from which the original records can be recovered.
+pub struct MergeInput {
+    /// A name for the input (e.g. its path), used in logging and reports.
+    pub name: String,
+
+    /// The header of the input. All inputs must have the same contigs, in
+    /// the same order (unless `--reconcile-contigs` is given).
+    pub header: Header,
+
+    /// The records, conforming to `vcf_core_schema`, with `row_num` numbering
+    /// the records from 0 in the order they are held in `store`.
+    pub records: RecordBatch,
+
+    /// The original records.
+    pub store: Box<dyn RecordStore>,
+
+    /// The lines of any records that were left out of `records` because
+    /// they couldn't be processed (see `--on-record-error`).
+    pub rejected: Vec<Vec<u8>>,
+}
+
+impl MergeInput {
+    /// Load an input from a VCF file.
+    pub fn from_path(
+        path: &str,
+        chroms: Rc<ChromSet>,
+        options: &MergeOptions,
+    ) -> std::io::Result<MergeInput> {
+        log::info!("reading {}", path);
+        let mut reader = VcfReader::new(path, chroms.clone())?;
+        check_format_version(&reader.header, path);
+        let filter = options.contig_filter().map_err(as_io_error)?;
+        let force_keep = options
+            .force_keep
+            .as_deref()
+            .map(ForceKeep::load)
+            .transpose()?;
+        let targets = options
+            .targets
+            .as_deref()
+            .map(|path| Targets::load(path, options.target_padding))
+            .transpose()?;
+        let regions = if options.regions.is_empty() {
+            None
+        } else {
+            Some(Targets::regions(&options.regions)?)
+        };
+        let load = |reader: &mut VcfReader, rejected: &mut Vec<Vec<u8>>| {
+            let rejected = match options.on_record_error {
+                RecordErrorAction::Abort => None,
+                RecordErrorAction::Skip | RecordErrorAction::Quarantine => Some(rejected),
+            };
+            load_vcf_core(
+                reader,
+                options,
+                filter.as_ref(),
+                targets.as_ref(),
+                regions.as_ref(),
+                force_keep.as_ref(),
+                rejected,
+            )
+        };
+        let mut rejected = Vec::new();
+        let (records, store): (RecordBatch, Box<dyn RecordStore>) =
+            match load(&mut reader, &mut rejected) {
+                Ok(records) => {
+                    let store =
+                        RecordSeeker::with_error_action(path, chroms, options.on_record_error)?;
+                    (records, Box::new(store))
+                }
+                Err(e)
+                    if options.sort_inputs
+                        && matches!(
+                            SveltError::cause_of(&e),
+                            Some(SveltError::UnsortedInput(_, _))
+                        ) =>
+                {
+                    log::warn!("sorting {} in memory: {}", path, error_chain(&e));
+                    reader.rewind()?;
+                    let mut lines = reader.read_lines()?;
+                    sort_lines(&mut lines, &chroms);
+                    let mut sorted =
+                        VcfReader::from_lines(path, reader.header.clone(), &lines, chroms)?;
+                    rejected.clear();
+                    let records = load(&mut sorted, &mut rejected)?;
+                    let header = Rc::new(reader.header.clone());
+                    let store = MemoryRecordStore::from_lines(header, &lines);
+                    (records, Box::new(store))
+                }
+                Err(e) => return Err(e),
+            };
+        Ok(MergeInput {
+            name: reader.path,
+            header: reader.header,
+            records,
+            store,
+            rejected,
+        })
+    }
+}
 
 pub async fn merge_vcfs(
     out: &str,
@@ -54,85 +209,402 @@ pub async fn merge_vcfs(
 ) -> std::io::Result<()> {
     options.check().map_err(as_io_error)?;
 
-    if vcf.len() > 64 {
-        log::error!(
-            "svelt can only merge up to 64 VCF files at a time ({} given)",
-            vcf.len()
-        );
-        return Err(as_io_error(SveltError::TooManyVcfs(vcf.len())));
+    let chroms = input_chroms(vcf, &options)?;
+
+    if options.audit_fields {
+        let mut headers = Vec::new();
+        for vcf in vcf.iter() {
+            headers.push(VcfReader::new(vcf, chroms.clone())?.header);
+        }
+        let mut output = headers[0].clone();
+        if options.reconcile_annotations {
+            AnnotationReconciler::new(&headers, &mut output, &options.unwanted_info_tags());
+        }
+        add_svelt_header_fields(&mut output, &options)?;
+        remove_unwanted_header_fields(&mut output, &options);
+        let audit = audit_fields(vcf, &headers, &output, &options.unwanted_info_tags());
+        write_field_audit(&audit, &mut std::io::stdout().lock())?;
+        return Ok(());
     }
 
-    let chroms = load_chroms(&vcf[0])?;
-    let chroms = Rc::new(chroms);
-    let mut readers = Vec::new();
+    let inputs = load_inputs(vcf, chroms, &options)?;
+
+    merge_inputs(out, inputs, options, common).await
+}
+
+/// The contigs of the VCFs to be merged (see `--reconcile-contigs` and
+/// `--soft-match-contigs`).
+fn input_chroms(vcf: &[String], options: &MergeOptions) -> std::io::Result<Rc<ChromSet>> {
+    let mut chroms = load_chroms(vcf, options.reconcile_contigs)?;
+    if options.soft_match_contigs {
+        chroms.add_soft_aliases();
+    }
+    Ok(Rc::new(chroms))
+}
+
+/// Load the VCFs to be merged.
+fn load_inputs(
+    vcf: &[String],
+    chroms: Rc<ChromSet>,
+    options: &MergeOptions,
+) -> std::io::Result<Vec<MergeInput>> {
+    let mut inputs = Vec::new();
     for vcf in vcf.iter() {
-        let reader = VcfReader::new(vcf, chroms.clone())?;
-        readers.push(reader);
-    }
-    let n = readers.len();
-
-    let ctx = make_session_context(common);
-
-    let mut acc: Option<DataFrame> = None;
-    for vix in 0..readers.len() {
-        log::info!("reading {}", readers[vix].path);
-        let reader: &mut VcfReader = &mut readers[vix];
-        let records = load_vcf_core(reader)?;
-        let df = ctx
-            .read_batch(records)
-            .map_err(|e| Error::new(ErrorKind::Other, e))?;
-        let df = df
-            .with_column("vix", lit(1u64 << vix))?
-            .with_column("row_id", RowKey::make(col("row_num"), vix as u32))?;
-
-        if let Some(df0) = acc {
-            let df = df0.union(df)?;
-            acc = Some(df);
-        } else {
-            acc = Some(df)
+        let input = MergeInput::from_path(vcf, chroms.clone(), options)?;
+        inputs.push(input);
+    }
+    Ok(inputs)
+}
+
+/// Merge inputs that have already been loaded.
+pub async fn merge_inputs(
+    out: &str,
+    inputs: Vec<MergeInput>,
+    options: Rc<MergeOptions>,
+    common: &CommonOptions,
+) -> std::io::Result<()> {
+    merge_inputs_with_observer(
+        out,
+        inputs,
+        options,
+        common,
+        Rc::new(RefCell::new(NullObserver)),
+    )
+    .await
+}
+
+/// Merge inputs that have already been loaded, calling the hooks of `observer`
+/// as the merge proceeds.
+pub async fn merge_inputs_with_observer(
+    out: &str,
+    inputs: Vec<MergeInput>,
+    options: Rc<MergeOptions>,
+    common: &CommonOptions,
+    observer: Rc<RefCell<dyn MergeObserver>>,
+) -> std::io::Result<()> {
+    options.check().map_err(as_io_error)?;
+
+    if !options.input_labels.is_empty() && options.input_labels.len() != inputs.len() {
+        return Err(as_io_error(SveltError::BadOptionValue(
+            String::from("--input-labels"),
+            format!(
+                "{} labels given for {} inputs",
+                options.input_labels.len(),
+                inputs.len()
+            ),
+        )));
+    }
+
+    let schema = vcf_core_schema();
+    let contigs = if options.reconcile_contigs {
+        let headers: Vec<&Header> = inputs.iter().map(|input| &input.header).collect();
+        reconcile_contigs(&headers).map_err(as_io_error)?
+    } else {
+        inputs[0].header.contigs().clone()
+    };
+    let mut chroms = ChromSet::from(
+        contigs
+            .keys()
+            .map(|name| name.as_str())
+            .collect::<Vec<&str>>()
+            .as_ref(),
+    );
+    if options.reconcile_contigs {
+        chroms.accept_partial_headers();
+    }
+    for input in inputs.iter() {
+        check_chroms(&input.header, &chroms)
+            .map_err(|e| wrap_file_error(e, &input.name))?;
+        if input.records.schema().fields() != schema.fields() {
+            return Err(as_io_error(SveltError::BadInputSchema(input.name.clone())));
+        }
+        // svelt's own fields in an input would collide with this run's.
+        let owned: Vec<&str> = input
+            .header
+            .infos()
+            .keys()
+            .map(|id| id.as_str())
+            .filter(|id| is_svelt_owned(id))
+            .collect();
+        if !owned.is_empty() {
+            if !options.allow_svelt_inputs {
+                return Err(as_io_error(SveltError::SveltOwnedFields(
+                    input.name.clone(),
+                    owned.join(","),
+                )));
+            }
+            log::warn!(
+                "{} has svelt's own INFO fields ({}), which are renamed in the output",
+                input.name,
+                owned.join(",")
+            );
         }
     }
-    let orig = acc.unwrap();
-    let orig = orig
+
+    let n = inputs.len();
+    let names: Vec<String> = inputs.iter().map(|input| input.name.clone()).collect();
+    let aliases: Vec<BTreeMap<String, String>> = inputs
+        .iter()
+        .map(|input| contig_aliases(&input.records))
+        .collect();
+
+    let bundle = match &options.bundle_output {
+        Some(dir) => {
+            let bundle = Bundle::create(dir)?;
+            bundle.write_config(out, &names, &options)?;
+            Some(bundle)
+        }
+        None => None,
+    };
+    let mut headers = Vec::new();
+    let mut seekers = Vec::new();
+    let mut rejected = Vec::new();
+
+    let ctx = make_session_context(common)?;
+    let keys = RowKey::new(n);
+
+    // The inputs are gathered into one table (rather than a union of one
+    // table per input), so the plans don't grow with the number of inputs.
+    let mut batches = Vec::new();
+    for (vix, input) in inputs.into_iter().enumerate() {
+        headers.push(input.header);
+        seekers.push(input.store);
+        rejected.extend(input.rejected);
+        batches.push(add_input_cols(input.records, &input.name, vix, n, &keys)?);
+    }
+    let orig = ctx
+        .read_batches(batches)?
         .with_column("row_key", cast(col("row_id"), DataType::UInt32))?
         .with_column("vix_count", lit(1))?
-        .with_column("vix_set", col("vix"))?
-        .with_column("criteria", nullif(lit(""), lit("")))?;
+        .with_column("criteria", nullif(lit(""), lit("")))?
+        .with_column("merge_score", nullif(lit(1.0f64), lit(1.0f64)))?;
+
+    // Records passed through unmodified (see `--force-keep`) take no part in
+    // the matching, but are put back for the breakend checks.
+    let mut force_kept = HashSet::new();
+    let (orig, kept) = if options.force_keep.is_some() {
+        let kept = prune_columns(
+            orig.clone().filter(col("force_keep"))?,
+            MATCH_COLUMNS,
+            "loading",
+        )?;
+        for recs in kept
+            .clone()
+            .select_columns(&["row_key"])?
+            .collect()
+            .await?
+            .iter()
+        {
+            let row_keys = get_array::<UInt32Array>(recs, "row_key");
+            force_kept.extend(row_keys.values().iter().copied());
+        }
+        (orig.filter(col("force_keep").is_false())?, Some(kept))
+    } else {
+        (orig, None)
+    };
+    let orig = prune_columns(orig, MATCH_COLUMNS, "loading")?;
+
+    // The row_ids of the duplicates collapsed into each record kept.
+    let mut collapsed = HashMap::new();
+    let orig = if options.dedup_within_inputs || options.allow_intra_input_merge {
+        log::info!("looking for duplicates within inputs");
+        let exact_only = !options.dedup_within_inputs;
+        let (orig, duplicates) = dedup_within_inputs(orig, n, &options, exact_only, &ctx).await?;
+        collapsed = duplicates;
+        orig
+    } else {
+        orig
+    };
 
     let mut results = orig.clone();
 
+    if true {
+        log::info!("looking for exact duplicates");
+        let join = exact_duplicates(results.clone(), n, &options, &ctx).await?;
+        let join = observer.borrow_mut().on_candidates("exact", join)?;
+        results = merge_with(results, join, &ctx, "exact", options.best_partner).await?;
+    }
+    let mut partial: Vec<&str> = Vec::new();
     if true {
         log::info!("looking for exact matches on indel type variants");
-        let join = full_exact_indel_join(results.clone(), n)?;
-        results = merge_with(results, join, &ctx, "exact").await?;
+        let stage = async {
+            let join = full_exact_indel_join(results.clone(), n, &options, Pairing::Across)?;
+            let join = observer.borrow_mut().on_candidates("exact", join)?;
+            merge_with(results.clone(), join, &ctx, "exact", options.best_partner).await
+        };
+        let candidates = col("kind").not_eq(lit("BND"));
+        results = within_time_limit(
+            stage,
+            results.clone(),
+            "exact",
+            candidates,
+            &options,
+            &mut partial,
+        )
+        .await?;
     }
     if true {
         log::info!("looking for almost exact matches on insertions");
-        let join = full_exact_locus_ins_join(results.clone(), n, &options)?;
-        results = merge_with(results, join, &ctx, "locus").await?;
+        let stage = async {
+            let join = full_exact_locus_ins_join(results.clone(), n, &options, Pairing::Across)?;
+            let join = observer.borrow_mut().on_candidates("locus", join)?;
+            merge_with(results.clone(), join, &ctx, "locus", options.best_partner).await
+        };
+        let candidates = col("kind").eq(lit("INS"));
+        results = within_time_limit(
+            stage,
+            results.clone(),
+            "locus",
+            candidates,
+            &options,
+            &mut partial,
+        )
+        .await?;
     }
     if true {
         log::info!("looking for exact matches on breakends");
-        let join = full_exact_bnd(results.clone(), n)?;
-        results = merge_with(results, join, &ctx, "exact").await?;
+        let stage = async {
+            let join = full_exact_bnd(results.clone(), n, &options, Pairing::Across)?;
+            let join = observer.borrow_mut().on_candidates("exact", join)?;
+            merge_with(results.clone(), join, &ctx, "exact", options.best_partner).await
+        };
+        let candidates = col("kind").eq(lit("BND"));
+        results = within_time_limit(
+            stage,
+            results.clone(),
+            "exact",
+            candidates,
+            &options,
+            &mut partial,
+        )
+        .await?;
     }
     if true {
         log::info!("looking for approximate matches on breakends (here-there)");
-        let join = approx_bnd_here_there_join(results.clone(), n, &options)?;
-        results = merge_with(results, join, &ctx, "here").await?;
+        let stage = async {
+            let join = approx_bnd_here_there_join(results.clone(), n, &options)?;
+            let join = observer.borrow_mut().on_candidates("here", join)?;
+            merge_with(results.clone(), join, &ctx, "here", options.best_partner).await
+        };
+        let candidates = col("kind").eq(lit("BND"));
+        results = within_time_limit(
+            stage,
+            results.clone(),
+            "here",
+            candidates,
+            &options,
+            &mut partial,
+        )
+        .await?;
     }
     if true {
         log::info!("looking for approximate matches on breakends (there-here)");
-        let join = approx_bnd_there_here_join(results.clone(), n, &options)?;
-        results = merge_with(results, join, &ctx, "there").await?;
+        let stage = async {
+            let join = approx_bnd_there_here_join(results.clone(), n, &options)?;
+            let join = observer.borrow_mut().on_candidates("there", join)?;
+            merge_with(results.clone(), join, &ctx, "there", options.best_partner).await
+        };
+        let candidates = col("kind").eq(lit("BND"));
+        results = within_time_limit(
+            stage,
+            results.clone(),
+            "there",
+            candidates,
+            &options,
+            &mut partial,
+        )
+        .await?;
     }
-    if true {
-        log::info!("looking for nearby matches on indel type variants");
-        let join = approx_near_join(results.clone(), n, &options, &ctx).await?;
-        results = merge_with(results, join, &ctx, "near").await?;
+    for rule in NearRule::in_use(&options) {
+        let criterion = rule.criterion();
+        match rule {
+            NearRule::Indel => log::info!("looking for nearby matches on indel type variants"),
+            NearRule::Breakpoints => log::info!("looking for inversions with nearby breakpoints"),
+            NearRule::Copies => log::info!("looking for nearby duplications of similar copies"),
+        }
+        let deadline = options
+            .criterion_time_limit
+            .map(|secs| Instant::now() + Duration::from_secs_f64(secs));
+        let stage = async {
+            let join = approx_near_join(
+                results.clone(),
+                n,
+                &options,
+                rule,
+                Pairing::Across,
+                &ctx,
+                deadline,
+            )
+            .await?;
+            let join = observer.borrow_mut().on_candidates(criterion, join)?;
+            merge_with(results.clone(), join, &ctx, criterion, options.best_partner).await
+        };
+        let candidates = rule.candidates_expr(&options);
+        results = within_time_limit(
+            stage,
+            results.clone(),
+            criterion,
+            candidates,
+            &options,
+            &mut partial,
+        )
+        .await?;
+    }
+    if let Some(min_overlap) = options.min_reciprocal_overlap {
+        log::info!("looking for overlapping deletions, duplications and inversions");
+        let deadline = options
+            .criterion_time_limit
+            .map(|secs| Instant::now() + Duration::from_secs_f64(secs));
+        let stage = async {
+            let join =
+                reciprocal_overlap_join(results.clone(), n, &options, min_overlap, &ctx, deadline)
+                    .await?;
+            let join = observer.borrow_mut().on_candidates("overlap", join)?;
+            merge_with(results.clone(), join, &ctx, "overlap", options.best_partner).await
+        };
+        let candidates = col("kind").in_list(vec![lit("DEL"), lit("DUP"), lit("INV")], false);
+        results = within_time_limit(
+            stage,
+            results.clone(),
+            "overlap",
+            candidates,
+            &options,
+            &mut partial,
+        )
+        .await?;
+    }
+    if let Some(min_identity) = options.min_insertion_identity {
+        log::info!("looking for nearby insertions with similar sequences");
+        let stage = async {
+            let join = similar_insertion_join(results.clone(), n, &options, min_identity)?;
+            let join = observer.borrow_mut().on_candidates("similar", join)?;
+            merge_with(results.clone(), join, &ctx, "similar", options.best_partner).await
+        };
+        let candidates = col("kind").eq(lit("INS"));
+        results = within_time_limit(
+            stage,
+            results.clone(),
+            "similar",
+            candidates,
+            &options,
+            &mut partial,
+        )
+        .await?;
     }
 
-    results = unpaired_breakend_check(results).await?;
+    // With --drop-uncalled, groups in which no sample has the variant are
+    // left out here, before the breakend checks, the merge table, or anything
+    // else sees them (records passed through by --force-keep are kept).
+    if options.drop_uncalled {
+        results = drop_uncalled_groups(results).await?;
+    }
+    if let Some(kept) = kept {
+        results = results.union_by_name(kept)?;
+    }
+    results = unpaired_breakend_check(results, &options, &ctx).await?;
+    results = breakend_events(results, &options, &ctx).await?;
+    results = prune_columns(results, MATCH_COLUMNS, "matching")?;
+    results = observer.borrow_mut().on_groups(results)?;
 
     let mut reference = None;
     if let Some(reference_filename) = &options.reference {
@@ -147,48 +619,85 @@ pub async fn merge_vcfs(
 
     let mut annot = false;
     if let Some(features) = &options.annotate_insertions {
-        let ins = results
+        // Insertions are classified by their sequence, and deletions (with
+        // --annotate-deletions) by the reference sequence they remove.
+        let class_key = if options.annotate_deletions {
+            let locus = concat(vec![
+                cast(col("chrom"), DataType::Utf8),
+                lit(":"),
+                cast(col("start"), DataType::Utf8),
+                lit("-"),
+                cast(col("end"), DataType::Utf8),
+            ]);
+            when(col("kind").eq(lit("DEL")), locus).otherwise(col("seq_hash"))?
+        } else {
+            col("seq_hash")
+        };
+        results = results.with_column("class_key", class_key)?;
+
+        let mut ins = results
             .clone()
             .select(vec![
-                col("seq_hash"),
+                col("class_key").alias("seq_hash"),
                 length(col("alt_seq")).alias("length"),
                 col("alt_seq"),
             ])?
-            .filter(length(col("alt_seq")).gt(lit(0)))?
-            .distinct()?;
-
-        let ins = ins.collect().await?;
-        let classifications = classify::find_classifications(ins, features, &ctx).await?;
-        if false {
-            classifications
-                .clone()
-                .sort_by(vec![col("distance")])?
-                .show()
-                .await?;
+            .filter(length(col("alt_seq")).gt(lit(0)))?;
+        if options.annotate_deletions {
+            let deleted = classify::deleted_sequences(
+                results.clone(),
+                reference.as_ref().unwrap(),
+                options.max_annotated_deletion,
+                &ctx,
+            )
+            .await?;
+            ins = ins.union(deleted)?;
         }
 
+        let classifications = classify::find_classifications(
+            ins,
+            features,
+            options.classify_batch_size,
+            &ctx,
+        )
+        .await?;
+        show_table(
+            "insertion classifications",
+            classifications.clone().sort_by(vec![col("distance")])?,
+        )
+        .await?;
+
         results = results
             .join(
                 classifications,
                 JoinType::Left,
-                &["seq_hash"],
+                &["class_key"],
                 &["query_name"],
                 None,
             )?
-            .drop_columns(&["query_name"])?
+            .drop_columns(&["query_name", "class_key"])?
             .with_column("class", concat(vec![lit(""), col("class")]))?
             .with_column("strand", concat(vec![lit(""), col("strand")]))?;
         annot = true;
     }
 
-    results = construct_variant_ids(results, &ctx).await?;
+    results = construct_variant_ids(results, &options.id_format, &ctx).await?;
 
     results = add_primary_cols(results)?;
+    results = add_offset_cols(results)?;
 
-    if let Some(table_out) = &options.write_merge_table {
-        produce_reporting_table(results.clone(), &table_out).await?;
-    }
+    // The merge table is written once the output is done, so the offsets
+    // can be given relative to the emitted records.
+    let report = (options.write_merge_table.is_some() || bundle.is_some()).then(|| results.clone());
+
+    let backward_mates = if options.synthesize_bnd_mates || options.tra_to_bnd {
+        unpaired_breakend_mates(results.clone()).await?
+    } else {
+        Vec::new()
+    };
 
+    let results = prune_columns(results, OUTPUT_COLUMNS, "annotation")?;
+    let columns = results.schema().fields().len();
     let table = results
         .sort_by(vec![
             col("chrom_id"),
@@ -199,36 +708,159 @@ pub async fn merge_vcfs(
         ])?
         .collect()
         .await?;
+    log::info!(
+        "merged table: {} rows, {} columns, {} bytes",
+        table.iter().map(|recs| recs.num_rows()).sum::<usize>(),
+        columns,
+        table
+            .iter()
+            .map(|recs| recs.get_array_memory_size())
+            .sum::<usize>()
+    );
 
-    let mut sample_names: Vec<String> = Vec::new();
-    let mut vix_samples = Vec::new();
-
-    for h in readers.iter().map(|r| &r.header) {
-        for s in h.sample_names() {
-            sample_names.push(s.clone());
+    // Tell each input which of its records will be taken (including any
+    // duplicates collapsed within it), so the rest needn't be held on to.
+    let mut plans: Vec<Vec<u32>> = vec![Vec::new(); n];
+    for recs in table.iter() {
+        let row_ids = get_array::<Int64Array>(recs, "row_id");
+        for row_id in row_ids.values().iter() {
+            let row_id = *row_id as u32;
+            let duplicates = collapsed.get(&row_id).into_iter().flatten();
+            for key in std::iter::once(&row_id).chain(duplicates) {
+                let (vix, rn) = keys.decode(*key);
+                plans[vix as usize].push(rn);
+            }
         }
-        vix_samples.push(h.sample_names().len());
+    }
+    for (seeker, plan) in seekers.iter_mut().zip(plans.iter()) {
+        seeker.plan(plan);
     }
 
-    let mut seekers = Vec::new();
-    for path in vcf.iter() {
-        let seeker = RecordSeeker::new(path, chroms.clone())?;
-        seekers.push(seeker);
+    // Samples with the same name are collapsed with --consensus-samples.
+    let layout = SampleLayout::new(&headers, options.consensus_samples);
+    if layout.is_collapsed() {
+        log::info!(
+            "{} samples in the inputs collapsed into {} by name",
+            headers
+                .iter()
+                .map(|h| h.sample_names().len())
+                .sum::<usize>(),
+            layout.len()
+        );
     }
 
-    let mut header = readers[0].header.clone();
-    *header.sample_names_mut() = SampleNames::from_iter(sample_names.iter().map(|s| s.clone()));
-    add_svelt_header_fields(&mut header, &options.unwanted_info)?;
+    let mut header = headers[0].clone();
+    *header.contigs_mut() = contigs;
+    *header.sample_names_mut() = SampleNames::from_iter(layout.names().iter().cloned());
+    // svelt's own fields in the inputs (see `--allow-svelt-inputs`) are
+    // declared under their new names.
+    header.infos_mut().retain(|id, _| !is_svelt_owned(id));
+    for (vix, h) in headers.iter().enumerate() {
+        for (id, info) in h.infos().iter().filter(|(id, _)| is_svelt_owned(id)) {
+            header
+                .infos_mut()
+                .insert(input_svelt_field(vix, id), info.clone());
+        }
+    }
+    // Symbolic ALTs (and their subtypes) may be declared in any of the inputs.
+    for h in headers.iter().skip(1) {
+        for (id, alt) in h.alternative_alleles().iter() {
+            if !header.alternative_alleles().contains_key(id) {
+                header
+                    .alternative_alleles_mut()
+                    .insert(id.clone(), alt.clone());
+            }
+        }
+    }
+    // Annotation fields may likewise be declared in any of the inputs.
+    let annotations = options
+        .reconcile_annotations
+        .then(|| AnnotationReconciler::new(&headers, &mut header, &options.unwanted_info_tags()));
+    add_svelt_header_fields(&mut header, &options)?;
+    let callers: Vec<Option<&str>> = (0..n)
+        .map(|vix| options.input_label(vix).map(|(caller, _)| caller))
+        .collect();
+    FormatMap::parse(&options.format_map)
+        .map_err(as_io_error)?
+        .declare(&headers, &callers, &mut header);
+    remove_unwanted_header_fields(&mut header, &options);
+    for (vix, aliases) in aliases.iter().enumerate() {
+        if aliases.is_empty() {
+            continue;
+        }
+        let name = Path::new(&names[vix])
+            .file_name()
+            .map_or(names[vix].as_str(), |name| {
+                name.to_str().unwrap_or(&names[vix])
+            });
+        let mapping: Vec<String> = aliases
+            .iter()
+            .map(|(alias, contig)| format!("{}={}", alias, contig))
+            .collect();
+        log::info!(
+            "contig aliases applied to {}: {}",
+            names[vix],
+            mapping.join(", ")
+        );
+        let key = "sveltContigAliases".parse().map_err(Error::other)?;
+        let value = format!("input {} ({}): {}", vix, name, mapping.join(", "));
+        header
+            .insert(key, vcf::header::record::Value::from(value))
+            .map_err(Error::other)?;
+    }
+    observer.borrow_mut().on_header(&mut header)?;
+    if !partial.is_empty() {
+        log::warn!(
+            "the output was produced without completing the criteria: {}",
+            partial.join(",")
+        );
+        let key = "sveltPartialCriteria"
+            .parse()
+            .map_err(Error::other)?;
+        header
+            .insert(key, vcf::header::record::Value::from(partial.join(",")))
+            .map_err(Error::other)?;
+    }
+
+    let mut qc = (options.qc_report.is_some() || bundle.is_some()).then(|| {
+        let headers: Vec<&Header> = headers.iter().collect();
+        QcReport::new(&names, &headers)
+    });
+    let qc_format = options.qc_report_format;
+    let qc_out = options.qc_report.clone();
+    let mut agreement = options
+        .agreement_matrix
+        .as_ref()
+        .map(|_| AgreementMatrix::new(&names));
 
-    let mut builder = MergeBuilder::new(out, options, header, reference)?;
+    let mut builder = MergeBuilder::new(out, options.clone(), header, reference)?;
+    builder.observe(observer.clone());
+    builder.expect_mates(backward_mates);
+    builder.force_keep(force_kept);
+    if report.is_some() {
+        builder.track_emitted();
+    }
+    if options.on_record_error == RecordErrorAction::Quarantine
+        && let Some(quarantine_out) = &options.quarantine
+    {
+        builder.quarantine(Quarantine::new(quarantine_out, &headers[0])?);
+    }
+    if let Some(annotations) = annotations {
+        builder.reconcile_annotations(annotations);
+    }
+    builder.reject_lines(&rejected)?;
 
     let mut current_row_key = u32::MAX;
     let mut current_row: Vec<Option<u32>> = (0..n).into_iter().map(|_| None).collect();
     let mut current_row_ids: Vec<String> = (0..n).into_iter().map(|_| String::new()).collect();
+    let mut current_row_collapsed: Vec<Vec<String>> = vec![Vec::new(); n];
     let mut current_row_alts: Vec<Option<String>> = (0..n).into_iter().map(|_| None).collect();
     let mut current_row_paired_bnd = false;
     let mut current_row_criteria = String::new();
+    let mut current_row_merge_score: Option<f64> = None;
+    let mut current_row_event_id = String::new();
     let mut current_row_classification = None;
+    let mut current_row_spread = GroupSpread::default();
 
     for recs in table.into_iter() {
         if false {
@@ -239,9 +871,14 @@ pub async fn merge_vcfs(
         let variant_ids = get_array::<GenericStringArray<i32>>(&recs, "variant_id");
         let row_ids = get_array::<Int64Array>(&recs, "row_id");
         let row_keys = get_array::<UInt32Array>(&recs, "row_key");
-        let alt_seqs = get_array::<GenericStringArray<i32>>(&recs, "alt_seq");
+        let alt_seqs = get_array::<GenericStringArray<i64>>(&recs, "alt_seq");
         let paired_bnds = get_array::<BooleanArray>(&recs, "paired_bnd");
         let criteria = get_array::<GenericStringArray<i32>>(&recs, "criteria");
+        let merge_scores = get_array::<Float64Array>(&recs, "merge_score");
+        let event_ids = get_array::<StringArray>(&recs, "event_id");
+        let start_offsets = get_array::<Int64Array>(&recs, "start_offset");
+        let end_offsets = get_array::<Int64Array>(&recs, "end_offset");
+        let length_ratios = get_array::<Float64Array>(&recs, "length_ratio");
         let classifications = if annot {
             let class = get_array::<StringArray>(&recs, "class");
             let strand = get_array::<StringArray>(&recs, "strand");
@@ -273,32 +910,58 @@ pub async fn merge_vcfs(
                         String::new()
                     };
 
+                    if let Some(qc) = &mut qc {
+                        qc.add_group(&recs)?;
+                    }
+                    if let Some(agreement) = &mut agreement {
+                        agreement.add_group(&recs)?;
+                    }
+
                     builder.construct(
+                        current_row_key,
                         recs,
-                        &vix_samples,
+                        &layout,
                         &current_row_ids,
+                        &current_row_collapsed,
                         &current_row_alts,
                         current_row_paired_bnd,
                         &current_row_criteria,
+                        current_row_merge_score,
+                        &current_row_event_id,
                         &feat,
+                        &current_row_spread,
                     )?;
                 }
 
                 current_row = (0..n).into_iter().map(|_| None).collect();
                 current_row_key = row_key;
                 current_row_ids = (0..n).into_iter().map(|_| String::new()).collect();
+                current_row_collapsed = vec![Vec::new(); n];
                 current_row_alts = (0..n).into_iter().map(|_| None).collect();
                 current_row_paired_bnd = false;
                 current_row_criteria = String::new();
+                current_row_merge_score = None;
+                current_row_event_id = String::new();
                 current_row_classification = None;
+                current_row_spread = GroupSpread::default();
             }
 
-            let (vix, rn) = RowKey::decode(row_id);
+            let (vix, rn) = keys.decode(row_id);
             current_row[vix as usize] = Some(rn);
 
             let variant_id = String::from(variant_ids.value(i));
             current_row_ids[vix as usize] = variant_id;
 
+            if let Some(duplicates) = collapsed.get(&row_id) {
+                for duplicate in duplicates {
+                    let (vix, rn) = keys.decode(*duplicate);
+                    if let Some((_, rec)) = seekers[vix as usize].take(rn)? {
+                        current_row_collapsed[vix as usize]
+                            .extend(rec.ids().iter().map(String::from));
+                    }
+                }
+            }
+
             let alt_seq = alt_seqs.value(i);
             if alt_seq.len() > 0 {
                 current_row_alts[vix as usize] = Some(String::from(alt_seq));
@@ -311,6 +974,22 @@ pub async fn merge_vcfs(
                 current_row_criteria = String::from(crit);
             }
 
+            if merge_scores.is_valid(i) {
+                let score = merge_scores.value(i);
+                current_row_merge_score =
+                    Some(current_row_merge_score.map_or(score, |s: f64| s.min(score)));
+            }
+
+            if event_ids.is_valid(i) {
+                current_row_event_id = String::from(event_ids.value(i));
+            }
+
+            current_row_spread.add(
+                start_offsets.is_valid(i).then(|| start_offsets.value(i)),
+                end_offsets.is_valid(i).then(|| end_offsets.value(i)),
+                length_ratios.is_valid(i).then(|| length_ratios.value(i)),
+            );
+
             if let Some((class, strand)) = &classifications {
                 let cls = class.value(i);
                 if cls.len() > 0 {
@@ -337,34 +1016,180 @@ pub async fn merge_vcfs(
             String::new()
         };
 
+        if let Some(qc) = &mut qc {
+            qc.add_group(&recs)?;
+        }
+        if let Some(agreement) = &mut agreement {
+            agreement.add_group(&recs)?;
+        }
+
         builder.construct(
+            current_row_key,
             recs,
-            &vix_samples,
+            &layout,
             &current_row_ids,
+            &current_row_collapsed,
             &current_row_alts,
             current_row_paired_bnd,
             &current_row_criteria,
+            current_row_merge_score,
+            &current_row_event_id,
             &feat,
+            &current_row_spread,
+        )?;
+    }
+    builder.finish()?;
+    for seeker in seekers.iter() {
+        seeker.log_usage();
+    }
+
+    if let Some(qc) = &mut qc {
+        if let Some(qc_out) = &qc_out {
+            qc.finish(qc_out, qc_format)?;
+        }
+        if let Some(bundle) = &bundle {
+            qc.finish(&bundle.path(BUNDLE_SUMMARY), QcReportFormat::Json)?;
+        }
+    }
+    if let Some(agreement) = &mut agreement
+        && let Some(agreement_out) = &options.agreement_matrix
+    {
+        agreement.finish(agreement_out, options.agreement_matrix_format)?;
+    }
+
+    if let Some(report) = report {
+        let inputs: Vec<InputProvenance> = headers
+            .iter()
+            .enumerate()
+            .map(|(vix, header)| InputProvenance {
+                label: options.input_labels.get(vix).unwrap_or(&names[vix]).clone(),
+                samples: header.sample_names().iter().cloned().collect(),
+            })
+            .collect();
+        let table = produce_reporting_table(
+            report,
+            builder.take_emitted(),
+            &LengthRule::new(&options),
+            options.bnd_insertion_window,
+            &inputs,
+            &ctx,
         )?;
+        if let Some(table_out) = &options.write_merge_table {
+            write_reporting_table(table.clone(), table_out).await?;
+        }
+        if let Some(bundle) = &bundle {
+            bundle.write_merge_table(table).await?;
+        }
+    }
+
+    if let Some(bundle) = &bundle {
+        bundle.add_vcf(out)?;
     }
 
     Ok(())
 }
 
-fn load_chroms(path: &str) -> std::io::Result<ChromSet> {
+/// Apply one stage of merging, unless it runs for longer than
+/// `--criterion-time-limit`, in which case it is abandoned, leaving `results`
+/// as they were, and the criterion is noted in `partial`.
+///
+/// The `candidates` are the records the stage considers, for reporting the
+/// regions most likely to have held it up.
+async fn within_time_limit<'a, F>(
+    stage: F,
+    results: DataFrame,
+    criterion: &'a str,
+    candidates: Expr,
+    options: &MergeOptions,
+    partial: &mut Vec<&'a str>,
+) -> std::io::Result<DataFrame>
+where
+    F: Future<Output = std::io::Result<DataFrame>>,
+{
+    let Some(secs) = options.criterion_time_limit else {
+        return stage.await;
+    };
+    match tokio::time::timeout(Duration::from_secs_f64(secs), stage).await {
+        Ok(Err(e)) if e.kind() == ErrorKind::TimedOut => {}
+        Ok(res) => return res,
+        Err(_) => {}
+    }
+
+    log::warn!(
+        "'{}' matching took longer than {}s, skipping the rest of it",
+        criterion,
+        secs
+    );
+    let regions = results
+        .clone()
+        .filter(candidates)?
+        .aggregate(
+            vec![
+                cast(col("chrom"), DataType::Utf8).alias("chrom"),
+                (col("start") / lit(CROWDED_REGION_SIZE)).alias("bin"),
+            ],
+            vec![count(lit(1)).alias("records")],
+        )?
+        .sort(vec![col("records").sort(false, false)])?
+        .limit(0, Some(CROWDED_REGIONS_REPORTED))?
+        .collect()
+        .await?;
+    for recs in regions.iter() {
+        let chroms = get_array::<StringArray>(recs, "chrom");
+        let bins = get_array::<Int32Array>(recs, "bin");
+        let counts = get_array::<Int64Array>(recs, "records");
+        for i in 0..recs.num_rows() {
+            let start = bins.value(i) * CROWDED_REGION_SIZE;
+            log::warn!(
+                "  {}:{}-{} has {} candidate records",
+                chroms.value(i),
+                start,
+                start + CROWDED_REGION_SIZE,
+                counts.value(i)
+            );
+        }
+    }
+
+    if !partial.contains(&criterion) {
+        partial.push(criterion);
+    }
+    Ok(results)
+}
+
+/// The size of the regions reported as crowded when a criterion times out.
+const CROWDED_REGION_SIZE: i32 = 1_000_000;
+
+/// The number of crowded regions reported when a criterion times out.
+const CROWDED_REGIONS_REPORTED: usize = 5;
+
+/// The contigs of the first of the `vcfs`, or with `reconcile`, those of
+/// all of them.
+fn load_chroms(vcfs: &[String], reconcile: bool) -> std::io::Result<ChromSet> {
+    let mut headers = Vec::new();
+    for path in vcfs.iter().take(if reconcile { vcfs.len() } else { 1 }) {
+        headers.push(load_header(path)?);
+    }
+    let headers: Vec<&Header> = headers.iter().collect();
+    let contigs = if reconcile {
+        reconcile_contigs(&headers).map_err(as_io_error)?
+    } else {
+        headers[0].contigs().clone()
+    };
+
+    let names: Vec<&str> = contigs.keys().map(|name| name.as_str()).collect();
+    let mut chroms = ChromSet::from(names.as_ref());
+    if reconcile {
+        chroms.accept_partial_headers();
+    }
+    Ok(chroms)
+}
+
+fn load_header(path: &str) -> std::io::Result<Header> {
     FileContext::new(path).with(|| {
         let reader = autocompress::autodetect_open(path)?;
         let mut reader: vcf::io::Reader<Box<dyn BufRead>> =
             vcf::io::reader::Builder::default().build_from_reader(reader)?;
-        let header: Header = reader.read_header()?;
-
-        let mut names: Vec<&str> = Vec::new();
-        for contig in header.contigs() {
-            let chrom = contig.0;
-            names.push(chrom);
-        }
-        let chroms = ChromSet::from(names.as_ref());
-        Ok(chroms)
+        reader.read_header()
     })
 }
 
@@ -379,6 +1204,104 @@ fn get_array<'a, Type: 'static>(recs: &'a RecordBatch, name: &str) -> &'a Type {
         .unwrap()
 }
 
+/// Add the columns identifying the input of each record: its index (`vix`),
+/// the set of inputs of its group (`vix_set`, initially just its own), and the
+/// `row_id` (see `RowKey`). Fails if the input has more records than can be
+/// given keys.
+fn add_input_cols(
+    recs: RecordBatch,
+    name: &str,
+    vix: usize,
+    n: usize,
+    keys: &RowKey,
+) -> std::io::Result<RecordBatch> {
+    let vix_set = VixSet::singleton(vix, n);
+    let row_nums = get_array::<UInt32Array>(&recs, "row_num");
+    if let Some(most) = row_nums.values().iter().max()
+        && *most > keys.capacity()
+    {
+        return Err(as_io_error(SveltError::TooManyRecords(
+            name.to_string(),
+            row_nums.len(),
+            keys.capacity() as usize + 1,
+        )));
+    }
+
+    let mut vix_builder = PrimitiveBuilder::<UInt32Type>::new();
+    let mut vix_set_builder = BinaryBuilder::new();
+    let mut row_id_builder = PrimitiveBuilder::<Int64Type>::new();
+    for rn in row_nums.values().iter() {
+        vix_builder.append_value(vix as u32);
+        vix_set_builder.append_value(vix_set.as_bytes());
+        row_id_builder.append_value(keys.encode(vix as u32, *rn) as i64);
+    }
+
+    let mut fields: Vec<Field> = recs
+        .schema()
+        .fields()
+        .iter()
+        .map(|f| f.as_ref().clone())
+        .collect();
+    fields.push(Field::new("vix", DataType::UInt32, false));
+    fields.push(Field::new("vix_set", DataType::Binary, false));
+    fields.push(Field::new("row_id", DataType::Int64, false));
+    let mut columns = recs.columns().to_vec();
+    columns.push(Arc::new(vix_builder.finish()));
+    columns.push(Arc::new(vix_set_builder.finish()));
+    columns.push(Arc::new(row_id_builder.finish()));
+
+    RecordBatch::try_new(Arc::new(Schema::new(fields)), columns)
+        .map_err(Error::other)
+}
+
+/// The aliases applied to the mate contigs of an input (see
+/// `--soft-match-contigs`), from the name used in the input to the declared
+/// contig.
+fn contig_aliases(recs: &RecordBatch) -> BTreeMap<String, String> {
+    let chrom2 = get_array::<DictionaryArray<UInt16Type>>(recs, "chrom2");
+    let chrom2 = chrom2.downcast_dict::<StringArray>().unwrap();
+    let chrom2_alias = get_array::<StringArray>(recs, "chrom2_alias");
+
+    let mut aliases = BTreeMap::new();
+    for i in 0..recs.num_rows() {
+        if chrom2_alias.is_valid(i) && chrom2.is_valid(i) {
+            aliases
+                .entry(String::from(chrom2_alias.value(i)))
+                .or_insert_with(|| String::from(chrom2.value(i)));
+        }
+    }
+    aliases
+}
+
+/// Leave out the groups (see `--drop-uncalled`) in which a record has
+/// genotypes, but none of them has a sample with a non-reference genotype
+/// (see `tables::calls_variant`).
+async fn drop_uncalled_groups(tbl: DataFrame) -> std::io::Result<DataFrame> {
+    let uncalled = tbl
+        .clone()
+        .aggregate(
+            vec![col("row_key")],
+            vec![bool_or(col("called")).alias("group_called")],
+        )?
+        .filter(col("group_called").is_false())?
+        .select(vec![col("row_key").alias("uncalled_row_key")])?;
+    let count = uncalled.clone().count().await?;
+    if count > 0 {
+        log::info!(
+            "{} merged records with no non-reference genotypes were left out",
+            count
+        );
+    }
+    let tbl = tbl.join(
+        uncalled,
+        JoinType::LeftAnti,
+        &["row_key"],
+        &["uncalled_row_key"],
+        None,
+    )?;
+    Ok(tbl)
+}
+
 fn add_primary_cols(tbl: DataFrame) -> std::io::Result<DataFrame> {
     let rhs = tbl
         .clone()
@@ -400,6 +1323,7 @@ fn add_primary_cols(tbl: DataFrame) -> std::io::Result<DataFrame> {
             col("end").alias("primary_end"),
             col("end2").alias("primary_end2"),
             col("length").alias("primary_length"),
+            col("bnd_ins_len").alias("primary_bnd_ins_len"),
         ])?;
 
     let tbl = tbl
@@ -415,3 +1339,28 @@ fn add_primary_cols(tbl: DataFrame) -> std::io::Result<DataFrame> {
 
     Ok(tbl)
 }
+
+/// Add the distances of the start and the end (for breakends, the mate
+/// position) of each record from those of the primary record of its group,
+/// and the ratio of their lengths (the shorter over the longer), which give
+/// SVELT_MAX_OFFSET and SVELT_MIN_RATIO. The table is expected to have the
+/// primary columns added.
+fn add_offset_cols(tbl: DataFrame) -> std::io::Result<DataFrame> {
+    let len = abs(cast(col("length"), DataType::Float64));
+    let primary_len = abs(cast(col("primary_length"), DataType::Float64));
+    let tbl = tbl
+        .with_column("start_offset", abs(difference("start", "primary_start")))?
+        .with_column(
+            "end_offset",
+            abs(coalesce(vec![
+                difference("end2", "primary_end2"),
+                difference("end", "primary_end"),
+            ])),
+        )?
+        .with_column(
+            "length_ratio",
+            least(vec![len.clone(), primary_len.clone()])
+                / nullif(greatest(vec![len, primary_len]), lit(0.0)),
+        )?;
+    Ok(tbl)
+}
@@ -1,35 +1,55 @@
 use std::{
     io::{Error, ErrorKind},
     sync::Arc,
+    time::Instant,
 };
 
 use datafusion::{
     arrow::{
         array::{
-            DictionaryArray, GenericByteArray, Int32Array, Int64Array, PrimitiveArray,
-            PrimitiveBuilder, RecordBatch, StringArray, UInt16Array, UInt32Array, UInt64Array,
+            Array, BinaryArray, BinaryBuilder, DictionaryArray, GenericByteArray, Int8Array,
+            Int32Array, Int64Array, LargeStringArray, PrimitiveArray, PrimitiveBuilder,
+            RecordBatch, StringArray, UInt16Array, UInt32Array,
         },
+        compute::{SortColumn, concat_batches, lexsort_to_indices, take_record_batch},
         datatypes::{
-            DataType, Field, GenericStringType, Int32Type, Int64Type, Schema, UInt8Type,
-            UInt16Type, UInt32Type, UInt64Type,
+            DataType, Field, Float64Type, GenericStringType, Int32Type, Int64Type, Schema,
+            SchemaRef, UInt8Type, UInt16Type, UInt32Type,
         },
+        error::ArrowError,
     },
     common::JoinType,
-    prelude::{DataFrame, SessionContext, abs, col, lit},
+    prelude::{DataFrame, Expr, SessionContext, abs, col, lit},
 };
 
 use crate::{
-    expressions::prefix_cols,
+    expressions::{difference, prefix_cols},
     heap::{Heap, HeapItem},
+    merge::{
+        columns::{NEAR_COLUMNS, prune_columns},
+        exact::{consistent_insertion_expr, consistent_orientation_expr},
+        genotypes::concordant_genotypes_expr,
+        length::LengthRule,
+        pairing::Pairing,
+        score::{MergeScorer, sequence_similarity},
+        vix_set::{self, disjoint_expr},
+    },
     options::MergeOptions,
     row_key::RowKey,
+    tables::{compatible_symbols, concordant_genotypes},
+    window::Window,
 };
 
+/// How many steps of the near sweep to take between checks of the deadline.
+const DEADLINE_CHECK_INTERVAL: u64 = 1 << 16;
+
 pub(super) fn approx_bnd_here_there_join(
     orig: DataFrame,
     n: usize,
     options: &MergeOptions,
 ) -> std::io::Result<DataFrame> {
+    let scorer = MergeScorer::new(options, "BND");
+
     let candidates = orig.clone().filter(
         lit(true)
             .and(col("kind").eq(lit("BND")))
@@ -49,9 +69,22 @@ pub(super) fn approx_bnd_here_there_join(
                 lit(true)
                     .and(col("lhs_row_id").lt(col("rhs_row_id")))
                     .and(col("lhs_row_key").not_eq(col("rhs_row_key")))
-                    .and(abs(col("lhs_end") - col("rhs_end")).lt(lit(options.position_window)))
-                    .and(abs(col("lhs_end2") - col("rhs_end2")).lt(lit(options.end2_window)))
-                    .and((col("lhs_vix_set") & col("rhs_vix_set")).eq(lit(0))),
+                    .and(bnd_windows(
+                        options,
+                        difference("lhs_end", "rhs_end"),
+                        difference("lhs_end2", "rhs_end2"),
+                    ))
+                    .and(disjoint_expr(col("lhs_vix_set"), col("rhs_vix_set")))
+                    .and(consistent_orientation_expr(options))
+                    .and(consistent_insertion_expr(options))
+                    .and(concordant_genotypes_expr(options)),
+            ),
+        )?
+        .with_column(
+            "score",
+            scorer.bnd_score_expr(
+                difference("lhs_end", "rhs_end"),
+                difference("lhs_end2", "rhs_end2"),
             ),
         )?
         .sort(vec![
@@ -60,7 +93,13 @@ pub(super) fn approx_bnd_here_there_join(
             col("rhs_row_key").sort(true, false),
         ])
         .unwrap()
-        .select_columns(&["lhs_row_key", "lhs_vix_set", "rhs_row_key", "rhs_vix_set"])
+        .select_columns(&[
+            "lhs_row_key",
+            "lhs_vix_set",
+            "rhs_row_key",
+            "rhs_vix_set",
+            "score",
+        ])
         .unwrap();
 
     Ok(exact)
@@ -71,6 +110,8 @@ pub(super) fn approx_bnd_there_here_join(
     n: usize,
     options: &MergeOptions,
 ) -> std::io::Result<DataFrame> {
+    let scorer = MergeScorer::new(options, "BND");
+
     let candidates = orig.clone().filter(
         lit(true)
             .and(col("kind").eq(lit("BND")))
@@ -90,9 +131,22 @@ pub(super) fn approx_bnd_there_here_join(
                 lit(true)
                     .and(col("lhs_row_id").lt(col("rhs_row_id")))
                     .and(col("lhs_row_key").not_eq(col("rhs_row_key")))
-                    .and(abs(col("lhs_end") - col("rhs_end")).lt(lit(options.end2_window)))
-                    .and(abs(col("lhs_end2") - col("rhs_end2")).lt(lit(options.position_window)))
-                    .and((col("lhs_vix_set") & col("rhs_vix_set")).eq(lit(0))),
+                    .and(bnd_windows(
+                        options,
+                        difference("lhs_end2", "rhs_end2"),
+                        difference("lhs_end", "rhs_end"),
+                    ))
+                    .and(disjoint_expr(col("lhs_vix_set"), col("rhs_vix_set")))
+                    .and(consistent_orientation_expr(options))
+                    .and(consistent_insertion_expr(options))
+                    .and(concordant_genotypes_expr(options)),
+            ),
+        )?
+        .with_column(
+            "score",
+            scorer.bnd_score_expr(
+                difference("lhs_end2", "rhs_end2"),
+                difference("lhs_end", "rhs_end"),
             ),
         )?
         .sort(vec![
@@ -101,299 +155,450 @@ pub(super) fn approx_bnd_there_here_join(
             col("rhs_row_key").sort(true, false),
         ])
         .unwrap()
-        .select_columns(&["lhs_row_key", "lhs_vix_set", "rhs_row_key", "rhs_vix_set"])
+        .select_columns(&[
+            "lhs_row_key",
+            "lhs_vix_set",
+            "rhs_row_key",
+            "rhs_vix_set",
+            "score",
+        ])
         .unwrap();
 
     Ok(exact)
 }
 
+/// The positional test for approximately matching breakends, where `near` is the
+/// difference between the positions that should be close, and `far` the difference
+/// between the positions that may be further apart. If a score threshold is given,
+/// it replaces the windows.
+fn bnd_windows(options: &MergeOptions, near: Expr, far: Expr) -> Expr {
+    let scorer = MergeScorer::new(options, "BND");
+    match scorer.threshold() {
+        Some(t) => scorer.bnd_score_expr(near, far).gt_eq(lit(t)),
+        None => abs(near)
+            .lt(lit(options.position_window.get("BND") as i64))
+            .and(abs(far).lt(lit(options.end2_window as i64))),
+    }
+}
+
+/// How the near sweep decides that a pair of records is close enough to merge.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) enum NearRule {
+    /// Records of the same kind whose starts and ends are within the window,
+    /// of similar lengths (the `near` criterion).
+    Indel,
+    /// Inversions whose breakpoints are each within their own window
+    /// (`--inversion-end-window` for the second), whatever their lengths say
+    /// (the `breakpoints` criterion).
+    Breakpoints,
+    /// Duplications whose starts are within the window, and whose copies
+    /// (from start to end) are of similar lengths (the `copies` criterion).
+    Copies,
+}
+
+impl NearRule {
+    /// The rules to apply, in order: with `--kind-specific-criteria`,
+    /// inversions and duplications have their own.
+    pub(super) fn in_use(options: &MergeOptions) -> Vec<NearRule> {
+        if options.kind_specific_criteria {
+            vec![NearRule::Indel, NearRule::Breakpoints, NearRule::Copies]
+        } else {
+            vec![NearRule::Indel]
+        }
+    }
+
+    /// The name of the criterion (see `MERGE_CRITERIA`).
+    pub(super) fn criterion(&self) -> &'static str {
+        match self {
+            NearRule::Indel => "near",
+            NearRule::Breakpoints => "breakpoints",
+            NearRule::Copies => "copies",
+        }
+    }
+
+    /// Which records the rule applies to.
+    pub(super) fn candidates_expr(&self, options: &MergeOptions) -> Expr {
+        match self {
+            NearRule::Indel if options.kind_specific_criteria => {
+                col("kind").in_list(vec![lit("BND"), lit("INV"), lit("DUP")], true)
+            }
+            NearRule::Indel => col("kind").not_eq(lit("BND")),
+            NearRule::Breakpoints => col("kind").eq(lit("INV")),
+            NearRule::Copies => col("kind").eq(lit("DUP")),
+        }
+    }
+}
+
+/// Find pairs of nearby indel type variants with a sweep along the sorted
+/// table, accepting those that pass `rule`.
+///
+/// Gives up (with a `TimedOut` error) if the sweep is still going at `deadline`.
 pub(super) async fn approx_near_join(
     orig: DataFrame,
     n: usize,
     options: &MergeOptions,
+    rule: NearRule,
+    pairing: Pairing,
     ctx: &SessionContext,
+    deadline: Option<Instant>,
 ) -> std::io::Result<DataFrame> {
-    let w = options.position_window as i32;
-    let r = options.length_ratio;
-    let d = options.length_window as i32;
+    let scoring = options.merge_scoring();
+    // The window (or the scale of the score) may differ by kind.
+    let for_kind = |kind: &str| {
+        let scorer = MergeScorer::new(options, kind);
+        let w = Window::new(match scorer.threshold() {
+            Some(_) => scorer.max_position_distance(),
+            None => options.position_window.get(kind),
+        });
+        (scorer, w)
+    };
+    let lengths = LengthRule::new(options);
+    let keys = RowKey::new(n);
 
     // This is the inner loop test:
     //   1. the starts are within w
-    //   2. the ends are within w
-    //   3. the length-ratio is >= r
-    //   4. the lhs row_id is less than the rhs row_id (avoid symmetric comparisons)
-    //   5. the vix (from the row_id) is different - no self-merges.
-    let row_is_good = |lhs: &Row<'_>, rhs: &Row<'_>| {
-        let lhs_key = RowKey::decode(lhs.row_id as u32);
-        let rhs_key = RowKey::decode(rhs.row_id as u32);
-
-        let lhs_abs_len = lhs.length.abs();
-        let rhs_abs_len = rhs.length.abs();
-        let min_abs_len = std::cmp::min(lhs_abs_len, rhs_abs_len);
-        let max_abs_len = std::cmp::max(lhs_abs_len, rhs_abs_len);
-
-        (lhs.start - rhs.start).abs() <= w
-            && (lhs.end - rhs.end).abs() <= w
-            && lhs.row_id < rhs.row_id
-            && lhs.row_key != rhs.row_key
-            && lhs_key.0 != rhs_key.0
-            && ((min_abs_len as f64) / (max_abs_len as f64) >= r || max_abs_len - min_abs_len <= d)
+    //   2. the ends are within w (see `Window` for the arithmetic)
+    //   3. the lengths pass the length rule (ratio or difference)
+    //   4. the tests that don't depend on the positions (see `compatible_rows`)
+    // If a score threshold is given, it replaces tests 1-3. Inversions (with
+    // the breakpoints rule) skip test 3, and have their own window for test
+    // 2, and duplications (with the copies rule) replace tests 2 and 3 with
+    // the length rule on their copies, and also have to pass any threshold.
+    // The score of the accepted pair is returned.
+    let pair_score = |lhs: &Row<'_>, rhs: &Row<'_>, scorer: &MergeScorer, w: Window| {
+        if !compatible_rows(lhs, rhs, &keys, pairing, options) {
+            return None;
+        }
+
+        let length_ratio = LengthRule::ratio(lhs.length as i64, rhs.length as i64);
+
+        let similarity = match (scoring, lhs.alt_seq, rhs.alt_seq) {
+            (true, Some(lhs_seq), Some(rhs_seq)) => sequence_similarity(lhs_seq, rhs_seq),
+            _ => None,
+        };
+        let score = scorer.indel_score(
+            Window::distance(lhs.start, rhs.start),
+            Window::distance(lhs.end, rhs.end),
+            length_ratio,
+            similarity,
+        );
+
+        let good = match (rule, scorer.threshold()) {
+            (NearRule::Indel, Some(t)) => score >= t,
+            (NearRule::Indel, None) => {
+                w.contains(lhs.start, rhs.start)
+                    && w.contains(lhs.end, rhs.end)
+                    && lengths.accepts(lhs.length as i64, rhs.length as i64)
+            }
+            (NearRule::Breakpoints, t) => {
+                let end_window = options.inversion_end_window.map_or(w, Window::new);
+                t.is_none_or(|t| score >= t)
+                    && w.contains(lhs.start, rhs.start)
+                    && end_window.contains(lhs.end, rhs.end)
+            }
+            (NearRule::Copies, t) => {
+                let copy = |row: &Row<'_>| row.end as i64 - row.start as i64;
+                t.is_none_or(|t| score >= t)
+                    && w.contains(lhs.start, rhs.start)
+                    && lengths.accepts(copy(lhs), copy(rhs))
+            }
+        };
+        if good { Some(score) } else { None }
     };
 
     let tbl = orig
         .clone()
         .filter(
             lit(true)
-                .and(col("kind").not_eq(lit("BND")))
-                .and(col("vix_count").lt(lit(n as u32))),
-        )?
-        .sort_by(vec![col("kind"), col("chrom_id"), col("start"), col("end")])?;
-    let batch = tbl.collect().await?;
-
-    let mut lhs_row_key_builder = PrimitiveBuilder::<UInt32Type>::new();
-    let mut lhs_vix_set_builder = PrimitiveBuilder::<UInt64Type>::new();
-    let mut lhs_vix_count_builder = PrimitiveBuilder::<UInt32Type>::new();
-    let mut rhs_row_key_builder = PrimitiveBuilder::<UInt32Type>::new();
-    let mut rhs_vix_set_builder = PrimitiveBuilder::<UInt64Type>::new();
-    let mut rhs_vix_count_builder = PrimitiveBuilder::<UInt32Type>::new();
-
-    let mut lhs_heap: Heap<Row<'_>> = Heap::new();
-    let mut lhs_itr = batch.iter().flat_map(|recs| MergeIterator::new(recs));
-    let mut lhs = lhs_itr.next();
-
-    let mut rhs_heap: Heap<Row<'_>> = Heap::new();
-    let mut rhs_itr = batch.iter().flat_map(|recs| MergeIterator::new(recs));
-    let mut rhs = rhs_itr.next();
-
-    let mut cached_kind = String::new();
-    let mut cached_chrom_id = 0;
-
-    while let (Some(lhs_val), Some(rhs_val)) = (&lhs, &rhs) {
-        //log::info!("iterating at {:?} and {:?}", lhs_val, rhs_val);
-
-        // Kind
-        if lhs_val.kind < rhs_val.kind {
-            //log::info!("skip lhs: {}", lhs_val.kind);
-            lhs_heap.clear();
-            rhs_heap.clear();
-            lhs = lhs_itr.next();
-            continue;
-        }
-        if rhs_val.kind < lhs_val.kind {
-            //log::info!("skip rhs: {}", rhs_val.kind);
-            lhs_heap.clear();
-            rhs_heap.clear();
-            rhs = rhs_itr.next();
-            continue;
+                .and(rule.candidates_expr(options))
+                .and(pairing.candidates_expr(n)),
+        )?;
+    let tbl = prune_columns(tbl, NEAR_COLUMNS, "near join selection")?;
+    let schema = tbl.schema().inner().clone();
+    let batch = sort_rows(schema, &tbl.collect().await?)?;
+
+    let mut pairs = PairBuilder::new();
+
+    // The rows are sorted by kind, chrom and start, so a single sweep, keeping
+    // the rows of the current kind and chrom that are still within the window,
+    // sees every pair of rows that might be merged.
+    let mut heap: Heap<Row<'_>> = Heap::new();
+    let mut current: Option<(&str, MergeScorer, Window)> = None;
+    let mut steps: u64 = 0;
+    for row in MergeIterator::new(&batch) {
+        steps += 1;
+        if steps.is_multiple_of(DEADLINE_CHECK_INTERVAL)
+            && deadline.is_some_and(|d| Instant::now() > d)
+        {
+            return Err(Error::new(
+                ErrorKind::TimedOut,
+                "near matching ran out of time",
+            ));
         }
 
-        // Chrom
-        if lhs_val.chrom_id < rhs_val.chrom_id {
-            //log::info!("skip lhs: {}", lhs_val.chrom_id);
-            lhs_heap.clear();
-            rhs_heap.clear();
-            lhs = lhs_itr.next();
-            continue;
-        }
-        if rhs_val.chrom_id < lhs_val.chrom_id {
-            //log::info!("skip rhs: {}", rhs_val.chrom_id);
-            lhs_heap.clear();
-            rhs_heap.clear();
-            rhs = rhs_itr.next();
-            continue;
+        if heap
+            .front()
+            .is_some_and(|item| item.kind != row.kind || item.chrom_id != row.chrom_id)
+        {
+            heap.clear();
         }
-
-        if cached_kind != lhs_val.kind {
-            cached_kind = String::from(lhs_val.kind);
+        if current.is_none_or(|(kind, _, _)| kind != row.kind) {
+            let (scorer, w) = for_kind(row.kind);
+            current = Some((row.kind, scorer, w));
         }
-        if cached_chrom_id != lhs_val.chrom_id {
-            cached_chrom_id = lhs_val.chrom_id;
-        }
-
-        let lhs_start = lhs_val.start;
-        let rhs_start = rhs_val.start;
-
-        if lhs_start <= rhs_start {
-            while let Some(rhs_item) = rhs_heap.front() {
-                if rhs_item.start + w < lhs_start {
-                    rhs_heap.pop();
-                } else {
-                    break;
-                }
+        let (_, scorer, w) = current.unwrap();
+        while let Some(item) = heap.front() {
+            if w.passed(item.start, row.start) {
+                heap.pop();
+            } else {
+                break;
             }
+        }
 
-            for rhs_item in rhs_heap.iter() {
-                assert_eq!(lhs_val.kind, rhs_item.kind);
-                assert_eq!(lhs_val.chrom_id, rhs_item.chrom_id);
-                assert!(rhs_item.start + w >= lhs_start);
-                if !row_is_good(lhs_val, rhs_item) {
-                    continue;
-                }
-                lhs_row_key_builder.append_value(lhs_val.row_key);
-                lhs_vix_set_builder.append_value(lhs_val.vix_set);
-                lhs_vix_count_builder.append_value(lhs_val.vix_set.count_ones());
-                rhs_row_key_builder.append_value(rhs_item.row_key);
-                rhs_vix_set_builder.append_value(rhs_item.vix_set);
-                rhs_vix_count_builder.append_value(rhs_item.vix_set.count_ones());
-                //log::info!("joining-lhs {:?} and {:?}", lhs_val, rhs_item);
-            }
+        for item in heap.iter() {
+            // The lhs is the row with the lesser row_id.
+            let (lhs, rhs) = if item.row_id < row.row_id {
+                (item, &row)
+            } else {
+                (&row, item)
+            };
+            let Some(score) = pair_score(lhs, rhs, &scorer, w) else {
+                continue;
+            };
+            pairs.add(lhs, rhs, score);
+        }
 
-            lhs_heap.push(lhs.take().unwrap());
-            lhs = lhs_itr.next();
-        } else {
-            while let Some(lhs_item) = lhs_heap.front() {
-                if lhs_item.start + w < rhs_start {
-                    lhs_heap.pop();
-                } else {
-                    break;
-                }
-            }
+        heap.push(row);
+    }
 
-            for lhs_item in lhs_heap.iter() {
-                assert_eq!(rhs_val.kind, lhs_item.kind);
-                assert_eq!(rhs_val.chrom_id, lhs_item.chrom_id);
-                assert!(lhs_item.start + w >= rhs_start);
-                if !row_is_good(lhs_item, rhs_val) {
-                    continue;
-                }
-                lhs_row_key_builder.append_value(lhs_item.row_key);
-                lhs_vix_set_builder.append_value(lhs_item.vix_set);
-                lhs_vix_count_builder.append_value(lhs_item.vix_set.count_ones());
-                rhs_row_key_builder.append_value(rhs_val.row_key);
-                rhs_vix_set_builder.append_value(rhs_val.vix_set);
-                rhs_vix_count_builder.append_value(rhs_val.vix_set.count_ones());
-                //log::info!("joining-rhs {:?} and {:?}", rhs_val, lhs_item);
-            }
+    pairs.finish(ctx)
+}
 
-            rhs_heap.push(rhs.take().unwrap());
-            rhs = rhs_itr.next();
+/// Find pairs of deletions, duplications or inversions that overlap each
+/// other by at least `min_overlap` of both their lengths (the reciprocal
+/// overlap), however far apart their ends are, with a sweep along the sorted
+/// table. The score of a pair is its reciprocal overlap.
+///
+/// Gives up (with a `TimedOut` error) if the sweep is still going at `deadline`.
+pub(super) async fn reciprocal_overlap_join(
+    orig: DataFrame,
+    n: usize,
+    options: &MergeOptions,
+    min_overlap: f64,
+    ctx: &SessionContext,
+    deadline: Option<Instant>,
+) -> std::io::Result<DataFrame> {
+    let keys = RowKey::new(n);
+    let pairing = Pairing::Across;
+
+    let tbl = orig.clone().filter(
+        col("kind")
+            .in_list(vec![lit("DEL"), lit("DUP"), lit("INV")], false)
+            .and(col("end").gt(col("start")))
+            .and(pairing.candidates_expr(n)),
+    )?;
+    let tbl = prune_columns(tbl, NEAR_COLUMNS, "overlap join selection")?;
+    let schema = tbl.schema().inner().clone();
+    let batch = sort_rows(schema, &tbl.collect().await?)?;
+
+    let mut pairs = PairBuilder::new();
+
+    // As for the near sweep, but the rows kept are those of the current kind
+    // and chrom that reach the start of the current one, so by their ends.
+    let mut heap: Heap<ByEnd<'_>> = Heap::new();
+    let mut steps: u64 = 0;
+    for row in MergeIterator::new(&batch) {
+        steps += 1;
+        if steps.is_multiple_of(DEADLINE_CHECK_INTERVAL)
+            && deadline.is_some_and(|d| Instant::now() > d)
+        {
+            return Err(Error::new(
+                ErrorKind::TimedOut,
+                "overlap matching ran out of time",
+            ));
         }
-    }
-
-    // Pick up the last items on either side.
-    // In practice only one side will fire, since
-    // we already dropped out of the loop above.
 
-    // Try the LHS first.
-    while let Some(lhs_val) = &lhs {
-        if lhs_val.kind != cached_kind {
-            break;
+        if heap
+            .front()
+            .is_some_and(|item| item.0.kind != row.kind || item.0.chrom_id != row.chrom_id)
+        {
+            heap.clear();
         }
-        if lhs_val.chrom_id != cached_chrom_id {
-            break;
+        while heap.front().is_some_and(|item| item.0.end <= row.start) {
+            heap.pop();
         }
 
-        let lhs_start = lhs_val.start;
-
-        while let Some(rhs_item) = rhs_heap.front() {
-            if rhs_item.start + w < lhs_start {
-                rhs_heap.pop();
+        for item in heap.iter() {
+            let (lhs, rhs) = if item.0.row_id < row.row_id {
+                (&item.0, &row)
             } else {
-                break;
-            }
-        }
-
-        for rhs_item in rhs_heap.iter() {
-            assert_eq!(lhs_val.kind, rhs_item.kind);
-            assert_eq!(lhs_val.chrom_id, rhs_item.chrom_id);
-            assert!(rhs_item.start + w >= lhs_start);
-            if !row_is_good(lhs_val, rhs_item) {
+                (&row, &item.0)
+            };
+            if !compatible_rows(lhs, rhs, &keys, pairing, options) {
                 continue;
             }
-            lhs_row_key_builder.append_value(lhs_val.row_key);
-            lhs_vix_set_builder.append_value(lhs_val.vix_set);
-            lhs_vix_count_builder.append_value(lhs_val.vix_set.count_ones());
-            rhs_row_key_builder.append_value(rhs_item.row_key);
-            rhs_vix_set_builder.append_value(rhs_item.vix_set);
-            rhs_vix_count_builder.append_value(rhs_item.vix_set.count_ones());
-            //log::info!("joining-lhs {:?} and {:?}", lhs_val, rhs_item);
+            let overlap = reciprocal_overlap((lhs.start, lhs.end), (rhs.start, rhs.end));
+            if overlap >= min_overlap {
+                pairs.add(lhs, rhs, overlap);
+            }
         }
 
-        lhs_heap.push(lhs.take().unwrap());
-        lhs = lhs_itr.next();
+        heap.push(ByEnd(row));
     }
 
-    // Now try the RHS
-    while let Some(rhs_val) = &rhs {
-        if rhs_val.kind != cached_kind {
-            break;
-        }
-        if rhs_val.chrom_id != cached_chrom_id {
-            break;
-        }
+    pairs.finish(ctx)
+}
 
-        let rhs_start = rhs_val.start;
+/// The reciprocal overlap of two intervals: the length of their overlap as
+/// a fraction of the longer of them (so of both).
+fn reciprocal_overlap(lhs: (i32, i32), rhs: (i32, i32)) -> f64 {
+    let overlap = lhs.1.min(rhs.1) as i64 - lhs.0.max(rhs.0) as i64;
+    let longer = (lhs.1 as i64 - lhs.0 as i64).max(rhs.1 as i64 - rhs.0 as i64);
+    if overlap <= 0 || longer <= 0 {
+        return 0.0;
+    }
+    overlap as f64 / longer as f64
+}
 
-        while let Some(lhs_item) = lhs_heap.front() {
-            if lhs_item.start + w < rhs_start {
-                lhs_heap.pop();
-            } else {
-                break;
-            }
-        }
+/// The tests of a pair of rows (the lhs being the one with the lesser
+/// row_id) that don't depend on their positions:
+///   1. the lhs row_id is less than the rhs row_id (avoid symmetric comparisons)
+///   2. the vix (from the row_id) is different - no self-merges (or, when
+///      deduplicating within inputs, the same).
+///   3. symbolic ALTs, if both have them, are compatible (see `compatible_symbols`).
+///   4. with --depth-consistency, the changes in depth, if both have them, agree.
+///   5. with --require-genotype-concordance, the genotypes of shared samples agree.
+fn compatible_rows(
+    lhs: &Row<'_>,
+    rhs: &Row<'_>,
+    keys: &RowKey,
+    pairing: Pairing,
+    options: &MergeOptions,
+) -> bool {
+    let lhs_key = keys.decode(lhs.row_id as u32);
+    let rhs_key = keys.decode(rhs.row_id as u32);
+
+    if !(lhs.row_id < rhs.row_id
+        && lhs.row_key != rhs.row_key
+        && pairing.accepts_inputs(lhs_key.0, rhs_key.0))
+    {
+        return false;
+    }
+    if let (Some(lhs_symbol), Some(rhs_symbol)) = (lhs.alt_symbol, rhs.alt_symbol)
+        && !compatible_symbols(lhs_symbol, rhs_symbol)
+    {
+        return false;
+    }
+    if options.depth_consistency
+        && let (Some(lhs_depth), Some(rhs_depth)) = (lhs.depth_change, rhs.depth_change)
+        && lhs_depth != rhs_depth
+    {
+        return false;
+    }
+    if options.require_genotype_concordance
+        && let (Some(lhs_genotypes), Some(rhs_genotypes)) = (lhs.genotypes, rhs.genotypes)
+        && !concordant_genotypes(lhs_genotypes, rhs_genotypes)
+    {
+        return false;
+    }
+    true
+}
 
-        for lhs_item in lhs_heap.iter() {
-            assert_eq!(rhs_val.kind, lhs_item.kind);
-            assert_eq!(rhs_val.chrom_id, lhs_item.chrom_id);
-            assert!(lhs_item.start + w >= rhs_start);
-            if !row_is_good(lhs_item, rhs_val) {
-                continue;
-            }
-            lhs_row_key_builder.append_value(lhs_item.row_key);
-            lhs_vix_set_builder.append_value(lhs_item.vix_set);
-            lhs_vix_count_builder.append_value(lhs_item.vix_set.count_ones());
-            rhs_row_key_builder.append_value(rhs_val.row_key);
-            rhs_vix_set_builder.append_value(rhs_val.vix_set);
-            rhs_vix_count_builder.append_value(rhs_val.vix_set.count_ones());
-            //log::info!("joining-rhs {:?} and {:?}", rhs_val, lhs_item);
+/// The pairs found by a sweep, made into a table of candidates for `merge_with`.
+struct PairBuilder {
+    lhs_row_key: PrimitiveBuilder<UInt32Type>,
+    lhs_vix_set: BinaryBuilder,
+    lhs_vix_count: PrimitiveBuilder<UInt32Type>,
+    rhs_row_key: PrimitiveBuilder<UInt32Type>,
+    rhs_vix_set: BinaryBuilder,
+    rhs_vix_count: PrimitiveBuilder<UInt32Type>,
+    score: PrimitiveBuilder<Float64Type>,
+}
+
+impl PairBuilder {
+    fn new() -> PairBuilder {
+        PairBuilder {
+            lhs_row_key: PrimitiveBuilder::new(),
+            lhs_vix_set: BinaryBuilder::new(),
+            lhs_vix_count: PrimitiveBuilder::new(),
+            rhs_row_key: PrimitiveBuilder::new(),
+            rhs_vix_set: BinaryBuilder::new(),
+            rhs_vix_count: PrimitiveBuilder::new(),
+            score: PrimitiveBuilder::new(),
         }
+    }
 
-        rhs_heap.push(rhs.take().unwrap());
-        rhs = rhs_itr.next();
+    fn add(&mut self, lhs: &Row<'_>, rhs: &Row<'_>, score: f64) {
+        self.lhs_row_key.append_value(lhs.row_key);
+        self.lhs_vix_set.append_value(lhs.vix_set);
+        self.lhs_vix_count.append_value(vix_set::count(lhs.vix_set));
+        self.rhs_row_key.append_value(rhs.row_key);
+        self.rhs_vix_set.append_value(rhs.vix_set);
+        self.rhs_vix_count.append_value(vix_set::count(rhs.vix_set));
+        self.score.append_value(score);
     }
 
-    let lhs_row_key_array = lhs_row_key_builder.finish();
-    let lhs_vix_set_array = lhs_vix_set_builder.finish();
-    let lhs_vix_count_array = lhs_vix_count_builder.finish();
-    let rhs_row_key_array = rhs_row_key_builder.finish();
-    let rhs_vix_set_array = rhs_vix_set_builder.finish();
-    let rhs_vix_count_array = rhs_vix_count_builder.finish();
-
-    let schema = Arc::new(Schema::new(vec![
-        Field::new("lhs_row_key", DataType::UInt32, false),
-        Field::new("lhs_vix_set", DataType::UInt64, false),
-        Field::new("lhs_vix_count", DataType::UInt32, false),
-        Field::new("rhs_row_key", DataType::UInt32, false),
-        Field::new("rhs_vix_set", DataType::UInt64, false),
-        Field::new("rhs_vix_count", DataType::UInt32, false),
-    ]));
-
-    let recs = RecordBatch::try_new(
-        schema,
-        vec![
-            Arc::new(lhs_row_key_array),
-            Arc::new(lhs_vix_set_array),
-            Arc::new(lhs_vix_count_array),
-            Arc::new(rhs_row_key_array),
-            Arc::new(rhs_vix_set_array),
-            Arc::new(rhs_vix_count_array),
-        ],
-    )
-    .map_err(|e| Error::new(ErrorKind::Other, e))?;
-
-    let res = ctx
-        .read_batch(recs)
+    fn finish(mut self, ctx: &SessionContext) -> std::io::Result<DataFrame> {
+        let schema = Arc::new(Schema::new(vec![
+            Field::new("lhs_row_key", DataType::UInt32, false),
+            Field::new("lhs_vix_set", DataType::Binary, false),
+            Field::new("lhs_vix_count", DataType::UInt32, false),
+            Field::new("rhs_row_key", DataType::UInt32, false),
+            Field::new("rhs_vix_set", DataType::Binary, false),
+            Field::new("rhs_vix_count", DataType::UInt32, false),
+            Field::new("score", DataType::Float64, false),
+        ]));
+
+        let recs = RecordBatch::try_new(
+            schema,
+            vec![
+                Arc::new(self.lhs_row_key.finish()),
+                Arc::new(self.lhs_vix_set.finish()),
+                Arc::new(self.lhs_vix_count.finish()),
+                Arc::new(self.rhs_row_key.finish()),
+                Arc::new(self.rhs_vix_set.finish()),
+                Arc::new(self.rhs_vix_count.finish()),
+                Arc::new(self.score.finish()),
+            ],
+        )
         .map_err(|e| Error::new(ErrorKind::Other, e))?;
 
-    let res = res
-        .sort(vec![
-            (col("lhs_vix_count") + col("rhs_vix_count")).sort(false, false),
-            col("lhs_row_key").sort(true, false),
-            col("rhs_row_key").sort(true, false),
-        ])
-        .unwrap();
+        let res = ctx
+            .read_batch(recs)
+            .map_err(Error::other)?;
+
+        let res = res
+            .sort(vec![
+                (col("lhs_vix_count") + col("rhs_vix_count")).sort(false, false),
+                col("lhs_row_key").sort(true, false),
+                col("rhs_row_key").sort(true, false),
+            ])
+            .unwrap();
 
-    Ok(res)
+        Ok(res)
+    }
+}
+
+/// Gather the rows of `batches` into a single batch, sorted by kind, chrom,
+/// start and end. The sort is done here, rather than by DataFusion, so the
+/// sweep doesn't depend on how the rows were partitioned: the batches may
+/// come in any order, and needn't be sorted themselves.
+fn sort_rows(schema: SchemaRef, batches: &[RecordBatch]) -> std::io::Result<RecordBatch> {
+    let sort = || -> Result<RecordBatch, ArrowError> {
+        let batch = concat_batches(&schema, batches)?;
+        let keys = ["kind", "chrom_id", "start", "end"]
+            .iter()
+            .map(|name| {
+                Ok(SortColumn {
+                    values: batch.column(schema.index_of(name)?).clone(),
+                    options: None,
+                })
+            })
+            .collect::<Result<Vec<SortColumn>, ArrowError>>()?;
+        let indices = lexsort_to_indices(&keys, None)?;
+        take_record_batch(&batch, &indices)
+    };
+    sort().map_err(Error::other)
 }
 
 struct MergeIterator<'a> {
@@ -405,7 +610,11 @@ struct MergeIterator<'a> {
     length: &'a PrimitiveArray<Int32Type>,
     row_id: &'a PrimitiveArray<Int64Type>,
     row_key: &'a PrimitiveArray<UInt32Type>,
-    vix_set: &'a PrimitiveArray<UInt64Type>,
+    vix_set: &'a BinaryArray,
+    alt_seq: &'a LargeStringArray,
+    alt_symbol: &'a StringArray,
+    depth_change: &'a Int8Array,
+    genotypes: &'a StringArray,
     i: usize,
 }
 
@@ -423,7 +632,11 @@ impl<'a> MergeIterator<'a> {
         let length = Self::get_array::<Int32Array>(recs, "length");
         let row_id = Self::get_array::<Int64Array>(recs, "row_id");
         let row_key = Self::get_array::<UInt32Array>(recs, "row_key");
-        let vix_set = Self::get_array::<UInt64Array>(recs, "vix_set");
+        let vix_set = Self::get_array::<BinaryArray>(recs, "vix_set");
+        let alt_seq = Self::get_array::<LargeStringArray>(recs, "alt_seq");
+        let alt_symbol = Self::get_array::<StringArray>(recs, "alt_symbol");
+        let depth_change = Self::get_array::<Int8Array>(recs, "depth_change");
+        let genotypes = Self::get_array::<StringArray>(recs, "genotypes");
         MergeIterator {
             kind,
             kind_values,
@@ -434,6 +647,10 @@ impl<'a> MergeIterator<'a> {
             row_id,
             row_key,
             vix_set,
+            alt_seq,
+            alt_symbol,
+            depth_change,
+            genotypes,
             i: 0,
         }
     }
@@ -463,8 +680,39 @@ impl<'a> Iterator for MergeIterator<'a> {
             let row_id = self.row_id.value(i);
             let row_key = self.row_key.value(i);
             let vix_set = self.vix_set.value(i);
+            let alt_seq = if self.alt_seq.is_valid(i) && !self.alt_seq.value(i).is_empty() {
+                Some(self.alt_seq.value(i))
+            } else {
+                None
+            };
+            let alt_symbol = if self.alt_symbol.is_valid(i) {
+                Some(self.alt_symbol.value(i))
+            } else {
+                None
+            };
+            let depth_change = if self.depth_change.is_valid(i) {
+                Some(self.depth_change.value(i))
+            } else {
+                None
+            };
+            let genotypes = if self.genotypes.is_valid(i) {
+                Some(self.genotypes.value(i))
+            } else {
+                None
+            };
             Some(Row::new(
-                kind, chrom_id, start, end, length, row_id, row_key, vix_set,
+                kind,
+                chrom_id,
+                start,
+                end,
+                length,
+                row_id,
+                row_key,
+                vix_set,
+                alt_seq,
+                alt_symbol,
+                depth_change,
+                genotypes,
             ))
         } else {
             None
@@ -481,7 +729,11 @@ struct Row<'a> {
     length: i32,
     row_id: i64,
     row_key: u32,
-    vix_set: u64,
+    vix_set: &'a [u8],
+    alt_seq: Option<&'a str>,
+    alt_symbol: Option<&'a str>,
+    depth_change: Option<i8>,
+    genotypes: Option<&'a str>,
 }
 
 impl<'a> Row<'a> {
@@ -493,7 +745,11 @@ impl<'a> Row<'a> {
         length: i32,
         row_id: i64,
         row_key: u32,
-        vix_set: u64,
+        vix_set: &'a [u8],
+        alt_seq: Option<&'a str>,
+        alt_symbol: Option<&'a str>,
+        depth_change: Option<i8>,
+        genotypes: Option<&'a str>,
     ) -> Row<'a> {
         Row {
             kind,
@@ -504,6 +760,10 @@ impl<'a> Row<'a> {
             row_id,
             row_key,
             vix_set,
+            alt_seq,
+            alt_symbol,
+            depth_change,
+            genotypes,
         }
     }
 }
@@ -515,3 +775,241 @@ impl<'a> HeapItem for Row<'a> {
         self.start
     }
 }
+
+/// A row kept by its end, rather than its start.
+struct ByEnd<'a>(Row<'a>);
+
+impl<'a> HeapItem for ByEnd<'a> {
+    type KeyType = i32;
+
+    fn key(&self) -> Self::KeyType {
+        self.0.end
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use datafusion::{datasource::MemTable, prelude::SessionConfig};
+
+    use super::*;
+
+    /// A batch of records (kind, chrom_id, start, end, length, vix, rn) from
+    /// two inputs, not yet merged.
+    fn make_batch(keys: &RowKey, rows: &[(&str, u16, i32, i32, i32, u32, u32)]) -> RecordBatch {
+        let schema = Arc::new(Schema::new(vec![
+            Field::new_dictionary("kind", DataType::UInt8, DataType::Utf8, false),
+            Field::new("chrom_id", DataType::UInt16, false),
+            Field::new("start", DataType::Int32, false),
+            Field::new("end", DataType::Int32, false),
+            Field::new("length", DataType::Int32, true),
+            Field::new("row_id", DataType::Int64, false),
+            Field::new("row_key", DataType::UInt32, false),
+            Field::new("vix_set", DataType::Binary, false),
+            Field::new("vix_count", DataType::UInt32, false),
+            Field::new("alt_seq", DataType::LargeUtf8, true),
+            Field::new("alt_symbol", DataType::Utf8, true),
+            Field::new("depth_change", DataType::Int8, true),
+            Field::new("genotypes", DataType::Utf8, true),
+        ]));
+        let key = |row: &(&str, u16, i32, i32, i32, u32, u32)| keys.encode(row.5, row.6);
+        let nulls = vec![None::<&str>; rows.len()];
+        RecordBatch::try_new(
+            schema,
+            vec![
+                Arc::new(DictionaryArray::<UInt8Type>::from_iter(
+                    rows.iter().map(|r| r.0),
+                )),
+                Arc::new(UInt16Array::from_iter_values(rows.iter().map(|r| r.1))),
+                Arc::new(Int32Array::from_iter_values(rows.iter().map(|r| r.2))),
+                Arc::new(Int32Array::from_iter_values(rows.iter().map(|r| r.3))),
+                Arc::new(Int32Array::from_iter_values(rows.iter().map(|r| r.4))),
+                Arc::new(Int64Array::from_iter_values(
+                    rows.iter().map(|r| key(r) as i64),
+                )),
+                Arc::new(UInt32Array::from_iter_values(rows.iter().map(key))),
+                Arc::new(BinaryArray::from_iter_values(
+                    rows.iter().map(|r| [1u8 << r.5]),
+                )),
+                Arc::new(UInt32Array::from_iter_values(rows.iter().map(|_| 1))),
+                Arc::new(LargeStringArray::from(nulls.clone())),
+                Arc::new(StringArray::from(nulls.clone())),
+                Arc::new(Int8Array::from(vec![None; rows.len()])),
+                Arc::new(StringArray::from(nulls)),
+            ],
+        )
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_near_join_partitions() {
+        let keys = RowKey::new(2);
+        // The rows are spread over the partitions out of order, with the
+        // records that should be merged in different partitions.
+        let partitions = vec![
+            vec![make_batch(
+                &keys,
+                &[
+                    ("DUP", 0, 1000, 1300, 300, 1, 1),
+                    ("DEL", 0, 2000, 2500, -500, 0, 1),
+                ],
+            )],
+            vec![make_batch(
+                &keys,
+                &[
+                    ("DEL", 0, 1000, 1500, -500, 0, 0),
+                    ("DEL", 1, 1000, 1500, -500, 1, 2),
+                ],
+            )],
+            vec![make_batch(
+                &keys,
+                &[
+                    ("DEL", 0, 2005, 2505, -500, 1, 3),
+                    ("DUP", 0, 1005, 1305, 300, 0, 2),
+                    ("DEL", 0, 1010, 1510, -500, 1, 0),
+                ],
+            )],
+        ];
+        let schema = partitions[0][0].schema();
+        let ctx = SessionContext::new_with_config(SessionConfig::new().with_target_partitions(4));
+        let tbl = MemTable::try_new(schema, partitions).unwrap();
+        ctx.register_table("records", Arc::new(tbl)).unwrap();
+        let orig = ctx.table("records").await.unwrap();
+
+        let options = MergeOptions::default();
+        let res = approx_near_join(orig, 2, &options, NearRule::Indel, Pairing::Across, &ctx, None)
+            .await
+            .unwrap();
+
+        let mut pairs = HashSet::new();
+        for recs in res.collect().await.unwrap() {
+            let lhs = recs
+                .column(0)
+                .as_any()
+                .downcast_ref::<UInt32Array>()
+                .unwrap();
+            let rhs = recs
+                .column(3)
+                .as_any()
+                .downcast_ref::<UInt32Array>()
+                .unwrap();
+            for (lhs, rhs) in lhs.values().iter().zip(rhs.values().iter()) {
+                pairs.insert((*lhs, *rhs));
+            }
+        }
+        let expected: HashSet<(u32, u32)> = [((0, 0), (1, 0)), ((0, 1), (1, 3)), ((1, 1), (0, 2))]
+            .iter()
+            .map(|((v1, r1), (v2, r2))| {
+                let (a, b) = (keys.encode(*v1, *r1), keys.encode(*v2, *r2));
+                (a.min(b), a.max(b))
+            })
+            .collect();
+        assert_eq!(pairs, expected);
+    }
+
+    #[tokio::test]
+    async fn test_kind_specific_rules() {
+        let keys = RowKey::new(2);
+        let batch = make_batch(
+            &keys,
+            &[
+                ("INV", 0, 1000, 5000, 0, 0, 0),
+                ("INV", 0, 1010, 5040, 4040, 1, 0),
+                ("DUP", 0, 8000, 8300, 0, 0, 1),
+                ("DUP", 0, 8020, 8330, 310, 1, 1),
+            ],
+        );
+        let ctx = SessionContext::new();
+        let orig = ctx.read_batch(batch).unwrap();
+
+        async fn pairs(
+            orig: &DataFrame,
+            options: &MergeOptions,
+            rule: NearRule,
+            ctx: &SessionContext,
+        ) -> Vec<((u32, u32), (u32, u32))> {
+            let keys = RowKey::new(2);
+            let res = approx_near_join(orig.clone(), 2, options, rule, Pairing::Across, ctx, None)
+                .await
+                .unwrap();
+            let mut pairs = Vec::new();
+            for recs in res.collect().await.unwrap() {
+                let lhs = MergeIterator::get_array::<UInt32Array>(&recs, "lhs_row_key");
+                let rhs = MergeIterator::get_array::<UInt32Array>(&recs, "rhs_row_key");
+                for i in 0..recs.num_rows() {
+                    pairs.push((keys.decode(lhs.value(i)), keys.decode(rhs.value(i))));
+                }
+            }
+            pairs
+        }
+
+        // Their lengths keep the inversions apart, and their ends the
+        // duplications, under the near rule.
+        let mut options = MergeOptions::default();
+        assert_eq!(pairs(&orig, &options, NearRule::Indel, &ctx).await, vec![]);
+        options.kind_specific_criteria = true;
+        assert_eq!(
+            pairs(&orig, &options, NearRule::Breakpoints, &ctx).await,
+            vec![]
+        );
+        assert_eq!(
+            pairs(&orig, &options, NearRule::Copies, &ctx).await,
+            vec![((0, 1), (1, 1))]
+        );
+        options.inversion_end_window = Some(50);
+        assert_eq!(
+            pairs(&orig, &options, NearRule::Breakpoints, &ctx).await,
+            vec![((0, 0), (1, 0))]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_reciprocal_overlap_join() {
+        assert_eq!(reciprocal_overlap((100, 200), (150, 250)), 0.5);
+        assert_eq!(reciprocal_overlap((100, 200), (120, 140)), 0.2);
+        assert_eq!(reciprocal_overlap((100, 200), (200, 300)), 0.0);
+
+        let keys = RowKey::new(2);
+        let batch = make_batch(
+            &keys,
+            &[
+                ("DEL", 0, 10000, 20000, -10000, 0, 0),
+                ("DEL", 0, 10500, 20800, -10300, 1, 0),
+                ("DUP", 0, 10000, 20000, 10000, 1, 1),
+                ("DEL", 0, 30000, 31000, -1000, 0, 1),
+                ("DEL", 0, 30600, 31600, -1000, 1, 2),
+                ("DEL", 0, 50000, 60000, -10000, 0, 2),
+                ("DEL", 0, 50100, 50200, -100, 1, 3),
+                ("DEL", 0, 50200, 59900, -9700, 1, 4),
+                ("INS", 0, 50500, 50500, 300, 0, 3),
+            ],
+        );
+        let ctx = SessionContext::new();
+        let orig = ctx.read_batch(batch).unwrap();
+
+        let options = MergeOptions::default();
+        let res = reciprocal_overlap_join(orig, 2, &options, 0.7, &ctx, None)
+            .await
+            .unwrap();
+
+        let mut pairs = Vec::new();
+        for recs in res.collect().await.unwrap() {
+            let lhs = MergeIterator::get_array::<UInt32Array>(&recs, "lhs_row_key");
+            let rhs = MergeIterator::get_array::<UInt32Array>(&recs, "rhs_row_key");
+            let score = MergeIterator::get_array::<PrimitiveArray<Float64Type>>(&recs, "score");
+            for i in 0..recs.num_rows() {
+                pairs.push((
+                    keys.decode(lhs.value(i)),
+                    keys.decode(rhs.value(i)),
+                    score.value(i),
+                ));
+            }
+        }
+        pairs.sort_by_key(|(lhs, _, _)| *lhs);
+        assert_eq!(
+            pairs,
+            vec![((0, 0), (1, 0), 9500.0 / 10300.0), ((0, 2), (1, 4), 0.97),]
+        );
+    }
+}
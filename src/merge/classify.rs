@@ -1,13 +1,21 @@
-use crate::features::FeatureIndex;
+use crate::{
+    features::FeatureIndex,
+    kmers::{Ambiguity, Kmer},
+    threads::WorkerPool,
+};
 use datafusion::{
     arrow::{
         array::{
-            Array as _, GenericStringArray, GenericStringBuilder, PrimitiveBuilder, RecordBatch,
+            Array as _, ArrayBuilder as _, BooleanBuilder, GenericStringArray, GenericStringBuilder,
+            Int32Array, PrimitiveBuilder, RecordBatch,
         },
+        compute::filter_record_batch,
         datatypes::{DataType, Field, Float64Type, Schema},
     },
-    prelude::{DataFrame, SessionContext},
+    prelude::{DataFrame, SessionContext, cast, col, length, lit},
 };
+use futures::StreamExt;
+use noodles::fasta::Repository;
 use std::{
     io::{Error, ErrorKind},
     sync::Arc,
@@ -15,50 +23,88 @@ use std::{
     u32,
 };
 
+/// Classify the insertion sequences in `ins` against a feature index, once per
+/// `seq_hash`.
+///
+/// The sequences are pulled from `ins` as a stream sorted by `seq_hash`,
+/// skipping repeats, and classified in batches of (at least) `batch_size`
+/// sequences. The sort holds all the sequences, not just the distinct ones,
+/// but can spill to disk under a memory limit; beyond that, the memory used
+/// for classifying is bounded by the batch size.
 pub(crate) async fn find_classifications(
-    batch: Vec<RecordBatch>,
+    ins: DataFrame,
     features: &str,
+    batch_size: usize,
     ctx: &SessionContext,
 ) -> std::io::Result<DataFrame> {
-    use rayon::prelude::*;
-
-    let n: usize = batch.iter().map(|recs| recs.num_rows()).sum();
-    log::info!("number of sequences to classify: {}", n);
-
     let idx = FeatureIndex::load(features, ctx).await?;
+    let pool = WorkerPool::of(ctx)?;
 
     log::info!("classifying insertion sequences with '{}'", features);
 
     let now = Instant::now();
 
-    let results: Vec<Vec<Option<(String, String, String, f64)>>> = batch
-        .par_iter()
-        .map(|recs| {
-            let itr = MergeIterator::new(recs);
-            let inner: Vec<Option<(String, String, String, f64)>> = itr
-                .map(|(seq_hash, sequence)| {
-                    find_best_classification(sequence, &idx)
-                        .map(|(class, strand, score)| (seq_hash.to_string(), class, strand, score))
-                })
-                .collect();
-            inner
-        })
-        .collect();
-
     let mut seq_hash_builder = GenericStringBuilder::<i32>::new();
     let mut class_builder = GenericStringBuilder::<i32>::new();
     let mut strand_builder = GenericStringBuilder::<i32>::new();
     let mut distance_builder = PrimitiveBuilder::<Float64Type>::new();
 
-    for block in results {
-        for item in block {
-            if let Some((seq_hash, class, strand, score)) = item {
-                seq_hash_builder.append_value(seq_hash);
-                class_builder.append_value(class);
-                strand_builder.append_value(strand);
-                distance_builder.append_value(score);
-            }
+    let mut n: usize = 0;
+    let mut ambiguous_sequences: usize = 0;
+    let mut pending: Vec<RecordBatch> = Vec::new();
+    let mut pending_rows: usize = 0;
+
+    let mut stream = ins
+        .sort(vec![col("seq_hash").sort(true, false)])?
+        .execute_stream()
+        .await?;
+    let mut last_seq_hash = None;
+    while let Some(recs) = stream.next().await {
+        let recs = skip_repeats(&recs?, &mut last_seq_hash)?;
+        if recs.num_rows() == 0 {
+            continue;
         }
+        if idx.ambiguity() == Ambiguity::Report {
+            ambiguous_sequences += MergeIterator::new(&recs)
+                .filter(|(_seq_hash, sequence)| Kmer::count_ambiguous(sequence) > 0)
+                .count();
+        }
+        pending_rows += recs.num_rows();
+        pending.push(recs);
+        if pending_rows >= batch_size {
+            log::info!("classifying batch of {} sequences", pending_rows);
+            n += pending_rows;
+            let results = pool.install(|| classify_batch(&pending, &idx));
+            append_results(
+                results,
+                &mut seq_hash_builder,
+                &mut class_builder,
+                &mut strand_builder,
+                &mut distance_builder,
+            );
+            pending.clear();
+            pending_rows = 0;
+        }
+    }
+    if pending_rows > 0 {
+        log::info!("classifying batch of {} sequences", pending_rows);
+        n += pending_rows;
+        let results = pool.install(|| classify_batch(&pending, &idx));
+        append_results(
+            results,
+            &mut seq_hash_builder,
+            &mut class_builder,
+            &mut strand_builder,
+            &mut distance_builder,
+        );
+    }
+
+    log::info!("number of sequences classified: {}", n);
+    if ambiguous_sequences > 0 {
+        log::warn!(
+            "{} insertion sequences contained ambiguity codes",
+            ambiguous_sequences
+        );
     }
 
     let dt = now.elapsed().as_secs_f64();
@@ -97,8 +143,147 @@ pub(crate) async fn find_classifications(
     Ok(result)
 }
 
+/// The reference sequences removed by the deletions in `tbl` no longer than
+/// `max_length`, keyed (as `seq_hash`) by their `class_key`, for
+/// classifying along with the insertion sequences.
+///
+/// The sequence removed by a deletion at POS with END is the bases after
+/// POS up to and including END.
+pub(crate) async fn deleted_sequences(
+    tbl: DataFrame,
+    reference: &Repository,
+    max_length: usize,
+    ctx: &SessionContext,
+) -> std::io::Result<DataFrame> {
+    let deletions = tbl
+        .filter(col("kind").eq(lit("DEL")))?
+        .select(vec![
+            col("class_key"),
+            cast(col("chrom"), DataType::Utf8).alias("chrom"),
+            col("start"),
+            col("end"),
+        ])?
+        .distinct()?
+        .collect()
+        .await?;
+
+    let mut key_builder = GenericStringBuilder::<i32>::new();
+    let mut seq_builder = GenericStringBuilder::<i64>::new();
+    let mut too_long: usize = 0;
+    for recs in deletions.iter() {
+        let keys = MergeIterator::get_array::<GenericStringArray<i32>>(recs, "class_key");
+        let chroms = MergeIterator::get_array::<GenericStringArray<i32>>(recs, "chrom");
+        let starts = MergeIterator::get_array::<Int32Array>(recs, "start");
+        let ends = MergeIterator::get_array::<Int32Array>(recs, "end");
+        for i in 0..recs.num_rows() {
+            let (start, end) = (
+                starts.value(i).max(0) as usize,
+                ends.value(i).max(0) as usize,
+            );
+            if end <= start {
+                continue;
+            }
+            if end - start > max_length {
+                too_long += 1;
+                continue;
+            }
+            let Some(seq) = reference.get(chroms.value(i).as_bytes()).transpose()? else {
+                continue;
+            };
+            let seq = seq.as_ref();
+            let removed = &seq[start.min(seq.len())..end.min(seq.len())];
+            if removed.is_empty() {
+                continue;
+            }
+            // Repeats are often soft masked.
+            let removed = String::from_utf8_lossy(removed).to_ascii_uppercase();
+            key_builder.append_value(keys.value(i));
+            seq_builder.append_value(removed);
+        }
+    }
+    log::info!(
+        "classifying the removed sequences of {} deletions ({} longer than {} left out)",
+        key_builder.len(),
+        too_long,
+        max_length
+    );
+
+    let schema = Arc::new(Schema::new(vec![
+        Field::new("seq_hash", DataType::Utf8, false),
+        Field::new("alt_seq", DataType::LargeUtf8, false),
+    ]));
+    let recs = RecordBatch::try_new(
+        schema,
+        vec![
+            Arc::new(key_builder.finish()),
+            Arc::new(seq_builder.finish()),
+        ],
+    )
+    .map_err(Error::other)?;
+    let res = ctx.read_batch(recs)?.select(vec![
+        col("seq_hash"),
+        length(col("alt_seq")).alias("length"),
+        col("alt_seq"),
+    ])?;
+    Ok(res)
+}
+
+/// The rows of `recs` (sorted by `seq_hash`) whose `seq_hash` differs from
+/// the row before, which for the first row is `last_seq_hash`, from the batch
+/// before. `last_seq_hash` is updated to the last of `recs`.
+fn skip_repeats(
+    recs: &RecordBatch,
+    last_seq_hash: &mut Option<String>,
+) -> std::io::Result<RecordBatch> {
+    let seq_hashes = MergeIterator::get_array::<GenericStringArray<i32>>(recs, "seq_hash");
+    let mut keep = BooleanBuilder::with_capacity(recs.num_rows());
+    for seq_hash in seq_hashes.iter() {
+        let repeat = seq_hash == last_seq_hash.as_deref();
+        keep.append_value(!repeat);
+        if !repeat {
+            *last_seq_hash = seq_hash.map(|s| s.to_string());
+        }
+    }
+    filter_record_batch(recs, &keep.finish()).map_err(Error::other)
+}
+
+type Classification = Option<(String, String, String, f64)>;
+
+fn classify_batch(batch: &[RecordBatch], idx: &FeatureIndex) -> Vec<Vec<Classification>> {
+    use rayon::prelude::*;
+
+    batch
+        .par_iter()
+        .map(|recs| {
+            let itr = MergeIterator::new(recs);
+            let inner: Vec<Classification> = itr
+                .map(|(seq_hash, sequence)| {
+                    find_best_classification(sequence, idx)
+                        .map(|(class, strand, score)| (seq_hash.to_string(), class, strand, score))
+                })
+                .collect();
+            inner
+        })
+        .collect()
+}
+
+fn append_results(
+    results: Vec<Vec<Classification>>,
+    seq_hash_builder: &mut GenericStringBuilder<i32>,
+    class_builder: &mut GenericStringBuilder<i32>,
+    strand_builder: &mut GenericStringBuilder<i32>,
+    distance_builder: &mut PrimitiveBuilder<Float64Type>,
+) {
+    for (seq_hash, class, strand, score) in results.into_iter().flatten().flatten() {
+        seq_hash_builder.append_value(seq_hash);
+        class_builder.append_value(class);
+        strand_builder.append_value(strand);
+        distance_builder.append_value(score);
+    }
+}
+
 fn find_best_classification(sequence: &str, idx: &FeatureIndex) -> Option<(String, String, f64)> {
-    let (fwd, rev) = idx.rank(sequence);
+    let (fwd, rev) = idx.rank_above(sequence, 0.5);
     let mut best_nix = u32::MAX;
     let mut best_score = -1.0;
     let mut best_strand = true;
@@ -140,7 +325,7 @@ pub(crate) async fn _materialise(
 
 pub(crate) struct MergeIterator<'a> {
     pub(crate) seq_hash: &'a GenericStringArray<i32>,
-    pub(crate) alt_seq: &'a GenericStringArray<i32>,
+    pub(crate) alt_seq: &'a GenericStringArray<i64>,
     pub(crate) i: usize,
 }
 
@@ -150,7 +335,7 @@ impl<'a> MergeIterator<'a> {
             log::debug!("field: {:?}", field);
         }
         let seq_hash = Self::get_array::<GenericStringArray<i32>>(recs, "seq_hash");
-        let alt_seq = Self::get_array::<GenericStringArray<i32>>(recs, "alt_seq");
+        let alt_seq = Self::get_array::<GenericStringArray<i64>>(recs, "alt_seq");
         MergeIterator {
             seq_hash,
             alt_seq,
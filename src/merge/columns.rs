@@ -0,0 +1,99 @@
+use datafusion::prelude::DataFrame;
+
+/// The columns of the working table needed by the matching stages (the
+/// exact and approximate joins, and the breakend checks).
+///
+/// This is the core VCF schema without `row_num` (which is recoverable from
+/// `row_id`), plus the bookkeeping columns maintained by `merge_with`.
+pub(crate) const MATCH_COLUMNS: &[&str] = &[
+    "row_id",
+    "vix",
+    "chrom_id",
+    "chrom",
+    "start",
+    "end",
+    "kind",
+    "length",
+    "chrom2_id",
+    "chrom2",
+    "chrom2_alias",
+    "end2",
+    "bnd_sides",
+    "bnd_ins_len",
+    "alt_seq",
+    "seq_hash",
+    "alt_symbol",
+    "depth_change",
+    "genotypes",
+    "id",
+    "called",
+    "row_key",
+    "vix_count",
+    "vix_set",
+    "criteria",
+    "merge_score",
+    "paired_bnd",
+    "event_id",
+];
+
+/// The columns read by the sweep in the near join.
+pub(crate) const NEAR_COLUMNS: &[&str] = &[
+    "kind",
+    "chrom_id",
+    "start",
+    "end",
+    "length",
+    "row_id",
+    "row_key",
+    "vix_set",
+    "alt_seq",
+    "alt_symbol",
+    "depth_change",
+    "genotypes",
+];
+
+/// The columns needed to sort the merged table and write the output.
+pub(crate) const OUTPUT_COLUMNS: &[&str] = &[
+    "chrom_id",
+    "primary_start",
+    "primary_end",
+    "row_key",
+    "row_id",
+    "variant_id",
+    "alt_seq",
+    "paired_bnd",
+    "criteria",
+    "merge_score",
+    "event_id",
+    "class",
+    "strand",
+    "start_offset",
+    "end_offset",
+    "length_ratio",
+];
+
+/// Keep only the given columns (those of them that are present), so that
+/// helper columns from earlier stages aren't carried through later joins and
+/// materialisations.
+pub(crate) fn prune_columns(
+    tbl: DataFrame,
+    keep: &[&str],
+    stage: &str,
+) -> std::io::Result<DataFrame> {
+    let schema = tbl.schema();
+    let dropped: Vec<&str> = schema
+        .fields()
+        .iter()
+        .map(|f| f.name().as_str())
+        .filter(|name| !keep.contains(name))
+        .collect();
+    if !dropped.is_empty() {
+        log::debug!("pruning columns after {}: {:?}", stage, dropped);
+    }
+    let kept: Vec<&str> = keep
+        .iter()
+        .copied()
+        .filter(|name| schema.has_column_with_unqualified_name(name))
+        .collect();
+    Ok(tbl.select_columns(&kept)?)
+}
@@ -0,0 +1,152 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    io::Error,
+    sync::Arc,
+};
+
+use datafusion::{
+    arrow::{
+        array::{ArrayBuilder, PrimitiveBuilder, RecordBatch, UInt32Array},
+        datatypes::{DataType, Field, Schema, UInt32Type},
+    },
+    common::JoinType,
+    prelude::{DataFrame, SessionContext, col, lit, when},
+};
+
+use crate::{
+    disjoint_set::DisjointSet,
+    merge::{
+        approx::{NearRule, approx_near_join},
+        exact::{full_exact_bnd, full_exact_indel_join, full_exact_locus_ins_join},
+        pairing::Pairing,
+    },
+    options::MergeOptions,
+};
+
+/// Collapse the records within each input that the exact, locus and near
+/// criteria would merge if they came from different inputs
+/// (`--dedup-within-inputs`), keeping the first record (in `row_id` order) of
+/// each set of duplicates.
+///
+/// With `exact_only` (`--allow-intra-input-merge`), only those the exact
+/// criterion would merge are collapsed, and the records kept are marked as
+/// merged by it.
+///
+/// Returns the table without the duplicates, along with the `row_id`s of the
+/// duplicates collapsed into each record kept.
+pub(super) async fn dedup_within_inputs(
+    orig: DataFrame,
+    n: usize,
+    options: &MergeOptions,
+    exact_only: bool,
+    ctx: &SessionContext,
+) -> std::io::Result<(DataFrame, HashMap<u32, Vec<u32>>)> {
+    let mut joins = vec![
+        full_exact_indel_join(orig.clone(), n, options, Pairing::Within)?,
+        full_exact_bnd(orig.clone(), n, options, Pairing::Within)?,
+    ];
+    if !exact_only {
+        joins.push(full_exact_locus_ins_join(
+            orig.clone(),
+            n,
+            options,
+            Pairing::Within,
+        )?);
+        for rule in NearRule::in_use(options) {
+            joins.push(
+                approx_near_join(orig.clone(), n, options, rule, Pairing::Within, ctx, None)
+                    .await?,
+            );
+        }
+    }
+
+    // Before any merging, each row_key is the row_id of its record.
+    let mut sets = DisjointSet::new();
+    let mut row_keys = Vec::new();
+    for join in joins {
+        for recs in join
+            .select_columns(&["lhs_row_key", "rhs_row_key"])?
+            .collect()
+            .await?
+        {
+            let lhs = get_array::<UInt32Array>(&recs, "lhs_row_key");
+            let rhs = get_array::<UInt32Array>(&recs, "rhs_row_key");
+            for i in 0..recs.num_rows() {
+                sets.union(lhs.value(i), rhs.value(i));
+                row_keys.push(lhs.value(i));
+                row_keys.push(rhs.value(i));
+            }
+        }
+    }
+    row_keys.sort();
+    row_keys.dedup();
+
+    let mut groups: BTreeMap<u32, Vec<u32>> = BTreeMap::new();
+    for row_key in row_keys {
+        groups.entry(sets.find(row_key)).or_default().push(row_key);
+    }
+
+    let mut collapsed = HashMap::new();
+    let mut dropped_builder = PrimitiveBuilder::<UInt32Type>::new();
+    let mut kept_builder = PrimitiveBuilder::<UInt32Type>::new();
+    for (_, group) in groups {
+        let (first, rest) = group.split_first().unwrap();
+        for row_key in rest {
+            dropped_builder.append_value(*row_key);
+        }
+        kept_builder.append_value(*first);
+        collapsed.insert(*first, Vec::from(rest));
+    }
+    log::info!(
+        "collapsed {} duplicate records within inputs into {} records",
+        dropped_builder.len(),
+        collapsed.len()
+    );
+
+    let schema = Arc::new(Schema::new(vec![Field::new(
+        "dropped_row_key",
+        DataType::UInt32,
+        false,
+    )]));
+    let dropped = RecordBatch::try_new(schema, vec![Arc::new(dropped_builder.finish())])
+        .map_err(Error::other)?;
+    let dropped = ctx.read_batch(dropped)?;
+
+    let tbl = orig.join(
+        dropped,
+        JoinType::LeftAnti,
+        &["row_key"],
+        &["dropped_row_key"],
+        None,
+    )?;
+
+    if !exact_only {
+        return Ok((tbl, collapsed));
+    }
+
+    let schema = Arc::new(Schema::new(vec![Field::new(
+        "kept_row_key",
+        DataType::UInt32,
+        false,
+    )]));
+    let kept = RecordBatch::try_new(schema, vec![Arc::new(kept_builder.finish())])
+        .map_err(Error::other)?;
+    let kept = ctx.read_batch(kept)?;
+
+    let tbl = tbl
+        .join(kept, JoinType::Left, &["row_key"], &["kept_row_key"], None)?
+        .with_column(
+            "criteria",
+            when(col("kept_row_key").is_not_null(), lit("exact")).otherwise(col("criteria"))?,
+        )?
+        .drop_columns(&["kept_row_key"])?;
+    Ok((tbl, collapsed))
+}
+
+fn get_array<'a, Type: 'static>(recs: &'a RecordBatch, name: &str) -> &'a Type {
+    recs.column_by_name(name)
+        .unwrap()
+        .as_any()
+        .downcast_ref::<Type>()
+        .unwrap()
+}
@@ -1,15 +1,244 @@
+use std::{collections::HashMap, io::Error, sync::Arc};
+
 use datafusion::{
+    arrow::{
+        array::{
+            Array, ArrayBuilder, BinaryArray, BinaryBuilder, Int8Array, Int32Array, Int64Array,
+            PrimitiveBuilder, RecordBatch, StringArray, UInt16Array, UInt32Array,
+        },
+        datatypes::{DataType, Field, Schema, UInt32Type},
+    },
     common::JoinType,
-    prelude::{DataFrame, abs, col, greatest, least, lit},
+    prelude::{DataFrame, Expr, SessionContext, abs, cast, col, concat, lit, starts_with},
+};
+
+use crate::{
+    expressions::{difference, prefix_cols},
+    merge::{
+        genotypes::concordant_genotypes_expr, length::LengthRule, pairing::Pairing,
+        vix_set::disjoint,
+    },
+    options::MergeOptions,
+    tables::concordant_genotypes,
 };
 
-use crate::{expressions::prefix_cols, options::MergeOptions};
+/// Everything that makes two records identical for the purposes of merging:
+/// chrom, start, end, kind, length, chrom2, end2, (with
+/// `--match-bnd-orientation`) breakend sides, the hash of the ALT, (with
+/// `--bnd-insertion-window`) the length of the sequence inserted at a
+/// breakend, and (with `--depth-consistency`) the direction of the change in
+/// depth.
+type DuplicateKey<'a> = (
+    u16,
+    i32,
+    i32,
+    &'a str,
+    Option<i32>,
+    Option<u16>,
+    Option<i32>,
+    Option<&'a str>,
+    Option<i64>,
+    Option<i32>,
+    Option<i8>,
+);
+
+/// Pair up records that are identical in every respect used for merging.
+///
+/// This is a single hashing pass over the table, so inputs with many
+/// identical records (e.g. joint-called VCFs split by sample) are largely
+/// merged before the joins, which are quadratic in the size of each locus.
+/// Each record is paired with the first identical one, in `row_id` order,
+/// from other inputs (or, with `--allow-intra-input-merge`, any input) whose
+/// genotypes (with `--require-genotype-concordance`) agree.
+pub(super) async fn exact_duplicates(
+    orig: DataFrame,
+    n: usize,
+    options: &MergeOptions,
+    ctx: &SessionContext,
+) -> std::io::Result<DataFrame> {
+    let batch = orig
+        .filter(col("vix_count").lt(lit(n as u32)))?
+        .select(vec![
+            col("row_id"),
+            col("row_key"),
+            col("vix_set"),
+            col("chrom_id"),
+            col("start"),
+            col("end"),
+            cast(col("kind"), DataType::Utf8).alias("kind"),
+            col("length"),
+            col("chrom2_id"),
+            col("end2"),
+            col("bnd_sides"),
+            col("bnd_ins_len"),
+            col("seq_hash"),
+            col("depth_change"),
+            col("genotypes"),
+        ])?
+        .sort_by(vec![col("row_id")])?
+        .collect()
+        .await?;
+
+    let mut lhs_row_key_builder = PrimitiveBuilder::<UInt32Type>::new();
+    let mut lhs_vix_set_builder = BinaryBuilder::new();
+    let mut rhs_row_key_builder = PrimitiveBuilder::<UInt32Type>::new();
+    let mut rhs_vix_set_builder = BinaryBuilder::new();
+
+    type Representative<'a> = (u32, &'a [u8], Option<&'a str>);
+    let mut firsts: HashMap<DuplicateKey, Vec<Representative>> = HashMap::new();
+    for recs in batch.iter() {
+        let row_key = get_array::<UInt32Array>(recs, "row_key");
+        let vix_set = get_array::<BinaryArray>(recs, "vix_set");
+        let chrom_id = get_array::<UInt16Array>(recs, "chrom_id");
+        let start = get_array::<Int32Array>(recs, "start");
+        let end = get_array::<Int32Array>(recs, "end");
+        let kind = get_array::<StringArray>(recs, "kind");
+        let length = get_array::<Int32Array>(recs, "length");
+        let chrom2_id = get_array::<UInt16Array>(recs, "chrom2_id");
+        let end2 = get_array::<Int32Array>(recs, "end2");
+        let bnd_sides = get_array::<StringArray>(recs, "bnd_sides");
+        let bnd_ins_len = get_array::<Int32Array>(recs, "bnd_ins_len");
+        let seq_hash = get_array::<Int64Array>(recs, "seq_hash");
+        let depth_change = get_array::<Int8Array>(recs, "depth_change");
+        let genotypes = get_array::<StringArray>(recs, "genotypes");
+
+        for i in 0..recs.num_rows() {
+            let key: DuplicateKey = (
+                chrom_id.value(i),
+                start.value(i),
+                end.value(i),
+                kind.value(i),
+                length.is_valid(i).then(|| length.value(i)),
+                chrom2_id.is_valid(i).then(|| chrom2_id.value(i)),
+                end2.is_valid(i).then(|| end2.value(i)),
+                (options.match_bnd_orientation && bnd_sides.is_valid(i))
+                    .then(|| bnd_sides.value(i)),
+                seq_hash.is_valid(i).then(|| seq_hash.value(i)),
+                (options.bnd_insertion_window.is_some() && bnd_ins_len.is_valid(i))
+                    .then(|| bnd_ins_len.value(i)),
+                (options.depth_consistency && depth_change.is_valid(i))
+                    .then(|| depth_change.value(i)),
+            );
+            let this = (
+                row_key.value(i),
+                vix_set.value(i),
+                genotypes.is_valid(i).then(|| genotypes.value(i)),
+            );
+            let representatives = firsts.entry(key).or_default();
+            let first = representatives
+                .iter()
+                .find(|(_, first_vix_set, first_genotypes)| {
+                    let inputs_ok =
+                        options.allow_intra_input_merge || disjoint(first_vix_set, this.1);
+                    let genotypes_ok = match (first_genotypes, this.2) {
+                        (Some(lhs), Some(rhs)) if options.require_genotype_concordance => {
+                            concordant_genotypes(lhs, rhs)
+                        }
+                        _ => true,
+                    };
+                    inputs_ok && genotypes_ok
+                });
+            if let Some((first_row_key, first_vix_set, _)) = first {
+                lhs_row_key_builder.append_value(*first_row_key);
+                lhs_vix_set_builder.append_value(first_vix_set);
+                rhs_row_key_builder.append_value(this.0);
+                rhs_vix_set_builder.append_value(this.1);
+            } else {
+                representatives.push(this);
+            }
+        }
+    }
+    log::info!(
+        "found {} exact duplicates among {} distinct records",
+        lhs_row_key_builder.len(),
+        firsts.values().map(Vec::len).sum::<usize>()
+    );
+
+    let schema = Arc::new(Schema::new(vec![
+        Field::new("lhs_row_key", DataType::UInt32, false),
+        Field::new("lhs_vix_set", DataType::Binary, false),
+        Field::new("rhs_row_key", DataType::UInt32, false),
+        Field::new("rhs_vix_set", DataType::Binary, false),
+    ]));
+
+    let recs = RecordBatch::try_new(
+        schema,
+        vec![
+            Arc::new(lhs_row_key_builder.finish()),
+            Arc::new(lhs_vix_set_builder.finish()),
+            Arc::new(rhs_row_key_builder.finish()),
+            Arc::new(rhs_vix_set_builder.finish()),
+        ],
+    )
+    .map_err(Error::other)?;
+
+    ctx.read_batch(recs).map_err(Error::other)
+}
+
+fn get_array<'a, Type: 'static>(recs: &'a RecordBatch, name: &str) -> &'a Type {
+    recs.column_by_name(name)
+        .unwrap()
+        .as_any()
+        .downcast_ref::<Type>()
+        .unwrap()
+}
+
+/// Whether the symbolic ALTs of `lhs` and `rhs` are compatible (see
+/// `tables::compatible_symbols`), or at least one of them has none.
+fn compatible_symbols_expr() -> Expr {
+    let lhs = concat(vec![col("lhs_alt_symbol"), lit(":")]);
+    let rhs = concat(vec![col("rhs_alt_symbol"), lit(":")]);
+    col("lhs_alt_symbol")
+        .is_null()
+        .or(col("rhs_alt_symbol").is_null())
+        .or(starts_with(lhs.clone(), rhs.clone()))
+        .or(starts_with(rhs, lhs))
+}
+
+/// Whether the changes in depth of `lhs` and `rhs` agree in direction, or at
+/// least one of them has none. Always true without `--depth-consistency`.
+fn consistent_depth_expr(options: &MergeOptions) -> Expr {
+    if !options.depth_consistency {
+        return lit(true);
+    }
+    col("lhs_depth_change")
+        .is_null()
+        .or(col("rhs_depth_change").is_null())
+        .or(col("lhs_depth_change").eq(col("rhs_depth_change")))
+}
+
+/// Whether the sequences inserted at the breakends `lhs` and `rhs` differ in
+/// length by no more than `--bnd-insertion-window`, or at least one of them
+/// doesn't say. Always true without `--bnd-insertion-window`.
+pub(super) fn consistent_insertion_expr(options: &MergeOptions) -> Expr {
+    let Some(window) = options.bnd_insertion_window else {
+        return lit(true);
+    };
+    col("lhs_bnd_ins_len")
+        .is_null()
+        .or(col("rhs_bnd_ins_len").is_null())
+        .or(abs(difference("lhs_bnd_ins_len", "rhs_bnd_ins_len")).lt_eq(lit(window as i64)))
+}
 
-pub(super) fn full_exact_indel_join(orig: DataFrame, n: usize) -> std::io::Result<DataFrame> {
+/// Whether the breakends `lhs` and `rhs` have the same orientation. Always
+/// true without `--match-bnd-orientation`.
+pub(super) fn consistent_orientation_expr(options: &MergeOptions) -> Expr {
+    if !options.match_bnd_orientation {
+        return lit(true);
+    }
+    col("lhs_bnd_sides").eq(col("rhs_bnd_sides"))
+}
+
+pub(super) fn full_exact_indel_join(
+    orig: DataFrame,
+    n: usize,
+    options: &MergeOptions,
+    pairing: Pairing,
+) -> std::io::Result<DataFrame> {
     let candidates = orig.clone().filter(
         lit(true)
             .and(col("kind").not_eq(lit("BND")))
-            .and(col("vix_count").lt(lit(n as u32))),
+            .and(pairing.candidates_expr(n)),
     )?;
 
     let lhs = prefix_cols(candidates.clone(), "lhs")?;
@@ -36,12 +265,18 @@ pub(super) fn full_exact_indel_join(orig: DataFrame, n: usize) -> std::io::Resul
             Some(
                 lit(true)
                     .and(col("lhs_row_id").lt(col("rhs_row_id")))
-                    .and((col("lhs_vix_set") & col("rhs_vix_set")).eq(lit(0)))
+                    .and(pairing.inputs_expr())
                     .and(
                         col("lhs_kind")
                             .not_eq(lit("INS"))
-                            .or(col("lhs_seq_hash").eq(col("rhs_seq_hash"))),
-                    ),
+                            .or(col("lhs_seq_hash").eq(col("rhs_seq_hash")))
+                            .or(col("lhs_alt_symbol")
+                                .is_not_null()
+                                .and(col("rhs_alt_symbol").is_not_null())),
+                    )
+                    .and(compatible_symbols_expr())
+                    .and(consistent_depth_expr(options))
+                    .and(concordant_genotypes_expr(options)),
             ),
         )?
         .sort(vec![
@@ -60,11 +295,12 @@ pub(super) fn full_exact_locus_ins_join(
     orig: DataFrame,
     n: usize,
     options: &MergeOptions,
+    pairing: Pairing,
 ) -> std::io::Result<DataFrame> {
     let candidates = orig.clone().filter(
         lit(true)
             .and(col("kind").eq(lit("INS")))
-            .and(col("vix_count").lt(lit(n as u32))),
+            .and(pairing.candidates_expr(n)),
     )?;
 
     let lhs = prefix_cols(candidates.clone(), "lhs")?;
@@ -79,19 +315,12 @@ pub(super) fn full_exact_locus_ins_join(
             Some(
                 lit(true)
                     .and(col("lhs_row_id").lt(col("rhs_row_id")))
-                    .and((col("lhs_vix_set") & col("rhs_vix_set")).eq(lit(0)))
+                    .and(pairing.inputs_expr())
                     .and(
-                        lit(false)
-                            .or((least(vec![abs(col("lhs_length")), abs(col("rhs_length"))])
-                                * lit(1.0)
-                                / greatest(vec![abs(col("lhs_length")), abs(col("rhs_length"))]))
-                            .gt_eq(lit(options.length_ratio)))
-                            .or(
-                                (greatest(vec![abs(col("lhs_length")), abs(col("rhs_length"))])
-                                    - least(vec![abs(col("lhs_length")), abs(col("rhs_length"))]))
-                                .lt_eq(lit(options.length_window)),
-                            ),
-                    ),
+                        LengthRule::new(options).accepts_expr(col("lhs_length"), col("rhs_length")),
+                    )
+                    .and(compatible_symbols_expr())
+                    .and(concordant_genotypes_expr(options)),
             ),
         )?
         .sort(vec![
@@ -106,11 +335,16 @@ pub(super) fn full_exact_locus_ins_join(
     Ok(exact)
 }
 
-pub(super) fn full_exact_bnd(orig: DataFrame, n: usize) -> std::io::Result<DataFrame> {
+pub(super) fn full_exact_bnd(
+    orig: DataFrame,
+    n: usize,
+    options: &MergeOptions,
+    pairing: Pairing,
+) -> std::io::Result<DataFrame> {
     let candidates = orig.clone().filter(
         lit(true)
             .and(col("kind").eq(lit("BND")))
-            .and(col("vix_count").lt(lit(n as u32))),
+            .and(pairing.candidates_expr(n)),
     )?;
 
     let lhs = prefix_cols(candidates.clone(), "lhs")?;
@@ -139,7 +373,10 @@ pub(super) fn full_exact_bnd(orig: DataFrame, n: usize) -> std::io::Result<DataF
             Some(
                 lit(true)
                     .and(col("lhs_row_id").lt(col("rhs_row_id")))
-                    .and((col("lhs_vix_set") & col("rhs_vix_set")).eq(lit(0))),
+                    .and(pairing.inputs_expr())
+                    .and(consistent_orientation_expr(options))
+                    .and(consistent_insertion_expr(options))
+                    .and(concordant_genotypes_expr(options)),
             ),
         )?
         .sort(vec![
@@ -152,3 +389,152 @@ pub(super) fn full_exact_bnd(orig: DataFrame, n: usize) -> std::io::Result<DataF
         .unwrap();
     Ok(exact)
 }
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use datafusion::arrow::{array::DictionaryArray, datatypes::UInt8Type};
+
+    use super::*;
+    use crate::merge::vix_set::VixSet;
+
+    /// A record (vix, row_id, kind, start, bnd_sides, seq_hash) on chrom 0,
+    /// ending 100bp after it starts.
+    type Row = (usize, u32, &'static str, i32, Option<&'static str>, i64);
+
+    /// A table of unmerged records from `n` inputs.
+    fn make_table(n: usize, rows: &[Row], ctx: &SessionContext) -> DataFrame {
+        let schema = Arc::new(Schema::new(vec![
+            Field::new("row_id", DataType::Int64, false),
+            Field::new("row_key", DataType::UInt32, false),
+            Field::new("vix_set", DataType::Binary, false),
+            Field::new("vix_count", DataType::UInt32, false),
+            Field::new("chrom_id", DataType::UInt16, false),
+            Field::new("start", DataType::Int32, false),
+            Field::new("end", DataType::Int32, false),
+            Field::new_dictionary("kind", DataType::UInt8, DataType::Utf8, false),
+            Field::new("length", DataType::Int32, true),
+            Field::new("chrom2_id", DataType::UInt16, true),
+            Field::new("end2", DataType::Int32, true),
+            Field::new("bnd_sides", DataType::Utf8, true),
+            Field::new("bnd_ins_len", DataType::Int32, true),
+            Field::new("seq_hash", DataType::Int64, true),
+            Field::new("depth_change", DataType::Int8, true),
+            Field::new("genotypes", DataType::Utf8, true),
+        ]));
+        let is_bnd = |r: &Row| r.2 == "BND";
+        let recs = RecordBatch::try_new(
+            schema,
+            vec![
+                Arc::new(Int64Array::from_iter_values(
+                    rows.iter().map(|r| r.1 as i64),
+                )),
+                Arc::new(UInt32Array::from_iter_values(rows.iter().map(|r| r.1))),
+                Arc::new(BinaryArray::from_iter_values(
+                    rows.iter()
+                        .map(|r| VixSet::singleton(r.0, n).as_bytes().to_vec()),
+                )),
+                Arc::new(UInt32Array::from_iter_values(rows.iter().map(|_| 1))),
+                Arc::new(UInt16Array::from_iter_values(rows.iter().map(|_| 0))),
+                Arc::new(Int32Array::from_iter_values(rows.iter().map(|r| r.3))),
+                Arc::new(Int32Array::from_iter_values(
+                    rows.iter().map(|r| if is_bnd(r) { r.3 } else { r.3 + 100 }),
+                )),
+                Arc::new(DictionaryArray::<UInt8Type>::from_iter(
+                    rows.iter().map(|r| r.2),
+                )),
+                Arc::new(Int32Array::from_iter(
+                    rows.iter().map(|r| (!is_bnd(r)).then_some(-100)),
+                )),
+                Arc::new(UInt16Array::from_iter(
+                    rows.iter().map(|r| is_bnd(r).then_some(1)),
+                )),
+                Arc::new(Int32Array::from_iter(
+                    rows.iter().map(|r| is_bnd(r).then_some(5000)),
+                )),
+                Arc::new(StringArray::from_iter(rows.iter().map(|r| r.4))),
+                Arc::new(Int32Array::from(vec![None; rows.len()])),
+                Arc::new(Int64Array::from_iter_values(rows.iter().map(|r| r.5))),
+                Arc::new(Int8Array::from(vec![None; rows.len()])),
+                Arc::new(StringArray::from(vec![None::<&str>; rows.len()])),
+            ],
+        )
+        .unwrap();
+        ctx.read_batch(recs).unwrap()
+    }
+
+    /// The pairs of row_keys `exact_duplicates` finds among `rows`.
+    async fn duplicates(n: usize, rows: &[Row], options: &MergeOptions) -> HashSet<(u32, u32)> {
+        let ctx = SessionContext::new();
+        let orig = make_table(n, rows, &ctx);
+        let res = exact_duplicates(orig, n, options, &ctx).await.unwrap();
+        let mut pairs = HashSet::new();
+        for recs in res.collect().await.unwrap() {
+            let lhs = get_array::<UInt32Array>(&recs, "lhs_row_key");
+            let rhs = get_array::<UInt32Array>(&recs, "rhs_row_key");
+            for i in 0..recs.num_rows() {
+                pairs.insert((lhs.value(i), rhs.value(i)));
+            }
+        }
+        pairs
+    }
+
+    #[tokio::test]
+    async fn test_exact_duplicates_across_inputs() {
+        let options = MergeOptions::default();
+        let rows = [
+            (0, 0, "DEL", 1000, None, 11),
+            (1, 1, "DEL", 1000, None, 11),
+            (0, 2, "BND", 2000, Some("]["), 0),
+            (1, 3, "BND", 2000, Some("]["), 0),
+        ];
+        let pairs = duplicates(2, &rows, &options).await;
+        assert_eq!(pairs, HashSet::from([(0, 1), (2, 3)]));
+    }
+
+    #[tokio::test]
+    async fn test_exact_duplicates_within_input() {
+        // The second record of input 0 is left for another input, so input
+        // 1's record pairs with the first.
+        let rows = [
+            (0, 0, "DEL", 1000, None, 11),
+            (0, 1, "DEL", 1000, None, 11),
+            (1, 2, "DEL", 1000, None, 11),
+            (1, 3, "INS", 3000, None, 12),
+            (1, 4, "INS", 3000, None, 12),
+        ];
+        let options = MergeOptions::default();
+        let pairs = duplicates(2, &rows, &options).await;
+        assert_eq!(pairs, HashSet::from([(0, 2)]));
+
+        let options = MergeOptions {
+            allow_intra_input_merge: true,
+            ..Default::default()
+        };
+        let pairs = duplicates(2, &rows, &options).await;
+        assert_eq!(pairs, HashSet::from([(0, 1), (0, 2), (3, 4)]));
+    }
+
+    #[tokio::test]
+    async fn test_exact_duplicates_distinct() {
+        let rows = [
+            (0, 0, "INS", 1000, None, 11),
+            (1, 1, "INS", 1000, None, 12),
+            (0, 2, "BND", 2000, Some("]["), 0),
+            (1, 3, "BND", 2000, Some("[]"), 0),
+        ];
+        let options = MergeOptions {
+            match_bnd_orientation: true,
+            ..Default::default()
+        };
+        let pairs = duplicates(2, &rows, &options).await;
+        assert!(pairs.is_empty(), "{:?}", pairs);
+
+        // Breakends of different orientations are only kept apart with
+        // --match-bnd-orientation.
+        let options = MergeOptions::default();
+        let pairs = duplicates(2, &rows, &options).await;
+        assert_eq!(pairs, HashSet::from([(2, 3)]));
+    }
+}
@@ -0,0 +1,56 @@
+use std::{iter::zip, sync::Arc};
+
+use datafusion::{
+    arrow::{
+        array::{ArrayRef, BooleanBuilder},
+        datatypes::DataType,
+    },
+    common::cast::as_string_array,
+    error::DataFusionError,
+    logical_expr::{ColumnarValue, ScalarUDF, Volatility},
+    prelude::{Expr, SimpleScalarUDF, col, lit},
+};
+
+use crate::{options::MergeOptions, tables::concordant_genotypes};
+
+/// Whether the genotypes of `lhs` and `rhs` agree in the samples they share
+/// (see `tables::concordant_genotypes`), or at least one of them has none.
+/// Always true without `--require-genotype-concordance`.
+pub(super) fn concordant_genotypes_expr(options: &MergeOptions) -> Expr {
+    if !options.require_genotype_concordance {
+        return lit(true);
+    }
+    genotype_concordance().call(vec![col("lhs_genotypes"), col("rhs_genotypes")])
+}
+
+fn genotype_concordance() -> ScalarUDF {
+    ScalarUDF::from(SimpleScalarUDF::new(
+        "genotype_concordance",
+        vec![DataType::Utf8, DataType::Utf8],
+        DataType::Boolean,
+        Volatility::Immutable,
+        Arc::new(do_genotype_concordance),
+    ))
+}
+
+fn do_genotype_concordance(
+    args: &[ColumnarValue],
+) -> std::result::Result<ColumnarValue, DataFusionError> {
+    let args = ColumnarValue::values_to_arrays(args)?;
+
+    let lhss = as_string_array(&args[0])?;
+    let rhss = as_string_array(&args[1])?;
+
+    let mut result_builder = BooleanBuilder::new();
+    for (lhs, rhs) in zip(lhss.iter(), rhss.iter()) {
+        let res = match (lhs, rhs) {
+            (Some(lhs), Some(rhs)) => concordant_genotypes(lhs, rhs),
+            _ => true,
+        };
+        result_builder.append_value(res);
+    }
+
+    let result_array = result_builder.finish();
+
+    Ok(ColumnarValue::from(Arc::new(result_array) as ArrayRef))
+}
@@ -0,0 +1,85 @@
+use datafusion::prelude::{Expr, abs, greatest, least, lit};
+
+use crate::options::MergeOptions;
+
+/// The rule for whether two events are close enough in length to merge.
+///
+/// A length ratio (shorter/longer) alone is too strict for small events (50bp
+/// and 60bp fail a ratio of 0.9, while 5000bp and 5500bp pass), so events also
+/// pass if their lengths differ by no more than a fixed number of bases. The
+/// same rule is applied by both the locus and near criteria.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct LengthRule {
+    ratio: f64,
+    difference: i64,
+}
+
+impl LengthRule {
+    pub fn new(options: &MergeOptions) -> LengthRule {
+        LengthRule {
+            ratio: options.length_ratio,
+            difference: options.length_window as i64,
+        }
+    }
+
+    /// The minimum length ratio.
+    pub fn min_ratio(&self) -> f64 {
+        self.ratio
+    }
+
+    /// The maximum length difference.
+    pub fn max_difference(&self) -> i64 {
+        self.difference
+    }
+
+    /// The ratio of the shorter length to the longer (1 if both are 0).
+    pub fn ratio(lhs: i64, rhs: i64) -> f64 {
+        let lhs = lhs.abs();
+        let rhs = rhs.abs();
+        let longer = std::cmp::max(lhs, rhs);
+        if longer > 0 {
+            (std::cmp::min(lhs, rhs) as f64) / (longer as f64)
+        } else {
+            1.0
+        }
+    }
+
+    pub fn accepts(&self, lhs: i64, rhs: i64) -> bool {
+        LengthRule::ratio(lhs, rhs) >= self.ratio
+            || (lhs.abs() - rhs.abs()).abs() <= self.difference
+    }
+
+    /// The rule as an expression over two length columns.
+    pub fn accepts_expr(&self, lhs: Expr, rhs: Expr) -> Expr {
+        let shorter = least(vec![abs(lhs.clone()), abs(rhs.clone())]);
+        let longer = greatest(vec![abs(lhs), abs(rhs)]);
+        (shorter.clone() * lit(1.0) / longer.clone())
+            .gt_eq(lit(self.ratio))
+            .or((longer - shorter).lt_eq(lit(self.difference)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_accepts() {
+        let rule = LengthRule {
+            ratio: 0.9,
+            difference: 25,
+        };
+        assert!(rule.accepts(50, 60));
+        assert!(rule.accepts(-50, -60));
+        assert!(rule.accepts(5000, 5500));
+        assert!(!rule.accepts(100, 200));
+        assert!(rule.accepts(0, 0));
+
+        let rule = LengthRule {
+            ratio: 0.9,
+            difference: 0,
+        };
+        assert!(!rule.accepts(50, 60));
+        assert!(rule.accepts(5000, 5500));
+    }
+}
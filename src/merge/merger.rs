@@ -0,0 +1,351 @@
+use std::{cell::RefCell, collections::BTreeMap, rc::Rc};
+
+use datafusion::prelude::DataFrame;
+use noodles::vcf::{
+    Header,
+    variant::{
+        RecordBuf,
+        record_buf::info::field::{Value as InfoValue, value::Array as InfoArray},
+    },
+};
+use serde::Serialize;
+
+use crate::{
+    errors::{SveltError, as_io_error},
+    merge::{MergeInput, input_chroms, load_inputs, merge_inputs_with_observer},
+    observer::{MergeObserver, NullObserver},
+    options::{CommonOptions, IndexFormat, KindWindows, MergeOptions, RecordErrorAction},
+};
+
+/// A merge, configured in code rather than on the command line.
+///
+/// ```no_run
+/// # async fn example() -> std::io::Result<()> {
+/// use svelt::merge::Merger;
+///
+/// let outcome = Merger::builder()
+///     .inputs(["sniffles.vcf", "cutesv.vcf"])
+///     .output("merged.vcf.gz")
+///     .reference("ref.fa")
+///     .position_window(50)
+///     .run()
+///     .await?;
+/// println!("{} records written", outcome.statistics.records);
+/// # Ok(())
+/// # }
+/// ```
+///
+/// The builder starts from `MergeOptions::default()` (the same defaults as
+/// the command line, so `fill_in_refs` wants a reference unless turned off),
+/// and options without a method of their own can be given with `configure`.
+pub struct Merger;
+
+impl Merger {
+    /// Start configuring a merge.
+    pub fn builder() -> MergerBuilder {
+        MergerBuilder {
+            inputs: MergerInputs::Paths(Vec::new()),
+            output: None,
+            options: MergeOptions::default(),
+            common: CommonOptions::default(),
+            observer: None,
+        }
+    }
+}
+
+enum MergerInputs {
+    Paths(Vec<String>),
+    Loaded(Vec<MergeInput>),
+}
+
+/// The configuration of a merge (see `Merger`).
+pub struct MergerBuilder {
+    inputs: MergerInputs,
+    output: Option<String>,
+    options: MergeOptions,
+    common: CommonOptions,
+    observer: Option<Rc<RefCell<dyn MergeObserver>>>,
+}
+
+impl MergerBuilder {
+    /// Merge the VCFs at these paths.
+    pub fn inputs<I, S>(mut self, paths: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.inputs = MergerInputs::Paths(paths.into_iter().map(Into::into).collect());
+        self
+    }
+
+    /// Merge inputs that have already been loaded (see `merge_inputs`),
+    /// rather than paths.
+    pub fn loaded_inputs(mut self, inputs: Vec<MergeInput>) -> Self {
+        self.inputs = MergerInputs::Loaded(inputs);
+        self
+    }
+
+    /// Where to write the merged VCF (or BCF).
+    pub fn output(mut self, path: impl Into<String>) -> Self {
+        self.output = Some(path.into());
+        self
+    }
+
+    /// The reference the inputs were called against.
+    pub fn reference(mut self, path: impl Into<String>) -> Self {
+        self.options.reference = Some(path.into());
+        self
+    }
+
+    /// The allowed distance for merging events of every kind (see
+    /// `position_windows` for overrides by kind).
+    pub fn position_window(mut self, window: u32) -> Self {
+        self.options.position_window = KindWindows::new(window);
+        self
+    }
+
+    /// The allowed distances for merging events, by kind.
+    pub fn position_windows(mut self, windows: KindWindows) -> Self {
+        self.options.position_window = windows;
+        self
+    }
+
+    /// The allowed end2 distance for merging breakends.
+    pub fn end2_window(mut self, window: u32) -> Self {
+        self.options.end2_window = window;
+        self
+    }
+
+    /// The minimum length ratio (shorter/longer) for merging two events.
+    pub fn length_ratio(mut self, ratio: f64) -> Self {
+        self.options.length_ratio = ratio;
+        self
+    }
+
+    /// The allowed length difference for events below the length ratio.
+    pub fn length_window(mut self, window: u32) -> Self {
+        self.options.length_window = window;
+        self
+    }
+
+    /// Also write the merge table (as TSV, or parquet for `.parquet`).
+    pub fn write_merge_table(mut self, path: impl Into<String>) -> Self {
+        self.options.write_merge_table = Some(path.into());
+        self
+    }
+
+    /// Also write an index of the (compressed) output.
+    pub fn write_index(mut self, format: IndexFormat) -> Self {
+        self.options.write_index = Some(format);
+        self
+    }
+
+    /// Change any of the merge options.
+    pub fn configure(mut self, configure: impl FnOnce(&mut MergeOptions)) -> Self {
+        configure(&mut self.options);
+        self
+    }
+
+    /// Replace the merge options altogether.
+    pub fn options(mut self, options: MergeOptions) -> Self {
+        self.options = options;
+        self
+    }
+
+    /// The threads, memory and spilling to use (see `CommonOptions`).
+    pub fn common(mut self, common: CommonOptions) -> Self {
+        self.common = common;
+        self
+    }
+
+    /// Call the hooks of `observer` as the merge proceeds.
+    pub fn observer(mut self, observer: Rc<RefCell<dyn MergeObserver>>) -> Self {
+        self.observer = Some(observer);
+        self
+    }
+
+    /// Run the merge.
+    ///
+    /// The field audit (`audit_fields`) is only available from the command
+    /// line, and is an error here.
+    pub async fn run(self) -> std::io::Result<MergeOutcome> {
+        let Some(output) = self.output else {
+            return Err(as_io_error(SveltError::BadOptionValue(
+                String::from("output"),
+                String::from("(required)"),
+            )));
+        };
+        if self.options.audit_fields {
+            return Err(as_io_error(SveltError::BadOptionValue(
+                String::from("--audit-fields"),
+                String::from("(only available from the command line)"),
+            )));
+        }
+        let options = Rc::new(self.options);
+        options.check().map_err(as_io_error)?;
+
+        let inputs = match self.inputs {
+            MergerInputs::Paths(paths) => {
+                load_inputs(&paths, input_chroms(&paths, &options)?, &options)?
+            }
+            MergerInputs::Loaded(inputs) => inputs,
+        };
+        if inputs.is_empty() {
+            return Err(as_io_error(SveltError::BadOptionValue(
+                String::from("inputs"),
+                String::from("(none given)"),
+            )));
+        }
+
+        let statistics = MergeStatistics {
+            inputs: inputs
+                .iter()
+                .map(|input| InputStatistics {
+                    name: input.name.clone(),
+                    records: input.records.num_rows(),
+                    rejected: input.rejected.len(),
+                })
+                .collect(),
+            ..Default::default()
+        };
+        let counter = Rc::new(RefCell::new(CountingObserver {
+            inner: self
+                .observer
+                .unwrap_or_else(|| Rc::new(RefCell::new(NullObserver))),
+            statistics,
+        }));
+        merge_inputs_with_observer(
+            &output,
+            inputs,
+            options.clone(),
+            &self.common,
+            counter.clone(),
+        )
+        .await?;
+
+        let statistics = std::mem::take(&mut counter.borrow_mut().statistics);
+        Ok(MergeOutcome {
+            paths: written_paths(&output, &options),
+            statistics,
+        })
+    }
+}
+
+/// What a merge wrote, and how it went.
+#[derive(Debug, Clone, Serialize)]
+pub struct MergeOutcome {
+    /// The paths written: the output first, then any of its index, the
+    /// merge table, the QC report, the agreement matrix, the event
+    /// documents, the IGV batch script, the quarantined records, and the
+    /// bundle directory that were asked for.
+    pub paths: Vec<String>,
+
+    /// Counts of the records read and written.
+    pub statistics: MergeStatistics,
+}
+
+/// Counts of the records read and written by a merge.
+#[derive(Debug, Default, Clone, Serialize)]
+pub struct MergeStatistics {
+    /// The records of each input, in order.
+    pub inputs: Vec<InputStatistics>,
+
+    /// The records written.
+    pub records: usize,
+
+    /// The records written that were merged from more than one record.
+    pub merged: usize,
+
+    /// The records written that each criterion (e.g. `exact`) helped merge.
+    pub criteria: BTreeMap<String, usize>,
+}
+
+/// Counts of the records of one input.
+#[derive(Debug, Default, Clone, Serialize)]
+pub struct InputStatistics {
+    /// The name of the input (e.g. its path).
+    pub name: String,
+
+    /// The records taken part in the merge (after any filtering).
+    pub records: usize,
+
+    /// The records left out because they couldn't be processed (see
+    /// `--on-record-error`).
+    pub rejected: usize,
+}
+
+/// Counts the records written, after passing each hook on to the observer
+/// given to the builder.
+struct CountingObserver {
+    inner: Rc<RefCell<dyn MergeObserver>>,
+    statistics: MergeStatistics,
+}
+
+impl MergeObserver for CountingObserver {
+    fn on_candidates(
+        &mut self,
+        criterion: &str,
+        candidates: DataFrame,
+    ) -> std::io::Result<DataFrame> {
+        self.inner.borrow_mut().on_candidates(criterion, candidates)
+    }
+
+    fn on_groups(&mut self, groups: DataFrame) -> std::io::Result<DataFrame> {
+        self.inner.borrow_mut().on_groups(groups)
+    }
+
+    fn on_header(&mut self, header: &mut Header) -> std::io::Result<()> {
+        self.inner.borrow_mut().on_header(header)
+    }
+
+    fn on_record(&mut self, header: &Header, record: &mut RecordBuf) -> std::io::Result<bool> {
+        if !self.inner.borrow_mut().on_record(header, record)? {
+            return Ok(false);
+        }
+        self.statistics.records += 1;
+        if let Some(Some(InfoValue::Array(InfoArray::String(criteria)))) =
+            record.info().get("SVELT_CRITERIA")
+        {
+            self.statistics.merged += 1;
+            for criterion in criteria.iter().flatten() {
+                *self
+                    .statistics
+                    .criteria
+                    .entry(criterion.clone())
+                    .or_default() += 1;
+            }
+        }
+        Ok(true)
+    }
+}
+
+/// The paths a merge into `out` writes, given its options.
+fn written_paths(out: &str, options: &MergeOptions) -> Vec<String> {
+    let mut paths = vec![String::from(out)];
+    match options.write_index {
+        Some(IndexFormat::Tbi) => paths.push(format!("{}.tbi", out)),
+        Some(IndexFormat::Csi) => paths.push(format!("{}.csi", out)),
+        None => {}
+    }
+    paths.extend(
+        [
+            &options.write_merge_table,
+            &options.qc_report,
+            &options.agreement_matrix,
+            &options.export_events,
+            &options.igv_batch,
+        ]
+        .into_iter()
+        .flatten()
+        .cloned(),
+    );
+    if options.on_record_error == RecordErrorAction::Quarantine
+        && let Some(quarantine) = &options.quarantine
+    {
+        paths.push(quarantine.clone());
+    }
+    if let Some(bundle) = &options.bundle_output {
+        paths.push(bundle.clone());
+    }
+    paths
+}
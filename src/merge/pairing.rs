@@ -0,0 +1,39 @@
+use datafusion::prelude::{Expr, col, lit};
+
+use crate::merge::vix_set::disjoint_expr;
+
+/// Which records a join pairs up.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) enum Pairing {
+    /// Records from different inputs, in groups still missing an input.
+    Across,
+
+    /// Records from the same input (see `--dedup-within-inputs`).
+    Within,
+}
+
+impl Pairing {
+    /// Which records (of `n` inputs) the join considers.
+    pub fn candidates_expr(self, n: usize) -> Expr {
+        match self {
+            Pairing::Across => col("vix_count").lt(lit(n as u32)),
+            Pairing::Within => lit(true),
+        }
+    }
+
+    /// Whether the inputs of `lhs` and `rhs` allow them to be paired.
+    pub fn inputs_expr(self) -> Expr {
+        match self {
+            Pairing::Across => disjoint_expr(col("lhs_vix_set"), col("rhs_vix_set")),
+            Pairing::Within => col("lhs_vix").eq(col("rhs_vix")),
+        }
+    }
+
+    /// Whether records from inputs `lhs_vix` and `rhs_vix` may be paired.
+    pub fn accepts_inputs(self, lhs_vix: u32, rhs_vix: u32) -> bool {
+        match self {
+            Pairing::Across => lhs_vix != rhs_vix,
+            Pairing::Within => lhs_vix == rhs_vix,
+        }
+    }
+}
@@ -1,15 +1,53 @@
+use std::sync::Arc;
+
 use datafusion::{
+    arrow::{
+        array::{GenericStringBuilder, PrimitiveBuilder, RecordBatch},
+        csv::WriterBuilder,
+        datatypes::{DataType, Field, Int32Type, Schema, UInt32Type},
+    },
+    common::{JoinType, ScalarValue},
     config::CsvOptions,
     dataframe::DataFrameWriteOptions,
-    prelude::{DataFrame, abs, case, col, greatest, least, lit, round},
+    prelude::{DataFrame, SessionContext, abs, case, col, greatest, least, lit, round},
+};
+
+use crate::{
+    construct::EmittedRecord,
+    merge::length::LengthRule,
+    report::{conform_merge_table, is_empty},
 };
 
-pub async fn produce_reporting_table(tbl: DataFrame, out: &str) -> std::io::Result<()> {
+/// Make the merge table, with the offsets of each contributing variant
+/// measured against the record actually emitted for its group, alongside the
+/// thresholds of the length rule and of `--bnd-insertion-window`, laid out as
+/// declared in `report::MERGE_TABLE_COLUMNS`. Each row also gives the label of
+/// its input and the samples it contributes genotypes for, from `inputs`
+/// (indexed by `vix`).
+pub fn produce_reporting_table(
+    tbl: DataFrame,
+    emitted: Vec<EmittedRecord>,
+    lengths: &LengthRule,
+    bnd_insertion_window: Option<u32>,
+    inputs: &[InputProvenance],
+    ctx: &SessionContext,
+) -> std::io::Result<DataFrame> {
+    let emitted = emitted_table(emitted, ctx)?;
+    let inputs = inputs_table(inputs, ctx)?;
     let report = tbl
-        .clone()
-        .with_column("start_offset", abs(col("start") - col("primary_start")))?
-        .with_column("end_offset", abs(col("end") - col("primary_end")))?
-        .with_column("end2_offset", abs(col("end2") - col("primary_end2")))?
+        .join(
+            emitted,
+            JoinType::Left,
+            &["row_key"],
+            &["emitted_row_key"],
+            None,
+        )?
+        .drop_columns(&["emitted_row_key"])?
+        .join(inputs, JoinType::Left, &["vix"], &["input_vix"], None)?
+        .drop_columns(&["input_vix"])?
+        .with_column("start_offset", abs(col("start") - col("emitted_start")))?
+        .with_column("end_offset", abs(col("end") - col("emitted_end")))?
+        .with_column("end2_offset", abs(col("end2") - col("emitted_end2")))?
         .with_column(
             "total_offset",
             case(col("kind"))
@@ -30,42 +68,119 @@ pub async fn produce_reporting_table(tbl: DataFrame, out: &str) -> std::io::Resu
             "length_difference",
             greatest(vec![abs(col("length")), abs(col("primary_length"))])
                 - least(vec![abs(col("length")), abs(col("primary_length"))]),
+        )?
+        .with_column("min_length_ratio", lit(lengths.min_ratio()))?
+        .with_column("max_length_difference", lit(lengths.max_difference()))?
+        .with_column(
+            "bnd_insertion_difference",
+            abs(col("bnd_ins_len") - col("primary_bnd_ins_len")),
+        )?
+        .with_column(
+            "max_bnd_insertion_difference",
+            lit(ScalarValue::UInt32(bnd_insertion_window)),
         )?;
 
-    // Move alt_seq to the back to make the table more readable
-    let columns = report.schema().columns();
-    let mut columns: Vec<&str> = columns
-        .iter()
-        .map(|c| c.name())
-        .filter(|nm| {
-            *nm != "primary_start"
-                && *nm != "primary_end"
-                && *nm != "primary_end2"
-                && *nm != "primary_length"
-        })
-        .collect();
-    if let Some((i, _)) = columns.iter().enumerate().find(|(_, nm)| **nm == "alt_seq") {
-        let j = columns.len() - 1;
-        if i != j {
-            columns.swap(i, j);
-        }
-    }
+    let report = report.sort_by(vec![
+        col("chrom_id"),
+        col("primary_start"),
+        col("primary_end"),
+        col("row_key"),
+        col("row_id"),
+    ])?;
+
+    conform_merge_table(report)
+}
 
+/// Write the merge table as tab separated values.
+pub async fn write_reporting_table(report: DataFrame, out: &str) -> std::io::Result<()> {
+    if is_empty(&report).await? {
+        // DataFusion writes no file at all for an empty table, so write the
+        // header alone.
+        let schema = Arc::new(report.schema().as_arrow().clone());
+        let mut writer = WriterBuilder::new()
+            .with_delimiter(b'\t')
+            .build(std::fs::File::create(out)?);
+        writer
+            .write(&RecordBatch::new_empty(schema))
+            .map_err(std::io::Error::other)?;
+        return Ok(());
+    }
     let opts = DataFrameWriteOptions::default();
     let csv_opts = CsvOptions::default().with_delimiter(b'\t');
     let csv_opts = Some(csv_opts);
-    report
-        .clone()
-        .sort_by(vec![
-            col("chrom_id"),
-            col("primary_start"),
-            col("primary_end"),
-            col("row_key"),
-            col("row_id"),
-        ])?
-        .select_columns(&columns)?
-        .write_csv(out, opts, csv_opts)
-        .await?;
-
+    report.write_csv(out, opts, csv_opts).await?;
     Ok(())
 }
+
+/// The label of an input (as given with `--input-labels`, or else its name),
+/// and the names of its samples, in order.
+pub struct InputProvenance {
+    pub label: String,
+    pub samples: Vec<String>,
+}
+
+fn inputs_table(inputs: &[InputProvenance], ctx: &SessionContext) -> std::io::Result<DataFrame> {
+    let mut vix_builder = PrimitiveBuilder::<UInt32Type>::new();
+    let mut label_builder = GenericStringBuilder::<i32>::new();
+    let mut samples_builder = GenericStringBuilder::<i32>::new();
+    for (vix, input) in inputs.iter().enumerate() {
+        vix_builder.append_value(vix as u32);
+        label_builder.append_value(&input.label);
+        samples_builder.append_value(input.samples.join(","));
+    }
+
+    let schema = Arc::new(Schema::new(vec![
+        Field::new("input_vix", DataType::UInt32, false),
+        Field::new("input_label", DataType::Utf8, false),
+        Field::new("samples", DataType::Utf8, false),
+    ]));
+
+    let recs = RecordBatch::try_new(
+        schema,
+        vec![
+            Arc::new(vix_builder.finish()),
+            Arc::new(label_builder.finish()),
+            Arc::new(samples_builder.finish()),
+        ],
+    )
+    .unwrap();
+
+    Ok(ctx.read_batch(recs)?)
+}
+
+fn emitted_table(emitted: Vec<EmittedRecord>, ctx: &SessionContext) -> std::io::Result<DataFrame> {
+    let mut row_key_builder = PrimitiveBuilder::<UInt32Type>::new();
+    let mut id_builder = GenericStringBuilder::<i32>::new();
+    let mut start_builder = PrimitiveBuilder::<Int32Type>::new();
+    let mut end_builder = PrimitiveBuilder::<Int32Type>::new();
+    let mut end2_builder = PrimitiveBuilder::<Int32Type>::new();
+    for rec in emitted.into_iter() {
+        row_key_builder.append_value(rec.row_key);
+        id_builder.append_value(rec.id);
+        start_builder.append_value(rec.start);
+        end_builder.append_value(rec.end);
+        end2_builder.append_option(rec.end2);
+    }
+
+    let schema = Arc::new(Schema::new(vec![
+        Field::new("emitted_row_key", DataType::UInt32, false),
+        Field::new("emitted_id", DataType::Utf8, false),
+        Field::new("emitted_start", DataType::Int32, false),
+        Field::new("emitted_end", DataType::Int32, false),
+        Field::new("emitted_end2", DataType::Int32, true),
+    ]));
+
+    let recs = RecordBatch::try_new(
+        schema,
+        vec![
+            Arc::new(row_key_builder.finish()),
+            Arc::new(id_builder.finish()),
+            Arc::new(start_builder.finish()),
+            Arc::new(end_builder.finish()),
+            Arc::new(end2_builder.finish()),
+        ],
+    )
+    .unwrap();
+
+    Ok(ctx.read_batch(recs)?)
+}
@@ -0,0 +1,158 @@
+use std::collections::HashSet;
+
+use datafusion::arrow::datatypes::DataType;
+use datafusion::prelude::{Expr, abs, cast, exp, lit};
+
+use crate::{kmers::Kmer, options::MergeOptions};
+
+/// The log-odds of a merge where the two events agree perfectly.
+const INTERCEPT: f64 = 4.0;
+
+/// The log-odds lost per unit of penalty.
+const SLOPE: f64 = 4.0;
+
+/// k-mer size used for comparing inserted sequences.
+const SIMILARITY_K: usize = 11;
+
+/// The k-mer similarity deficit that counts as one unit of penalty.
+/// k-mer Jaccard similarity falls quickly with small differences in
+/// sequence, so this is much more tolerant than the length ratio.
+const SIMILARITY_SCALE: f64 = 0.5;
+
+/// A logistic model of the probability that two events are the same.
+///
+/// Each piece of evidence (start distance, end distance, length ratio, and where
+/// available, sequence similarity) contributes a penalty, scaled by the
+/// corresponding merge option, so that an event pair that is perfect apart from
+/// one criterion sitting exactly on its threshold scores 0.5. For sequence
+/// similarity the threshold is a k-mer Jaccard similarity of 0.5.
+///
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct MergeScorer {
+    position_scale: f64,
+    end2_scale: f64,
+    length_scale: f64,
+    threshold: Option<f64>,
+}
+
+impl MergeScorer {
+    /// The scorer for pairs of events of `kind`, whose positions are scaled
+    /// by the `--position-window` for the kind.
+    pub fn new(options: &MergeOptions, kind: &str) -> MergeScorer {
+        MergeScorer {
+            position_scale: options.position_window.get(kind).max(1) as f64,
+            end2_scale: options.end2_window.max(1) as f64,
+            length_scale: (1.0 - options.length_ratio).max(f64::EPSILON),
+            threshold: options.merge_score_threshold,
+        }
+    }
+
+    /// The score threshold, if scores are being used in place of the windows.
+    pub fn threshold(&self) -> Option<f64> {
+        self.threshold
+    }
+
+    /// Turn a total penalty into a probability.
+    pub fn probability(penalty: f64) -> f64 {
+        1.0 / (1.0 + (SLOPE * penalty - INTERCEPT).exp())
+    }
+
+    /// The largest total penalty that still meets the threshold.
+    fn max_penalty(&self) -> f64 {
+        match self.threshold {
+            Some(t) => {
+                let t = t.clamp(1e-6, 1.0 - 1e-6);
+                (INTERCEPT - (t / (1.0 - t)).ln()) / SLOPE
+            }
+            None => 0.0,
+        }
+    }
+
+    /// The widest start (and end) distance that could meet the threshold.
+    pub fn max_position_distance(&self) -> u32 {
+        (self.max_penalty() * self.position_scale).ceil() as u32
+    }
+
+    /// Score a pair of non-breakend events.
+    pub fn indel_score(
+        &self,
+        start_diff: i64,
+        end_diff: i64,
+        length_ratio: f64,
+        similarity: Option<f64>,
+    ) -> f64 {
+        let mut penalty = (start_diff.abs() as f64) / self.position_scale
+            + (end_diff.abs() as f64) / self.position_scale
+            + (1.0 - length_ratio) / self.length_scale;
+        if let Some(similarity) = similarity {
+            penalty += (1.0 - similarity) / SIMILARITY_SCALE;
+        }
+        Self::probability(penalty)
+    }
+
+    /// An expression for the score of a pair of breakends, given the differences
+    /// between their ends, and between their end2s.
+    pub fn bnd_score_expr(&self, end_diff: Expr, end2_diff: Expr) -> Expr {
+        let penalty = cast(abs(end_diff), DataType::Float64) / lit(self.position_scale)
+            + cast(abs(end2_diff), DataType::Float64) / lit(self.end2_scale);
+        lit(1.0) / (lit(1.0) + exp(lit(SLOPE) * penalty - lit(INTERCEPT)))
+    }
+}
+
+/// The Jaccard similarity of the k-mer content of two sequences.
+pub(crate) fn sequence_similarity(lhs: &str, rhs: &str) -> Option<f64> {
+    let lhs: HashSet<u64> = Kmer::make_many(SIMILARITY_K, lhs)
+        .into_iter()
+        .map(|x| x.0)
+        .collect();
+    let rhs: HashSet<u64> = Kmer::make_many(SIMILARITY_K, rhs)
+        .into_iter()
+        .map(|x| x.0)
+        .collect();
+    let union = lhs.union(&rhs).count();
+    if union == 0 {
+        return None;
+    }
+    let intersection = lhs.intersection(&rhs).count();
+    Some(intersection as f64 / union as f64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn scorer(threshold: Option<f64>) -> MergeScorer {
+        MergeScorer {
+            position_scale: 25.0,
+            end2_scale: 150.0,
+            length_scale: 0.1,
+            threshold,
+        }
+    }
+
+    #[test]
+    fn test_calibration() {
+        let s = scorer(None);
+        assert!(s.indel_score(0, 0, 1.0, Some(1.0)) > 0.98);
+        assert!((s.indel_score(25, 0, 1.0, None) - 0.5).abs() < 1e-9);
+        assert!((s.indel_score(0, 0, 0.9, None) - 0.5).abs() < 1e-9);
+        assert!(s.indel_score(25, 25, 0.9, None) < 0.01);
+    }
+
+    #[test]
+    fn test_max_distance() {
+        let s = scorer(Some(0.5));
+        assert_eq!(s.max_position_distance(), 25);
+        let s = scorer(Some(0.9));
+        assert!(s.max_position_distance() < 25);
+    }
+
+    #[test]
+    fn test_sequence_similarity() {
+        let a = "ACGTTGCAAGGCTTAACCGGTTAACCGGTATATATGCGC";
+        assert_eq!(sequence_similarity(a, a), Some(1.0));
+        assert_eq!(sequence_similarity("ACGT", "ACGT"), None);
+        let b = "TTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTT";
+        assert_eq!(sequence_similarity(a, b), Some(0.0));
+    }
+}
@@ -0,0 +1,126 @@
+use std::{iter::zip, sync::Arc};
+
+use datafusion::{
+    arrow::{
+        array::{ArrayRef, BooleanBuilder},
+        datatypes::DataType,
+    },
+    common::{JoinType, cast::as_large_string_array},
+    error::DataFusionError,
+    logical_expr::{ColumnarValue, ScalarUDF, Volatility},
+    prelude::{DataFrame, SimpleScalarUDF, cast, col, length, lit, make_array},
+};
+
+use crate::{
+    distance::needleman_wunsch::NeedlemanWunsch,
+    expressions::{prefix_cols, window_match},
+    merge::{genotypes::concordant_genotypes_expr, pairing::Pairing},
+    options::MergeOptions,
+};
+
+/// Pair up insertions from different inputs within `--position-window` of
+/// each other whose inserted sequences align with at least
+/// `--min-insertion-identity` (see `NeedlemanWunsch::identity`), even where
+/// their lengths are too different for the other criteria.
+///
+/// To avoid comparing every pair of insertions on a contig, the positions are
+/// put in bins the width of the window, and each record is joined with those
+/// in its own and the neighbouring bins.
+pub(super) fn similar_insertion_join(
+    orig: DataFrame,
+    n: usize,
+    options: &MergeOptions,
+    min_identity: f64,
+) -> std::io::Result<DataFrame> {
+    let window = options.position_window.get("INS");
+    let w = window as i64;
+    let candidates = orig
+        .clone()
+        .filter(
+            lit(true)
+                .and(col("kind").eq(lit("INS")))
+                .and(length(col("alt_seq")).gt(lit(0)))
+                .and(Pairing::Across.candidates_expr(n)),
+        )?
+        .with_column("bin", cast(col("start"), DataType::Int64) / lit(w + 1))?;
+
+    let lhs = prefix_cols(candidates.clone(), "lhs")?;
+    let rhs = prefix_cols(candidates.clone(), "rhs")?
+        .with_column(
+            "rhs_bin",
+            make_array(vec![
+                col("rhs_bin") - lit(1),
+                col("rhs_bin"),
+                col("rhs_bin") + lit(1),
+            ]),
+        )?
+        .unnest_columns(&["rhs_bin"])?;
+
+    let similar = lhs
+        .join(
+            rhs,
+            JoinType::Inner,
+            &["lhs_chrom_id", "lhs_bin"],
+            &["rhs_chrom_id", "rhs_bin"],
+            Some(
+                lit(true)
+                    .and(col("lhs_row_id").lt(col("rhs_row_id")))
+                    .and(col("lhs_row_key").not_eq(col("rhs_row_key")))
+                    .and(window_match("lhs_start", "rhs_start", window))
+                    .and(Pairing::Across.inputs_expr())
+                    .and(concordant_genotypes_expr(options))
+                    .and(
+                        similar_insertions(min_identity)
+                            .call(vec![col("lhs_alt_seq"), col("rhs_alt_seq")]),
+                    ),
+            ),
+        )?
+        .sort(vec![
+            (col("lhs_vix_count") + col("rhs_vix_count")).sort(false, false),
+            col("lhs_row_key").sort(true, false),
+            col("rhs_row_key").sort(true, false),
+        ])?
+        .select_columns(&["lhs_row_key", "lhs_vix_set", "rhs_row_key", "rhs_vix_set"])?;
+
+    Ok(similar)
+}
+
+/// Whether two inserted sequences align with at least `min_identity`.
+fn similar_insertions(min_identity: f64) -> ScalarUDF {
+    ScalarUDF::from(SimpleScalarUDF::new(
+        "similar_insertions",
+        vec![DataType::LargeUtf8, DataType::LargeUtf8],
+        DataType::Boolean,
+        Volatility::Immutable,
+        Arc::new(move |args: &[ColumnarValue]| do_similar_insertions(args, min_identity)),
+    ))
+}
+
+fn do_similar_insertions(
+    args: &[ColumnarValue],
+    min_identity: f64,
+) -> std::result::Result<ColumnarValue, DataFusionError> {
+    let args = ColumnarValue::values_to_arrays(args)?;
+
+    let lhss = as_large_string_array(&args[0])?;
+    let rhss = as_large_string_array(&args[1])?;
+
+    let aligner = NeedlemanWunsch::default();
+    let mut result_builder = BooleanBuilder::new();
+    for (lhs, rhs) in zip(lhss.iter(), rhss.iter()) {
+        let res = match (lhs, rhs) {
+            // The identity can't exceed the ratio of the lengths, so there's
+            // no need to align sequences too different in length.
+            (Some(lhs), Some(rhs)) => {
+                let ratio = lhs.len().min(rhs.len()) as f64 / lhs.len().max(rhs.len()) as f64;
+                ratio >= min_identity && aligner.identity(lhs, rhs) >= min_identity
+            }
+            _ => false,
+        };
+        result_builder.append_value(res);
+    }
+
+    let result_array = result_builder.finish();
+
+    Ok(ColumnarValue::from(Arc::new(result_array) as ArrayRef))
+}
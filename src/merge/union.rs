@@ -6,22 +6,37 @@ use std::{
 
 use datafusion::{
     arrow::{
-        array::{PrimitiveBuilder, RecordBatch, UInt32Array, UInt64Array},
-        datatypes::{DataType, Field, Schema, UInt32Type, UInt64Type},
+        array::{
+            Array, BinaryArray, BinaryBuilder, Float64Array, PrimitiveBuilder, RecordBatch,
+            UInt32Array,
+        },
+        datatypes::{DataType, Field, Float64Type, Schema, UInt32Type},
     },
     common::JoinType,
-    prelude::{DataFrame, SessionContext, coalesce, col, concat_ws, lit, nullif},
+    prelude::{DataFrame, SessionContext, coalesce, col, concat_ws, least, lit, nullif},
 };
 
-use crate::disjoint_set::DisjointSet;
+use crate::{
+    diagnostics::show_table,
+    disjoint_set::DisjointSet,
+    merge::vix_set::{self, VixSet},
+};
 
 pub async fn merge_with(
     tbl: DataFrame,
     union: DataFrame,
     ctx: &SessionContext,
     criterion: &str,
+    best_partner: bool,
 ) -> std::io::Result<DataFrame> {
-    let updates = make_merge_table(union, ctx)
+    // Joins that don't score their pairs are taken to be certain.
+    let union = if union.schema().has_column_with_unqualified_name("score") {
+        union
+    } else {
+        union.with_column("score", lit(1.0f64))?
+    };
+
+    let updates = make_merge_table(union, ctx, best_partner)
         .await?
         .with_column("new_criterion", lit(criterion))?;
 
@@ -55,6 +70,10 @@ pub async fn merge_with(
             "vix_count",
             coalesce(vec![col("new_vix_count"), col("vix_count")]),
         )?
+        .with_column(
+            "merge_score",
+            least(vec![col("merge_score"), col("new_merge_score")]),
+        )?
         .with_column(
             "criteria",
             concat_ws(
@@ -67,52 +86,69 @@ pub async fn merge_with(
             "new_row_key",
             "new_vix_set",
             "new_vix_count",
+            "new_merge_score",
             "new_criterion",
         ])?;
 
-    if false {
+    show_table(
+        "merged rows",
         tbl.clone()
             .drop_columns(&["alt_seq", "seq_hash"])?
-            .sort_by(vec![col("chrom_id"), col("start"), col("row_id")])?
-            .show()
-            .await?;
-    }
+            .sort_by(vec![col("chrom_id"), col("start"), col("row_id")])?,
+    )
+    .await?;
 
     Ok(tbl)
 }
 
-async fn make_merge_table(union: DataFrame, ctx: &SessionContext) -> std::io::Result<DataFrame> {
-    let union =
-        union.select_columns(&["lhs_row_key", "lhs_vix_set", "rhs_row_key", "rhs_vix_set"])?;
+async fn make_merge_table(
+    union: DataFrame,
+    ctx: &SessionContext,
+    best_partner: bool,
+) -> std::io::Result<DataFrame> {
+    let union = union.select_columns(&[
+        "lhs_row_key",
+        "lhs_vix_set",
+        "rhs_row_key",
+        "rhs_vix_set",
+        "score",
+    ])?;
 
-    if false {
-        union.clone().show().await?;
-    }
+    show_table("merge pairs", union.clone()).await?;
 
     let union = union.collect().await?;
 
-    let itr = union.iter().flat_map(|recs| MergeIterator::new(recs));
+    let mut pairs: Vec<MergePair<'_>> = union
+        .iter()
+        .flat_map(MergeIterator::new)
+        .collect();
+    if best_partner {
+        let before = pairs.len();
+        pairs = best_partners(pairs);
+        log::info!("kept {} of {} candidate pairs", pairs.len(), before);
+    }
 
     let mut updated_row_keys = HashSet::new();
-    let mut vix_set_index = HashMap::new();
+    let mut vix_set_index: HashMap<u32, VixSet> = HashMap::new();
+    let mut score_index: HashMap<u32, f64> = HashMap::new();
     let mut sets = DisjointSet::new();
-    for (lhs_row_key, lhs_vix_set, rhs_row_key, rhs_vix_set) in itr {
+    for (lhs_row_key, lhs_vix_set, rhs_row_key, rhs_vix_set, score) in pairs {
         let x = sets.find(lhs_row_key);
         let y = sets.find(rhs_row_key);
         if x != y {
             // First, make sure we're not about to merge variants from the same VCF
             //
             let x_vix_set = if let Some(vix_set) = vix_set_index.get(&x) {
-                *vix_set
+                vix_set.clone()
             } else {
-                lhs_vix_set
+                VixSet::from_bytes(lhs_vix_set)
             };
             let y_vix_set = if let Some(vix_set) = vix_set_index.get(&y) {
-                *vix_set
+                vix_set.clone()
             } else {
-                rhs_vix_set
+                VixSet::from_bytes(rhs_vix_set)
             };
-            if x_vix_set & y_vix_set != 0 {
+            if !x_vix_set.is_disjoint(&y_vix_set) {
                 continue;
             }
 
@@ -120,7 +156,12 @@ async fn make_merge_table(union: DataFrame, ctx: &SessionContext) -> std::io::Re
             updated_row_keys.insert(rhs_row_key);
 
             let z = sets.union(x, y);
-            let vix_set = x_vix_set | y_vix_set;
+            let vix_set = x_vix_set.union(&y_vix_set);
+
+            // A group is only as good as the weakest link that formed it.
+            let x_score = score_index.remove(&x).unwrap_or(1.0);
+            let y_score = score_index.remove(&y).unwrap_or(1.0);
+            score_index.insert(z, score.min(x_score).min(y_score));
 
             vix_set_index.remove(&x);
             vix_set_index.remove(&y);
@@ -135,26 +176,30 @@ async fn make_merge_table(union: DataFrame, ctx: &SessionContext) -> std::io::Re
 
     let mut orig_row_key_builder = PrimitiveBuilder::<UInt32Type>::new();
     let mut new_row_key_builder = PrimitiveBuilder::<UInt32Type>::new();
-    let mut new_vix_set_builder = PrimitiveBuilder::<UInt64Type>::new();
+    let mut new_vix_set_builder = BinaryBuilder::new();
     let mut new_vix_count_builder = PrimitiveBuilder::<UInt32Type>::new();
+    let mut new_merge_score_builder = PrimitiveBuilder::<Float64Type>::new();
     for orig_row_key in updated_row_keys.into_iter() {
         let new_row_key = sets.find(orig_row_key);
-        let new_vix_set = *vix_set_index.get(&new_row_key).unwrap();
+        let new_vix_set = vix_set_index.get(&new_row_key).unwrap();
         orig_row_key_builder.append_value(orig_row_key);
         new_row_key_builder.append_value(new_row_key);
-        new_vix_set_builder.append_value(new_vix_set);
-        new_vix_count_builder.append_value(new_vix_set.count_ones());
+        new_vix_set_builder.append_value(new_vix_set.as_bytes());
+        new_vix_count_builder.append_value(new_vix_set.len());
+        new_merge_score_builder.append_value(*score_index.get(&new_row_key).unwrap());
     }
     let orig_row_key_array = orig_row_key_builder.finish();
     let new_row_key_array = new_row_key_builder.finish();
     let new_vix_set_array = new_vix_set_builder.finish();
     let new_vix_count_array = new_vix_count_builder.finish();
+    let new_merge_score_array = new_merge_score_builder.finish();
 
     let schema = Arc::new(Schema::new(vec![
         Field::new("orig_row_key", DataType::UInt32, false),
         Field::new("new_row_key", DataType::UInt32, false),
-        Field::new("new_vix_set", DataType::UInt64, false),
+        Field::new("new_vix_set", DataType::Binary, false),
         Field::new("new_vix_count", DataType::UInt32, false),
+        Field::new("new_merge_score", DataType::Float64, false),
     ]));
 
     let recs = RecordBatch::try_new(
@@ -164,6 +209,7 @@ async fn make_merge_table(union: DataFrame, ctx: &SessionContext) -> std::io::Re
             Arc::new(new_row_key_array),
             Arc::new(new_vix_set_array),
             Arc::new(new_vix_count_array),
+            Arc::new(new_merge_score_array),
         ],
     )
     .unwrap();
@@ -173,11 +219,50 @@ async fn make_merge_table(union: DataFrame, ctx: &SessionContext) -> std::io::Re
         .map_err(|e| Error::new(ErrorKind::Other, e))
 }
 
+/// A candidate pair: the row key and set of inputs of each side, and its score.
+type MergePair<'a> = (u32, &'a [u8], u32, &'a [u8], f64);
+
+/// Keep only the pairs in which each side is the other's best candidate from
+/// each of its inputs: the one with the highest score, or where scores tie,
+/// the lowest row key. This makes the groups independent of the order in
+/// which the pairs come, at the cost of leaving some records unmerged.
+fn best_partners(pairs: Vec<MergePair<'_>>) -> Vec<MergePair<'_>> {
+    // The best (score, partner) for each record and input.
+    let mut best: HashMap<(u32, usize), (f64, u32)> = HashMap::new();
+    let mut consider = |row_key: u32, partner: u32, partner_vix_set: &[u8], score: f64| {
+        for vix in vix_set::members(partner_vix_set) {
+            best.entry((row_key, vix))
+                .and_modify(|b| {
+                    if score > b.0 || (score == b.0 && partner < b.1) {
+                        *b = (score, partner);
+                    }
+                })
+                .or_insert((score, partner));
+        }
+    };
+    for &(lhs_row_key, lhs_vix_set, rhs_row_key, rhs_vix_set, score) in pairs.iter() {
+        consider(lhs_row_key, rhs_row_key, rhs_vix_set, score);
+        consider(rhs_row_key, lhs_row_key, lhs_vix_set, score);
+    }
+
+    let is_best = |row_key: u32, partner: u32, partner_vix_set: &[u8]| {
+        vix_set::members(partner_vix_set).all(|vix| best[&(row_key, vix)].1 == partner)
+    };
+    pairs
+        .into_iter()
+        .filter(|&(lhs_row_key, lhs_vix_set, rhs_row_key, rhs_vix_set, _)| {
+            is_best(lhs_row_key, rhs_row_key, rhs_vix_set)
+                && is_best(rhs_row_key, lhs_row_key, lhs_vix_set)
+        })
+        .collect()
+}
+
 pub(crate) struct MergeIterator<'a> {
     pub(crate) lhs_row_key: &'a UInt32Array,
-    pub(crate) lhs_vix_set: &'a UInt64Array,
+    pub(crate) lhs_vix_set: &'a BinaryArray,
     pub(crate) rhs_row_key: &'a UInt32Array,
-    pub(crate) rhs_vix_set: &'a UInt64Array,
+    pub(crate) rhs_vix_set: &'a BinaryArray,
+    pub(crate) score: &'a Float64Array,
     pub(crate) i: usize,
 }
 
@@ -187,14 +272,16 @@ impl<'a> MergeIterator<'a> {
             log::debug!("field: {:?}", field);
         }
         let lhs_row_key = Self::get_array::<UInt32Array>(recs, "lhs_row_key");
-        let lhs_vix_set = Self::get_array::<UInt64Array>(recs, "lhs_vix_set");
+        let lhs_vix_set = Self::get_array::<BinaryArray>(recs, "lhs_vix_set");
         let rhs_row_key = Self::get_array::<UInt32Array>(recs, "rhs_row_key");
-        let rhs_vix_set = Self::get_array::<UInt64Array>(recs, "rhs_vix_set");
+        let rhs_vix_set = Self::get_array::<BinaryArray>(recs, "rhs_vix_set");
+        let score = Self::get_array::<Float64Array>(recs, "score");
         MergeIterator {
             lhs_row_key,
             lhs_vix_set,
             rhs_row_key,
             rhs_vix_set,
+            score,
             i: 0,
         }
     }
@@ -212,7 +299,7 @@ impl<'a> MergeIterator<'a> {
 }
 
 impl<'a> Iterator for MergeIterator<'a> {
-    type Item = (u32, u64, u32, u64);
+    type Item = MergePair<'a>;
 
     fn next(&mut self) -> Option<Self::Item> {
         if self.i < self.lhs_vix_set.len() {
@@ -223,7 +310,8 @@ impl<'a> Iterator for MergeIterator<'a> {
             let lhs_vix_set = self.lhs_vix_set.value(i);
             let rhs_row_key = self.rhs_row_key.value(i);
             let rhs_vix_set = self.rhs_vix_set.value(i);
-            Some((lhs_row_key, lhs_vix_set, rhs_row_key, rhs_vix_set))
+            let score = self.score.value(i);
+            Some((lhs_row_key, lhs_vix_set, rhs_row_key, rhs_vix_set, score))
         } else {
             None
         }
@@ -1,10 +1,13 @@
-use std::{collections::HashMap, sync::Arc};
+use std::{
+    collections::{HashMap, HashSet},
+    sync::Arc,
+};
 
 use datafusion::{
     arrow::{
         array::{
-            ArrayRef, GenericStringArray, GenericStringBuilder, PrimitiveBuilder, RecordBatch,
-            UInt32Array,
+            Array, ArrayRef, GenericStringArray, GenericStringBuilder, Int32Array, PrimitiveBuilder,
+            RecordBatch, UInt32Array,
         },
         datatypes::{DataType, Field, Schema, UInt32Type},
     },
@@ -12,9 +15,13 @@ use datafusion::{
     error::DataFusionError,
     functions_aggregate::expr_fn::first_value,
     logical_expr::{ColumnarValue, ScalarUDF, Volatility},
-    prelude::{DataFrame, SessionContext, SimpleScalarUDF, col, concat_ws, left, lit, sha256},
+    prelude::{
+        DataFrame, SessionContext, SimpleScalarUDF, cast, col, concat_ws, left, lit, sha256,
+    },
 };
 
+use crate::{diagnostics::show_table, options::IdFormat};
+
 /// Generate values to populate the ID column.
 ///
 /// There are competing priorities in constructing IDs:
@@ -37,8 +44,16 @@ use datafusion::{
 /// that allows multiple possible IDs to be generated for a given variant, while
 /// making it vanishingly unlikely that two different variants will have the same ID.
 ///
+/// The IDs themselves are made by filling in the `--id-format` template from the
+/// primary record of each group, with `{hash}` standing for the hash above, and
+/// `{counter}` for the number of the group (counting from 1 in `row_key` order).
+/// Templates that don't make unique IDs (e.g. `{chrom}_{pos}`) have the second
+/// and subsequent uses of an ID numbered apart with a suffix (`_2` and so on).
+/// With `--id-format original`, the primary record's own ID is kept, unless it
+/// has none, or it has already been used, when the template is used instead.
 pub async fn construct_variant_ids(
     orig: DataFrame,
+    format: &IdFormat,
     ctx: &SessionContext,
 ) -> std::io::Result<DataFrame> {
     let enc = base62();
@@ -153,19 +168,81 @@ pub async fn construct_variant_ids(
             ),
         )?
         .with_column("vid_hash", left(enc.call(vec![sha256(col("vid"))]), lit(7)))?
-        .with_column(
-            "variant_id",
-            concat_ws(lit("_"), vec![lit("SVELT"), col("kind"), col("vid_hash")]),
-        )?
-        .select(vec![col("row_key").alias("rhs_row_key"), col("variant_id")])?;
+        .select(vec![
+            col("row_key"),
+            cast(col("kind"), DataType::Utf8).alias("kind"),
+            cast(col("chrom"), DataType::Utf8).alias("chrom"),
+            col("start"),
+            col("end"),
+            col("vid_hash"),
+            col("id"),
+        ])?
+        .sort_by(vec![col("row_key")])?;
 
-    if false {
-        ids.clone()
-            .sort_by(vec![col("rhs_row_key")])?
-            .show()
-            .await?;
+    // And out again to fill in the template, so the IDs can be checked for
+    // uniqueness as they're made.
+    let id_batches = ids.collect().await?;
+
+    let mut used: HashSet<String> = HashSet::new();
+    let mut counter = 0;
+
+    let mut row_key_builder = PrimitiveBuilder::<UInt32Type>::new();
+    let mut variant_id_builder = GenericStringBuilder::<i32>::new();
+
+    for batch in id_batches.into_iter() {
+        let row_keys = get_array::<UInt32Array>(&batch, "row_key");
+        let kinds = get_array::<GenericStringArray<i32>>(&batch, "kind");
+        let chroms = get_array::<GenericStringArray<i32>>(&batch, "chrom");
+        let starts = get_array::<Int32Array>(&batch, "start");
+        let ends = get_array::<Int32Array>(&batch, "end");
+        let vid_hashes = get_array::<GenericStringArray<i32>>(&batch, "vid_hash");
+        let orig_ids = get_array::<GenericStringArray<i32>>(&batch, "id");
+
+        for i in 0..row_keys.len() {
+            counter += 1;
+            let original = (format.original() && orig_ids.is_valid(i))
+                .then(|| orig_ids.value(i))
+                .filter(|id| !used.contains(*id));
+            let variant_id = match original {
+                Some(id) => String::from(id),
+                None => {
+                    let id = format.render(|field| match field {
+                        "kind" => String::from(kinds.value(i)),
+                        "chrom" => String::from(chroms.value(i)),
+                        "pos" => starts.value(i).to_string(),
+                        "end" => ends.value(i).to_string(),
+                        "hash" => String::from(vid_hashes.value(i)),
+                        "counter" => counter.to_string(),
+                        _ => String::new(),
+                    });
+                    number_apart(id, &used)
+                }
+            };
+            used.insert(variant_id.clone());
+
+            row_key_builder.append_value(row_keys.value(i));
+            variant_id_builder.append_value(variant_id);
+        }
     }
 
+    let schema = Arc::new(Schema::new(vec![
+        Field::new("rhs_row_key", DataType::UInt32, false),
+        Field::new("variant_id", DataType::Utf8, false),
+    ]));
+
+    let recs = RecordBatch::try_new(
+        schema,
+        vec![
+            Arc::new(row_key_builder.finish()),
+            Arc::new(variant_id_builder.finish()),
+        ],
+    )
+    .unwrap();
+
+    let ids = ctx.read_batch(recs)?;
+
+    show_table("variant ids", ids.clone().sort_by(vec![col("rhs_row_key")])?).await?;
+
     let res = orig
         .join(ids, JoinType::Left, &["row_key"], &["rhs_row_key"], None)?
         .drop_columns(&["rhs_row_key"])?;
@@ -173,6 +250,18 @@ pub async fn construct_variant_ids(
     Ok(res)
 }
 
+/// Number an ID apart from those already used, with the first free suffix
+/// (`_2`, `_3`, and so on), if need be.
+fn number_apart(id: String, used: &HashSet<String>) -> String {
+    if !used.contains(&id) {
+        return id;
+    }
+    (2..)
+        .map(|n| format!("{}_{}", id, n))
+        .find(|candidate| !used.contains(candidate))
+        .unwrap()
+}
+
 fn get_array<'a, Type: 'static>(recs: &'a RecordBatch, name: &str) -> &'a Type {
     if false {
         log::info!("getting {}", name);
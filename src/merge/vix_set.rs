@@ -0,0 +1,127 @@
+use std::{iter::zip, sync::Arc};
+
+use datafusion::{
+    arrow::{
+        array::{ArrayRef, BooleanBuilder},
+        datatypes::DataType,
+    },
+    common::cast::as_binary_array,
+    error::DataFusionError,
+    logical_expr::{ColumnarValue, ScalarUDF, Volatility},
+    prelude::{Expr, SimpleScalarUDF},
+};
+
+/// The set of inputs contributing to a group of records, as a bitset with one
+/// bit per input (bit `vix % 8` of byte `vix / 8`), held in a `Binary` column.
+///
+/// All the sets in a merge are the same width, enough for every input, so
+/// there is no limit on the number of inputs.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub(crate) struct VixSet(Vec<u8>);
+
+impl VixSet {
+    /// The set holding just `vix`, out of `n` inputs.
+    pub fn singleton(vix: usize, n: usize) -> VixSet {
+        let mut bytes = vec![0u8; n.div_ceil(8)];
+        bytes[vix / 8] |= 1 << (vix % 8);
+        VixSet(bytes)
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> VixSet {
+        VixSet(Vec::from(bytes))
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.0
+    }
+
+    /// The number of inputs in the set.
+    pub fn len(&self) -> u32 {
+        count(&self.0)
+    }
+
+    pub fn is_disjoint(&self, other: &VixSet) -> bool {
+        disjoint(&self.0, &other.0)
+    }
+
+    pub fn union(&self, other: &VixSet) -> VixSet {
+        VixSet(
+            zip(self.0.iter(), other.0.iter())
+                .map(|(x, y)| x | y)
+                .collect(),
+        )
+    }
+}
+
+/// The number of inputs in a set held as bytes.
+pub(crate) fn count(bytes: &[u8]) -> u32 {
+    bytes.iter().map(|b| b.count_ones()).sum()
+}
+
+/// The inputs in a set held as bytes, in order.
+pub(crate) fn members(bytes: &[u8]) -> impl Iterator<Item = usize> + '_ {
+    bytes.iter().enumerate().flat_map(|(i, b)| {
+        (0..8)
+            .filter(move |j| b & (1 << j) != 0)
+            .map(move |j| 8 * i + j)
+    })
+}
+
+/// Do two sets held as bytes have no inputs in common?
+pub(crate) fn disjoint(lhs: &[u8], rhs: &[u8]) -> bool {
+    zip(lhs.iter(), rhs.iter()).all(|(x, y)| x & y == 0)
+}
+
+/// Whether the sets of inputs `lhs` and `rhs` have no inputs in common.
+pub(crate) fn disjoint_expr(lhs: Expr, rhs: Expr) -> Expr {
+    vix_disjoint().call(vec![lhs, rhs])
+}
+
+fn vix_disjoint() -> ScalarUDF {
+    ScalarUDF::from(SimpleScalarUDF::new(
+        "vix_disjoint",
+        vec![DataType::Binary, DataType::Binary],
+        DataType::Boolean,
+        Volatility::Immutable,
+        Arc::new(do_vix_disjoint),
+    ))
+}
+
+fn do_vix_disjoint(args: &[ColumnarValue]) -> std::result::Result<ColumnarValue, DataFusionError> {
+    let args = ColumnarValue::values_to_arrays(args)?;
+
+    let lhss = as_binary_array(&args[0])?;
+    let rhss = as_binary_array(&args[1])?;
+
+    let mut result_builder = BooleanBuilder::new();
+    for (lhs, rhs) in zip(lhss.iter(), rhss.iter()) {
+        let res = match (lhs, rhs) {
+            (Some(lhs), Some(rhs)) => Some(disjoint(lhs, rhs)),
+            _ => None,
+        };
+        result_builder.append_option(res);
+    }
+
+    let result_array = result_builder.finish();
+
+    Ok(ColumnarValue::from(Arc::new(result_array) as ArrayRef))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_vix_sets() {
+        let a = VixSet::singleton(3, 200);
+        let b = VixSet::singleton(130, 200);
+        assert_eq!(a.as_bytes().len(), 25);
+        assert!(a.is_disjoint(&b));
+        let ab = a.union(&b);
+        assert_eq!(ab.len(), 2);
+        assert!(!ab.is_disjoint(&a));
+        assert!(!ab.is_disjoint(&VixSet::from_bytes(b.as_bytes())));
+        assert!(ab.is_disjoint(&VixSet::singleton(64, 200)));
+        assert_eq!(members(ab.as_bytes()).collect::<Vec<_>>(), vec![3, 130]);
+    }
+}
@@ -0,0 +1,50 @@
+use datafusion::prelude::DataFrame;
+use noodles::vcf::{Header, variant::RecordBuf};
+
+/// Hooks into the stages of a merge, for library users who want to add their
+/// own filtering, logging or annotation without forking the pipeline.
+///
+/// Register an observer with `merge::merge_inputs_with_observer`. Each hook is
+/// called at a fixed point in the pipeline, in a fixed order, so a merge with
+/// a deterministic observer is itself deterministic. Every hook defaults to
+/// leaving things as they are.
+pub trait MergeObserver {
+    /// Called with the candidate pairs found by a merge criterion (`exact`,
+    /// `locus`, `here`, `there` or `near`), before they are merged. The table
+    /// has at least the columns `lhs_row_key`, `lhs_vix_set`, `rhs_row_key` and
+    /// `rhs_vix_set` (the inputs of each side, as a `Binary` bitset); the pairs
+    /// returned are the ones merged.
+    fn on_candidates(
+        &mut self,
+        _criterion: &str,
+        candidates: DataFrame,
+    ) -> std::io::Result<DataFrame> {
+        Ok(candidates)
+    }
+
+    /// Called with the merge table once all the criteria have been applied,
+    /// with a row for each input record, and the records of a group sharing a
+    /// `row_key`. Rows left out of the table returned are left out of the
+    /// output.
+    fn on_groups(&mut self, groups: DataFrame) -> std::io::Result<DataFrame> {
+        Ok(groups)
+    }
+
+    /// Called with the output header before it is written, e.g. to declare
+    /// INFO fields added by `on_record`.
+    fn on_header(&mut self, _header: &mut Header) -> std::io::Result<()> {
+        Ok(())
+    }
+
+    /// Called with each output record before it is written. Returning false
+    /// leaves the record out. The position of the record must not be changed,
+    /// as the output is written in order.
+    fn on_record(&mut self, _header: &Header, _record: &mut RecordBuf) -> std::io::Result<bool> {
+        Ok(true)
+    }
+}
+
+/// An observer that leaves the merge as it is.
+pub struct NullObserver;
+
+impl MergeObserver for NullObserver {}
@@ -1,69 +1,594 @@
-use clap::{ArgAction, Args};
-use datafusion::prelude::{SessionConfig, SessionContext};
+use std::path::Path;
 
-use crate::errors::SveltError;
+#[cfg(feature = "cli")]
+use clap::{ArgAction, Args, ValueEnum};
+use datafusion::{
+    execution::{
+        disk_manager::{DiskManagerBuilder, DiskManagerMode},
+        runtime_env::RuntimeEnvBuilder,
+    },
+    prelude::{SessionConfig, SessionContext},
+};
+use regex::Regex;
+use serde::Serialize;
+
+use crate::{
+    chroms::ContigFilter,
+    errors::SveltError,
+    format_map::FormatMap,
+    info_rules::InfoRules,
+    inputs::{OTHER_KIND, sv_kind},
+    kmers::{Ambiguity, INDEX_K, check_k},
+    resources::Resources,
+    tables::digest_udf,
+    tag_patterns::TagPatterns,
+    targets::parse_region_string,
+    threads::WorkerPool,
+};
+
+pub use crate::samples::GenotypeConflict;
+
+/// How reciprocal breakends are recognised when checking for unpaired breakends
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[cfg_attr(feature = "cli", derive(ValueEnum))]
+#[serde(rename_all = "kebab-case")]
+pub enum BndPairing {
+    /// The mate must point back at exactly this position
+    Exact,
+    /// The mate must point back to within --bnd-pairing-window of this position
+    Window,
+}
+
+/// What to do with breakends that have no reciprocal mate
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[cfg_attr(feature = "cli", derive(ValueEnum))]
+#[serde(rename_all = "kebab-case")]
+pub enum UnpairedBndAction {
+    /// Set the UNPAIRED_BND filter
+    Filter,
+    /// Set the UNPAIRED_BND INFO flag, leaving FILTER alone
+    Annotate,
+}
+
+/// What to do with coordinates beyond the end of their contig
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[cfg_attr(feature = "cli", derive(ValueEnum))]
+#[serde(rename_all = "kebab-case")]
+pub enum ContigLengthPolicy {
+    /// Stop with an error
+    Error,
+    /// Clamp the coordinate to the contig length, with a warning
+    Clamp,
+}
+
+/// What to do with records that can't be processed
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[cfg_attr(feature = "cli", derive(ValueEnum))]
+#[serde(rename_all = "kebab-case")]
+pub enum RecordErrorAction {
+    /// Stop with an error
+    Abort,
+    /// Leave the record out, with a warning
+    Skip,
+    /// Leave the record out, with a warning, and write it to the --quarantine VCF
+    Quarantine,
+}
+
+/// What to do with records whose mate (CHR2) lies on an excluded contig
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[cfg_attr(feature = "cli", derive(ValueEnum))]
+#[serde(rename_all = "kebab-case")]
+pub enum ExcludedMateAction {
+    /// Leave the record out
+    Drop,
+    /// Merge the record as usual
+    Keep,
+}
+
+/// What to do with records whose SVTYPE isn't one svelt recognises (see `inputs::SV_KINDS`)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[cfg_attr(feature = "cli", derive(ValueEnum))]
+#[serde(rename_all = "kebab-case")]
+pub enum UnknownSvtypeAction {
+    /// Merge the record as kind OTHER, only with other such records
+    Other,
+    /// Leave the record out
+    Skip,
+}
+
+/// The format of the QC report
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[cfg_attr(feature = "cli", derive(ValueEnum))]
+#[serde(rename_all = "kebab-case")]
+pub enum QcReportFormat {
+    /// Tab separated values, one line per input and per sample
+    Tsv,
+    /// A single JSON document
+    Json,
+}
+
+/// The format of the pairwise input agreement matrix
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[cfg_attr(feature = "cli", derive(ValueEnum))]
+#[serde(rename_all = "kebab-case")]
+pub enum AgreementMatrixFormat {
+    /// Tab separated values, one line per pair of inputs
+    Tsv,
+    /// A single JSON document, with a matrix for each statistic
+    Json,
+}
+
+/// The kind of index written alongside BGZF output
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[cfg_attr(feature = "cli", derive(ValueEnum))]
+#[serde(rename_all = "kebab-case")]
+pub enum IndexFormat {
+    /// A tabix index (.tbi)
+    Tbi,
+    /// A coordinate sorted index (.csi), for contigs longer than 2^29 bases
+    Csi,
+}
+
+/// The format of the merged output
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[cfg_attr(feature = "cli", derive(ValueEnum))]
+#[serde(rename_all = "kebab-case")]
+pub enum OutputFormat {
+    /// VCF text (BGZF for .gz and .bgz output)
+    Vcf,
+    /// BCF, which is always BGZF
+    Bcf,
+}
+
+/// What to do with features whose names duplicate an earlier feature's
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[cfg_attr(feature = "cli", derive(ValueEnum))]
+#[serde(rename_all = "kebab-case")]
+pub enum DuplicateNames {
+    /// Give the later features the suffix #2, #3, and so on, with a warning
+    Rename,
+    /// Stop with an error
+    Error,
+}
 
 /// Options controlling the merge process
-#[derive(Debug, Args)]
+#[derive(Debug, Clone, Serialize)]
+#[cfg_attr(feature = "cli", derive(Args))]
 pub struct MergeOptions {
-    /// Allowed distance for merging of events
-    #[arg(long, required = false, default_value = "25")]
-    pub position_window: u32,
+    /// Allowed distance for merging of events, optionally with overrides for some kinds (e.g. 25,INS=10,BND=200)
+    #[cfg_attr(
+        feature = "cli",
+        arg(long, required = false, default_value = "25", value_parser = parse_kind_windows)
+    )]
+    pub position_window: KindWindows,
 
     /// Allowed end2 distance for merging BND events
-    #[arg(long, required = false, default_value = "150")]
+    #[cfg_attr(feature = "cli", arg(long, required = false, default_value = "150"))]
     pub end2_window: u32,
 
-    /// Minimum length ratio (shorter/longer) for merging two events
-    #[arg(long, required = false, default_value = "0.9")]
+    /// Allowed difference in the lengths of sequence inserted at the junction for merging BND events (unlimited if not given)
+    #[cfg_attr(feature = "cli", arg(long))]
+    pub bnd_insertion_window: Option<u32>,
+
+    /// Only merge BND events with the same orientation (the sides of the junction at each end), keeping the breakends of balanced rearrangements distinct
+    #[cfg_attr(feature = "cli", arg(long))]
+    pub match_bnd_orientation: bool,
+
+    /// Minimum length ratio (shorter/longer) for merging two events (or see --length-window)
+    #[cfg_attr(feature = "cli", arg(long, required = false, default_value = "0.9"))]
     pub length_ratio: f64,
 
-    /// Allowed length difference for merging two events
-    #[arg(long, required = false, default_value = "25")]
+    /// Allowed length difference for merging two events whose length ratio is below --length-ratio
+    #[cfg_attr(feature = "cli", arg(long, required = false, default_value = "25"))]
     pub length_window: u32,
 
+    /// Also merge insertions within --position-window of each other whose inserted sequences align with at least this identity (0-1), regardless of their lengths
+    #[cfg_attr(feature = "cli", arg(long))]
+    pub min_insertion_identity: Option<f64>,
+
+    /// Also merge deletions, duplications or inversions that overlap each other by at least this fraction (0-1) of both their lengths, however far apart their ends are
+    #[cfg_attr(feature = "cli", arg(long))]
+    pub min_reciprocal_overlap: Option<f64>,
+
+    /// Merge nearby inversions by their two breakpoints, and duplications by their starts and the lengths of their copies, rather than as other indels (marking them with the criteria breakpoints and copies)
+    #[cfg_attr(feature = "cli", arg(long))]
+    pub kind_specific_criteria: bool,
+
+    /// Allowed distance between the second breakpoints of inversions, with --kind-specific-criteria (the inversion --position-window if not given)
+    #[cfg_attr(feature = "cli", arg(long))]
+    pub inversion_end_window: Option<u32>,
+
+    /// Only merge deletions or duplications whose depth fold changes (DHFFC), where both have one, agree in direction
+    #[cfg_attr(feature = "cli", arg(long))]
+    pub depth_consistency: bool,
+
+    /// Only merge records whose genotypes (by number of ALT alleles) agree in every sample they share, where both are called
+    #[cfg_attr(feature = "cli", arg(long))]
+    pub require_genotype_concordance: bool,
+
+    /// Of the candidate partners a record has in another input, only consider the best scoring (the nearest, most similar in length) when grouping, rather than whichever is found first
+    #[cfg_attr(feature = "cli", arg(long))]
+    pub best_partner: bool,
+
+    /// Before merging, collapse the records within each input that would be merged (by the exact, locus and near criteria) if they came from different inputs
+    #[cfg_attr(feature = "cli", arg(long))]
+    pub dedup_within_inputs: bool,
+
+    /// Merge the records within each input that the exact criterion would merge if they came from different inputs (e.g. records called twice under different IDs), keeping all their IDs in ORIGINAL_IDS
+    #[cfg_attr(feature = "cli", arg(long))]
+    pub allow_intra_input_merge: bool,
+
+    /// Merge inputs that have svelt's own INFO fields (e.g. svelt's output), renaming those fields by input (e.g. IN0_ORIGINAL_IDS), rather than stopping with an error
+    #[cfg_attr(feature = "cli", arg(long))]
+    pub allow_svelt_inputs: bool,
+
+    /// Sort inputs that aren't sorted by position in memory, rather than stopping with an error
+    #[cfg_attr(feature = "cli", arg(long))]
+    pub sort_inputs: bool,
+
+    /// How to make the IDs of the merged records: a template of {kind}, {chrom}, {pos}, {end}, {hash} and {counter}, or 'original' to keep the primary record's own ID (falling back to the default where it has none, or it's taken)
+    #[cfg_attr(
+        feature = "cli",
+        arg(long, required = false, default_value = DEFAULT_ID_FORMAT, value_parser = parse_id_format)
+    )]
+    pub id_format: IdFormat,
+
+    /// Compression level (0-9) for .gz and .bgz output, which is written as BGZF
+    #[cfg_attr(feature = "cli", arg(long))]
+    pub output_compression_level: Option<u8>,
+
+    /// Number of threads compressing .gz and .bgz output
+    #[cfg_attr(feature = "cli", arg(long, required = false, default_value = "1"))]
+    pub output_threads: usize,
+
+    /// Also write an index of .gz or .bgz output, built as the records are written
+    #[cfg_attr(feature = "cli", arg(long, value_enum))]
+    pub write_index: Option<IndexFormat>,
+
+    /// Format of the merged output [default: bcf for .bcf output, otherwise vcf]
+    #[cfg_attr(feature = "cli", arg(long, value_enum))]
+    pub output_format: Option<OutputFormat>,
+
     /// Write out the final merge table
-    #[arg(long)]
+    #[cfg_attr(feature = "cli", arg(long))]
     pub write_merge_table: Option<String>,
 
-    /// INFO fields to drop (if they exist)
-    #[arg(short, long, value_delimiter = ',')]
+    /// Also write the merged variants as JSON Lines event documents
+    #[cfg_attr(feature = "cli", arg(long))]
+    pub export_events: Option<String>,
+
+    /// Instead of merging, print which INFO and FORMAT fields of each input are carried through to the output
+    #[cfg_attr(feature = "cli", arg(long))]
+    pub audit_fields: bool,
+
+    /// Also write a directory holding the merged VCF, the merge table (as parquet), the QC report (as JSON), and the merge configuration
+    #[cfg_attr(feature = "cli", arg(long))]
+    pub bundle_output: Option<String>,
+
+    /// Write a per-input and per-sample QC report of the merge
+    #[cfg_attr(feature = "cli", arg(long))]
+    pub qc_report: Option<String>,
+
+    /// The format of the QC report
+    #[cfg_attr(feature = "cli", arg(long, value_enum, default_value_t = QcReportFormat::Tsv))]
+    pub qc_report_format: QcReportFormat,
+
+    /// Write the pairwise agreement between inputs: shared groups, Jaccard index, and median breakpoint offset
+    #[cfg_attr(feature = "cli", arg(long))]
+    pub agreement_matrix: Option<String>,
+
+    /// The format of the agreement matrix
+    #[cfg_attr(feature = "cli", arg(long, value_enum, default_value_t = AgreementMatrixFormat::Tsv))]
+    pub agreement_matrix_format: AgreementMatrixFormat,
+
+    /// Write an IGV batch script going to (and taking a snapshot of) each of the best supported merged records, for manual review
+    #[cfg_attr(feature = "cli", arg(long))]
+    pub igv_batch: Option<String>,
+
+    /// The number of merged records in the IGV batch script
+    #[cfg_attr(feature = "cli", arg(long, default_value_t = 50))]
+    pub igv_top: usize,
+
+    /// The padding (in bp) around the breakpoints of each record in the IGV batch script
+    #[cfg_attr(feature = "cli", arg(long, default_value_t = 500))]
+    pub igv_padding: u32,
+
+    /// Only put merged records made from at least this many records in the IGV batch script
+    #[cfg_attr(feature = "cli", arg(long, default_value_t = 1))]
+    pub igv_min_support: usize,
+
+    /// The caller and sample of each input, as CALLER:SAMPLE, in the order the inputs are given
+    #[cfg_attr(feature = "cli", arg(long, value_delimiter = ','))]
+    pub input_labels: Vec<String>,
+
+    /// Add the INFO fields SUPP (the number of inputs with a variant merged into each record) and SUPP_VEC (a 1 or 0 for each input, in order), as SURVIVOR and Jasmine do
+    #[cfg_attr(feature = "cli", arg(long))]
+    pub support_fields: bool,
+
+    /// Harmonise FORMAT fields across callers, as CALLER:KEY=SOURCE, where SOURCE is a key to copy, or a sum of keys and array elements (e.g. manta:DV=SR[1]+PR[1]); CALLER is as given with --input-labels, or * for every input
+    #[cfg_attr(feature = "cli", arg(long, value_delimiter = ','))]
+    pub format_map: Vec<String>,
+
+    /// Combine INFO fields across the records merged, rather than taking them from the first, as KEY=POLICY, where POLICY is first, sum, mean, min, max, or union (the distinct values of fields with several values), e.g. SVLEN=mean,PE=sum
+    #[cfg_attr(feature = "cli", arg(long, value_delimiter = ','))]
+    pub info_rule: Vec<String>,
+
+    /// Collapse samples with the same name in several inputs (e.g. calls from several callers on one sample) into one, whose genotype is the consensus of those of the inputs with the variant
+    #[cfg_attr(feature = "cli", arg(long))]
+    pub consensus_samples: bool,
+
+    /// How --consensus-samples resolves genotypes that disagree
+    #[cfg_attr(feature = "cli", arg(long, value_enum, default_value_t = GenotypeConflict::Majority))]
+    pub genotype_conflict: GenotypeConflict,
+
+    /// Keep the consequence annotations (CSQ and ANN) of all the records merged, without duplicates, rather than just those of the first
+    #[cfg_attr(feature = "cli", arg(long))]
+    pub reconcile_annotations: bool,
+
+    /// INFO fields to drop (if they exist), by name or by a pattern with the wildcards '*' and '?' (e.g. 'SUPP*')
+    #[cfg_attr(feature = "cli", arg(short, long, value_delimiter = ','))]
     pub unwanted_info: Vec<String>,
 
+    /// FORMAT fields to drop (if they exist), by name or by a pattern as for --unwanted-info
+    #[cfg_attr(feature = "cli", arg(long, value_delimiter = ','))]
+    pub unwanted_format: Vec<String>,
+
     /// Reference sequence. Required for some extended type of merging.
-    #[arg(short, long)]
+    #[cfg_attr(feature = "cli", arg(short, long))]
     pub reference: Option<String>,
 
     /// Use a pre-indexed set of sequences to annotate inserted sequences
-    #[arg(long)]
+    #[cfg_attr(feature = "cli", arg(long))]
     pub annotate_insertions: Option<String>,
 
+    /// Reverse complement the inserted sequences of insertions classified (by --annotate-insertions) as matching the reverse strand of a feature, so each family is reported in one orientation
+    #[cfg_attr(feature = "cli", arg(long))]
+    pub orient_insertions: bool,
+
+    /// Also classify (against the index given by --annotate-insertions) the reference sequence removed by each deletion, tagging those that remove annotated elements with SVELT_DEL_CLASS. Requires --reference
+    #[cfg_attr(feature = "cli", arg(long))]
+    pub annotate_deletions: bool,
+
+    /// Longest deletion whose removed sequence is classified (see --annotate-deletions)
+    #[cfg_attr(feature = "cli", arg(long, default_value_t = 20000))]
+    pub max_annotated_deletion: usize,
+
+    /// Annotate each record with the repeats its breakpoints (POS, and END or the breakend mate) lie in, given by this BED file (e.g. of RepeatMasker annotations, named in the fourth column), as SVELT_REPEATS and SVELT_REPEAT_ENDS
+    #[cfg_attr(feature = "cli", arg(long))]
+    pub repeat_context: Option<String>,
+
+    /// Number of distinct insertion sequences to classify per batch
+    #[cfg_attr(feature = "cli", arg(long, required = false, default_value = "100000"))]
+    pub classify_batch_size: usize,
+
     /// Force ALTs to be symbolic
-    #[arg(long,
+    #[cfg_attr(feature = "cli", arg(long,
         action = ArgAction::Set,
         default_value_t = true,
         default_missing_value = "true",
-        num_args = 0..=1,)]
+        num_args = 0..=1,))]
     pub force_alt_tags: bool,
 
     /// Fill in correct reference bases
-    #[arg(long,
+    #[cfg_attr(feature = "cli", arg(long,
         action = ArgAction::Set,
         default_value_t = true,
         default_missing_value = "true",
-        num_args = 0..=1,)]
+        num_args = 0..=1,))]
     pub fill_in_refs: bool,
 
     /// Fill in unknown alleles with ref (0/0) rather than unknown.
-    #[arg(long,
+    #[cfg_attr(feature = "cli", arg(long,
     action = ArgAction::Set,
     default_value_t = true,
     default_missing_value = "true",
-    num_args = 0..=1,)]
+    num_args = 0..=1,))]
     pub use_ref_alleles: bool,
+
+    /// Emit a probability (SVELT_MERGE_SCORE) that the events in each merged group are the same
+    #[cfg_attr(feature = "cli", arg(long))]
+    pub merge_score: bool,
+
+    /// Merge events whose score is at least this, instead of using the individual windows and ratio
+    #[cfg_attr(feature = "cli", arg(long))]
+    pub merge_score_threshold: Option<f64>,
+
+    /// What to do with POS, END, or END2 beyond the declared length of the contig
+    #[cfg_attr(feature = "cli", arg(long, value_enum, default_value_t = ContigLengthPolicy::Clamp))]
+    pub contig_length_policy: ContigLengthPolicy,
+
+    /// How to decide whether a breakend has a reciprocal mate
+    #[cfg_attr(feature = "cli", arg(long, value_enum, default_value_t = BndPairing::Exact))]
+    pub bnd_pairing: BndPairing,
+
+    /// Allowed distance between the positions of reciprocal breakends with --bnd-pairing window
+    #[cfg_attr(feature = "cli", arg(long, required = false, default_value = "25"))]
+    pub bnd_pairing_window: u32,
+
+    /// Accept a reciprocal mate from any input, not just the input the breakend came from
+    #[cfg_attr(feature = "cli", arg(long))]
+    pub bnd_pairing_across_inputs: bool,
+
+    /// How to mark breakends that have no reciprocal mate
+    #[cfg_attr(feature = "cli", arg(long, value_enum, default_value_t = UnpairedBndAction::Filter))]
+    pub unpaired_bnd_action: UnpairedBndAction,
+
+    /// Synthesize the reciprocal mate record for unpaired breakends (requires a reference)
+    #[cfg_attr(feature = "cli", arg(long))]
+    pub synthesize_bnd_mates: bool,
+
+    /// Give up on any merge criterion that runs for longer than this many seconds (the output is marked as partial)
+    #[cfg_attr(feature = "cli", arg(long))]
+    pub criterion_time_limit: Option<f64>,
+
+    /// Rewrite legacy SVTYPE=TRA records as breakends, synthesizing their mates (requires a reference)
+    #[cfg_attr(feature = "cli", arg(long))]
+    pub tra_to_bnd: bool,
+
+    /// What to do with malformed records
+    #[cfg_attr(feature = "cli", arg(long, value_enum, default_value_t = RecordErrorAction::Abort))]
+    pub on_record_error: RecordErrorAction,
+
+    /// Where to write malformed records with --on-record-error quarantine
+    #[cfg_attr(feature = "cli", arg(long))]
+    pub quarantine: Option<String>,
+
+    /// Only merge records on contigs matching this regular expression (e.g. '^chr[0-9XYM]+$')
+    #[cfg_attr(feature = "cli", arg(long))]
+    pub contig_include: Option<String>,
+
+    /// Leave out records on contigs matching this regular expression (e.g. '_alt$|_decoy$')
+    #[cfg_attr(feature = "cli", arg(long))]
+    pub contig_exclude: Option<String>,
+
+    /// Match mate contigs (in CHR2 and breakend ALTs) named differently from the declared contigs only by a 'chr' prefix (e.g. 1 for chr1)
+    #[cfg_attr(feature = "cli", arg(long))]
+    pub soft_match_contigs: bool,
+
+    /// Accept inputs declaring different contigs, or the same ones in a different order, merging over the contigs of all the inputs (contigs declared with different lengths are still an error)
+    #[cfg_attr(feature = "cli", arg(long))]
+    pub reconcile_contigs: bool,
+
+    /// What to do with records whose CHR2 is on a contig left out by --contig-include/--contig-exclude
+    #[cfg_attr(feature = "cli", arg(long, value_enum, default_value_t = ExcludedMateAction::Drop))]
+    pub excluded_mate_action: ExcludedMateAction,
+
+    /// What to do with records whose SVTYPE isn't one svelt recognises (the numbers of each are logged)
+    #[cfg_attr(feature = "cli", arg(long, value_enum, default_value_t = UnknownSvtypeAction::Other))]
+    pub unknown_svtype_action: UnknownSvtypeAction,
+
+    /// Pass the records with the IDs listed in this file (or, for a .bed file, in its regions) through to the output unmodified, rather than merging them
+    #[cfg_attr(feature = "cli", arg(long))]
+    pub force_keep: Option<String>,
+
+    /// Leave out merged records in which no sample has a non-reference genotype (e.g. all 0/0 or ./.), other than those passed through by --force-keep
+    #[cfg_attr(feature = "cli", arg(long))]
+    pub drop_uncalled: bool,
+
+    /// Only merge records with a breakpoint (POS, END, or the mate's position on CHR2) in the regions of this BED file (e.g. a gene panel)
+    #[cfg_attr(feature = "cli", arg(long))]
+    pub targets: Option<String>,
+
+    /// The number of bases to pad each of the --targets regions by on each side
+    #[cfg_attr(feature = "cli", arg(long, default_value_t = 0))]
+    pub target_padding: usize,
+
+    /// Only merge records overlapping these regions, each CHROM:START-END (1-based and inclusive), a whole CHROM, or a BED file; breakends are kept if either end is in a region
+    #[cfg_attr(feature = "cli", arg(long, value_delimiter = ','))]
+    pub regions: Vec<String>,
+}
+
+/// The same defaults as the command line, for use as a library (without the
+/// `cli` feature).
+impl Default for MergeOptions {
+    fn default() -> Self {
+        MergeOptions {
+            position_window: KindWindows::new(DEFAULT_POSITION_WINDOW),
+            end2_window: 150,
+            bnd_insertion_window: None,
+            match_bnd_orientation: false,
+            length_ratio: 0.9,
+            length_window: 25,
+            min_insertion_identity: None,
+            min_reciprocal_overlap: None,
+            kind_specific_criteria: false,
+            inversion_end_window: None,
+            depth_consistency: false,
+            require_genotype_concordance: false,
+            best_partner: false,
+            dedup_within_inputs: false,
+            allow_intra_input_merge: false,
+            allow_svelt_inputs: false,
+            sort_inputs: false,
+            id_format: IdFormat::default(),
+            output_compression_level: None,
+            output_threads: 1,
+            write_index: None,
+            output_format: None,
+            write_merge_table: None,
+            export_events: None,
+            audit_fields: false,
+            bundle_output: None,
+            qc_report: None,
+            qc_report_format: QcReportFormat::Tsv,
+            agreement_matrix: None,
+            agreement_matrix_format: AgreementMatrixFormat::Tsv,
+            igv_batch: None,
+            igv_top: 50,
+            igv_padding: 500,
+            igv_min_support: 1,
+            input_labels: Vec::new(),
+            support_fields: false,
+            format_map: Vec::new(),
+            info_rule: Vec::new(),
+            consensus_samples: false,
+            genotype_conflict: GenotypeConflict::Majority,
+            reconcile_annotations: false,
+            unwanted_info: Vec::new(),
+            unwanted_format: Vec::new(),
+            reference: None,
+            annotate_insertions: None,
+            orient_insertions: false,
+            annotate_deletions: false,
+            max_annotated_deletion: 20000,
+            repeat_context: None,
+            classify_batch_size: 100000,
+            force_alt_tags: true,
+            fill_in_refs: true,
+            use_ref_alleles: true,
+            merge_score: false,
+            merge_score_threshold: None,
+            contig_length_policy: ContigLengthPolicy::Clamp,
+            bnd_pairing: BndPairing::Exact,
+            bnd_pairing_window: 25,
+            bnd_pairing_across_inputs: false,
+            unpaired_bnd_action: UnpairedBndAction::Filter,
+            synthesize_bnd_mates: false,
+            criterion_time_limit: None,
+            tra_to_bnd: false,
+            on_record_error: RecordErrorAction::Abort,
+            quarantine: None,
+            contig_include: None,
+            contig_exclude: None,
+            soft_match_contigs: false,
+            reconcile_contigs: false,
+            excluded_mate_action: ExcludedMateAction::Drop,
+            unknown_svtype_action: UnknownSvtypeAction::Other,
+            force_keep: None,
+            drop_uncalled: false,
+            targets: None,
+            target_padding: 0,
+            regions: Vec::new(),
+        }
+    }
 }
 
 impl MergeOptions {
+    /// Describe the criteria used for identifying unpaired breakends
+    pub fn unpaired_bnd_description(&self) -> String {
+        let pairing = match self.bnd_pairing {
+            BndPairing::Exact => String::from("an exactly reciprocal mate"),
+            BndPairing::Window => format!(
+                "a reciprocal mate within {}bp",
+                self.bnd_pairing_window
+            ),
+        };
+        let inputs = if self.bnd_pairing_across_inputs {
+            "any input VCF"
+        } else {
+            "the same input VCF"
+        };
+        format!(
+            "Breakend variant does not have a symmetric pair ({} from {})",
+            pairing, inputs
+        )
+    }
+
+    /// Are merge scores being computed?
+    pub fn merge_scoring(&self) -> bool {
+        self.merge_score || self.merge_score_threshold.is_some()
+    }
+
     /// Check merge options for mutual consistency
     pub fn check(&self) -> std::result::Result<(), SveltError> {
         if self.fill_in_refs && self.reference.is_none() {
@@ -71,63 +596,629 @@ impl MergeOptions {
                 "--fill-in-refs",
             )));
         }
+        if self.synthesize_bnd_mates && self.reference.is_none() {
+            return Err(SveltError::OptionReferenceRequired(String::from(
+                "--synthesize-bnd-mates",
+            )));
+        }
+        if self.tra_to_bnd && self.reference.is_none() {
+            return Err(SveltError::OptionReferenceRequired(String::from(
+                "--tra-to-bnd",
+            )));
+        }
+        if self.orient_insertions && self.annotate_insertions.is_none() {
+            return Err(SveltError::BadOptionValue(
+                String::from("--orient-insertions"),
+                String::from("(requires --annotate-insertions)"),
+            ));
+        }
+        if self.annotate_deletions && self.annotate_insertions.is_none() {
+            return Err(SveltError::BadOptionValue(
+                String::from("--annotate-deletions"),
+                String::from("(requires --annotate-insertions)"),
+            ));
+        }
+        if self.annotate_deletions && self.reference.is_none() {
+            return Err(SveltError::OptionReferenceRequired(String::from(
+                "--annotate-deletions",
+            )));
+        }
+        if self.on_record_error == RecordErrorAction::Quarantine && self.quarantine.is_none() {
+            return Err(SveltError::BadOptionValue(
+                String::from("--on-record-error"),
+                String::from("quarantine (requires --quarantine)"),
+            ));
+        }
+        if let Some(level) = self.output_compression_level
+            && level > 9
+        {
+            return Err(SveltError::BadOptionValue(
+                String::from("--output-compression-level"),
+                format!("{} (must be between 0 and 9)", level),
+            ));
+        }
+        if let Some(t) = self.criterion_time_limit
+            && (t.is_nan() || t <= 0.0)
+        {
+            return Err(SveltError::BadOptionValue(
+                String::from("--criterion-time-limit"),
+                format!("{} (must be positive)", t),
+            ));
+        }
+        if let Some(identity) = self.min_insertion_identity
+            && !(identity > 0.0 && identity <= 1.0)
+        {
+            return Err(SveltError::BadOptionValue(
+                String::from("--min-insertion-identity"),
+                format!("{} (must be between 0 and 1)", identity),
+            ));
+        }
+        if let Some(overlap) = self.min_reciprocal_overlap
+            && !(overlap > 0.0 && overlap <= 1.0)
+        {
+            return Err(SveltError::BadOptionValue(
+                String::from("--min-reciprocal-overlap"),
+                format!("{} (must be between 0 and 1)", overlap),
+            ));
+        }
+        self.contig_filter()?;
+        for label in self.input_labels.iter() {
+            if parse_input_label(label).is_none() {
+                return Err(SveltError::BadOptionValue(
+                    String::from("--input-labels"),
+                    format!("{} (expected CALLER:SAMPLE)", label),
+                ));
+            }
+        }
+        if FormatMap::parse(&self.format_map)?.names_callers() && self.input_labels.is_empty() {
+            return Err(SveltError::BadOptionValue(
+                String::from("--format-map"),
+                format!("{} (requires --input-labels)", self.format_map.join(",")),
+            ));
+        }
+        InfoRules::parse(&self.info_rule)?;
+        for region in self.regions.iter() {
+            if !Path::new(region).is_file() {
+                parse_region_string(region)?;
+            }
+        }
+        if let Some(t) = self.merge_score_threshold
+            && !(t > 0.0 && t < 1.0)
+        {
+            return Err(SveltError::BadOptionValue(
+                String::from("--merge-score-threshold"),
+                format!("{} (must be between 0 and 1)", t),
+            ));
+        }
         Ok(())
     }
+
+    /// The caller and sample of the given input, if inputs have been labelled.
+    pub fn input_label(&self, vix: usize) -> Option<(&str, &str)> {
+        self.input_labels
+            .get(vix)
+            .and_then(|label| parse_input_label(label))
+    }
+
+    /// The filter selecting which contigs' records are merged, if any.
+    pub fn contig_filter(&self) -> std::result::Result<Option<ContigFilter>, SveltError> {
+        if self.contig_include.is_none() && self.contig_exclude.is_none() {
+            return Ok(None);
+        }
+        let compile = |opt: &str, pattern: &Option<String>| {
+            pattern
+                .as_ref()
+                .map(|p| {
+                    Regex::new(p).map_err(|e| {
+                        SveltError::BadOptionValue(String::from(opt), format!("{} ({})", p, e))
+                    })
+                })
+                .transpose()
+        };
+        let include = compile("--contig-include", &self.contig_include)?;
+        let exclude = compile("--contig-exclude", &self.contig_exclude)?;
+        Ok(Some(ContigFilter::new(include, exclude)))
+    }
+
+    /// The format of the output `out` (`--output-format`, or from its name).
+    pub fn output_format(&self, out: &str) -> OutputFormat {
+        match self.output_format {
+            Some(format) => format,
+            None if out.ends_with(".bcf") => OutputFormat::Bcf,
+            None => OutputFormat::Vcf,
+        }
+    }
+
+    /// The INFO fields to drop (`--unwanted-info`).
+    pub fn unwanted_info_tags(&self) -> TagPatterns {
+        TagPatterns::new(&self.unwanted_info)
+    }
+
+    /// The FORMAT fields to drop (`--unwanted-format`).
+    pub fn unwanted_format_tags(&self) -> TagPatterns {
+        TagPatterns::new(&self.unwanted_format)
+    }
 }
 
 /// Options controlling feature indexing
-#[derive(Debug, Args)]
+#[derive(Debug)]
+#[cfg_attr(feature = "cli", derive(Args))]
 pub struct IndexingOptions {
-    /// k-mer length
-    #[arg(short, long, required = false, default_value = "11")]
+    /// k-mer length (3 to 32)
+    ///
+    /// Longer k-mers are shared by fewer unrelated features, so matches are
+    /// more specific and queries visit fewer index entries (so run faster),
+    /// but each difference between a query and a feature breaks up to k of
+    /// the k-mers they share, so similar sequences with scattered differences
+    /// may share none. The index holds an entry for each distinct k-mer of
+    /// each feature, which for all but the shortest k is nearly one per base,
+    /// whatever k is: use --minimizer-window to make it smaller.
+    #[cfg_attr(feature = "cli", arg(short, long, required = false, default_value = "11"))]
     pub k: usize,
 
-    /// Regular expression for parsing names
-    #[arg(
+    /// Minimizer window size (number of consecutive k-mers), 0 to index every k-mer
+    #[cfg_attr(feature = "cli", arg(long, required = false, default_value = "0"))]
+    pub minimizer_window: usize,
+
+    /// How to treat IUPAC ambiguity codes (recorded in the index, so queries are treated the same way)
+    #[cfg_attr(feature = "cli", arg(long, value_enum, default_value_t = Ambiguity::Break))]
+    pub ambiguity: Ambiguity,
+
+    /// Leave the feature sequences out of the index (they are only needed for find-similar --report-alignment)
+    #[cfg_attr(feature = "cli", arg(long))]
+    pub omit_sequences: bool,
+
+    /// Index the sequences as a reference genome, cut into windows of this many bases (0 to index whole features)
+    #[cfg_attr(feature = "cli", arg(long, required = false, default_value = "0"))]
+    pub genome_window: usize,
+
+    /// Distance between the starts of successive genome windows (defaults to half the window)
+    #[cfg_attr(feature = "cli", arg(long))]
+    pub genome_step: Option<usize>,
+
+    /// Also write the index as a single portable file (<out>.svx) that can be loaded without DataFusion
+    #[cfg_attr(feature = "cli", arg(long))]
+    pub portable: bool,
+
+    /// What to do with features whose names (the whole FASTA definition line) duplicate an earlier feature's, as they would be ambiguous as classifications
+    #[cfg_attr(feature = "cli", arg(long, value_enum, default_value_t = DuplicateNames::Rename))]
+    pub duplicate_names: DuplicateNames,
+
+        /// Regular expression for parsing names
+    #[cfg_attr(feature = "cli", arg(
         long,
         required = false,
         default_value = "(?<class>[^/]+/[^/]+)/(?<name>[^(]+).*"
-    )]
+    ))]
     pub pattern: String,
 
     /// Replacement to get the name (use $ to reference name/number groups)
-    #[arg(long, required = false, default_value = "$name")]
+    #[cfg_attr(feature = "cli", arg(long, required = false, default_value = "$name"))]
     pub name: String,
 
     /// Replacement to get the class (use $ to reference name/number groups)
-    #[arg(long, required = false, default_value = "$class")]
+    #[cfg_attr(feature = "cli", arg(long, required = false, default_value = "$class"))]
     pub class: String,
 }
 
+/// The same defaults as the command line, for use as a library (without the
+/// `cli` feature).
+impl Default for IndexingOptions {
+    fn default() -> Self {
+        IndexingOptions {
+            k: 11,
+            minimizer_window: 0,
+            ambiguity: Ambiguity::Break,
+            omit_sequences: false,
+            genome_window: 0,
+            genome_step: None,
+            portable: false,
+            duplicate_names: DuplicateNames::Rename,
+            pattern: String::from("(?<class>[^/]+/[^/]+)/(?<name>[^(]+).*"),
+            name: String::from("$name"),
+            class: String::from("$class"),
+        }
+    }
+}
+
+impl IndexingOptions {
+    /// Check the options are consistent and in range.
+    pub fn check(&self) -> std::result::Result<(), SveltError> {
+        check_k(self.k, INDEX_K).map_err(|msg| SveltError::BadOptionValue(String::from("-k"), msg))
+    }
+}
+
 /// Options for different kinds of query
-#[derive(Debug, Args)]
-#[group(required = true, multiple = false)]
+#[derive(Debug)]
+#[cfg_attr(feature = "cli", derive(Args))]
+#[cfg_attr(feature = "cli", group(required = true, multiple = false))]
 pub struct QueryOptions {
     /// A literal query sequence given on the command line
-    #[arg(short, long)]
+    #[cfg_attr(feature = "cli", arg(short, long))]
     pub query: Option<String>,
 
     /// Read query sequences from a FASTA file
-    #[arg(short, long)]
+    #[cfg_attr(feature = "cli", arg(long))]
     pub query_file: Option<String>,
 
     /// Read query sequences from a VCF (either insersion sequences,
     /// or from SVELT_ALT_SEQ).
-    #[arg(short, long)]
+    #[cfg_attr(feature = "cli", arg(short, long))]
     pub vcf: Option<String>,
 }
 
 /// Options common to all commands
-#[derive(Debug, Args)]
+#[derive(Debug, Default, Clone)]
+#[cfg_attr(feature = "cli", derive(Args))]
 pub struct CommonOptions {
-    /// Number of threads to use (0 to use all available)
-    #[arg(long, required = false, default_value = "4")]
-    pub threads: usize,
+    /// Number of threads to use (0 for all available CPUs; defaults to 4, or the container's CPU limit if lower)
+    #[cfg_attr(feature = "cli", arg(long))]
+    pub threads: Option<usize>,
+
+    /// Cap on the memory used for query processing (e.g. 32G, 512M; defaults to 3/4 of the container's memory limit, if any)
+    #[cfg_attr(feature = "cli", arg(long, value_parser = parse_size))]
+    pub memory_limit: Option<usize>,
+
+    /// Directory for temporary files when spilling to disk (defaults to the system temp directory)
+    #[cfg_attr(feature = "cli", arg(long))]
+    pub temp_dir: Option<String>,
+
+    /// Cap on the total size of temporary spill files (e.g. 100G)
+    #[cfg_attr(feature = "cli", arg(long, value_parser = parse_size))]
+    pub max_spill_size: Option<usize>,
+}
+
+/// Parse a size in bytes with an optional K, M, G, or T suffix (powers of 1024).
+pub fn parse_size(s: &str) -> std::result::Result<usize, String> {
+    let t = s.trim();
+    let (digits, scale) = match t.chars().last().map(|c| c.to_ascii_uppercase()) {
+        Some('K') => (&t[..t.len() - 1], 1usize << 10),
+        Some('M') => (&t[..t.len() - 1], 1usize << 20),
+        Some('G') => (&t[..t.len() - 1], 1usize << 30),
+        Some('T') => (&t[..t.len() - 1], 1usize << 40),
+        _ => (t, 1),
+    };
+    let n: usize = digits
+        .trim()
+        .parse()
+        .map_err(|_| format!("invalid size '{}'", s))?;
+    n.checked_mul(scale)
+        .ok_or_else(|| format!("size '{}' is too large", s))
+}
+
+/// The `--position-window` for kinds that aren't given one of their own.
+pub const DEFAULT_POSITION_WINDOW: u32 = 25;
+
+/// A window for each kind of variant: one for all kinds, with overrides for
+/// some of them. Written (and parsed) as a comma separated list of the
+/// window for all kinds and `KIND=WINDOW` overrides, such as `25,BND=200`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KindWindows {
+    all: u32,
+    kinds: Vec<(&'static str, u32)>,
+}
+
+impl KindWindows {
+    /// The same window for every kind.
+    pub fn new(all: u32) -> KindWindows {
+        KindWindows {
+            all,
+            kinds: Vec::new(),
+        }
+    }
+
+    /// Override the window for a kind (as given by `inputs::sv_kind`).
+    pub fn with(mut self, kind: &'static str, window: u32) -> KindWindows {
+        self.kinds.retain(|(k, _)| *k != kind);
+        self.kinds.push((kind, window));
+        self
+    }
+
+    /// The window for kinds without an override.
+    pub fn all(&self) -> u32 {
+        self.all
+    }
+
+    /// The window for `kind`.
+    pub fn get(&self, kind: &str) -> u32 {
+        self.kinds
+            .iter()
+            .find(|(k, _)| *k == kind)
+            .map_or(self.all, |(_, w)| *w)
+    }
+}
+
+impl std::fmt::Display for KindWindows {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.all)?;
+        for (kind, w) in self.kinds.iter() {
+            write!(f, ",{}={}", kind, w)?;
+        }
+        Ok(())
+    }
 }
 
-pub fn make_session_context(options: &CommonOptions) -> SessionContext {
+impl Serialize for KindWindows {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+/// Parse a `KindWindows`. Kinds are named as in SVTYPE (in any case, and
+/// with `TRA` standing for `BND`, as legacy translocations are merged as
+/// breakends), and the window for all kinds is `DEFAULT_POSITION_WINDOW`
+/// unless given.
+pub fn parse_kind_windows(s: &str) -> std::result::Result<KindWindows, String> {
+    let window = |w: &str| {
+        w.trim()
+            .parse::<u32>()
+            .map_err(|_| format!("invalid window '{}'", w))
+    };
+    let mut res = KindWindows::new(DEFAULT_POSITION_WINDOW);
+    for item in s.split(',') {
+        match item.split_once('=') {
+            Some((kind, w)) => {
+                let kind = match kind.trim().to_ascii_uppercase().as_str() {
+                    OTHER_KIND => OTHER_KIND,
+                    "TRA" => "BND",
+                    name => sv_kind(name).ok_or_else(|| format!("unknown kind '{}'", kind))?,
+                };
+                res = res.with(kind, window(w)?);
+            }
+            None => res.all = window(item)?,
+        }
+    }
+    Ok(res)
+}
+
+/// The `--id-format` giving svelt's own IDs, e.g. `SVELT_DEL_6lluU0C`.
+pub const DEFAULT_ID_FORMAT: &str = "SVELT_{kind}_{hash}";
+
+/// The fields that may appear in an `--id-format` template.
+const ID_FORMAT_TOKENS: &[&str] = &["kind", "chrom", "pos", "end", "hash", "counter"];
+
+/// How the IDs of merged records are made: from a template naming fields of
+/// the primary record of each group (see `ID_FORMAT_TOKENS`) in braces, or
+/// (for `original`) from the primary record's own ID, with the template used
+/// where it has none.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IdFormat {
+    template: String,
+    original: bool,
+}
+
+impl IdFormat {
+    /// The template the IDs are made from (where they aren't the originals).
+    pub fn template(&self) -> &str {
+        &self.template
+    }
+
+    /// Whether the primary record's own ID is kept, where it has one.
+    pub fn original(&self) -> bool {
+        self.original
+    }
+
+    /// Fill in the template with the value of each field.
+    pub fn render(&self, value: impl Fn(&str) -> String) -> String {
+        let mut res = String::new();
+        let mut rest = self.template.as_str();
+        while let Some(open) = rest.find('{') {
+            res.push_str(&rest[..open]);
+            // The template was checked when parsed, so each '{' is closed.
+            let close = open + rest[open..].find('}').unwrap();
+            res.push_str(&value(&rest[open + 1..close]));
+            rest = &rest[close + 1..];
+        }
+        res.push_str(rest);
+        res
+    }
+}
+
+impl Default for IdFormat {
+    fn default() -> Self {
+        IdFormat {
+            template: String::from(DEFAULT_ID_FORMAT),
+            original: false,
+        }
+    }
+}
+
+impl std::fmt::Display for IdFormat {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.original {
+            write!(f, "original")
+        } else {
+            write!(f, "{}", self.template)
+        }
+    }
+}
+
+impl Serialize for IdFormat {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+/// Parse an `IdFormat`: `original`, or a template whose fields are all known.
+/// IDs may not contain whitespace or semicolons, so neither may the template.
+pub fn parse_id_format(s: &str) -> std::result::Result<IdFormat, String> {
+    if s == "original" {
+        return Ok(IdFormat {
+            template: String::from(DEFAULT_ID_FORMAT),
+            original: true,
+        });
+    }
+    if s.is_empty() {
+        return Err(String::from("empty ID format"));
+    }
+    if s.chars().any(|c| c.is_whitespace() || c == ';') {
+        return Err(format!("invalid character in ID format '{}'", s));
+    }
+    let mut rest = s;
+    while let Some(open) = rest.find(['{', '}']) {
+        if rest.as_bytes()[open] == b'}' {
+            return Err(format!("unopened '}}' in ID format '{}'", s));
+        }
+        let Some(close) = rest[open..].find('}').map(|i| open + i) else {
+            return Err(format!("unclosed '{{' in ID format '{}'", s));
+        };
+        let token = &rest[open + 1..close];
+        if !ID_FORMAT_TOKENS.contains(&token) {
+            return Err(format!("unknown field '{}' in ID format '{}'", token, s));
+        }
+        rest = &rest[close + 1..];
+    }
+    Ok(IdFormat {
+        template: String::from(s),
+        original: false,
+    })
+}
+
+/// Split an input label into its caller and sample, both of which must be non-empty.
+fn parse_input_label(label: &str) -> Option<(&str, &str)> {
+    let (caller, sample) = label.split_once(':')?;
+    if caller.is_empty() || sample.is_empty() {
+        return None;
+    }
+    Some((caller, sample))
+}
+
+/// The default number of threads, when not limited by the environment.
+const DEFAULT_THREADS: usize = 4;
+
+/// The fraction of a container's memory limit given over to query processing
+/// by default, leaving room for everything else.
+const CONTAINER_MEMORY_FRACTION: f64 = 0.75;
+
+pub fn make_session_context(options: &CommonOptions) -> std::io::Result<SessionContext> {
+    let resources = Resources::detect();
+    log::info!(
+        "detected {} CPUs (cgroup CPU limit: {}, cgroup memory limit: {})",
+        resources.host_cpus,
+        resources
+            .cpu_limit
+            .map_or(String::from("none"), |c| format!("{:.2}", c)),
+        resources
+            .memory_limit
+            .map_or(String::from("none"), |m| format!("{} bytes", m))
+    );
+
+    let threads = match options.threads {
+        Some(0) => resources.available_cpus(),
+        Some(threads) => threads,
+        None => DEFAULT_THREADS.min(resources.available_cpus()),
+    };
+    log::info!("using {} threads", threads);
+
     let mut cfg = SessionConfig::new();
-    cfg.options_mut().execution.target_partitions = options.threads;
+    cfg.options_mut().execution.target_partitions = threads;
+    let cfg = WorkerPool::configure(cfg, threads)?;
 
-    SessionContext::new_with_config(cfg)
+    let memory_limit = options.memory_limit.or_else(|| {
+        resources
+            .memory_limit
+            .map(|m| (m as f64 * CONTAINER_MEMORY_FRACTION) as usize)
+    });
+
+    let mut runtime = RuntimeEnvBuilder::new();
+    if let Some(limit) = memory_limit {
+        log::info!("limiting query memory to {} bytes", limit);
+        runtime = runtime.with_memory_limit(limit, 1.0);
+    }
+    let mut disk_manager = DiskManagerBuilder::default();
+    if let Some(temp_dir) = &options.temp_dir {
+        log::info!("spilling temporary files to {}", temp_dir);
+        disk_manager = disk_manager.with_mode(DiskManagerMode::Directories(vec![temp_dir.into()]));
+    }
+    if let Some(max_spill_size) = options.max_spill_size {
+        disk_manager = disk_manager.with_max_temp_directory_size(max_spill_size as u64);
+    }
+    let runtime = runtime.with_disk_manager_builder(disk_manager).build_arc()?;
+
+    let ctx = SessionContext::new_with_config_rt(cfg, runtime);
+    ctx.register_udf(digest_udf());
+    Ok(ctx)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_size() {
+        assert_eq!(parse_size("1024"), Ok(1024));
+        assert_eq!(parse_size("4k"), Ok(4096));
+        assert_eq!(parse_size("512M"), Ok(512 << 20));
+        assert_eq!(parse_size("32G"), Ok(32 << 30));
+        assert!(parse_size("lots").is_err());
+        assert!(parse_size("G").is_err());
+    }
+
+    #[test]
+    fn test_parse_kind_windows() {
+        let windows = parse_kind_windows("50").unwrap();
+        assert_eq!(windows, KindWindows::new(50));
+        assert_eq!(windows.get("INS"), 50);
+
+        let windows = parse_kind_windows("ins=10,DEL=50,tra=200").unwrap();
+        assert_eq!(windows.all(), DEFAULT_POSITION_WINDOW);
+        assert_eq!(windows.get("INS"), 10);
+        assert_eq!(windows.get("DEL"), 50);
+        assert_eq!(windows.get("BND"), 200);
+        assert_eq!(windows.get("DUP"), DEFAULT_POSITION_WINDOW);
+        assert_eq!(windows.to_string(), "25,INS=10,DEL=50,BND=200");
+        assert_eq!(parse_kind_windows(&windows.to_string()), Ok(windows));
+
+        assert_eq!(parse_kind_windows("INS=5,INS=15").unwrap().get("INS"), 15);
+        assert!(parse_kind_windows("FOO=10").is_err());
+        assert!(parse_kind_windows("INS=ten").is_err());
+        assert!(parse_kind_windows("").is_err());
+    }
+
+    #[test]
+    fn test_parse_id_format() {
+        let format = parse_id_format(DEFAULT_ID_FORMAT).unwrap();
+        assert_eq!(format, IdFormat::default());
+        let value = |field: &str| match field {
+            "kind" => String::from("DEL"),
+            "chrom" => String::from("chr1"),
+            "pos" => String::from("1000"),
+            _ => String::from("x"),
+        };
+        assert_eq!(format.render(value), "SVELT_DEL_x");
+
+        let format = parse_id_format("{chrom}:{pos}-{kind}").unwrap();
+        assert!(!format.original());
+        assert_eq!(format.render(value), "chr1:1000-DEL");
+        assert_eq!(parse_id_format("fixed").unwrap().render(value), "fixed");
+
+        let format = parse_id_format("original").unwrap();
+        assert!(format.original());
+        assert_eq!(format.template(), DEFAULT_ID_FORMAT);
+        assert_eq!(format.to_string(), "original");
+
+        assert!(parse_id_format("").is_err());
+        assert!(parse_id_format("{chrom").is_err());
+        assert!(parse_id_format("chrom}").is_err());
+        assert!(parse_id_format("{sample}").is_err());
+        assert!(parse_id_format("{chrom} {pos}").is_err());
+    }
+
+    #[cfg(feature = "cli")]
+    #[test]
+    fn test_default_merge_options() {
+        use clap::Parser;
+
+        #[derive(Parser)]
+        struct TestCli {
+            #[command(flatten)]
+            options: MergeOptions,
+        }
+
+        let parsed = TestCli::parse_from(["svelt"]).options;
+        assert_eq!(
+            serde_json::to_value(MergeOptions::default()).unwrap(),
+            serde_json::to_value(&parsed).unwrap()
+        );
+    }
 }
@@ -0,0 +1,411 @@
+use noodles::vcf::{
+    Header,
+    header::record::value::{
+        Collection, Map,
+        map::{
+            Info,
+            info::{Number, Type},
+        },
+    },
+};
+
+/// The version of the layout of the VCF svelt writes, given in the header as
+/// `##svelt_format=`.
+///
+/// Any change to the fields svelt defines (`SVELT_INFO_FIELDS`,
+/// `SVELT_FILTERS` and `MERGE_CRITERIA`) must bump the version, so that tools
+/// reading the output can tell what they have. The fingerprint test below
+/// fails until it is bumped.
+pub const SVELT_FORMAT_VERSION: u32 = 9;
+
+/// The header key for the version of the output format.
+pub const SVELT_FORMAT_KEY: &str = "svelt_format";
+
+/// An INFO field svelt writes.
+#[derive(Debug)]
+pub struct InfoDefinition {
+    pub id: &'static str,
+    pub number: Number,
+    pub ty: Type,
+
+    /// The description, or `None` if it depends on the options.
+    pub description: Option<&'static str>,
+}
+
+impl InfoDefinition {
+    /// The header declaration of the field (with `description` in place of
+    /// the one depending on the options, if any).
+    pub fn declaration(&self, description: &str) -> Map<Info> {
+        Map::<Info>::new(
+            self.number,
+            self.ty,
+            self.description.unwrap_or(description),
+        )
+    }
+}
+
+/// The INFO fields svelt writes (besides the flags for each of the
+/// `MERGE_CRITERIA`). Which are declared depends on the options (see
+/// `construct::add_svelt_header_fields`).
+pub const SVELT_INFO_FIELDS: &[InfoDefinition] = &[
+    InfoDefinition {
+        id: "UNPAIRED_BND",
+        number: Number::Count(0),
+        ty: Type::Flag,
+        description: None,
+    },
+    InfoDefinition {
+        id: "CHR2",
+        number: Number::Count(1),
+        ty: Type::String,
+        description: Some("Chromosome for end coordinate in case of a translocation"),
+    },
+    InfoDefinition {
+        id: "END2",
+        number: Number::Count(1),
+        ty: Type::Integer,
+        description: Some("End position of the structural variant on CHR2"),
+    },
+    InfoDefinition {
+        id: "EVENT",
+        number: Number::Count(1),
+        ty: Type::String,
+        description: Some("ID of event associated to breakend"),
+    },
+    InfoDefinition {
+        id: "MATEID",
+        number: Number::Unknown,
+        ty: Type::String,
+        description: Some("ID of mate breakends"),
+    },
+    InfoDefinition {
+        id: "SVELT_SYNTHETIC_MATE",
+        number: Number::Count(0),
+        ty: Type::Flag,
+        description: Some(
+            "This breakend was synthesized by svelt as the mate of an unpaired breakend.",
+        ),
+    },
+    InfoDefinition {
+        id: "ORIGINAL_IDS",
+        number: Number::Unknown,
+        ty: Type::String,
+        description: Some("The variant IDs from the original VCFs"),
+    },
+    InfoDefinition {
+        id: "SVELT_CRITERIA",
+        number: Number::Unknown,
+        ty: Type::String,
+        description: Some("The list of criteria that resulted in the merging of these variants."),
+    },
+    InfoDefinition {
+        id: "SVELT_CALLERS",
+        number: Number::Count(1),
+        ty: Type::Integer,
+        description: Some(
+            "The number of distinct callers (from --input-labels) supporting the merged variants.",
+        ),
+    },
+    InfoDefinition {
+        id: "SVELT_SAMPLES",
+        number: Number::Count(1),
+        ty: Type::Integer,
+        description: Some(
+            "The number of distinct samples (from --input-labels) supporting the merged variants.",
+        ),
+    },
+    InfoDefinition {
+        id: "SUPP",
+        number: Number::Count(1),
+        ty: Type::Integer,
+        description: Some("The number of inputs with a variant merged into this one."),
+    },
+    InfoDefinition {
+        id: "SUPP_VEC",
+        number: Number::Count(1),
+        ty: Type::String,
+        description: Some(
+            "Whether each input (1) or not (0) has a variant merged into this one, in the order the inputs were given.",
+        ),
+    },
+    InfoDefinition {
+        id: "SVELT_MERGE_SCORE",
+        number: Number::Count(1),
+        ty: Type::Float,
+        description: Some("Probability that the merged variants represent the same event."),
+    },
+    InfoDefinition {
+        id: "SVELT_MAX_OFFSET",
+        number: Number::Count(2),
+        ty: Type::Integer,
+        description: Some(
+            "The largest distances of the starts and of the ends (for breakends, the mate positions) of the merged variants from those of this one.",
+        ),
+    },
+    InfoDefinition {
+        id: "SVELT_MIN_RATIO",
+        number: Number::Count(1),
+        ty: Type::Float,
+        description: Some(
+            "The smallest ratio of the length of a merged variant to that of this one (the shorter over the longer), rounded down.",
+        ),
+    },
+    InfoDefinition {
+        id: "SVELT_ALT_SEQ",
+        number: Number::Unknown,
+        ty: Type::String,
+        description: Some("The list of alt sequences that were replaced with the ALT tag."),
+    },
+    InfoDefinition {
+        id: "SVELT_ALT_CLASS",
+        number: Number::Count(1),
+        ty: Type::String,
+        description: Some("Classification of the inserted sequence."),
+    },
+    InfoDefinition {
+        id: "SVELT_DEL_CLASS",
+        number: Number::Count(1),
+        ty: Type::String,
+        description: Some("Classification of the reference sequence removed by the deletion."),
+    },
+    InfoDefinition {
+        id: "SVELT_REPEATS",
+        number: Number::Count(2),
+        ty: Type::String,
+        description: Some(
+            "The repeats (from --repeat-context) the start and the end (for breakends, the mate) lie in.",
+        ),
+    },
+    InfoDefinition {
+        id: "SVELT_REPEAT_ENDS",
+        number: Number::Count(1),
+        ty: Type::String,
+        description: Some("Whether both breakpoints, or only one, lie in a repeat."),
+    },
+    InfoDefinition {
+        id: "SVELT_ALT_REVERSED",
+        number: Number::Count(0),
+        ty: Type::Flag,
+        description: Some(
+            "The inserted sequences were reverse complemented to the strand of the classifying feature.",
+        ),
+    },
+];
+
+/// The FILTERs svelt writes. Their descriptions depend on the options.
+pub const SVELT_FILTERS: &[&str] = &["UNPAIRED_BND"];
+
+/// The criteria by which variants are merged, in the order they are applied,
+/// with the INFO flag set for each, and its description.
+pub const MERGE_CRITERIA: &[(&str, &str, &str)] = &[
+    (
+        "exact",
+        "SVELT_EXACT",
+        "Merged with variants of exactly the same type, position, and length (and breakend mate).",
+    ),
+    (
+        "locus",
+        "SVELT_LOCUS",
+        "Merged with insertions at exactly the same position, of similar length.",
+    ),
+    (
+        "here",
+        "SVELT_HERE",
+        "Merged with breakends whose positions and mate positions are nearby (and, with --match-bnd-orientation, of the same orientation).",
+    ),
+    (
+        "there",
+        "SVELT_THERE",
+        "Merged with breakends whose mate positions and positions are nearby (and, with --match-bnd-orientation, of the same orientation).",
+    ),
+    (
+        "near",
+        "SVELT_NEAR",
+        "Merged with variants of the same type at nearby positions, of similar length.",
+    ),
+    (
+        "breakpoints",
+        "SVELT_BREAKPOINTS",
+        "Merged with inversions whose breakpoints are each nearby.",
+    ),
+    (
+        "copies",
+        "SVELT_COPIES",
+        "Merged with duplications at nearby positions whose copies are of similar length.",
+    ),
+    (
+        "overlap",
+        "SVELT_OVERLAP",
+        "Merged with deletions, duplications or inversions of the same type that overlap it reciprocally.",
+    ),
+    (
+        "similar",
+        "SVELT_SIMILAR",
+        "Merged with insertions at nearby positions whose inserted sequences are similar.",
+    ),
+];
+
+/// The INFO fields svelt writes that callers write too (for breakends), so
+/// that the inputs' values and declarations of them are kept.
+pub const STANDARD_INFO_FIELDS: &[&str] = &["CHR2", "END2", "EVENT", "MATEID"];
+
+/// The INFO fields svelt writes (with `--support-fields`) that other mergers,
+/// such as SURVIVOR and Jasmine, write too. Their outputs may be merged as
+/// they are, and svelt's values and declarations replace theirs.
+pub const SHARED_INFO_FIELDS: &[&str] = &["SUPP", "SUPP_VEC"];
+
+/// Whether the INFO field `id` is svelt's own: one it writes, other than the
+/// standard and shared fields, or the flag of a merge criterion. Inputs with
+/// such fields (e.g. svelt's output) are only merged with
+/// `--allow-svelt-inputs`.
+pub fn is_svelt_owned(id: &str) -> bool {
+    (SVELT_INFO_FIELDS.iter().any(|def| def.id == id)
+        && !STANDARD_INFO_FIELDS.contains(&id)
+        && !SHARED_INFO_FIELDS.contains(&id))
+        || MERGE_CRITERIA.iter().any(|(_, flag, _)| *flag == id)
+}
+
+/// The name given in the output to svelt's own INFO field `id` in input `vix`
+/// (with `--allow-svelt-inputs`), so it can't be taken for this run's.
+pub fn input_svelt_field(vix: usize, id: &str) -> String {
+    format!("IN{}_{}", vix, id)
+}
+
+/// The definition of the INFO field `id` svelt writes.
+pub fn svelt_info(id: &str) -> &'static InfoDefinition {
+    SVELT_INFO_FIELDS
+        .iter()
+        .find(|def| def.id == id)
+        .unwrap_or_else(|| panic!("{} is not an INFO field defined by svelt", id))
+}
+
+/// The version of the output format a VCF header declares, if it was written
+/// by svelt: `Some(Err(..))` if the declaration can't be understood.
+pub fn format_version(header: &Header) -> Option<Result<u32, String>> {
+    let value = match header.get(SVELT_FORMAT_KEY)? {
+        Collection::Unstructured(values) => values.first()?,
+        Collection::Structured(_) => return Some(Err(String::from("(structured)"))),
+    };
+    Some(value.trim().parse().map_err(|_| value.clone()))
+}
+
+/// Warn if `source` was written by svelt with a different version of the
+/// output format, whose fields may not mean what this version expects.
+///
+/// Returns whether the format is compatible (which it is if the VCF wasn't
+/// written by svelt, or predates the versioning).
+pub fn check_format_version(header: &Header, source: &str) -> bool {
+    match format_version(header) {
+        None => true,
+        Some(Ok(version)) if version == SVELT_FORMAT_VERSION => true,
+        Some(Ok(version)) => {
+            log::warn!(
+                "{} was written with svelt output format {}, but this is format {}, so svelt's fields in it may be read incorrectly",
+                source,
+                version,
+                SVELT_FORMAT_VERSION
+            );
+            false
+        }
+        Some(Err(value)) => {
+            log::warn!(
+                "{} has an unrecognised svelt output format '{}' (this is format {})",
+                source,
+                value,
+                SVELT_FORMAT_VERSION
+            );
+            false
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use blake2::{Blake2b512, Digest};
+
+    use super::*;
+
+    /// The fingerprints of the field definitions for each version of the
+    /// output format. Bump `SVELT_FORMAT_VERSION` and add an entry here when
+    /// they change.
+    const FORMAT_FINGERPRINTS: &[(u32, &str)] = &[
+        (1, "edcf5ed7a8a4f562"),
+        (2, "1c646be2e109269c"),
+        (3, "900b30caa163aed6"),
+        (4, "90681731888626fd"),
+        (5, "3f89933aaec4a1a1"),
+        (6, "a5c70f1092089023"),
+        (7, "a3524fa5ed77ca09"),
+        (8, "c7571a38fa12ed51"),
+        (9, "9482014d015a4a5f"),
+    ];
+
+    fn fingerprint() -> String {
+        let mut hasher = Blake2b512::new();
+        for def in SVELT_INFO_FIELDS.iter() {
+            hasher.update(format!("INFO\t{:?}\n", def));
+        }
+        for id in SVELT_FILTERS.iter() {
+            hasher.update(format!("FILTER\t{}\n", id));
+        }
+        for criterion in MERGE_CRITERIA.iter() {
+            hasher.update(format!("CRITERION\t{:?}\n", criterion));
+        }
+        let digest = hasher.finalize();
+        digest[..8].iter().map(|b| format!("{:02x}", b)).collect()
+    }
+
+    #[test]
+    fn test_format_fingerprint() {
+        let (version, expected) = FORMAT_FINGERPRINTS.last().unwrap();
+        assert_eq!(*version, SVELT_FORMAT_VERSION);
+        assert_eq!(
+            fingerprint(),
+            *expected,
+            "the fields svelt writes have changed: bump SVELT_FORMAT_VERSION and record the new fingerprint"
+        );
+    }
+
+    #[test]
+    fn test_svelt_owned() {
+        assert!(is_svelt_owned("ORIGINAL_IDS"));
+        assert!(is_svelt_owned("SVELT_CRITERIA"));
+        assert!(is_svelt_owned("SVELT_NEAR"));
+        assert!(is_svelt_owned("UNPAIRED_BND"));
+        assert!(!is_svelt_owned("MATEID"));
+        assert!(!is_svelt_owned("CHR2"));
+        assert!(!is_svelt_owned("SUPP_VEC"));
+        assert!(!is_svelt_owned("SVTYPE"));
+        assert_eq!(input_svelt_field(1, "ORIGINAL_IDS"), "IN1_ORIGINAL_IDS");
+    }
+
+    #[test]
+    fn test_format_version() {
+        let header = |line: &str| -> Header {
+            format!(
+                "##fileformat=VCFv4.2\n{}#CHROM\tPOS\tID\tREF\tALT\tQUAL\tFILTER\tINFO\n",
+                line
+            )
+            .parse()
+            .unwrap()
+        };
+        let current = format!("##svelt_format={}\n", SVELT_FORMAT_VERSION);
+
+        assert_eq!(format_version(&header("")), None);
+        assert_eq!(
+            format_version(&header(&current)),
+            Some(Ok(SVELT_FORMAT_VERSION))
+        );
+        assert_eq!(
+            format_version(&header("##svelt_format=x\n")),
+            Some(Err(String::from("x")))
+        );
+
+        assert!(check_format_version(&header(""), "a.vcf"));
+        assert!(check_format_version(&header(&current), "a.vcf"));
+        assert!(!check_format_version(
+            &header("##svelt_format=0\n"),
+            "a.vcf"
+        ));
+    }
+}
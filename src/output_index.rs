@@ -0,0 +1,269 @@
+use std::fs::File;
+use std::io::{BufReader, Error, ErrorKind, Read, Seek, SeekFrom};
+
+use noodles::bgzf::gzi;
+use noodles::core::Position;
+use noodles::csi::{
+    self,
+    binning_index::{
+        Indexer,
+        index::{
+            header::{self, ReferenceSequenceNames},
+            reference_sequence::{
+                Index,
+                bin::Chunk,
+                index::{BinnedIndex, LinearIndex},
+            },
+        },
+    },
+};
+use noodles::tabix;
+use noodles::vcf::Header;
+
+use crate::options::IndexFormat;
+
+const MIN_SHIFT: u8 = 14;
+const TABIX_DEPTH: u8 = 5;
+
+/// The extent of a record, and where its line lies in the uncompressed output.
+struct IndexedRecord {
+    reference_sequence_id: usize,
+    start: Position,
+    end: Position,
+    offsets: (u64, u64),
+}
+
+/// An index (`--write-index`) of BGZF output, built up as the records are
+/// written.
+///
+/// The blocks of the output are compressed in parallel, so the compressed
+/// offset of a record isn't known as it is written. Instead, the uncompressed
+/// offsets of each record are held, and turned into virtual positions from
+/// the block sizes once the output is finished.
+///
+/// The index of BCF output is a CSI index without names, whose reference
+/// sequences are the contigs of the header, in order.
+pub struct OutputIndexer {
+    format: IndexFormat,
+    names: ReferenceSequenceNames,
+    records: Vec<IndexedRecord>,
+    bcf_contigs: Option<Vec<String>>,
+}
+
+impl OutputIndexer {
+    pub fn new(format: IndexFormat) -> OutputIndexer {
+        OutputIndexer {
+            format,
+            names: ReferenceSequenceNames::default(),
+            records: Vec::new(),
+            bcf_contigs: None,
+        }
+    }
+
+    /// An indexer of BCF output with the given `header`.
+    pub fn bcf(header: &Header) -> OutputIndexer {
+        OutputIndexer {
+            bcf_contigs: Some(header.contigs().keys().cloned().collect()),
+            ..OutputIndexer::new(IndexFormat::Csi)
+        }
+    }
+
+    /// Note a record of `chrom` covering `start..=end`, whose line lies
+    /// between the given uncompressed `offsets` of the output.
+    ///
+    /// The records of each contig must be written together, in order of
+    /// position.
+    pub fn add_record(
+        &mut self,
+        chrom: &str,
+        start: Position,
+        end: Position,
+        offsets: (u64, u64),
+    ) -> std::io::Result<()> {
+        let reference_sequence_id = match self.names.get_index_of(chrom.as_bytes()) {
+            Some(id) if id + 1 == self.names.len() => id,
+            Some(_) => {
+                return Err(Error::new(
+                    ErrorKind::InvalidData,
+                    format!(
+                        "can't index the output: the records for {} are not together",
+                        chrom
+                    ),
+                ));
+            }
+            None => self.names.insert_full(chrom.into()).0,
+        };
+        if let Some(prev) = self.records.last()
+            && prev.reference_sequence_id == reference_sequence_id
+            && prev.start > start
+        {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!(
+                    "can't index the output: {}:{} comes after {}:{}",
+                    chrom, start, chrom, prev.start
+                ),
+            ));
+        }
+        self.records.push(IndexedRecord {
+            reference_sequence_id,
+            start,
+            end: end.max(start),
+            offsets,
+        });
+        Ok(())
+    }
+
+    /// Build the index of the finished output `out`, and write it alongside
+    /// (as `out.tbi` or `out.csi`).
+    pub fn finish(&mut self, out: &str) -> std::io::Result<()> {
+        let blocks = block_offsets(out)?;
+        match self.format {
+            IndexFormat::Tbi => {
+                let index = self.build::<LinearIndex>(TABIX_DEPTH, &blocks)?;
+                tabix::fs::write(format!("{}.tbi", out), &index)
+            }
+            IndexFormat::Csi => {
+                let max_end = self.records.iter().map(|r| r.end.get()).max().unwrap_or(0);
+                let index = self.build::<BinnedIndex>(csi_depth(max_end), &blocks)?;
+                csi::fs::write(format!("{}.csi", out), &index)
+            }
+        }
+    }
+
+    fn build<I>(
+        &mut self,
+        depth: u8,
+        blocks: &gzi::Index,
+    ) -> std::io::Result<csi::binning_index::Index<I>>
+    where
+        I: Index + Default,
+    {
+        let names = std::mem::take(&mut self.names);
+        // BCF refers to the contigs by their place in the header.
+        let ids: Vec<usize> = match &self.bcf_contigs {
+            Some(contigs) => names
+                .iter()
+                .map(|name| {
+                    contigs
+                        .iter()
+                        .position(|contig| contig.as_bytes() == name.as_slice())
+                        .ok_or_else(|| {
+                            Error::new(
+                                ErrorKind::InvalidData,
+                                format!("can't index the output: {} isn't a contig", name),
+                            )
+                        })
+                })
+                .collect::<std::io::Result<Vec<usize>>>()?,
+            None => (0..names.len()).collect(),
+        };
+        let (count, mut indexer) = match &self.bcf_contigs {
+            Some(contigs) => (contigs.len(), Indexer::<I>::new(MIN_SHIFT, depth)),
+            None => {
+                let header = header::Builder::vcf()
+                    .set_reference_sequence_names(names)
+                    .build();
+                let count = header.reference_sequence_names().len();
+                (
+                    count,
+                    Indexer::<I>::new(MIN_SHIFT, depth).set_header(header),
+                )
+            }
+        };
+        for rec in self.records.drain(..) {
+            let chunk = Chunk::new(blocks.query(rec.offsets.0)?, blocks.query(rec.offsets.1)?);
+            indexer.add_record(
+                Some((ids[rec.reference_sequence_id], rec.start, rec.end, true)),
+                chunk,
+            )?;
+        }
+        Ok(indexer.build(count))
+    }
+}
+
+/// The number of levels of bins a CSI index needs to cover `max_end`, as
+/// htslib works it out.
+fn csi_depth(max_end: usize) -> u8 {
+    let max_end = max_end as u64 + 256;
+    let mut depth = 0;
+    let mut size = 1u64 << MIN_SHIFT;
+    while max_end > size {
+        depth += 1;
+        size <<= 3;
+    }
+    depth
+}
+
+/// The compressed and uncompressed offsets of each block (bar the first) of a
+/// BGZF file, read from the block headers and trailers.
+fn block_offsets(path: &str) -> std::io::Result<gzi::Index> {
+    let mut reader = BufReader::new(File::open(path)?);
+    let mut offsets = Vec::new();
+    let mut compressed = 0u64;
+    let mut uncompressed = 0u64;
+    let mut header = [0u8; 18];
+    loop {
+        match reader.read_exact(&mut header) {
+            Ok(()) => {}
+            Err(e) if e.kind() == ErrorKind::UnexpectedEof => break,
+            Err(e) => return Err(e),
+        }
+        if header[..4] != [0x1f, 0x8b, 0x08, 0x04] || header[12..14] != *b"BC" {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!("{} is not BGZF at offset {}", path, compressed),
+            ));
+        }
+        let block_size = u16::from_le_bytes([header[16], header[17]]) as u64 + 1;
+        reader.seek(SeekFrom::Current(block_size as i64 - 18 - 4))?;
+        let mut isize = [0u8; 4];
+        reader.read_exact(&mut isize)?;
+
+        if compressed > 0 {
+            offsets.push((compressed, uncompressed));
+        }
+        compressed += block_size;
+        uncompressed += u32::from_le_bytes(isize) as u64;
+    }
+    Ok(gzi::Index::from(offsets))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use noodles::bgzf;
+
+    use super::*;
+
+    #[test]
+    fn test_block_offsets() {
+        let path = std::env::temp_dir().join(format!("svelt-blocks-{}.gz", std::process::id()));
+        let path = path.to_str().unwrap();
+        let mut writer = bgzf::io::Writer::new(File::create(path).unwrap());
+        let line = "chr1\t1000\t.\tA\t<DEL>\t.\tPASS\tSVTYPE=DEL;END=2000\n";
+        let mut positions = Vec::new();
+        let mut offset = 0;
+        for _ in 0..5000 {
+            positions.push((offset, writer.virtual_position()));
+            writer.write_all(line.as_bytes()).unwrap();
+            offset += line.len() as u64;
+        }
+        writer.finish().unwrap();
+
+        let blocks = block_offsets(path).unwrap();
+        std::fs::remove_file(path).unwrap();
+        assert!(blocks.as_ref().len() > 2);
+        for (offset, virtual_position) in positions {
+            assert_eq!(blocks.query(offset).unwrap(), virtual_position);
+        }
+    }
+
+    #[test]
+    fn test_csi_depth() {
+        assert_eq!(csi_depth(100_000), 1);
+        assert_eq!(csi_depth(248_956_422), 5);
+        assert_eq!(csi_depth(1_000_000_000), 6);
+    }
+}
@@ -0,0 +1,252 @@
+use std::{
+    collections::BTreeMap,
+    io::{BufWriter, Write},
+    rc::Rc,
+};
+
+use autocompress::{CompressionLevel, autodetect_create};
+use noodles::vcf::{
+    Header, Record,
+    variant::record::samples::{Sample as _, keys::key, series::Value},
+};
+use serde::Serialize;
+
+use crate::{
+    inputs::{OTHER_KIND, get_svtype, sv_kind},
+    options::QcReportFormat,
+};
+
+/// Counts of genotype calls, by zygosity.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize)]
+pub struct GenotypeCounts {
+    pub hom_ref: usize,
+    pub het: usize,
+    pub hom_alt: usize,
+    pub missing: usize,
+}
+
+impl GenotypeCounts {
+    fn add(&mut self, alleles: &[Option<usize>]) {
+        match classify_genotype(alleles) {
+            Zygosity::HomRef => self.hom_ref += 1,
+            Zygosity::Het => self.het += 1,
+            Zygosity::HomAlt => self.hom_alt += 1,
+            Zygosity::Missing => self.missing += 1,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Zygosity {
+    HomRef,
+    Het,
+    HomAlt,
+    Missing,
+}
+
+/// Classify a genotype from its allele indexes. A genotype with
+/// any missing allele counts as missing.
+fn classify_genotype(alleles: &[Option<usize>]) -> Zygosity {
+    if alleles.is_empty() || alleles.iter().any(|a| a.is_none()) {
+        return Zygosity::Missing;
+    }
+    let first = alleles[0];
+    if alleles.iter().all(|a| *a == first) {
+        if first == Some(0) {
+            Zygosity::HomRef
+        } else {
+            Zygosity::HomAlt
+        }
+    } else {
+        Zygosity::Het
+    }
+}
+
+/// Summary statistics for the records contributed by one input, or by
+/// one sample.
+///
+/// For a sample, only the records in which it carries an ALT allele are
+/// counted, but the genotype distribution covers all of the input's records.
+#[derive(Debug, Default, Clone, Serialize)]
+pub struct QcSummary {
+    pub input: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sample: Option<String>,
+    pub records: usize,
+    pub merged: usize,
+    pub fraction_merged: f64,
+    pub mean_qual: Option<f64>,
+    pub genotypes: GenotypeCounts,
+    pub svtypes: BTreeMap<String, usize>,
+    #[serde(skip)]
+    qual_sum: f64,
+    #[serde(skip)]
+    qual_count: usize,
+}
+
+impl QcSummary {
+    fn new(input: &str, sample: Option<&str>) -> QcSummary {
+        QcSummary {
+            input: String::from(input),
+            sample: sample.map(String::from),
+            ..Default::default()
+        }
+    }
+
+    fn add_record(&mut self, svtype: &str, quality: Option<f32>, merged: bool) {
+        self.records += 1;
+        if merged {
+            self.merged += 1;
+        }
+        if let Some(q) = quality {
+            self.qual_sum += q as f64;
+            self.qual_count += 1;
+        }
+        *self.svtypes.entry(String::from(svtype)).or_default() += 1;
+    }
+
+    fn finish(&mut self) {
+        if self.records > 0 {
+            self.fraction_merged = self.merged as f64 / self.records as f64;
+        }
+        if self.qual_count > 0 {
+            self.mean_qual = Some(self.qual_sum / self.qual_count as f64);
+        }
+    }
+
+    fn write_tsv<W: Write>(&self, scope: &str, out: &mut W) -> std::io::Result<()> {
+        let mean_qual = match self.mean_qual {
+            Some(q) => format!("{:.2}", q),
+            None => String::from("."),
+        };
+        let svtypes: Vec<String> = self
+            .svtypes
+            .iter()
+            .map(|(kind, count)| format!("{}:{}", kind, count))
+            .collect();
+        writeln!(
+            out,
+            "{}\t{}\t{}\t{}\t{}\t{:.4}\t{}\t{}\t{}\t{}\t{}\t{}",
+            scope,
+            self.input,
+            self.sample.as_deref().unwrap_or("."),
+            self.records,
+            self.merged,
+            self.fraction_merged,
+            mean_qual,
+            self.genotypes.hom_ref,
+            self.genotypes.het,
+            self.genotypes.hom_alt,
+            self.genotypes.missing,
+            if svtypes.is_empty() {
+                String::from(".")
+            } else {
+                svtypes.join(",")
+            }
+        )
+    }
+}
+
+/// Accumulate QC statistics over the merged groups.
+#[derive(Debug, Serialize)]
+pub struct QcReport {
+    pub inputs: Vec<QcSummary>,
+    pub samples: Vec<QcSummary>,
+    #[serde(skip)]
+    sample_offsets: Vec<usize>,
+}
+
+impl QcReport {
+    pub fn new(paths: &[String], headers: &[&Header]) -> QcReport {
+        let mut inputs = Vec::new();
+        let mut samples = Vec::new();
+        let mut sample_offsets = Vec::new();
+        for (path, header) in paths.iter().zip(headers.iter()) {
+            inputs.push(QcSummary::new(path, None));
+            sample_offsets.push(samples.len());
+            for sample in header.sample_names() {
+                samples.push(QcSummary::new(path, Some(sample)));
+            }
+        }
+        QcReport {
+            inputs,
+            samples,
+            sample_offsets,
+        }
+    }
+
+    /// Add the records making up one merged group (indexed by input).
+    pub fn add_group(&mut self, recs: &[Option<(Rc<Header>, Record)>]) -> std::io::Result<()> {
+        let merged = recs.iter().filter(|r| r.is_some()).count() > 1;
+        for (vix, hnr) in recs.iter().enumerate() {
+            let Some((header, rec)) = hnr else {
+                continue;
+            };
+            let svtype = sv_kind(&get_svtype(rec, header)?).unwrap_or(OTHER_KIND);
+            let quality = rec.quality_score().transpose()?;
+            self.inputs[vix].add_record(svtype, quality, merged);
+
+            for (six, sample) in rec.samples().iter().enumerate() {
+                let alleles = match sample.get(header, key::GENOTYPE).transpose()? {
+                    Some(Some(Value::Genotype(gt))) => gt
+                        .iter()
+                        .map(|a| a.map(|(position, _phasing)| position))
+                        .collect::<std::io::Result<Vec<Option<usize>>>>()?,
+                    _ => Vec::new(),
+                };
+                let summary = &mut self.samples[self.sample_offsets[vix] + six];
+                if alleles.iter().any(|a| a.unwrap_or(0) > 0) {
+                    summary.add_record(svtype, quality, merged);
+                }
+                summary.genotypes.add(&alleles);
+                self.inputs[vix].genotypes.add(&alleles);
+            }
+        }
+        Ok(())
+    }
+
+    /// Compute the derived statistics and write the report.
+    pub fn finish(&mut self, out: &str, format: QcReportFormat) -> std::io::Result<()> {
+        for summary in self.inputs.iter_mut().chain(self.samples.iter_mut()) {
+            summary.finish();
+        }
+
+        let writer = autodetect_create(out, CompressionLevel::Default)?;
+        let mut writer = BufWriter::new(writer);
+        match format {
+            QcReportFormat::Tsv => {
+                writeln!(
+                    writer,
+                    "scope\tinput\tsample\trecords\tmerged\tfraction_merged\tmean_qual\thom_ref\thet\thom_alt\tmissing\tsvtypes"
+                )?;
+                for summary in self.inputs.iter() {
+                    summary.write_tsv("input", &mut writer)?;
+                }
+                for summary in self.samples.iter() {
+                    summary.write_tsv("sample", &mut writer)?;
+                }
+            }
+            QcReportFormat::Json => {
+                serde_json::to_writer_pretty(&mut writer, self)?;
+                writeln!(writer)?;
+            }
+        }
+        writer.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_classify_genotype() {
+        assert_eq!(classify_genotype(&[Some(0), Some(0)]), Zygosity::HomRef);
+        assert_eq!(classify_genotype(&[Some(0), Some(1)]), Zygosity::Het);
+        assert_eq!(classify_genotype(&[Some(1), Some(2)]), Zygosity::Het);
+        assert_eq!(classify_genotype(&[Some(1), Some(1)]), Zygosity::HomAlt);
+        assert_eq!(classify_genotype(&[Some(1)]), Zygosity::HomAlt);
+        assert_eq!(classify_genotype(&[None, Some(1)]), Zygosity::Missing);
+        assert_eq!(classify_genotype(&[]), Zygosity::Missing);
+    }
+}
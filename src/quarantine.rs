@@ -0,0 +1,44 @@
+use std::io::Write;
+
+use autocompress::{CompressionLevel, autodetect_create};
+use noodles::vcf::{self, Header, Record};
+
+use crate::construct::InnerWriter;
+
+/// A side VCF for records that couldn't be processed (`--on-record-error quarantine`).
+///
+/// Records rejected when loading are copied verbatim. Since they may come from
+/// any of the inputs, the samples in the header (that of the first input) need
+/// not match those of every record.
+pub struct Quarantine {
+    writer: vcf::io::Writer<InnerWriter>,
+}
+
+impl Quarantine {
+    pub fn new(out: &str, header: &Header) -> std::io::Result<Quarantine> {
+        let writer = autodetect_create(out, CompressionLevel::Default)?;
+        let writer = std::io::BufWriter::new(writer);
+        let mut writer = vcf::io::Writer::new(writer);
+        writer.write_header(header)?;
+        Ok(Quarantine { writer })
+    }
+
+    /// Copy the line of a record.
+    pub fn write_line(&mut self, line: &[u8]) -> std::io::Result<()> {
+        let writer = self.writer.get_mut();
+        writer.write_all(line)?;
+        if !line.ends_with(b"\n") {
+            writer.write_all(b"\n")?;
+        }
+        Ok(())
+    }
+
+    /// Write a record that was read successfully, but couldn't be merged.
+    pub fn write_record(&mut self, header: &Header, rec: &Record) -> std::io::Result<()> {
+        self.writer.write_record(header, rec)
+    }
+
+    pub fn finish(&mut self) -> std::io::Result<()> {
+        self.writer.get_mut().flush()
+    }
+}
@@ -1,18 +1,66 @@
-use std::{collections::HashMap, rc::Rc};
+use std::{
+    collections::{HashMap, HashSet},
+    io::BufRead,
+    rc::Rc,
+};
 
-use noodles::vcf::{Header, Record};
+use noodles::vcf::{self, Header, Record};
 
-use crate::{chroms::ChromSet, errors::{Context, FileContext}, record::traverse_record, vcf_reader::VcfReader};
+use crate::{
+    chroms::ChromSet,
+    errors::{Context, FileContext},
+    options::RecordErrorAction,
+    record::traverse_record,
+    vcf_reader::VcfReader,
+};
 
+/// A source of the original records behind the rows of an input table.
+///
+/// Records are requested by their `row_num`, and each is taken at most once.
+/// They needn't be requested in increasing order (the output is written in
+/// order of position, which needn't be the order of the input), but stores
+/// reading forward through a file have to hold on to any records passed over
+/// until they are taken.
+pub trait RecordStore {
+    fn take(&mut self, rn: u32) -> std::io::Result<Option<(Rc<Header>, Record)>>;
+
+    /// Declare the `row_num`s of all the records that will be taken, so that
+    /// the others needn't be held on to.
+    fn plan(&mut self, _rns: &[u32]) {}
+
+    /// Log how the records were fetched.
+    fn log_usage(&self) {}
+}
+
+/// Read records on demand from a VCF file.
+///
+/// The file is only ever read forwards: records passed over on the way to the
+/// one requested are buffered (just those in the plan, if one is given) so
+/// that requests for earlier records don't need the file to be read again.
 pub struct RecordSeeker {
     reader: VcfReader,
     header: Rc<Header>,
     number_read: u32,
     buffer: HashMap<u32, Record>,
+    on_error: RecordErrorAction,
+    planned: Option<HashSet<u32>>,
+    peak_buffered: usize,
+    rescans_avoided: usize,
 }
 
 impl RecordSeeker {
     pub fn new(path: &str, chroms: Rc<ChromSet>) -> std::io::Result<RecordSeeker> {
+        RecordSeeker::with_error_action(path, chroms, RecordErrorAction::Abort)
+    }
+
+    /// Create a seeker which, unless `on_error` is `Abort`, passes over
+    /// malformed records (which will have been rejected when the input was
+    /// loaded) rather than failing.
+    pub fn with_error_action(
+        path: &str,
+        chroms: Rc<ChromSet>,
+        on_error: RecordErrorAction,
+    ) -> std::io::Result<RecordSeeker> {
         let reader = VcfReader::new(path, chroms)?;
         let header = Rc::new(reader.header.clone());
         let buffer = HashMap::new();
@@ -21,29 +69,158 @@ impl RecordSeeker {
             header,
             number_read: 0,
             buffer,
+            on_error,
+            planned: None,
+            peak_buffered: 0,
+            rescans_avoided: 0,
         })
     }
 
-    pub fn take(&mut self, rn: u32) -> std::io::Result<Option<(Rc<Header>, Record)>> {
+}
+
+/// Read the next record, giving `None` at EOF, and `Some(None)` for a
+/// record that is passed over.
+fn read_record(
+    reader: &mut vcf::io::Reader<Box<dyn BufRead>>,
+    header: &Header,
+    on_error: RecordErrorAction,
+    line: &mut Vec<u8>,
+) -> std::io::Result<Option<Option<Record>>> {
+    line.clear();
+    if reader.get_mut().read_until(b'\n', line)? == 0 {
+        // EOF
+        return Ok(None);
+    }
+    let rec = Record::try_from(line.as_slice());
+
+    // Check to see if the record has errors
+    let rec = rec.and_then(|rec| traverse_record(&rec, header).map(|_| rec));
+
+    match rec {
+        Ok(rec) => Ok(Some(Some(rec))),
+        Err(e) if on_error == RecordErrorAction::Abort => Err(e),
+        Err(e) => {
+            log::debug!("passing over malformed record: {}", e);
+            Ok(Some(None))
+        }
+    }
+}
+
+impl RecordStore for RecordSeeker {
+    fn take(&mut self, rn: u32) -> std::io::Result<Option<(Rc<Header>, Record)>> {
         FileContext::new(&self.reader.path).with(|| {
+            let backwards = rn < self.number_read;
+            let mut line = Vec::new();
             while self.number_read <= rn {
-                let mut rec = Record::default();
-                let res = self.reader.reader.read_record(&mut rec)?;
-                if res == 0 {
-                    // EOF
+                let Some(rec) = read_record(
+                    &mut self.reader.reader,
+                    &self.reader.header,
+                    self.on_error,
+                    &mut line,
+                )?
+                else {
                     break;
+                };
+                let wanted = self
+                    .planned
+                    .as_ref()
+                    .is_none_or(|planned| planned.contains(&self.number_read));
+                if let Some(rec) = rec
+                    && (wanted || self.number_read == rn)
+                {
+                    self.buffer.insert(self.number_read, rec);
+                    self.peak_buffered = self.peak_buffered.max(self.buffer.len());
                 }
-
-                // Check to see if the record has errors
-                traverse_record(&rec, &self.reader.header)?;
-
-                self.buffer.insert(self.number_read, rec);
                 self.number_read += 1;
             }
-            Ok(self
-                .buffer
-                .remove(&rn)
-                .map(|rec| (self.header.clone(), rec)))
+            let rec = self.buffer.remove(&rn);
+            if backwards && rec.is_some() {
+                self.rescans_avoided += 1;
+            }
+            Ok(rec.map(|rec| (self.header.clone(), rec)))
         })
     }
+
+    fn plan(&mut self, rns: &[u32]) {
+        self.planned = Some(rns.iter().copied().collect());
+    }
+
+    fn log_usage(&self) {
+        log::info!(
+            "read {}: {} records taken out of order without rereading the file, at most {} records held at once",
+            self.reader.path,
+            self.rescans_avoided,
+            self.peak_buffered
+        );
+    }
+}
+
+/// Hold records in memory, for inputs that don't come from a VCF file.
+pub struct MemoryRecordStore {
+    header: Rc<Header>,
+    records: HashMap<u32, Record>,
+}
+
+impl MemoryRecordStore {
+    /// Create a store from records in `row_num` order (i.e. the first
+    /// record has `row_num` 0).
+    pub fn new(header: Rc<Header>, records: Vec<Record>) -> MemoryRecordStore {
+        let records = records
+            .into_iter()
+            .enumerate()
+            .map(|(rn, rec)| (rn as u32, rec))
+            .collect();
+        MemoryRecordStore { header, records }
+    }
+
+    /// Create a store from record lines in `row_num` order, passing over
+    /// those that are malformed (which will have been rejected when the input
+    /// was loaded).
+    pub fn from_lines(header: Rc<Header>, lines: &[Vec<u8>]) -> MemoryRecordStore {
+        let records = lines
+            .iter()
+            .enumerate()
+            .filter_map(|(rn, line)| {
+                let rec = Record::try_from(line.as_slice()).ok()?;
+                traverse_record(&rec, &header).ok()?;
+                Some((rn as u32, rec))
+            })
+            .collect();
+        MemoryRecordStore { header, records }
+    }
+}
+
+impl RecordStore for MemoryRecordStore {
+    fn take(&mut self, rn: u32) -> std::io::Result<Option<(Rc<Header>, Record)>> {
+        Ok(self
+            .records
+            .remove(&rn)
+            .map(|rec| (self.header.clone(), rec)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_planned_seeker() {
+        let path = concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/tests/data/quirks/dedup_within_inputs/a.vcf"
+        );
+        let chroms = Rc::new(ChromSet::from(["chr1", "chr2"].as_ref()));
+        let mut seeker = RecordSeeker::new(path, chroms).unwrap();
+        seeker.plan(&[3, 0, 2]);
+
+        let (_, rec) = seeker.take(3).unwrap().unwrap();
+        assert_eq!(rec.ids().as_ref(), "a4");
+        // Record 1 isn't in the plan, so it isn't held on to.
+        assert_eq!(seeker.buffer.len(), 2);
+        assert!(seeker.take(0).unwrap().is_some());
+        assert!(seeker.take(2).unwrap().is_some());
+        assert!(seeker.take(1).unwrap().is_none());
+        assert_eq!(seeker.peak_buffered, 3);
+        assert_eq!(seeker.rescans_avoided, 2);
+    }
 }
@@ -0,0 +1,124 @@
+use std::{
+    collections::HashMap,
+    io::{BufRead, BufReader},
+};
+
+use autocompress::autodetect_open;
+
+use crate::{
+    errors::{Context, FileContext, SveltError, as_io_error},
+    force_keep::parse_region,
+};
+
+/// The repeats of a genome (`--repeat-context`, a BED file such as one made
+/// from RepeatMasker's annotations), named by the fourth column.
+///
+/// Unlike the regions of `Targets`, repeats may overlap (e.g. an Alu inside
+/// an L1), so they are kept separately.
+#[derive(Debug, Default)]
+pub struct RepeatMask {
+    contigs: HashMap<String, ContigRepeats>,
+}
+
+#[derive(Debug, Default)]
+struct ContigRepeats {
+    /// The (0-based, half open) repeats, sorted by their start.
+    repeats: Vec<(usize, usize, String)>,
+
+    /// The furthest end of the repeats up to and including each one.
+    reach: Vec<usize>,
+}
+
+impl RepeatMask {
+    pub fn load(path: &str) -> std::io::Result<RepeatMask> {
+        FileContext::new(path).with(|| {
+            let reader = BufReader::new(autodetect_open(path)?);
+            let mut res = RepeatMask::default();
+            for line in reader.lines() {
+                let line = line?;
+                let line = line.trim();
+                if line.is_empty()
+                    || line.starts_with('#')
+                    || line.starts_with("track")
+                    || line.starts_with("browser")
+                {
+                    continue;
+                }
+                let (chrom, begin, end) = parse_region(line)?;
+                let name = line
+                    .split('\t')
+                    .nth(3)
+                    .map(str::trim)
+                    .filter(|name| !name.is_empty())
+                    .ok_or_else(|| as_io_error(SveltError::BadRegion(String::from(line))))?;
+                res.add(chrom, begin, end, String::from(name));
+            }
+            res.finish();
+            Ok(res)
+        })
+    }
+
+    fn add(&mut self, chrom: String, begin: usize, end: usize, name: String) {
+        self.contigs
+            .entry(chrom)
+            .or_default()
+            .repeats
+            .push((begin, end, name));
+    }
+
+    fn finish(&mut self) {
+        for contig in self.contigs.values_mut() {
+            contig.repeats.sort();
+            let mut reach = 0;
+            contig.reach = contig
+                .repeats
+                .iter()
+                .map(|&(_, end, _)| {
+                    reach = reach.max(end);
+                    reach
+                })
+                .collect();
+        }
+    }
+
+    /// The name of the repeat `chrom`:`pos` (1-based) lies in, if any. Where
+    /// repeats overlap, the one starting last (the innermost, when they nest)
+    /// is given.
+    pub fn at(&self, chrom: &str, pos: usize) -> Option<&str> {
+        let contig = self.contigs.get(chrom)?;
+        // The repeats starting before `pos`.
+        let ix = contig.repeats.partition_point(|&(begin, _, _)| begin < pos);
+        (0..ix)
+            .rev()
+            .take_while(|&i| contig.reach[i] >= pos)
+            .find(|&i| contig.repeats[i].1 >= pos)
+            .map(|i| contig.repeats[i].2.as_str())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_at() {
+        let mut mask = RepeatMask::default();
+        mask.add(String::from("chr1"), 1000, 7000, String::from("L1"));
+        mask.add(String::from("chr1"), 2000, 2300, String::from("Alu"));
+        mask.add(String::from("chr1"), 8000, 9000, String::from("ALR"));
+        mask.add(String::from("chr2"), 50, 60, String::from("(CA)n"));
+        mask.finish();
+
+        assert_eq!(mask.at("chr1", 1000), None);
+        assert_eq!(mask.at("chr1", 1001), Some("L1"));
+        assert_eq!(mask.at("chr1", 2001), Some("Alu"));
+        assert_eq!(mask.at("chr1", 2300), Some("Alu"));
+        assert_eq!(mask.at("chr1", 2301), Some("L1"));
+        assert_eq!(mask.at("chr1", 7000), Some("L1"));
+        assert_eq!(mask.at("chr1", 7500), None);
+        assert_eq!(mask.at("chr1", 8500), Some("ALR"));
+        assert_eq!(mask.at("chr1", 9500), None);
+        assert_eq!(mask.at("chr2", 55), Some("(CA)n"));
+        assert_eq!(mask.at("chr3", 55), None);
+    }
+}
@@ -0,0 +1,225 @@
+use std::{
+    io::{Error, ErrorKind},
+    path::Path,
+    sync::Arc,
+};
+
+use datafusion::{
+    arrow::{
+        datatypes::{DataType, Field, Schema},
+        json::ArrayWriter,
+    },
+    common::ScalarValue,
+    functions::expr_fn::{encode, nullif},
+    prelude::{
+        CsvReadOptions, DataFrame, Expr, ParquetReadOptions, SessionContext, cast, col, lit,
+    },
+};
+use serde::{Deserialize, Serialize};
+
+/// The columns of the merge table (`--write-merge-table`, and the bundle's
+/// `merge-table.parquet`), in order, with their types and whether they may
+/// be null. `MergeTableRow` has a field for each.
+pub const MERGE_TABLE_COLUMNS: &[(&str, DataType, bool)] = &[
+    ("row_id", DataType::Int64, false),
+    ("vix", DataType::UInt32, false),
+    ("chrom_id", DataType::UInt16, false),
+    ("chrom", DataType::Utf8, false),
+    ("start", DataType::Int32, false),
+    ("end", DataType::Int32, false),
+    ("kind", DataType::Utf8, false),
+    ("length", DataType::Int32, true),
+    ("chrom2_id", DataType::UInt16, true),
+    ("chrom2", DataType::Utf8, true),
+    ("chrom2_alias", DataType::Utf8, true),
+    ("end2", DataType::Int32, true),
+    ("bnd_sides", DataType::Utf8, true),
+    ("bnd_ins_len", DataType::Int32, true),
+    ("seq_hash", DataType::Utf8, true),
+    ("alt_symbol", DataType::Utf8, true),
+    ("depth_change", DataType::Int8, true),
+    ("genotypes", DataType::Utf8, true),
+    ("input_label", DataType::Utf8, true),
+    ("samples", DataType::Utf8, true),
+    ("row_key", DataType::UInt32, false),
+    ("vix_count", DataType::Int64, false),
+    ("vix_set", DataType::Utf8, false),
+    ("criteria", DataType::Utf8, true),
+    ("merge_score", DataType::Float64, true),
+    ("paired_bnd", DataType::Boolean, true),
+    ("event_id", DataType::Utf8, true),
+    ("variant_id", DataType::Utf8, false),
+    ("class", DataType::Utf8, true),
+    ("strand", DataType::Utf8, true),
+    ("emitted_id", DataType::Utf8, true),
+    ("emitted_start", DataType::Int32, true),
+    ("emitted_end", DataType::Int32, true),
+    ("emitted_end2", DataType::Int32, true),
+    ("start_offset", DataType::Int32, true),
+    ("end_offset", DataType::Int32, true),
+    ("end2_offset", DataType::Int32, true),
+    ("total_offset", DataType::Int32, true),
+    ("length_ratio", DataType::Float64, true),
+    ("length_difference", DataType::Int32, true),
+    ("min_length_ratio", DataType::Float64, false),
+    ("max_length_difference", DataType::Int64, false),
+    ("bnd_insertion_difference", DataType::Int32, true),
+    ("max_bnd_insertion_difference", DataType::UInt32, true),
+    ("alt_seq", DataType::LargeUtf8, true),
+];
+
+/// The schema of the merge table.
+pub fn merge_table_schema() -> Arc<Schema> {
+    Arc::new(Schema::new(
+        MERGE_TABLE_COLUMNS
+            .iter()
+            .map(|(name, ty, nullable)| Field::new(*name, ty.clone(), *nullable))
+            .collect::<Vec<Field>>(),
+    ))
+}
+
+/// A row of the merge table: one of the input records, with the group it was
+/// merged into, and how it compares with the record emitted for the group.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MergeTableRow {
+    /// The input (`vix`) and record number, packed (see `RowKey`).
+    pub row_id: i64,
+    /// The number of the input, in the order given.
+    pub vix: u32,
+    pub chrom_id: u16,
+    pub chrom: String,
+    pub start: i32,
+    pub end: i32,
+    pub kind: String,
+    pub length: Option<i32>,
+    pub chrom2_id: Option<u16>,
+    pub chrom2: Option<String>,
+    /// The mate contig as the input named it, if that differs.
+    pub chrom2_alias: Option<String>,
+    pub end2: Option<i32>,
+    pub bnd_sides: Option<String>,
+    pub bnd_ins_len: Option<i32>,
+    /// The hash of the inserted sequence (or ALT tag), in hex.
+    pub seq_hash: Option<String>,
+    pub alt_symbol: Option<String>,
+    pub depth_change: Option<i8>,
+    pub genotypes: Option<String>,
+    /// The label of the record's input (`--input-labels`, or else its name).
+    pub input_label: Option<String>,
+    /// The samples of the record's input, comma separated, whose genotypes
+    /// it contributes to the merged record.
+    pub samples: Option<String>,
+    /// The group the record was merged into.
+    pub row_key: u32,
+    /// The number of inputs contributing to the group.
+    pub vix_count: i64,
+    /// The inputs contributing to the group, as a bit set, in hex.
+    pub vix_set: String,
+    pub criteria: Option<String>,
+    pub merge_score: Option<f64>,
+    pub paired_bnd: Option<bool>,
+    pub event_id: Option<String>,
+    pub variant_id: String,
+    /// The classification of the inserted sequence (with `--annotate-insertions`).
+    pub class: Option<String>,
+    pub strand: Option<String>,
+    /// The ID and coordinates of the record emitted for the group.
+    pub emitted_id: Option<String>,
+    pub emitted_start: Option<i32>,
+    pub emitted_end: Option<i32>,
+    pub emitted_end2: Option<i32>,
+    pub start_offset: Option<i32>,
+    pub end_offset: Option<i32>,
+    pub end2_offset: Option<i32>,
+    pub total_offset: Option<i32>,
+    pub length_ratio: Option<f64>,
+    pub length_difference: Option<i32>,
+    pub min_length_ratio: f64,
+    pub max_length_difference: i64,
+    pub bnd_insertion_difference: Option<i32>,
+    pub max_bnd_insertion_difference: Option<u32>,
+    pub alt_seq: Option<String>,
+}
+
+/// Put a table into the layout of the merge table: the declared columns, in
+/// order, cast to their types. Columns it doesn't have (e.g. `class` without
+/// `--annotate-insertions`) are null, as are empty strings, which TSV can't
+/// tell apart from null.
+pub fn conform_merge_table(tbl: DataFrame) -> std::io::Result<DataFrame> {
+    let schema = tbl.schema().clone();
+    let columns: Vec<Expr> = MERGE_TABLE_COLUMNS
+        .iter()
+        .map(|(name, ty, _)| {
+            let value = match schema.field_with_unqualified_name(name) {
+                Ok(field)
+                    if matches!(
+                        field.data_type(),
+                        DataType::Binary | DataType::LargeBinary | DataType::BinaryView
+                    ) =>
+                {
+                    encode(cast(col(*name), DataType::Binary), lit("hex"))
+                }
+                Ok(_) => col(*name),
+                Err(_) => lit(ScalarValue::Null),
+            };
+            let value = cast(value, ty.clone());
+            let value = if matches!(ty, DataType::Utf8 | DataType::LargeUtf8) {
+                nullif(value, lit(""))
+            } else {
+                value
+            };
+            value.alias(*name)
+        })
+        .collect();
+    Ok(tbl.select(columns)?)
+}
+
+/// Whether `tbl` has no rows (e.g. when all the inputs were header-only).
+pub async fn is_empty(tbl: &DataFrame) -> std::io::Result<bool> {
+    Ok(tbl.clone().limit(0, Some(1))?.count().await? == 0)
+}
+
+/// Read a merge table written by svelt, as TSV or (if the name ends
+/// `.parquet`) as parquet.
+pub async fn read_merge_table(path: &str) -> std::io::Result<Vec<MergeTableRow>> {
+    let ctx = SessionContext::new();
+    let tbl = if path.ends_with(".parquet") {
+        ctx.read_parquet(path, ParquetReadOptions::default())
+            .await?
+    } else {
+        let extension = Path::new(path)
+            .extension()
+            .map_or(String::new(), |ext| format!(".{}", ext.to_string_lossy()));
+        // The CSV reader only has 32 bit offsets, which is enough for a
+        // single sequence (and it is cast back below).
+        let schema = Schema::new(
+            merge_table_schema()
+                .fields()
+                .iter()
+                .map(|field| match field.data_type() {
+                    DataType::LargeUtf8 => field.as_ref().clone().with_data_type(DataType::Utf8),
+                    _ => field.as_ref().clone(),
+                })
+                .collect::<Vec<Field>>(),
+        );
+        let options = CsvReadOptions::new()
+            .delimiter(b'\t')
+            .has_header(true)
+            .schema(&schema)
+            .file_extension(&extension);
+        ctx.read_csv(path, options).await?
+    };
+    let tbl = conform_merge_table(tbl)?;
+
+    let batches = tbl.collect().await?;
+    let mut writer = ArrayWriter::new(Vec::new());
+    for batch in batches.iter() {
+        writer.write(batch).map_err(Error::other)?;
+    }
+    writer.finish().map_err(Error::other)?;
+    let json = writer.into_inner();
+    if json.is_empty() {
+        return Ok(Vec::new());
+    }
+    serde_json::from_slice(&json).map_err(|e| Error::new(ErrorKind::InvalidData, e))
+}
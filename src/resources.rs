@@ -0,0 +1,146 @@
+use std::path::Path;
+
+/// The root of the cgroup hierarchy, as seen from inside a container.
+const CGROUP_ROOT: &str = "/sys/fs/cgroup";
+
+/// Values of the cgroup v1 memory limit at or above this are "unlimited"
+/// (the kernel reports a large page-aligned number rather than a sentinel).
+const CGROUP_V1_UNLIMITED: u64 = 1 << 62;
+
+/// The CPU and memory resources available to this process.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Resources {
+    /// The number of CPUs reported by the standard library (which may
+    /// already take account of some limits, e.g. CPU affinity).
+    pub host_cpus: usize,
+
+    /// The CPU quota imposed by a cgroup, in CPUs, if any.
+    pub cpu_limit: Option<f64>,
+
+    /// The memory limit imposed by a cgroup, in bytes, if any.
+    pub memory_limit: Option<u64>,
+}
+
+impl Resources {
+    /// Detect the resources available, taking account of cgroup (v2 or v1)
+    /// limits as set by container runtimes.
+    pub fn detect() -> Resources {
+        let host_cpus = std::thread::available_parallelism()
+            .map(|n| n.get())
+            .unwrap_or(1);
+        let root = Path::new(CGROUP_ROOT);
+        let (cpu_limit, memory_limit) = if root.join("cgroup.controllers").exists() {
+            (
+                read(root, "cpu.max").and_then(|s| parse_cpu_max(&s)),
+                read(root, "memory.max").and_then(|s| parse_memory_max(&s)),
+            )
+        } else {
+            (
+                read(root, "cpu/cpu.cfs_quota_us").and_then(|quota| {
+                    read(root, "cpu/cpu.cfs_period_us")
+                        .and_then(|period| parse_cfs_quota(&quota, &period))
+                }),
+                read(root, "memory/memory.limit_in_bytes")
+                    .and_then(|s| parse_memory_limit_in_bytes(&s)),
+            )
+        };
+        Resources {
+            host_cpus,
+            cpu_limit,
+            memory_limit,
+        }
+    }
+
+    /// The number of CPUs we may use: the cgroup quota (rounded up), if
+    /// there is one, otherwise all of the host's CPUs.
+    pub fn available_cpus(&self) -> usize {
+        match self.cpu_limit {
+            Some(limit) => (limit.ceil() as usize).clamp(1, self.host_cpus.max(1)),
+            None => self.host_cpus.max(1),
+        }
+    }
+}
+
+fn read(root: &Path, name: &str) -> Option<String> {
+    std::fs::read_to_string(root.join(name)).ok()
+}
+
+/// Parse cgroup v2 `cpu.max` ("$QUOTA $PERIOD", where the quota may be "max").
+fn parse_cpu_max(s: &str) -> Option<f64> {
+    let mut parts = s.split_whitespace();
+    let quota = parts.next()?;
+    let period = parts.next().unwrap_or("100000");
+    if quota == "max" {
+        return None;
+    }
+    let quota: f64 = quota.parse().ok()?;
+    let period: f64 = period.parse().ok()?;
+    if quota > 0.0 && period > 0.0 {
+        Some(quota / period)
+    } else {
+        None
+    }
+}
+
+/// Parse cgroup v2 `memory.max` (bytes, or "max").
+fn parse_memory_max(s: &str) -> Option<u64> {
+    let s = s.trim();
+    if s == "max" {
+        return None;
+    }
+    s.parse().ok()
+}
+
+/// Parse cgroup v1 `cpu.cfs_quota_us` and `cpu.cfs_period_us` (a quota of -1 is unlimited).
+fn parse_cfs_quota(quota: &str, period: &str) -> Option<f64> {
+    let quota: i64 = quota.trim().parse().ok()?;
+    let period: i64 = period.trim().parse().ok()?;
+    if quota > 0 && period > 0 {
+        Some(quota as f64 / period as f64)
+    } else {
+        None
+    }
+}
+
+/// Parse cgroup v1 `memory.limit_in_bytes`.
+fn parse_memory_limit_in_bytes(s: &str) -> Option<u64> {
+    let limit: u64 = s.trim().parse().ok()?;
+    if limit >= CGROUP_V1_UNLIMITED {
+        None
+    } else {
+        Some(limit)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_cgroup_limits() {
+        assert_eq!(parse_cpu_max("max 100000\n"), None);
+        assert_eq!(parse_cpu_max("250000 100000\n"), Some(2.5));
+        assert_eq!(parse_memory_max("max\n"), None);
+        assert_eq!(parse_memory_max("4294967296\n"), Some(1 << 32));
+        assert_eq!(parse_cfs_quota("-1\n", "100000\n"), None);
+        assert_eq!(parse_cfs_quota("50000\n", "100000\n"), Some(0.5));
+        assert_eq!(
+            parse_memory_limit_in_bytes("9223372036854771712\n"),
+            None
+        );
+        assert_eq!(parse_memory_limit_in_bytes("1073741824\n"), Some(1 << 30));
+
+        let r = Resources {
+            host_cpus: 16,
+            cpu_limit: Some(0.5),
+            memory_limit: None,
+        };
+        assert_eq!(r.available_cpus(), 1);
+        let r = Resources {
+            host_cpus: 16,
+            cpu_limit: Some(2.5),
+            memory_limit: None,
+        };
+        assert_eq!(r.available_cpus(), 3);
+    }
+}
@@ -1,27 +1,60 @@
 use datafusion::prelude::{lit, Expr};
 
 /// We make a "global" key for each row of each VCF by composing the VCF number (or VCF index `vix`)
-/// and the row number. The number of VCFs is constrained to 64 elsewhere, and the number of variants
+/// and the row number. The number of VCFs is known when the merge starts, and the number of variants
 /// in each VCF is (effectively) unconstrained, so we encode the VCF index in the low order bits,
 /// even though it would seem more natural to encode them as the most significant bits. Further, we
-/// reserve 0..100 for the `vix` so that when we print them in tables as decimal numbers, it is easy
-/// to distinguish the `vix` and the row number.
-
-pub struct RowKey {}
+/// reserve a power of ten (100, unless there are more than 100 VCFs) for the `vix` so that when we
+/// print them in tables as decimal numbers, it is easy to distinguish the `vix` and the row number.
+///
+/// Keys are 32 bits, so the larger the base, the fewer records each input may have (see
+/// `capacity`); inputs are checked against it when they are loaded.
+pub struct RowKey {
+    base: u32,
+}
 
 impl RowKey {
+    /// Keys for merging `n` VCFs.
+    pub fn new(n: usize) -> RowKey {
+        let mut base = 100;
+        while (base as usize) < n {
+            base *= 10;
+        }
+        RowKey { base }
+    }
+
+    /// The largest row number that can be encoded, for any VCF index.
+    pub fn capacity(&self) -> u32 {
+        (u32::MAX - (self.base - 1)) / self.base
+    }
+
     /// Compose a VCF index and a row number into a single identifier.
-    pub fn encode(vix: u32, rn: u32) -> u32 {
-        vix + 100 * rn
+    pub fn encode(&self, vix: u32, rn: u32) -> u32 {
+        vix + self.base * rn
     }
 
     /// Decompose an identifier into the VCF index and row number
-    pub fn decode(key: u32) -> (u32, u32) {
-        (key % 100, key / 100)
+    pub fn decode(&self, key: u32) -> (u32, u32) {
+        (key % self.base, key / self.base)
     }
 
-    pub fn make(row_num: Expr, vix: u32) -> Expr {
-        lit(vix) + row_num * lit(100)
+    pub fn make(&self, row_num: Expr, vix: u32) -> Expr {
+        lit(vix) + row_num * lit(self.base as i32)
     }
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_capacity() {
+        for n in [2, 100, 101, 1000, 1001] {
+            let keys = RowKey::new(n);
+            let vix = keys.base - 1;
+            let key = keys.encode(vix, keys.capacity());
+            assert_eq!(keys.decode(key), (vix, keys.capacity()));
+            assert!((keys.capacity() as u64 + 1) * keys.base as u64 + vix as u64 > u32::MAX as u64);
+        }
+    }
+}
@@ -0,0 +1,185 @@
+use noodles::vcf::{
+    Header,
+    variant::record_buf::samples::sample::value::{Genotype, genotype::Allele},
+};
+
+#[cfg(feature = "cli")]
+use clap::ValueEnum;
+use serde::Serialize;
+
+/// How the genotypes of a sample are resolved when they disagree (see
+/// `--consensus-samples`)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[cfg_attr(feature = "cli", derive(ValueEnum))]
+#[serde(rename_all = "kebab-case")]
+pub enum GenotypeConflict {
+    /// The most common genotype, or the first of those tied
+    Majority,
+    /// The genotype from the first input with one
+    First,
+    /// The genotype with the most alt alleles
+    Highest,
+    /// A missing genotype (./.)
+    Missing,
+}
+
+/// Where the samples of each input go in the output.
+///
+/// Normally the samples of the inputs follow one another, in the order of the
+/// inputs. With `--consensus-samples`, samples with the same name (in any of
+/// the inputs) share a column, in the order the names are first seen.
+#[derive(Debug, Clone)]
+pub struct SampleLayout {
+    names: Vec<String>,
+    columns: Vec<Vec<usize>>,
+}
+
+impl SampleLayout {
+    pub fn new(headers: &[Header], consensus: bool) -> SampleLayout {
+        let mut names: Vec<String> = Vec::new();
+        let mut columns = Vec::new();
+        for header in headers.iter() {
+            let mut input_columns = Vec::new();
+            for name in header.sample_names().iter() {
+                let column = match names.iter().position(|other| other == name) {
+                    Some(column) if consensus => column,
+                    _ => {
+                        names.push(name.clone());
+                        names.len() - 1
+                    }
+                };
+                input_columns.push(column);
+            }
+            columns.push(input_columns);
+        }
+        SampleLayout { names, columns }
+    }
+
+    /// The names of the samples of the output, in order.
+    pub fn names(&self) -> &[String] {
+        &self.names
+    }
+
+    /// The number of samples in the output.
+    pub fn len(&self) -> usize {
+        self.names.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.names.is_empty()
+    }
+
+    /// The output columns of the samples of input `vix`, in order.
+    pub fn columns(&self, vix: usize) -> &[usize] {
+        &self.columns[vix]
+    }
+
+    /// Are any of the output samples made from more than one input sample?
+    pub fn is_collapsed(&self) -> bool {
+        self.columns.iter().map(|cs| cs.len()).sum::<usize>() > self.names.len()
+    }
+}
+
+/// The genotype of a sample with the given `genotypes` (from each input with
+/// the variant, in order), resolving any disagreement as `conflict` says.
+/// Missing genotypes (with any allele missing) don't count, unless they are
+/// all there is.
+pub fn consensus_genotype(genotypes: &[Genotype], conflict: GenotypeConflict) -> Option<Genotype> {
+    let called: Vec<&Genotype> = genotypes
+        .iter()
+        .filter(|gt| !gt.as_ref().is_empty() && gt.as_ref().iter().all(|a| a.position().is_some()))
+        .collect();
+    let Some(first) = called.first() else {
+        return genotypes.first().cloned();
+    };
+    // Genotypes agree if they have the same alleles, whatever the phasing.
+    let alleles = |gt: &Genotype| {
+        let mut alleles: Vec<usize> = gt.as_ref().iter().filter_map(|a| a.position()).collect();
+        alleles.sort();
+        alleles
+    };
+    if called.iter().all(|gt| alleles(gt) == alleles(first)) {
+        return Some((*first).clone());
+    }
+    let dosage = |gt: &Genotype| alleles(gt).iter().filter(|a| **a > 0).count();
+    match conflict {
+        GenotypeConflict::First => Some((*first).clone()),
+        GenotypeConflict::Majority => {
+            let votes = |gt: &Genotype| {
+                called
+                    .iter()
+                    .filter(|other| alleles(other) == alleles(gt))
+                    .count()
+            };
+            let most = called.iter().map(|gt| votes(gt)).max()?;
+            called
+                .iter()
+                .find(|gt| votes(gt) == most)
+                .map(|gt| (*gt).clone())
+        }
+        GenotypeConflict::Highest => {
+            let most = called.iter().map(|gt| dosage(gt)).max()?;
+            called
+                .iter()
+                .find(|gt| dosage(gt) == most)
+                .map(|gt| (*gt).clone())
+        }
+        GenotypeConflict::Missing => Some(Genotype::from_iter(
+            first
+                .as_ref()
+                .iter()
+                .map(|a| Allele::new(None, a.phasing())),
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use super::*;
+
+    #[test]
+    fn test_layout() {
+        let header = |names: &[&str]| {
+            let mut header = Header::default();
+            *header.sample_names_mut() = names.iter().map(|n| String::from(*n)).collect();
+            header
+        };
+        let headers = [header(&["HG002", "HG003"]), header(&["HG003", "HG004"])];
+
+        let layout = SampleLayout::new(&headers, true);
+        assert_eq!(layout.names(), ["HG002", "HG003", "HG004"]);
+        assert_eq!(layout.columns(0), [0, 1]);
+        assert_eq!(layout.columns(1), [1, 2]);
+        assert!(layout.is_collapsed());
+
+        let layout = SampleLayout::new(&headers, false);
+        assert_eq!(layout.len(), 4);
+        assert_eq!(layout.columns(1), [2, 3]);
+        assert!(!layout.is_collapsed());
+    }
+
+    #[test]
+    fn test_consensus_genotype() {
+        let gt = |s: &str| Genotype::from_str(s).unwrap();
+        let gts = [gt("0/1"), gt("1/1"), gt("1|0"), gt("./.")];
+        let consensus = |conflict| consensus_genotype(&gts, conflict).unwrap();
+        assert_eq!(consensus(GenotypeConflict::Majority), gt("0/1"));
+        assert_eq!(consensus(GenotypeConflict::First), gt("0/1"));
+        assert_eq!(consensus(GenotypeConflict::Highest), gt("1/1"));
+        assert_eq!(consensus(GenotypeConflict::Missing), gt("./."));
+
+        // Agreeing genotypes (ignoring those missing) are kept, whatever
+        // the conflict resolution.
+        let gts = [gt("./."), gt("1/1"), gt("1/1")];
+        assert_eq!(
+            consensus_genotype(&gts, GenotypeConflict::Missing),
+            Some(gt("1/1"))
+        );
+        assert_eq!(
+            consensus_genotype(&[gt("./.")], GenotypeConflict::Majority),
+            Some(gt("./."))
+        );
+    }
+}
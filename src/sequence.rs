@@ -13,7 +13,7 @@ use datafusion::{
     prelude::{DataFrame, SessionContext, col, lit},
 };
 
-use crate::kmers::kmerize::kmers_fwd;
+use crate::{diagnostics::show_table, kmers::kmerize::kmers_fwd};
 
 pub trait SequenceIterator: Iterator<Item = std::io::Result<(String, String)>> {}
 
@@ -61,7 +61,7 @@ pub async fn make_kmer_table<Itr: SequenceIterator>(
             vec![count(lit(1)).alias("count")],
         )?;
 
-    df.clone().show().await?;
+    show_table("k-mer counts", df.clone()).await?;
 
     Ok(df)
 }
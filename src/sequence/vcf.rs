@@ -2,6 +2,8 @@ use super::SequenceIterator;
 
 use crate::{
     errors::{as_io_error, wrap_file_error, Context, FileContext, SveltError, VariantContext},
+    inputs::sv_kind,
+    output_format::check_format_version,
     tables::is_seq,
 };
 use autocompress::autodetect_open;
@@ -31,6 +33,7 @@ impl VcfSequenceIterator {
             let header = reader
                 .read_header()
                 .map_err(|e| wrap_file_error(e, &filename))?;
+            check_format_version(&header, filename);
             Ok(VcfSequenceIterator {
                 path,
                 reader,
@@ -54,7 +57,7 @@ impl VcfSequenceIterator {
                     // First, if it has SVTYPE and it isn't INS, skip.
                     let kind = VcfSequenceIterator::get_info_str(&record, "SVTYPE")?;
                     if let Some(kind) = kind {
-                        if kind != "INS" {
+                        if sv_kind(&kind) != Some("INS") {
                             // Go round the outer loop again
                             return Ok(None);
                         }
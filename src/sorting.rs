@@ -0,0 +1,124 @@
+use std::collections::HashSet;
+
+use crate::{chroms::ChromSet, errors::SveltError};
+
+/// The contig and position of a VCF record line, if they can be read.
+pub fn locus(line: &[u8]) -> Option<(&str, usize)> {
+    let mut fields = line.splitn(3, |c| *c == b'\t');
+    let chrom = std::str::from_utf8(fields.next()?).ok()?;
+    let pos = std::str::from_utf8(fields.next()?).ok()?;
+    let pos = pos.trim_end().parse().ok()?;
+    Some((chrom, pos))
+}
+
+/// Check that records come sorted by position: all the records on each contig
+/// together, and in order of position within a contig.
+///
+/// The order of the contigs themselves isn't checked, since the sweeps over
+/// records only need those on each contig to be together.
+#[derive(Debug, Default)]
+pub struct SortCheck {
+    finished: HashSet<String>,
+    last: Option<(String, usize)>,
+}
+
+impl SortCheck {
+    pub fn new() -> SortCheck {
+        SortCheck::default()
+    }
+
+    /// Check the next record, at `chrom:pos`.
+    pub fn check(&mut self, chrom: &str, pos: usize) -> Result<(), SveltError> {
+        match &mut self.last {
+            Some((last_chrom, last_pos)) if last_chrom == chrom => {
+                if pos < *last_pos {
+                    return Err(SveltError::UnsortedInput(
+                        format!("{}:{}", chrom, pos),
+                        format!("{}:{}", last_chrom, last_pos),
+                    ));
+                }
+                *last_pos = pos;
+            }
+            last => {
+                if self.finished.contains(chrom) {
+                    let (last_chrom, last_pos) = last.as_ref().unwrap();
+                    return Err(SveltError::UnsortedInput(
+                        format!("{}:{}", chrom, pos),
+                        format!("{}:{}", last_chrom, last_pos),
+                    ));
+                }
+                if let Some((last_chrom, _)) = last.take() {
+                    self.finished.insert(last_chrom);
+                }
+                *last = Some((String::from(chrom), pos));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Sort record lines by position, with the contigs in the order of `chroms`.
+///
+/// Lines on contigs not in `chroms` come after the rest, and lines whose
+/// position can't be read stay where they are relative to their neighbours,
+/// so that they are rejected (or not) just as they would be unsorted.
+pub fn sort_lines(lines: &mut [Vec<u8>], chroms: &ChromSet) {
+    let mut last = (usize::MAX, String::new(), 0);
+    let mut keys = Vec::with_capacity(lines.len());
+    for line in lines.iter() {
+        if let Some((chrom, pos)) = locus(line) {
+            let ix = chroms.index(chrom).unwrap_or(usize::MAX);
+            last = (ix, String::from(chrom), pos);
+        }
+        keys.push(last.clone());
+    }
+    let mut order: Vec<usize> = (0..lines.len()).collect();
+    // Stable, so records at the same position keep their order.
+    order.sort_by(|lhs, rhs| keys[*lhs].cmp(&keys[*rhs]));
+    let mut sorted: Vec<Vec<u8>> = order
+        .into_iter()
+        .map(|ix| std::mem::take(&mut lines[ix]))
+        .collect();
+    lines.swap_with_slice(&mut sorted);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sort_check() {
+        let mut check = SortCheck::new();
+        assert!(check.check("chr2", 100).is_ok());
+        assert!(check.check("chr2", 100).is_ok());
+        assert!(check.check("chr2", 200).is_ok());
+        assert!(check.check("chr1", 50).is_ok());
+        let e = check.check("chr1", 40).unwrap_err();
+        assert!(matches!(e, SveltError::UnsortedInput(here, previous)
+            if here == "chr1:40" && previous == "chr1:50"));
+        // chr2 was left behind, so can't be returned to.
+        let e = check.check("chr2", 300).unwrap_err();
+        assert!(matches!(e, SveltError::UnsortedInput(here, previous)
+            if here == "chr2:300" && previous == "chr1:50"));
+    }
+
+    #[test]
+    fn test_sort_lines() {
+        let chroms = ChromSet::from(["chr1", "chr2"].as_ref());
+        let mut lines: Vec<Vec<u8>> = [
+            "chr2\t100\ta\n",
+            "chr1\t300\tb\n",
+            "chrUn\t5\tc\n",
+            "chr1\t200\td\n",
+            "junk\n",
+            "chr1\t200\te\n",
+        ]
+        .iter()
+        .map(|line| line.as_bytes().to_vec())
+        .collect();
+        sort_lines(&mut lines, &chroms);
+        let ids: Vec<u8> = lines.iter().map(|line| line[line.len() - 2]).collect();
+        // The unreadable line stays after the one before it.
+        assert_eq!(ids, b"dkebac");
+    }
+}
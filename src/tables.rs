@@ -1,22 +1,46 @@
 use std::{
-    io::{Error, ErrorKind},
+    collections::{BTreeMap, HashMap},
+    io::{BufRead, Error, ErrorKind},
+    iter::zip,
     sync::Arc,
 };
 
 use blake2::{Blake2b512, Digest};
-use datafusion::arrow::{
-    array::{GenericStringBuilder, PrimitiveBuilder, RecordBatch, StringDictionaryBuilder},
-    datatypes::{DataType, Field, Int32Type, Int64Type, Schema, UInt8Type, UInt16Type, UInt32Type},
+use datafusion::{
+    arrow::{
+        array::{
+            ArrayRef, BooleanBuilder, GenericStringBuilder, PrimitiveBuilder, RecordBatch,
+            StringDictionaryBuilder,
+        },
+        compute::cast,
+        datatypes::{
+            DataType, Field, Int8Type, Int32Type, Int64Type, Schema, UInt8Type, UInt16Type,
+            UInt32Type,
+        },
+    },
+    common::cast::as_large_string_array,
+    error::DataFusionError,
+    logical_expr::{ColumnarValue, ScalarUDF, Signature, Volatility},
+    prelude::SimpleScalarUDF,
 };
 use noodles::vcf::{
-    Header,
-    variant::record::{AlternateBases, Record},
+    Header, Record,
+    variant::record::{
+        AlternateBases, Ids as _, Record as _,
+        samples::{Sample as _, keys::key, series::Value},
+    },
 };
 
 use crate::{
-    chroms::ChromSet,
-    errors::{Context, FileContext, SveltError, VariantContext, as_io_error},
-    inputs::{get_breakend, get_svtype},
+    breakends::breakend_insertion,
+    chroms::{ChromSet, ContigFilter},
+    errors::{Context, FileContext, SveltError, VariantContext, as_io_error, error_chain},
+    force_keep::ForceKeep,
+    inputs::{OTHER_KIND, get_breakend, get_svtype, get_translocation, sv_kind},
+    options::{ContigLengthPolicy, ExcludedMateAction, MergeOptions, UnknownSvtypeAction},
+    record::traverse_record,
+    sorting::{SortCheck, locus},
+    targets::Targets,
     vcf_reader::VcfReader,
 };
 
@@ -32,14 +56,76 @@ pub fn vcf_core_schema() -> Arc<Schema> {
         Field::new("length", DataType::Int32, true),
         Field::new("chrom2_id", DataType::UInt16, true),
         Field::new_dictionary("chrom2", DataType::UInt16, DataType::Utf8, true),
+        Field::new("chrom2_alias", DataType::Utf8, true),
         Field::new("end2", DataType::Int32, true),
-        Field::new("alt_seq", DataType::Utf8, true),
+        Field::new("bnd_sides", DataType::Utf8, true),
+        Field::new("bnd_ins_len", DataType::Int32, true),
+        // Inserted sequences can be hundreds of kb, too many for 32 bit offsets.
+        Field::new("alt_seq", DataType::LargeUtf8, true),
         Field::new("seq_hash", DataType::Int64, true),
+        Field::new("alt_symbol", DataType::Utf8, true),
+        Field::new("depth_change", DataType::Int8, true),
+        Field::new("genotypes", DataType::Utf8, true),
+        Field::new("id", DataType::Utf8, true),
+        Field::new("called", DataType::Boolean, true),
+        Field::new("force_keep", DataType::Boolean, false),
     ]))
 }
 
 /// Read all the records in the VCF are return them as a `RecordBatch`
-pub fn load_vcf_core(reader: &mut VcfReader) -> std::io::Result<RecordBatch> {
+///
+/// Coordinates beyond the length of their contig (where the header declares one)
+/// are treated according to `--contig-length-policy`.
+///
+/// If `filter` is given, records on contigs it doesn't keep are left out of the
+/// table, as are records whose CHR2 is on such a contig, unless
+/// `--excluded-mate-action` is `keep`. The numbers of records left out are
+/// logged.
+///
+/// Records whose SVTYPE isn't one svelt recognises (see `sv_kind`) are given
+/// the kind `OTHER`, or left out of the table, according to
+/// `--unknown-svtype-action`. The numbers of each such SVTYPE are logged.
+///
+/// If `--require-genotype-concordance` is set, the genotype of each sample is
+/// summarised (see `genotype_dosages`), otherwise the `genotypes` column is
+/// null.
+///
+/// If `targets` is given, records none of whose breakpoints (POS, END, or on
+/// the mate contig, END2) lie in one of its regions are left out of the table,
+/// and the number left out is logged.
+///
+/// If `regions` is given (see `--regions`), records that don't overlap one of
+/// them (or for breakends, whose position and mate position both lie outside
+/// them) are left out likewise.
+///
+/// If `--id-format original` is given, the `id` column holds the (first) ID of
+/// each record, otherwise it is null.
+///
+/// If `--drop-uncalled` is given, the `called` column says whether any
+/// sample of each record has a non-reference genotype (see `calls_variant`),
+/// or is null for records without genotypes; otherwise it is null.
+///
+/// Records kept by `force_keep` (if given) are marked in the `force_keep`
+/// column.
+///
+/// If `rejected` is given, records which can't be processed are left out of the
+/// table (with a warning), and their lines are collected there, rather than
+/// causing an error. The `row_num`s of the remaining records are unaffected.
+pub fn load_vcf_core(
+    reader: &mut VcfReader,
+    options: &MergeOptions,
+    filter: Option<&ContigFilter>,
+    targets: Option<&Targets>,
+    regions: Option<&Targets>,
+    force_keep: Option<&ForceKeep>,
+    mut rejected: Option<&mut Vec<Vec<u8>>>,
+) -> std::io::Result<RecordBatch> {
+    let policy = options.contig_length_policy;
+    let mate_action = options.excluded_mate_action;
+    let svtype_action = options.unknown_svtype_action;
+    let genotypes = options.require_genotype_concordance;
+    let ids = options.id_format.original();
+    let called = options.drop_uncalled;
     FileContext::new(&reader.path).with(|| {
         let header: &Header = &reader.header;
         let chroms: &ChromSet = reader.chroms.as_ref();
@@ -53,50 +139,109 @@ pub fn load_vcf_core(reader: &mut VcfReader) -> std::io::Result<RecordBatch> {
         let mut length_builder = PrimitiveBuilder::<Int32Type>::new();
         let mut chrom2_id_builder = PrimitiveBuilder::<UInt16Type>::new();
         let mut chrom2_builder = StringDictionaryBuilder::<UInt16Type>::new();
+        let mut chrom2_alias_builder = GenericStringBuilder::<i32>::new();
         let mut end2_builder = PrimitiveBuilder::<Int32Type>::new();
-        let mut alt_seq_builder = GenericStringBuilder::<i32>::new();
+        let mut bnd_sides_builder = GenericStringBuilder::<i32>::new();
+        let mut bnd_ins_len_builder = PrimitiveBuilder::<Int32Type>::new();
+        let mut alt_seq_builder = GenericStringBuilder::<i64>::new();
         let mut seq_hash_builder = PrimitiveBuilder::<Int64Type>::new();
+        let mut alt_symbol_builder = GenericStringBuilder::<i32>::new();
+        let mut depth_change_builder = PrimitiveBuilder::<Int8Type>::new();
+        let mut genotypes_builder = GenericStringBuilder::<i32>::new();
+        let mut id_builder = GenericStringBuilder::<i32>::new();
+        let mut called_builder = BooleanBuilder::new();
+        let mut force_keep_builder = BooleanBuilder::new();
 
-        for (rn, rec) in reader.reader.records().enumerate() {
-            log::debug!("processing record {}", rn);
-            let rec = rec?;
+        let mut excluded = 0;
+        let mut kept = 0;
+        let mut excluded_mates = 0;
+        let mut off_target = 0;
+        let mut out_of_regions = 0;
+        let mut unknown_svtypes: BTreeMap<String, usize> = BTreeMap::new();
 
-            let chrom = String::from(rec.reference_sequence_name());
-            let chrom_id = if let Some(chrom_id) = chroms.index(&chrom) {
-                chrom_id
-            } else {
-                return Err(as_io_error(SveltError::BadChrom(chrom)));
-            };
+        // Records are numbered in the order they come, and are swept over in
+        // that order, so an unsorted input is an error (even if records that
+        // can't be processed are being skipped).
+        let mut sort_check = SortCheck::new();
 
-            let start = if let Some(start) = rec.variant_start() {
-                start?.get()
-            } else {
-                0
-            };
+        let mut line = Vec::new();
+        let mut rn = 0;
+        loop {
+            line.clear();
+            if reader.reader.get_mut().read_until(b'\n', &mut line)? == 0 {
+                break;
+            }
+            log::debug!("processing record {}", rn);
+            if let Some((chrom, pos)) = locus(&line) {
+                sort_check.check(chrom, pos).map_err(as_io_error)?;
+            }
+            let res = (|| -> std::io::Result<()> {
+                let rec = Record::try_from(line.as_slice())?;
+                if filter.is_some_and(|f| !f.keeps(rec.reference_sequence_name())) {
+                    excluded += 1;
+                    return Ok(());
+                }
+                if rejected.is_some() {
+                    // Make sure we reject anything the record seeker would.
+                    traverse_record(&rec, header)?;
+                }
 
-            VariantContext::new(&chrom, start).with(|| {
-                let mut end = if start > 0 {
-                    rec.variant_end(header)?.get()
+                let chrom = String::from(rec.reference_sequence_name());
+                let chrom_id = if let Some(chrom_id) = chroms.index(&chrom) {
+                    chrom_id
                 } else {
-                    if let Some(value) = VcfReader::info_as_int(&rec, header, "END")? {
-                        value as usize
-                    } else if let Some(value) = VcfReader::info_as_int(&rec, header, "SVLEN")? {
-                        start + (value as usize)
-                    } else {
-                        0
-                    }
+                    return Err(as_io_error(SveltError::BadChrom(chrom)));
                 };
 
-                let kind = get_svtype(&rec, header)?;
-
-                let length = if let Some(value) = VcfReader::info_as_int(&rec, header, "SVLEN")? {
-                    Some(value)
+                let start = if let Some(start) = rec.variant_start() {
+                    start?.get()
                 } else {
-                    None
+                    0
                 };
 
-                if let Some(l) = &length {
-                    if kind == "DEL" && start + l.abs() as usize != end {
+                VariantContext::new(&chrom, start).with(|| {
+                    let contig_length = |name: &str| header.contigs().get(name).and_then(|c| c.length());
+
+                    // Legacy translocations are matched as breakends, and their
+                    // END (if any) is on the other contig.
+                    let translocation = get_translocation(&rec, header)?;
+
+                    let mut end = if translocation.is_some() {
+                        start
+                    } else if start > 0 {
+                        rec.variant_end(header)?.get()
+                    } else {
+                        if let Some(value) = VcfReader::info_as_int(&rec, header, "END")? {
+                            value as usize
+                        } else if let Some(value) = VcfReader::info_as_int(&rec, header, "SVLEN")? {
+                            start + (value as usize)
+                        } else {
+                            0
+                        }
+                    };
+
+                    let kind = if translocation.is_some() {
+                        String::from("BND")
+                    } else {
+                        let svtype = get_svtype(&rec, header)?;
+                        match sv_kind(&svtype) {
+                            Some(kind) => String::from(kind),
+                            None => {
+                                *unknown_svtypes.entry(svtype).or_default() += 1;
+                                match svtype_action {
+                                    UnknownSvtypeAction::Other => String::from(OTHER_KIND),
+                                    UnknownSvtypeAction::Skip => return Ok(()),
+                                }
+                            }
+                        }
+                    };
+
+                    let length = VcfReader::info_as_int(&rec, header, "SVLEN")?;
+
+                    if let Some(l) = &length
+                        && kind == "DEL"
+                        && start + l.unsigned_abs() as usize != end
+                    {
                         let d = (start as i32) + l.abs() - (end as i32);
                         log::warn!(
                             "at {}:{}, end ({}) and length ({}) are inconsistent (off by {}).",
@@ -106,102 +251,311 @@ pub fn load_vcf_core(reader: &mut VcfReader) -> std::io::Result<RecordBatch> {
                             l,
                             d
                         );
-                        end = start + l.abs() as usize;
+                        end = start + l.unsigned_abs() as usize;
                     }
-                }
 
-                let bnd = get_breakend(&rec)?;
+                    let start = check_contig_bounds("POS", &chrom, start, contig_length(&chrom), policy)?;
+                    let end = check_contig_bounds("END", &chrom, end, contig_length(&chrom), policy)?;
+
+                    let bnd = match translocation {
+                        Some(translocation) => Some(translocation),
+                        None => get_breakend(&rec)?,
+                    };
+
+                    // Mate contigs may be named differently from the primary ones
+                    // (see `ChromSet::add_soft_aliases`), so use the declared names.
+                    let canonical = |name: &str| match chroms.resolve(name) {
+                        Some(ix) => String::from(chroms.name(ix)),
+                        None => String::from(name),
+                    };
+                    // Where CHR2 can't be read (e.g. an array of several), the
+                    // mate contig can still be taken from a breakend ALT.
+                    let raw_chr2 = match VcfReader::info_as_str(&rec, header, "CHR2") {
+                        Ok(value) => value,
+                        Err(e) if bnd.is_some() => {
+                            log::warn!(
+                                "at {}:{}, ignoring CHR2 ({}), and taking the mate contig from the ALT.",
+                                chrom,
+                                start,
+                                error_chain(&e)
+                            );
+                            None
+                        }
+                        Err(e) => return Err(e),
+                    };
+                    let raw_names: Vec<String> = raw_chr2
+                        .iter()
+                        .chain(bnd.iter().map(|b| &b.0))
+                        .cloned()
+                        .collect();
+                    let bnd =
+                        bnd.map(|(chr2, pos2, side, side2)| (canonical(&chr2), pos2, side, side2));
 
-                let chrom2 = if let Some(value) = VcfReader::info_as_str(&rec, header, "CHR2")? {
-                    if let Some(bnd) = &bnd {
-                        if value != bnd.0 {
-                            Err(SveltError::BadChr2(bnd.0.clone(), value.clone()))
+                    let chr2 = raw_chr2.map(|value| canonical(&value));
+                    let chrom2 = if let Some(value) = chr2 {
+                        if let Some(bnd) = &bnd {
+                            if value != bnd.0 {
+                                Err(SveltError::BadChr2(value.clone(), bnd.0.clone()))
+                            } else {
+                                Ok(Some(value))
+                            }
                         } else {
-                            Ok(Some(String::from(value)))
+                            Ok(Some(value))
                         }
                     } else {
-                        Ok(Some(String::from(value)))
+                        if let Some(bnd) = &bnd {
+                            Ok(Some(bnd.0.clone()))
+                        } else if kind == "BND" {
+                            Err(SveltError::MissingChr2)
+                        } else {
+                            Ok(None)
+                        }
                     }
-                } else {
-                    if let Some(bnd) = &bnd {
-                        Ok(Some(bnd.0.clone()))
-                    } else if kind == "BND" {
-                        Err(SveltError::MissingChr2)
+                    .map_err(as_io_error)?;
+                    if let (Some(filter), Some(name2)) = (filter, chrom2.as_ref())
+                        && !filter.keeps(name2)
+                    {
+                        excluded_mates += 1;
+                        if mate_action == ExcludedMateAction::Drop {
+                            return Ok(());
+                        }
+                    }
+                    // The mate contig as the input names it, where that is an alias.
+                    let chrom2_alias: Option<String> = chrom2
+                        .as_ref()
+                        .and_then(|name2| raw_names.into_iter().find(|raw| raw != name2));
+                    let chrom2_id = if let Some(name2) = chrom2.as_ref() {
+                        chroms.index(name2).map(|x| x as u16)
                     } else {
-                        Ok(None)
+                        None
+                    };
+                    if let (Some(name2), None) = (chrom2.as_ref(), chrom2_id) {
+                        log::warn!(
+                            "at {}:{}, the mate contig '{}' is not one of the declared contigs.",
+                            chrom,
+                            start,
+                            name2
+                        );
                     }
-                }
-                .map_err(as_io_error)?;
-                let chrom2_id = if let Some(name2) = chrom2.as_ref() {
-                    chroms.index(name2).map(|x| x as u16)
-                } else {
-                    None
-                };
-                let end2: Option<i32> = if kind == "BND" {
-                    if let Some(bnd) = &bnd {
-                        let (chr2, pos2, _here, _there) = bnd;
-                        if chr2 != chrom2.as_ref().unwrap() {
-                            Err(SveltError::BadChr2(
-                                chrom2.as_ref().unwrap().clone(),
-                                chr2.clone(),
-                            ))
+                    let end2: Option<i32> = if kind == "BND" {
+                        if let Some(bnd) = &bnd {
+                            let (chr2, pos2, _here, _there) = bnd;
+                            if chr2 != chrom2.as_ref().unwrap() {
+                                Err(SveltError::BadChr2(
+                                    chrom2.as_ref().unwrap().clone(),
+                                    chr2.clone(),
+                                ))
+                            } else {
+                                Ok(Some(*pos2 as i32))
+                            }
                         } else {
-                            Ok(Some(*pos2 as i32))
+                            Err(SveltError::MissingAlt)
                         }
                     } else {
-                        Err(SveltError::MissingAlt)
+                        Ok(None)
                     }
-                } else {
-                    Ok(None)
-                }
-                .map_err(as_io_error)?;
-                let seq: Option<String> = if kind == "INS" {
-                    if let Some(alt) = rec.alternate_bases().iter().next() {
-                        let alt = alt?;
-                        if is_seq(alt) {
-                            Ok(Some(String::from(&alt[1..])))
+                    .map_err(as_io_error)?;
+                    let end2 = if let (Some(chrom2), Some(pos2)) = (chrom2.as_ref(), end2) {
+                        let pos2 = check_contig_bounds(
+                            "END2",
+                            chrom2,
+                            pos2 as usize,
+                            contig_length(chrom2),
+                            policy,
+                        )?;
+                        Some(pos2 as i32)
+                    } else {
+                        end2
+                    };
+                    if let Some(targets) = targets {
+                        let mut breakpoints = vec![(chrom.as_str(), start)];
+                        if kind != "BND" {
+                            breakpoints.push((chrom.as_str(), end));
+                        }
+                        if let (Some(chrom2), Some(end2)) = (chrom2.as_ref(), end2) {
+                            breakpoints.push((chrom2.as_str(), end2 as usize));
+                        }
+                        if !targets.touches(breakpoints) {
+                            off_target += 1;
+                            return Ok(());
+                        }
+                    }
+                    if let Some(regions) = regions {
+                        let overlaps = if kind == "BND" {
+                            let mut breakpoints = vec![(chrom.as_str(), start)];
+                            if let (Some(chrom2), Some(end2)) = (chrom2.as_ref(), end2) {
+                                breakpoints.push((chrom2.as_str(), end2 as usize));
+                            }
+                            regions.touches(breakpoints)
+                        } else {
+                            regions.overlaps(&chrom, start, end)
+                        };
+                        if !overlaps {
+                            out_of_regions += 1;
+                            return Ok(());
+                        }
+                    }
+                    // The sides of the join at each end, as codes (see `BreakEndSide::code`).
+                    let bnd_sides: Option<String> = bnd
+                        .as_ref()
+                        .map(|(_, _, side, side2)| [side.code(), side2.code()].iter().collect());
+                    // Legacy translocations don't say what (if anything) is inserted.
+                    let bnd_ins_len: Option<i32> = match rec.alternate_bases().iter().next() {
+                        Some(alt) if kind == "BND" => breakend_insertion(alt?).map(|n| n as i32),
+                        _ => None,
+                    };
+                    let seq: Option<String> = if kind == "INS" {
+                        if let Some(alt) = rec.alternate_bases().iter().next() {
+                            let alt = alt?;
+                            if is_seq(alt) {
+                                Ok(Some(String::from(&alt[1..])))
+                            } else {
+                                Ok(None)
+                            }
                         } else {
                             Ok(None)
                         }
                     } else {
                         Ok(None)
                     }
-                } else {
-                    Ok(None)
-                }
-                .map_err(as_io_error)?;
-                let seq_hash = if kind == "INS" {
-                    if let Some(alt) = rec.alternate_bases().iter().next() {
-                        let alt = alt?;
-                        if is_seq(alt) {
-                            Ok(Some(digest(&alt[1..]) as i64))
+                    .map_err(as_io_error)?;
+                    let seq_hash = if kind == "INS" {
+                        if let Some(alt) = rec.alternate_bases().iter().next() {
+                            let alt = alt?;
+                            if is_seq(alt) {
+                                Ok(Some(digest(&alt[1..]) as i64))
+                            } else {
+                                // hash the tag
+                                Ok(Some(digest(alt) as i64))
+                            }
                         } else {
-                            // hash the tag
-                            Ok(Some(digest(alt) as i64))
+                            Ok(None)
                         }
                     } else {
                         Ok(None)
                     }
-                } else {
-                    Ok(None)
+                    .map_err(as_io_error)?;
+                    let alt_symbol: Option<String> = match rec.alternate_bases().iter().next() {
+                        Some(alt) => symbolic_alt(alt?).map(String::from),
+                        None => None,
+                    };
+                    let depth_change = if kind == "DEL" || kind == "DUP" {
+                        let fold = VcfReader::info_as_float(&rec, header, DEPTH_FOLD_CHANGE)?;
+                        let fold = match fold {
+                            Some(fold) => Some(fold),
+                            None => VcfReader::format_as_float(&rec, header, DEPTH_FOLD_CHANGE)?,
+                        };
+                        fold.map(depth_change)
+                    } else {
+                        None
+                    };
+                    let dosages = if genotypes {
+                        genotype_dosages(&rec, header)?
+                    } else {
+                        None
+                    };
+
+                    row_num_builder.append_value(rn as u32);
+                    chrom_id_builder.append_value(chrom_id as u16);
+                    chrom_builder.append_value(chrom.clone());
+                    start_builder.append_value(start as i32);
+                    end_builder.append_value(end as i32);
+                    kind_builder.append_value(kind);
+                    length_builder.append_option(length);
+                    chrom2_id_builder.append_option(chrom2_id);
+                    chrom2_builder.append_option(chrom2);
+                    chrom2_alias_builder.append_option(chrom2_alias);
+                    end2_builder.append_option(end2);
+                    bnd_sides_builder.append_option(bnd_sides);
+                    bnd_ins_len_builder.append_option(bnd_ins_len);
+                    alt_seq_builder.append_option(seq);
+                    seq_hash_builder.append_option(seq_hash);
+                    alt_symbol_builder.append_option(alt_symbol);
+                    depth_change_builder.append_option(depth_change);
+                    genotypes_builder.append_option(dosages);
+                    let id = if ids {
+                        rec.ids().iter().next().map(String::from)
+                    } else {
+                        None
+                    };
+                    id_builder.append_option(id);
+                    called_builder.append_option(if called {
+                        calls_variant(&rec, header)?
+                    } else {
+                        None
+                    });
+                    let keep = force_keep.is_some_and(|k| k.keeps(rec.ids().iter(), &chrom, start));
+                    if keep {
+                        kept += 1;
+                    }
+                    force_keep_builder.append_value(keep);
+
+                    Ok(())
+                })
+            })();
+            match (res, rejected.as_mut()) {
+                (Ok(()), _) => {}
+                (Err(e), Some(rejected)) => {
+                    log::warn!("skipping record {} of {}: {}", rn, reader.path, error_chain(&e));
+                    rejected.push(line.clone());
                 }
-                .map_err(as_io_error)?;
-
-                row_num_builder.append_value(rn as u32);
-                chrom_id_builder.append_value(chrom_id as u16);
-                chrom_builder.append_value(chrom.clone());
-                start_builder.append_value(start as i32);
-                end_builder.append_value(end as i32);
-                kind_builder.append_value(kind);
-                length_builder.append_option(length);
-                chrom2_id_builder.append_option(chrom2_id);
-                chrom2_builder.append_option(chrom2);
-                end2_builder.append_option(end2);
-                alt_seq_builder.append_option(seq);
-                seq_hash_builder.append_option(seq_hash);
-
-                Ok(())
-            })?;
+                (Err(e), None) => return Err(e),
+            }
+            rn += 1;
+        }
+
+        if filter.is_some() {
+            log::info!(
+                "left out {} records on excluded contigs from {}",
+                excluded,
+                reader.path
+            );
+            let verb = match mate_action {
+                ExcludedMateAction::Drop => "left out",
+                ExcludedMateAction::Keep => "kept",
+            };
+            log::info!(
+                "{} {} records with CHR2 on excluded contigs from {}",
+                verb,
+                excluded_mates,
+                reader.path
+            );
+        }
+
+        if targets.is_some() {
+            log::info!(
+                "left out {} records outside the targets from {}",
+                off_target,
+                reader.path
+            );
+        }
+        if regions.is_some() {
+            log::info!(
+                "left out {} records outside the regions from {}",
+                out_of_regions,
+                reader.path
+            );
+        }
+
+        let verb = match svtype_action {
+            UnknownSvtypeAction::Other => "merged as OTHER",
+            UnknownSvtypeAction::Skip => "left out",
+        };
+        for (svtype, count) in unknown_svtypes.iter() {
+            log::warn!(
+                "{} {} records with unrecognised SVTYPE '{}' from {}",
+                verb,
+                count,
+                svtype,
+                reader.path
+            );
+        }
+        if force_keep.is_some() {
+            log::info!(
+                "passing {} records from {} through unmodified (--force-keep)",
+                kept,
+                reader.path
+            );
         }
 
         let row_num_array = row_num_builder.finish();
@@ -213,9 +567,18 @@ pub fn load_vcf_core(reader: &mut VcfReader) -> std::io::Result<RecordBatch> {
         let length_array = length_builder.finish();
         let chrom2_id_array = chrom2_id_builder.finish();
         let chrom2_array = chrom2_builder.finish();
+        let chrom2_alias_array = chrom2_alias_builder.finish();
         let end2_array = end2_builder.finish();
+        let bnd_sides_array = bnd_sides_builder.finish();
+        let bnd_ins_len_array = bnd_ins_len_builder.finish();
         let alt_seq_array = alt_seq_builder.finish();
         let seq_hash_array = seq_hash_builder.finish();
+        let alt_symbol_array = alt_symbol_builder.finish();
+        let depth_change_array = depth_change_builder.finish();
+        let genotypes_array = genotypes_builder.finish();
+        let id_array = id_builder.finish();
+        let called_array = called_builder.finish();
+        let force_keep_array = force_keep_builder.finish();
 
         let res = RecordBatch::try_new(
             vcf_core_schema(),
@@ -229,9 +592,18 @@ pub fn load_vcf_core(reader: &mut VcfReader) -> std::io::Result<RecordBatch> {
                 Arc::new(length_array),
                 Arc::new(chrom2_id_array),
                 Arc::new(chrom2_array),
+                Arc::new(chrom2_alias_array),
                 Arc::new(end2_array),
+                Arc::new(bnd_sides_array),
+                Arc::new(bnd_ins_len_array),
                 Arc::new(alt_seq_array),
                 Arc::new(seq_hash_array),
+                Arc::new(alt_symbol_array),
+                Arc::new(depth_change_array),
+                Arc::new(genotypes_array),
+                Arc::new(id_array),
+                Arc::new(called_array),
+                Arc::new(force_keep_array),
             ],
         )
         .map_err(|e| Error::new(ErrorKind::Other, e))?;
@@ -239,6 +611,40 @@ pub fn load_vcf_core(reader: &mut VcfReader) -> std::io::Result<RecordBatch> {
     })
 }
 
+/// Check a coordinate against the length of its contig (if known).
+///
+/// Depending on the policy, a coordinate beyond the end of the contig is
+/// either an error, or clamped to the contig length with a warning.
+pub fn check_contig_bounds(
+    field: &str,
+    chrom: &str,
+    pos: usize,
+    length: Option<usize>,
+    policy: ContigLengthPolicy,
+) -> std::io::Result<usize> {
+    match length {
+        Some(length) if pos > length => match policy {
+            ContigLengthPolicy::Error => Err(as_io_error(SveltError::CoordinateBeyondContig(
+                String::from(field),
+                String::from(chrom),
+                pos,
+                length,
+            ))),
+            ContigLengthPolicy::Clamp => {
+                log::warn!(
+                    "{} ({}) is beyond the end of contig '{}' (length {}), clamping.",
+                    field,
+                    pos,
+                    chrom,
+                    length
+                );
+                Ok(length)
+            }
+        },
+        _ => Ok(pos),
+    }
+}
+
 pub fn is_seq(seq: &str) -> bool {
     seq.chars().all(|c| match c {
         'A' | 'C' | 'G' | 'T' | 'N' => true,
@@ -248,6 +654,98 @@ pub fn is_seq(seq: &str) -> bool {
     })
 }
 
+/// The symbol of a symbolic ALT without its angle brackets (e.g. `INS:ME:ALU`
+/// for `<INS:ME:ALU>`), or `None` for anything else.
+pub fn symbolic_alt(alt: &str) -> Option<&str> {
+    alt.strip_prefix('<')?.strip_suffix('>')
+}
+
+/// Whether two symbols are compatible, i.e. one is the other or one of its
+/// subtypes (`INS` and `INS:ME:ALU`, but not `INS:ME:ALU` and `INS:ME:L1`).
+pub fn compatible_symbols(lhs: &str, rhs: &str) -> bool {
+    let (shorter, longer) = if lhs.len() <= rhs.len() {
+        (lhs, rhs)
+    } else {
+        (rhs, lhs)
+    };
+    longer
+        .strip_prefix(shorter)
+        .is_some_and(|rest| rest.is_empty() || rest.starts_with(':'))
+}
+
+/// The field giving the fold change in read depth across an event, relative
+/// to its flanks (as written by duphold), from INFO or else the first sample.
+pub const DEPTH_FOLD_CHANGE: &str = "DHFFC";
+
+/// Fold changes below this are a loss of depth (duphold's suggested cutoff
+/// for deletions).
+pub const DEPTH_LOSS_THRESHOLD: f32 = 0.7;
+
+/// Fold changes above this are a gain of depth (duphold's suggested cutoff
+/// for duplications).
+pub const DEPTH_GAIN_THRESHOLD: f32 = 1.3;
+
+/// The direction of the change in depth given by a fold change: -1 for a
+/// loss, 1 for a gain, and 0 for neither.
+pub fn depth_change(fold: f32) -> i8 {
+    if fold < DEPTH_LOSS_THRESHOLD {
+        -1
+    } else if fold > DEPTH_GAIN_THRESHOLD {
+        1
+    } else {
+        0
+    }
+}
+
+/// Summarise the genotypes of a record as `SAMPLE=DOSAGE` (the number of ALT
+/// alleles called), separated by tabs, leaving out samples whose genotype is
+/// missing (in whole or in part). `None` if no sample has a genotype.
+pub fn genotype_dosages(rec: &Record, header: &Header) -> std::io::Result<Option<String>> {
+    let mut dosages = Vec::new();
+    for (name, sample) in zip(header.sample_names().iter(), rec.samples().iter()) {
+        let Some(Some(Value::Genotype(gt))) = sample.get(header, key::GENOTYPE).transpose()? else {
+            continue;
+        };
+        let alleles = gt
+            .iter()
+            .map(|a| a.map(|(position, _phasing)| position))
+            .collect::<std::io::Result<Vec<Option<usize>>>>()?;
+        if alleles.is_empty() || alleles.iter().any(|a| a.is_none()) {
+            continue;
+        }
+        let dosage = alleles.iter().filter(|a| a.unwrap_or(0) > 0).count();
+        dosages.push(format!("{}={}", name, dosage));
+    }
+    Ok((!dosages.is_empty()).then(|| dosages.join("\t")))
+}
+
+/// Whether any sample of a record has a genotype with a non-reference allele
+/// (see `--drop-uncalled`), or `None` if no sample has a genotype.
+pub fn calls_variant(rec: &Record, header: &Header) -> std::io::Result<Option<bool>> {
+    let mut genotyped = false;
+    for sample in rec.samples().iter() {
+        let Some(Some(Value::Genotype(gt))) = sample.get(header, key::GENOTYPE).transpose()? else {
+            continue;
+        };
+        genotyped = true;
+        for allele in gt.iter() {
+            if allele?.0.is_some_and(|position| position > 0) {
+                return Ok(Some(true));
+            }
+        }
+    }
+    Ok(genotyped.then_some(false))
+}
+
+/// Whether two genotype summaries (see `genotype_dosages`) agree, i.e. every
+/// sample they have in common has the same dosage in both.
+pub fn concordant_genotypes(lhs: &str, rhs: &str) -> bool {
+    let rhs: HashMap<&str, &str> = rhs.split('\t').filter_map(|g| g.rsplit_once('=')).collect();
+    lhs.split('\t')
+        .filter_map(|g| g.rsplit_once('='))
+        .all(|(name, dosage)| rhs.get(name).is_none_or(|other| *other == dosage))
+}
+
 pub fn digest(seq: &str) -> u64 {
     let mut hasher = Blake2b512::new();
     hasher.update(seq.as_bytes());
@@ -258,3 +756,145 @@ pub fn digest(seq: &str) -> u64 {
     }
     h
 }
+
+/// `digest` as a scalar function (`svelt_digest`) over a string column, giving
+/// the `seq_hash` of each sequence, so hashes can be computed or checked in
+/// queries. It is registered on the session (see `make_session_context`).
+pub fn digest_udf() -> ScalarUDF {
+    ScalarUDF::from(SimpleScalarUDF::new_with_signature(
+        "svelt_digest",
+        Signature::string(1, Volatility::Immutable),
+        DataType::Int64,
+        Arc::new(do_digest),
+    ))
+}
+
+fn do_digest(args: &[ColumnarValue]) -> std::result::Result<ColumnarValue, DataFusionError> {
+    let args = ColumnarValue::values_to_arrays(args)?;
+
+    // Any string column can be cast to LargeUtf8, whereas a LargeUtf8 column
+    // of long sequences can't always be cast to Utf8.
+    let seqs = cast(&args[0], &DataType::LargeUtf8)?;
+    let seqs = as_large_string_array(&seqs)?;
+
+    let mut result_builder = PrimitiveBuilder::<Int64Type>::new();
+    for seq in seqs.iter() {
+        result_builder.append_option(seq.map(|seq| digest(seq) as i64));
+    }
+
+    let result_array = result_builder.finish();
+
+    Ok(ColumnarValue::from(Arc::new(result_array) as ArrayRef))
+}
+
+#[cfg(test)]
+mod tests {
+    use datafusion::{
+        arrow::array::{Array, Int64Array},
+        prelude::SessionContext,
+    };
+
+    use super::*;
+
+    #[test]
+    fn test_check_contig_bounds() {
+        let check = |pos, length, policy| check_contig_bounds("POS", "chr1", pos, length, policy);
+
+        // Within the contig, or on a contig of unknown length, nothing changes.
+        assert_eq!(check(1000, Some(5000), ContigLengthPolicy::Error).unwrap(), 1000);
+        assert_eq!(check(5000, Some(5000), ContigLengthPolicy::Error).unwrap(), 5000);
+        assert_eq!(check(9000, None, ContigLengthPolicy::Error).unwrap(), 9000);
+
+        assert_eq!(check(5001, Some(5000), ContigLengthPolicy::Clamp).unwrap(), 5000);
+        let err = check(5001, Some(5000), ContigLengthPolicy::Error).unwrap_err();
+        assert!(matches!(
+            err.get_ref().and_then(|e| e.downcast_ref::<SveltError>()),
+            Some(SveltError::CoordinateBeyondContig(_, _, 5001, 5000))
+        ));
+    }
+
+    #[test]
+    fn test_compatible_symbols() {
+        assert_eq!(symbolic_alt("<INS:ME:ALU>"), Some("INS:ME:ALU"));
+        assert_eq!(symbolic_alt("ACGT"), None);
+        assert!(compatible_symbols("INS", "INS:ME:ALU"));
+        assert!(compatible_symbols("INS:ME:ALU", "INS:ME"));
+        assert!(compatible_symbols("INS:ME:ALU", "INS:ME:ALU"));
+        assert!(!compatible_symbols("INS:ME:ALU", "INS:ME:L1"));
+        assert!(!compatible_symbols("INS:ME", "INS:MEI"));
+        assert!(!compatible_symbols("INS", "DEL"));
+    }
+
+    #[test]
+    fn test_depth_change() {
+        assert_eq!(depth_change(0.45), -1);
+        assert_eq!(depth_change(0.95), 0);
+        assert_eq!(depth_change(1.3), 0);
+        assert_eq!(depth_change(1.8), 1);
+    }
+
+    #[tokio::test]
+    async fn test_digest_udf() {
+        let ctx = SessionContext::new();
+        ctx.register_udf(digest_udf());
+        let res = ctx
+            .sql("SELECT svelt_digest(seq) AS seq_hash FROM (VALUES ('ACGT'), (NULL), ('TTAGGG')) AS t(seq)")
+            .await
+            .unwrap()
+            .collect()
+            .await
+            .unwrap();
+        let hashes = res[0]
+            .column(0)
+            .as_any()
+            .downcast_ref::<Int64Array>()
+            .unwrap();
+        assert_eq!(hashes.value(0), digest("ACGT") as i64);
+        assert!(hashes.is_null(1));
+        assert_eq!(hashes.value(2), digest("TTAGGG") as i64);
+
+        let res = ctx
+            .sql("SELECT svelt_digest(arrow_cast(seq, 'LargeUtf8')) AS seq_hash FROM (VALUES ('ACGT'), (NULL)) AS t(seq)")
+            .await
+            .unwrap()
+            .collect()
+            .await
+            .unwrap();
+        let hashes = res[0]
+            .column(0)
+            .as_any()
+            .downcast_ref::<Int64Array>()
+            .unwrap();
+        assert_eq!(hashes.value(0), digest("ACGT") as i64);
+        assert!(hashes.is_null(1));
+    }
+
+    #[test]
+    fn test_calls_variant() {
+        let header: Header = "##fileformat=VCFv4.3\n\
+            ##FORMAT=<ID=GT,Number=1,Type=String,Description=\"Genotype\">\n\
+            #CHROM\tPOS\tID\tREF\tALT\tQUAL\tFILTER\tINFO\tFORMAT\tA\tB\n"
+            .parse()
+            .unwrap();
+        let calls = |samples: &str| {
+            let line = format!("chr1\t1000\t.\tA\t<DEL>\t.\tPASS\t.{}", samples);
+            let rec = Record::try_from(line.as_bytes()).unwrap();
+            calls_variant(&rec, &header).unwrap()
+        };
+
+        assert_eq!(calls("\tGT\t0/0\t./."), Some(false));
+        assert_eq!(calls("\tGT\t./.\t0|0"), Some(false));
+        assert_eq!(calls("\tGT\t0/0\t0|1"), Some(true));
+        assert_eq!(calls("\tGT\t./1\t./."), Some(true));
+        // Without genotypes, there's nothing to say either way.
+        assert_eq!(calls(""), None);
+    }
+
+    #[test]
+    fn test_concordant_genotypes() {
+        assert!(concordant_genotypes("A=1\tB=2", "A=1\tC=0"));
+        assert!(concordant_genotypes("A=1", "B=2"));
+        assert!(!concordant_genotypes("A=1\tB=2", "B=1"));
+        assert!(!concordant_genotypes("A=0", "A=1\tB=1"));
+    }
+}
@@ -0,0 +1,88 @@
+/// Names of INFO or FORMAT fields (e.g. from `--unwanted-info`), each of
+/// which may include the wildcards `*` (any run of characters) and `?` (any
+/// one character).
+#[derive(Debug, Clone, Default)]
+pub struct TagPatterns {
+    patterns: Vec<String>,
+}
+
+impl TagPatterns {
+    pub fn new(patterns: &[String]) -> TagPatterns {
+        TagPatterns {
+            patterns: patterns.to_vec(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.patterns.is_empty()
+    }
+
+    /// Whether `tag` matches any of the patterns.
+    pub fn matches(&self, tag: &str) -> bool {
+        self.patterns
+            .iter()
+            .any(|pattern| glob(pattern.as_bytes(), tag.as_bytes()))
+    }
+
+    /// The patterns that match none of `tags`.
+    pub fn unmatched<'a, I: IntoIterator<Item = &'a str>>(&self, tags: I) -> Vec<&str> {
+        let tags: Vec<&str> = tags.into_iter().collect();
+        self.patterns
+            .iter()
+            .filter(|pattern| {
+                !tags
+                    .iter()
+                    .any(|tag| glob(pattern.as_bytes(), tag.as_bytes()))
+            })
+            .map(|pattern| pattern.as_str())
+            .collect()
+    }
+}
+
+/// Match `text` against `pattern`, going back to the last `*` on a mismatch.
+fn glob(pattern: &[u8], text: &[u8]) -> bool {
+    let (mut p, mut t) = (0, 0);
+    let mut star: Option<(usize, usize)> = None;
+    while t < text.len() {
+        if p < pattern.len() && (pattern[p] == b'?' || pattern[p] == text[t]) {
+            p += 1;
+            t += 1;
+        } else if p < pattern.len() && pattern[p] == b'*' {
+            star = Some((p, t));
+            p += 1;
+        } else if let Some((sp, st)) = star {
+            // Let the `*` take one more character.
+            star = Some((sp, st + 1));
+            p = sp + 1;
+            t = st + 1;
+        } else {
+            return false;
+        }
+    }
+    pattern[p..].iter().all(|c| *c == b'*')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tag_patterns() {
+        let patterns = TagPatterns::new(&[
+            String::from("SUPP*"),
+            String::from("RE"),
+            String::from("?R"),
+            String::from("*_CI*"),
+        ]);
+        for tag in ["SUPP", "SUPP_VEC", "RE", "DR", "POS_CI", "POS_CI95"] {
+            assert!(patterns.matches(tag), "{}", tag);
+        }
+        for tag in ["SUP", "RES", "R", "DRV", "CIPOS"] {
+            assert!(!patterns.matches(tag), "{}", tag);
+        }
+        assert_eq!(patterns.unmatched(["SUPP_VEC", "DR"]), vec!["RE", "*_CI*"]);
+
+        assert!(!TagPatterns::default().matches("RE"));
+        assert!(TagPatterns::new(&[String::from("*")]).matches(""));
+    }
+}
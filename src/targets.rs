@@ -0,0 +1,207 @@
+use std::{
+    collections::HashMap,
+    io::{BufRead, BufReader},
+    path::Path,
+};
+
+use autocompress::autodetect_open;
+
+use crate::{
+    errors::{Context, FileContext, SveltError, as_io_error},
+    force_keep::parse_region,
+};
+
+/// The regions of a panel (`--targets`, a BED file), padded on each side by
+/// `--target-padding` bases. Only records with a breakpoint in one of them
+/// are loaded.
+///
+/// The same is used for `--regions`, where records overlapping one of the
+/// regions are loaded.
+#[derive(Debug, Default)]
+pub struct Targets {
+    /// The (0-based, half open) padded regions of each contig, sorted, with
+    /// overlapping ones joined.
+    regions: HashMap<String, Vec<(usize, usize)>>,
+}
+
+impl Targets {
+    pub fn load(path: &str, padding: usize) -> std::io::Result<Targets> {
+        let mut res = Targets::default();
+        res.add_bed(path, padding)?;
+        res.finish();
+        Ok(res)
+    }
+
+    /// The regions of `--regions`: each either a BED file, or a region
+    /// (`CHROM:START-END`, 1-based and inclusive, or `CHROM` for the whole
+    /// contig).
+    pub fn regions(specs: &[String]) -> std::io::Result<Targets> {
+        let mut res = Targets::default();
+        for spec in specs.iter() {
+            if Path::new(spec).is_file() {
+                res.add_bed(spec, 0)?;
+            } else {
+                let (chrom, begin, end) = parse_region_string(spec).map_err(as_io_error)?;
+                res.add(chrom, begin, end, 0);
+            }
+        }
+        res.finish();
+        Ok(res)
+    }
+
+    fn add_bed(&mut self, path: &str, padding: usize) -> std::io::Result<()> {
+        FileContext::new(path).with(|| {
+            let reader = BufReader::new(autodetect_open(path)?);
+            for line in reader.lines() {
+                let line = line?;
+                let line = line.trim();
+                if line.is_empty()
+                    || line.starts_with('#')
+                    || line.starts_with("track")
+                    || line.starts_with("browser")
+                {
+                    continue;
+                }
+                let (chrom, begin, end) = parse_region(line)?;
+                self.add(chrom, begin, end, padding);
+            }
+            Ok(())
+        })
+    }
+
+    fn add(&mut self, chrom: String, begin: usize, end: usize, padding: usize) {
+        self.regions
+            .entry(chrom)
+            .or_default()
+            .push((begin.saturating_sub(padding), end + padding));
+    }
+
+    fn finish(&mut self) {
+        for regions in self.regions.values_mut() {
+            regions.sort();
+            let mut joined: Vec<(usize, usize)> = Vec::new();
+            for &(begin, end) in regions.iter() {
+                match joined.last_mut() {
+                    Some(last) if begin <= last.1 => last.1 = last.1.max(end),
+                    _ => joined.push((begin, end)),
+                }
+            }
+            *regions = joined;
+        }
+    }
+
+    /// Whether `chrom`:`pos` (1-based) lies in one of the padded regions.
+    pub fn contains(&self, chrom: &str, pos: usize) -> bool {
+        let Some(regions) = self.regions.get(chrom) else {
+            return false;
+        };
+        // The first region finishing at or after `pos`.
+        let ix = regions.partition_point(|&(_, end)| end < pos);
+        regions
+            .get(ix)
+            .is_some_and(|&(begin, end)| begin < pos && pos <= end)
+    }
+
+    /// Whether the (1-based, inclusive) span `begin`..=`end` of `chrom`
+    /// overlaps one of the padded regions.
+    pub fn overlaps(&self, chrom: &str, begin: usize, end: usize) -> bool {
+        let Some(regions) = self.regions.get(chrom) else {
+            return false;
+        };
+        let ix = regions.partition_point(|&(_, region_end)| region_end < begin);
+        regions
+            .get(ix)
+            .is_some_and(|&(region_begin, _)| region_begin < end.max(begin))
+    }
+
+    /// Whether any of the breakpoints (contig and 1-based position) of a
+    /// record lies in one of the padded regions.
+    pub fn touches<'a, I: IntoIterator<Item = (&'a str, usize)>>(&self, breakpoints: I) -> bool {
+        breakpoints
+            .into_iter()
+            .any(|(chrom, pos)| self.contains(chrom, pos))
+    }
+}
+
+/// Parse a region given on the command line (`--regions`), as `CHROM` or
+/// `CHROM:START-END` (1-based and inclusive), giving the (0-based, half open)
+/// region.
+pub fn parse_region_string(spec: &str) -> Result<(String, usize, usize), SveltError> {
+    let bad = || {
+        SveltError::BadOptionValue(
+            String::from("--regions"),
+            format!("{} (expected CHROM, CHROM:START-END, or a BED file)", spec),
+        )
+    };
+    // Contig names may contain ':' (e.g. HLA alleles), so only a trailing
+    // range is taken as one.
+    let range = spec.rsplit_once(':').and_then(|(chrom, range)| {
+        let (begin, end) = range.split_once('-')?;
+        let begin: usize = begin.parse().ok()?;
+        let end: usize = end.parse().ok()?;
+        Some((chrom, begin, end))
+    });
+    match range {
+        Some((chrom, begin, end)) if !chrom.is_empty() && begin >= 1 && begin <= end => {
+            Ok((String::from(chrom), begin - 1, end))
+        }
+        Some(_) => Err(bad()),
+        None if !spec.is_empty() => Ok((String::from(spec), 0, usize::MAX)),
+        None => Err(bad()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_contains() {
+        let mut targets = Targets::default();
+        targets.add(String::from("chr1"), 1000, 2000, 100);
+        targets.add(String::from("chr1"), 1950, 2500, 100);
+        targets.add(String::from("chr1"), 5000, 6000, 100);
+        targets.add(String::from("chr2"), 50, 60, 100);
+        targets.finish();
+        assert_eq!(targets.regions["chr1"], vec![(900, 2600), (4900, 6100)]);
+
+        assert!(!targets.contains("chr1", 900));
+        assert!(targets.contains("chr1", 901));
+        assert!(targets.contains("chr1", 2600));
+        assert!(!targets.contains("chr1", 2601));
+        assert!(targets.contains("chr1", 5500));
+        assert!(!targets.contains("chr1", 7000));
+        assert!(targets.contains("chr2", 1));
+        assert!(!targets.contains("chr3", 1000));
+
+        assert!(targets.touches([("chr3", 1000), ("chr2", 100)]));
+        assert!(!targets.touches([("chr1", 3000), ("chr1", 4000)]));
+    }
+
+    #[test]
+    fn test_regions() {
+        let regions = Targets::regions(&[
+            String::from("chr1:1001-2000"),
+            String::from("chr2"),
+            String::from("HLA-A*01:01"),
+        ])
+        .unwrap();
+        assert_eq!(regions.regions["chr1"], vec![(1000, 2000)]);
+
+        // A deletion spanning the whole region overlaps it.
+        assert!(regions.overlaps("chr1", 500, 3000));
+        assert!(regions.overlaps("chr1", 2000, 2500));
+        assert!(!regions.overlaps("chr1", 2001, 2500));
+        assert!(regions.overlaps("chr1", 900, 1001));
+        assert!(!regions.overlaps("chr1", 900, 1000));
+        assert!(regions.overlaps("chr1", 1500, 1500));
+        assert!(regions.overlaps("chr2", 4_000_000, 4_000_100));
+        assert!(regions.overlaps("HLA-A*01:01", 1, 10));
+        assert!(!regions.overlaps("chr3", 1, 10));
+
+        assert!(parse_region_string("chr1:2000-1000").is_err());
+        assert!(parse_region_string("chr1:0-1000").is_err());
+        assert!(parse_region_string(":1-10").is_err());
+        assert!(parse_region_string("").is_err());
+    }
+}
@@ -0,0 +1,80 @@
+use std::sync::Arc;
+
+use datafusion::prelude::{SessionConfig, SessionContext};
+use rayon::{ThreadPool, ThreadPoolBuilder};
+
+/// The threads for CPU-bound work done outside DataFusion (classifying
+/// insertions, counting the k-mers of features, and ranking queries), sized
+/// by `--threads`, as DataFusion's partitions are.
+///
+/// It is kept in the session's config (see `make_session_context`), so that
+/// anything with the session context can find it with `WorkerPool::of`.
+#[derive(Debug)]
+pub struct WorkerPool {
+    pool: ThreadPool,
+}
+
+impl WorkerPool {
+    pub fn new(threads: usize) -> std::io::Result<WorkerPool> {
+        let pool = ThreadPoolBuilder::new()
+            .num_threads(threads)
+            .thread_name(|n| format!("svelt-worker-{}", n))
+            .build()
+            .map_err(std::io::Error::other)?;
+        Ok(WorkerPool { pool })
+    }
+
+    /// Add a pool of `threads` threads to a session config.
+    pub fn configure(cfg: SessionConfig, threads: usize) -> std::io::Result<SessionConfig> {
+        Ok(cfg.with_extension(Arc::new(WorkerPool::new(threads)?)))
+    }
+
+    /// The pool of a session, or for a session made otherwise than with
+    /// `make_session_context`, a new pool with a thread for each of its
+    /// partitions.
+    pub fn of(ctx: &SessionContext) -> std::io::Result<Arc<WorkerPool>> {
+        let state = ctx.state();
+        match state.config().get_extension::<WorkerPool>() {
+            Some(pool) => Ok(pool),
+            None => Ok(Arc::new(WorkerPool::new(
+                state.config().target_partitions(),
+            )?)),
+        }
+    }
+
+    pub fn threads(&self) -> usize {
+        self.pool.current_num_threads()
+    }
+
+    /// Run `op` in the pool, so that rayon's parallel iterators within it
+    /// use the pool's threads.
+    pub fn install<R: Send, F: FnOnce() -> R + Send>(&self, op: F) -> R {
+        self.pool.install(op)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use rayon::prelude::*;
+
+    #[test]
+    fn test_worker_pool() {
+        let cfg = WorkerPool::configure(SessionConfig::new(), 3).unwrap();
+        let ctx = SessionContext::new_with_config(cfg);
+        let pool = WorkerPool::of(&ctx).unwrap();
+        assert_eq!(pool.threads(), 3);
+        let threads = pool.install(|| {
+            (0..100)
+                .into_par_iter()
+                .map(|_| rayon::current_num_threads())
+                .max()
+        });
+        assert_eq!(threads, Some(3));
+
+        let cfg = SessionConfig::new().with_target_partitions(2);
+        let ctx = SessionContext::new_with_config(cfg);
+        assert_eq!(WorkerPool::of(&ctx).unwrap().threads(), 2);
+    }
+}
@@ -0,0 +1,291 @@
+use std::{
+    collections::HashMap,
+    io::{BufRead, BufReader, Write},
+    path::PathBuf,
+    rc::Rc,
+};
+
+use autocompress::{CompressionLevel, autodetect_create, autodetect_open};
+use noodles::vcf::{
+    self,
+    variant::record::info::field::{Value, value::Array},
+};
+
+use crate::{
+    errors::{Context, FileContext, SveltError, as_io_error},
+    merge::merge_vcfs,
+    options::{CommonOptions, KindWindows, MergeOptions},
+};
+
+/// A pair of record IDs, labelled with whether they should be merged.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TruthPair {
+    pub lhs: String,
+    pub rhs: String,
+    pub should_merge: bool,
+}
+
+/// Read a truth set: one pair per line, giving two IDs and 1 (should merge)
+/// or 0 (shouldn't merge), separated by whitespace. Blank lines and lines
+/// starting with '#' are ignored.
+pub fn load_truth(path: &str) -> std::io::Result<Vec<TruthPair>> {
+    FileContext::new(path).with(|| {
+        let reader = BufReader::new(autodetect_open(path)?);
+        let mut res = Vec::new();
+        for line in reader.lines() {
+            let line = line?;
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            res.push(parse_truth_pair(line)?);
+        }
+        Ok(res)
+    })
+}
+
+fn parse_truth_pair(line: &str) -> std::io::Result<TruthPair> {
+    let bad = || as_io_error(SveltError::BadTruthPair(String::from(line)));
+    let fields: Vec<&str> = line.split_whitespace().collect();
+    if fields.len() != 3 {
+        return Err(bad());
+    }
+    let should_merge = match fields[2] {
+        "1" => true,
+        "0" => false,
+        _ => return Err(bad()),
+    };
+    Ok(TruthPair {
+        lhs: String::from(fields[0]),
+        rhs: String::from(fields[1]),
+        should_merge,
+    })
+}
+
+/// Counts of the truth pairs by outcome.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Confusion {
+    pub true_pos: usize,
+    pub false_pos: usize,
+    pub false_neg: usize,
+    pub true_neg: usize,
+    /// Pairs for which an ID wasn't found (exactly once) in the output.
+    pub unmatched: usize,
+}
+
+impl Confusion {
+    /// Score the truth pairs against the groups of original IDs in a merge.
+    pub fn evaluate(groups: &HashMap<String, Option<usize>>, truth: &[TruthPair]) -> Confusion {
+        let mut res = Confusion::default();
+        for pair in truth.iter() {
+            let (Some(Some(lhs)), Some(Some(rhs))) = (groups.get(&pair.lhs), groups.get(&pair.rhs))
+            else {
+                res.unmatched += 1;
+                continue;
+            };
+            match (lhs == rhs, pair.should_merge) {
+                (true, true) => res.true_pos += 1,
+                (true, false) => res.false_pos += 1,
+                (false, true) => res.false_neg += 1,
+                (false, false) => res.true_neg += 1,
+            }
+        }
+        res
+    }
+
+    pub fn precision(&self) -> Option<f64> {
+        ratio(self.true_pos, self.true_pos + self.false_pos)
+    }
+
+    pub fn recall(&self) -> Option<f64> {
+        ratio(self.true_pos, self.true_pos + self.false_neg)
+    }
+
+    pub fn f1(&self) -> Option<f64> {
+        ratio(
+            2 * self.true_pos,
+            2 * self.true_pos + self.false_pos + self.false_neg,
+        )
+    }
+}
+
+fn ratio(num: usize, den: usize) -> Option<f64> {
+    if den > 0 {
+        Some(num as f64 / den as f64)
+    } else {
+        None
+    }
+}
+
+/// Merge the VCFs with each combination of position window and length
+/// ratio, and report the precision and recall of the merging against the
+/// truth set.
+///
+/// Each combination is a complete run of `merge_vcfs` (to a temporary file),
+/// so the evaluation reflects exactly what `svelt merge` would do.
+pub async fn tune(
+    out: Option<&str>,
+    vcf: &Vec<String>,
+    truth: &str,
+    position_windows: &[u32],
+    length_ratios: &[f64],
+    options: &MergeOptions,
+    common: &CommonOptions,
+) -> std::io::Result<()> {
+    let truth = load_truth(truth)?;
+    log::info!("loaded {} truth pairs", truth.len());
+
+    let writer: Box<dyn Write> = match out {
+        Some(out) => Box::new(autodetect_create(out, CompressionLevel::Default)?),
+        None => Box::new(std::io::stdout()),
+    };
+    let mut writer = std::io::BufWriter::new(writer);
+    writeln!(
+        writer,
+        "position_window\tlength_ratio\ttrue_pos\tfalse_pos\tfalse_neg\ttrue_neg\tunmatched\tprecision\trecall\tf1"
+    )?;
+
+    let tmp_dir = match &common.temp_dir {
+        Some(dir) => PathBuf::from(dir),
+        None => std::env::temp_dir(),
+    };
+    let merged = tmp_dir.join(format!("svelt-tune-{}.vcf", std::process::id()));
+    let merged = merged.to_str().unwrap();
+
+    for position_window in position_windows.iter() {
+        for length_ratio in length_ratios.iter() {
+            log::info!(
+                "evaluating position window {}, length ratio {}",
+                position_window,
+                length_ratio
+            );
+            let mut options = options.clone();
+            options.position_window = KindWindows::new(*position_window);
+            options.length_ratio = *length_ratio;
+
+            // Only the merged VCF is needed.
+            options.write_merge_table = None;
+            options.export_events = None;
+            options.qc_report = None;
+            options.agreement_matrix = None;
+            options.synthesize_bnd_mates = false;
+            options.tra_to_bnd = false;
+
+            merge_vcfs(merged, vcf, Rc::new(options), common).await?;
+            let groups = read_groups(merged)?;
+            std::fs::remove_file(merged)?;
+
+            let res = Confusion::evaluate(&groups, &truth);
+            if res.unmatched > 0 {
+                log::warn!(
+                    "{} truth pairs have IDs not found exactly once in the output",
+                    res.unmatched
+                );
+            }
+            writeln!(
+                writer,
+                "{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}",
+                position_window,
+                length_ratio,
+                res.true_pos,
+                res.false_pos,
+                res.false_neg,
+                res.true_neg,
+                res.unmatched,
+                format_score(res.precision()),
+                format_score(res.recall()),
+                format_score(res.f1())
+            )?;
+        }
+    }
+    writer.flush()
+}
+
+fn format_score(score: Option<f64>) -> String {
+    match score {
+        Some(x) => format!("{:.4}", x),
+        None => String::from("."),
+    }
+}
+
+/// Map each original ID in a merged VCF to the number of the record it
+/// ended up in, or `None` if it turns up in more than one.
+pub(crate) fn read_groups(path: &str) -> std::io::Result<HashMap<String, Option<usize>>> {
+    FileContext::new(path).with(|| {
+        let mut reader = vcf::io::reader::Builder::default().build_from_path(path)?;
+        let header = reader.read_header()?;
+        let mut groups: HashMap<String, Option<usize>> = HashMap::new();
+        for (n, rec) in reader.records().enumerate() {
+            let rec = rec?;
+            let info = rec.info();
+            let Some(value) = info.get(&header, "ORIGINAL_IDS").transpose()? else {
+                continue;
+            };
+            let ids: Vec<String> = match value {
+                Some(Value::Array(Array::String(ids))) => ids
+                    .iter()
+                    .filter_map(|id| id.transpose())
+                    .map(|id| id.map(String::from))
+                    .collect::<std::io::Result<Vec<String>>>()?,
+                Some(Value::String(id)) => vec![String::from(id)],
+                _ => Vec::new(),
+            };
+            for id in ids {
+                groups
+                    .entry(id)
+                    .and_modify(|group| *group = None)
+                    .or_insert(Some(n));
+            }
+        }
+        Ok(groups)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_evaluate() {
+        let truth = vec![
+            parse_truth_pair("a1 b1 1").unwrap(),
+            parse_truth_pair("a2 b2 1").unwrap(),
+            parse_truth_pair("a3 b3 0").unwrap(),
+            parse_truth_pair("a4 b4 0").unwrap(),
+            parse_truth_pair("a5 b5 1").unwrap(),
+        ];
+        assert!(parse_truth_pair("a1 b1 yes").is_err());
+
+        let groups: HashMap<String, Option<usize>> = vec![
+            ("a1", Some(0)),
+            ("b1", Some(0)),
+            ("a2", Some(1)),
+            ("b2", Some(2)),
+            ("a3", Some(3)),
+            ("b3", Some(3)),
+            ("a4", Some(4)),
+            ("b4", Some(5)),
+            ("a5", None),
+            ("b5", Some(6)),
+        ]
+        .into_iter()
+        .map(|(id, group)| (String::from(id), group))
+        .collect();
+
+        let res = Confusion::evaluate(&groups, &truth);
+        assert_eq!(
+            res,
+            Confusion {
+                true_pos: 1,
+                false_pos: 1,
+                false_neg: 1,
+                true_neg: 1,
+                unmatched: 1,
+            }
+        );
+        assert_eq!(res.precision(), Some(0.5));
+        assert_eq!(res.recall(), Some(0.5));
+        assert_eq!(res.f1(), Some(0.5));
+        assert_eq!(Confusion::default().precision(), None);
+    }
+}
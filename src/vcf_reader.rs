@@ -1,6 +1,21 @@
-use std::{io::BufRead, rc::Rc};
+use std::{
+    collections::BTreeSet,
+    io::BufRead,
+    rc::Rc,
+    sync::Mutex,
+};
 
-use noodles::vcf::{self, variant::record::info::field::{value::Array, Value}, Header, Record};
+use noodles::vcf::{
+    self, Header, Record,
+    header::Contigs,
+    variant::record::{
+        info::field::{Value, value::Array},
+        samples::{
+            Series as _,
+            series::{Value as SampleValue, value::Array as SampleArray},
+        },
+    },
+};
 
 use crate::{
     chroms::ChromSet,
@@ -35,6 +50,41 @@ impl VcfReader {
         })
     }
 
+    /// Construct a `VcfReader` over record lines held in memory (e.g. once
+    /// sorted), which are taken to come from `path`, with the given header.
+    pub fn from_lines(
+        path: &str,
+        header: Header,
+        lines: &[Vec<u8>],
+        chroms: Rc<ChromSet>,
+    ) -> std::io::Result<VcfReader> {
+        let reader: Box<dyn BufRead> = Box::new(std::io::Cursor::new(lines.concat()));
+        let reader = vcf::io::reader::Builder::default().build_from_reader(reader)?;
+        Ok(VcfReader {
+            path: String::from(path),
+            reader,
+            header,
+            chroms,
+        })
+    }
+
+    /// Read the rest of the record lines.
+    pub fn read_lines(&mut self) -> std::io::Result<Vec<Vec<u8>>> {
+        FileContext::new(&self.path).with(|| {
+            let mut lines = Vec::new();
+            loop {
+                let mut line = Vec::new();
+                if self.reader.get_mut().read_until(b'\n', &mut line)? == 0 {
+                    return Ok(lines);
+                }
+                if !line.ends_with(b"\n") {
+                    line.push(b'\n');
+                }
+                lines.push(line);
+            }
+        })
+    }
+
     /// Rewind the `VcfReader` to the start. (Actually implemented by reopening it.)
     pub fn rewind(&mut self) -> std::io::Result<()> {
         FileContext::new(&self.path).with(|| {
@@ -48,6 +98,9 @@ impl VcfReader {
     }
 
     /// Pull out an INFO field that we expect to be of String type.
+    ///
+    /// Single element arrays are taken as their element, and Integers and
+    /// Characters are converted, with a warning.
     pub fn info_as_str(
         rec: &Record,
         header: &Header,
@@ -56,16 +109,27 @@ impl VcfReader {
         if let Some(field) = rec.info().get(header, name) {
             let field = field?;
             match field {
-                Some(value) => {
-                    if let Value::String(value) = value {
-                        Ok(Some(String::from(value)))
-                    } else {
-                        Err(as_io_error(SveltError::BadInfoType(
-                            String::from(name),
-                            String::from("String"),
-                        )))
-                    }
+                Some(Value::String(value)) => Ok(Some(String::from(value))),
+                Some(Value::Array(Array::String(array))) if array.len() == 1 => {
+                    Ok(array.iter().next().unwrap()?.map(String::from))
+                }
+                // Some callers declare CHR2 as an Integer (or a Character).
+                Some(Value::Integer(value)) => {
+                    warn_coerced(name, "Integer", "String");
+                    Ok(Some(value.to_string()))
+                }
+                Some(Value::Array(Array::Integer(array))) if array.len() == 1 => {
+                    warn_coerced(name, "Integer", "String");
+                    Ok(array.iter().next().unwrap()?.map(|value| value.to_string()))
                 }
+                Some(Value::Character(value)) => {
+                    warn_coerced(name, "Character", "String");
+                    Ok(Some(value.to_string()))
+                }
+                Some(_) => Err(as_io_error(SveltError::BadInfoType(
+                    String::from(name),
+                    String::from("String"),
+                ))),
                 None => Ok(None),
             }
         } else {
@@ -74,41 +138,133 @@ impl VcfReader {
     }
 
     /// Pull out an INFO field that we expect to be of Integer type.
+    ///
+    /// Single element arrays are taken as their element, and Strings holding
+    /// an integer (as some callers declare END2) are converted, with a warning.
     pub fn info_as_int(rec: &Record, header: &Header, name: &str) -> std::io::Result<Option<i32>> {
         if let Some(field) = rec.info().get(header, name) {
             let field = field?;
             match field {
-                Some(value) => {
-                    if let Value::Integer(value) = value {
-                        Ok(Some(value))
-                    } 
-                    else if let Value::Array(Array::Integer(array)) = value {
-                        if array.len() == 1 {
-                            let value = array.iter().next().unwrap()?;
-                            Ok(value)
-                        } else {
-                            Err(as_io_error(SveltError::BadInfoType(
-                                String::from(name),
-                                String::from("Integer"),
-                            )))
-                        }
-                    } else {
-                        Err(as_io_error(SveltError::BadInfoType(
-                            String::from(name),
-                            String::from("Integer"),
-                        )))
+                Some(Value::Integer(value)) => Ok(Some(value)),
+                Some(Value::Array(Array::Integer(array))) if array.len() == 1 => {
+                    array.iter().next().unwrap()
+                }
+                Some(Value::String(value)) => parse_int(name, &value).map(Some),
+                Some(Value::Array(Array::String(array))) if array.len() == 1 => {
+                    match array.iter().next().unwrap()? {
+                        Some(value) => parse_int(name, &value).map(Some),
+                        None => Ok(None),
                     }
                 }
+                Some(_) => Err(as_io_error(SveltError::BadInfoType(
+                    String::from(name),
+                    String::from("Integer"),
+                ))),
                 None => Ok(None),
             }
         } else {
             Ok(None)
         }
     }
+
+    /// Pull out an INFO field that we expect to be of Float type.
+    pub fn info_as_float(
+        rec: &Record,
+        header: &Header,
+        name: &str,
+    ) -> std::io::Result<Option<f32>> {
+        if let Some(field) = rec.info().get(header, name) {
+            let field = field?;
+            match field {
+                Some(Value::Float(value)) => Ok(Some(value)),
+                Some(Value::Array(Array::Float(array))) if array.len() == 1 => {
+                    array.iter().next().unwrap()
+                }
+                Some(_) => Err(as_io_error(SveltError::BadInfoType(
+                    String::from(name),
+                    String::from("Float"),
+                ))),
+                None => Ok(None),
+            }
+        } else {
+            Ok(None)
+        }
+    }
+
+    /// Pull out a FORMAT field that we expect to be of Float type, from the
+    /// first sample that has a value for it.
+    pub fn format_as_float(
+        rec: &Record,
+        header: &Header,
+        name: &str,
+    ) -> std::io::Result<Option<f32>> {
+        let samples = rec.samples();
+        if let Some(series) = samples.select(name) {
+            for value in series.iter(header) {
+                match value? {
+                    Some(SampleValue::Float(value)) => return Ok(Some(value)),
+                    Some(SampleValue::Array(SampleArray::Float(array))) if array.len() == 1 => {
+                        if let Some(value) = array.iter().next().unwrap()? {
+                            return Ok(Some(value));
+                        }
+                    }
+                    Some(_) => {
+                        return Err(as_io_error(SveltError::BadFormatType(
+                            String::from(name),
+                            String::from("Float"),
+                        )));
+                    }
+                    None => {}
+                }
+            }
+        }
+        Ok(None)
+    }
+}
+
+/// Convert an INFO value given as a String to the Integer expected.
+fn parse_int(name: &str, value: &str) -> std::io::Result<i32> {
+    match value.trim().parse() {
+        Ok(value) => {
+            warn_coerced(name, "String", "Integer");
+            Ok(value)
+        }
+        Err(_) => Err(as_io_error(SveltError::BadInfoType(
+            String::from(name),
+            String::from("Integer"),
+        ))),
+    }
+}
+
+/// The INFO fields (and the types found) that have been converted to the
+/// type expected, so each is only warned about once.
+static COERCED: Mutex<BTreeSet<(String, &'static str)>> = Mutex::new(BTreeSet::new());
+
+fn warn_coerced(name: &str, found: &'static str, expected: &str) {
+    let mut coerced = COERCED.lock().unwrap();
+    if coerced.insert((String::from(name), found)) {
+        log::warn!(
+            "converting INFO field {} from {} to {} (further conversions are not reported)",
+            name,
+            found,
+            expected
+        );
+    }
 }
 
 /// Check that the set of chromosomes in the header are what we expect.
+///
+/// If `chroms` accepts partial headers (see `reconcile_contigs`), the header
+/// need only declare some of them, in any order.
 pub fn check_chroms(header: &Header, chroms: &ChromSet) -> std::result::Result<(), SveltError> {
+    if chroms.accepts_partial_headers() {
+        for (ix0, name) in header.contigs().keys().enumerate() {
+            if chroms.index(name).is_none() {
+                return Err(SveltError::ContigMissing(name.clone(), ix0));
+            }
+        }
+        return Ok(());
+    }
     let n = chroms.len();
     let n0 = header.contigs().len();
     if n != n0 {
@@ -126,3 +282,27 @@ pub fn check_chroms(header: &Header, chroms: &ChromSet) -> std::result::Result<(
     }
     Ok(())
 }
+
+/// The contigs declared in any of the `headers` (for `--reconcile-contigs`):
+/// those of the first, followed by any others in the order they are first
+/// seen. A contig declared with different lengths is an error, but one
+/// without a length takes the length declared elsewhere.
+pub fn reconcile_contigs(headers: &[&Header]) -> std::result::Result<Contigs, SveltError> {
+    let mut contigs = Contigs::default();
+    for header in headers.iter() {
+        for (name, contig) in header.contigs().iter() {
+            let Some(existing) = contigs.get_mut(name) else {
+                contigs.insert(name.clone(), contig.clone());
+                continue;
+            };
+            match (existing.length(), contig.length()) {
+                (Some(exp), Some(got)) if exp != got => {
+                    return Err(SveltError::ContigLength(name.clone(), exp, got));
+                }
+                (None, Some(got)) => *existing.length_mut() = Some(got),
+                _ => {}
+            }
+        }
+    }
+    Ok(contigs)
+}
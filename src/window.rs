@@ -0,0 +1,99 @@
+/// A window of positions (e.g. `--position-window`) within which records may
+/// be paired.
+///
+/// Windows are given as `u32` and positions as `i32`, so the distances are
+/// worked out in `i64`, where neither a position near the end of the range
+/// nor a very large window can overflow.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Window(i64);
+
+impl Window {
+    pub fn new(width: u32) -> Window {
+        Window(width as i64)
+    }
+
+    pub fn width(&self) -> u32 {
+        self.0 as u32
+    }
+
+    /// The distance between two positions.
+    pub fn distance(lhs: i32, rhs: i32) -> i64 {
+        (lhs as i64 - rhs as i64).abs()
+    }
+
+    /// Whether `lhs` and `rhs` are no further apart than the window.
+    pub fn contains(&self, lhs: i32, rhs: i32) -> bool {
+        Self::distance(lhs, rhs) <= self.0
+    }
+
+    /// Whether `pos` lies beyond the window following `start`, so that a sweep
+    /// reaching `pos` is done with `start`.
+    pub fn passed(&self, start: i32, pos: i32) -> bool {
+        start as i64 + self.0 < pos as i64
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{Rng, SeedableRng, rngs::StdRng};
+
+    use super::*;
+
+    /// Positions and widths at and near the ends of their ranges, as well as
+    /// ordinary ones.
+    fn extreme_values(rng: &mut StdRng) -> (i32, i32, u32) {
+        let pos = |rng: &mut StdRng| match rng.random_range(0..4) {
+            0 => rng.random_range(i32::MIN..i32::MIN + 100),
+            1 => rng.random_range(-100..100),
+            2 => rng.random_range(i32::MAX - 100..=i32::MAX),
+            _ => rng.random(),
+        };
+        let width = match rng.random_range(0..3) {
+            0 => rng.random_range(0..100),
+            1 => rng.random_range(u32::MAX - 100..=u32::MAX),
+            _ => rng.random(),
+        };
+        (pos(rng), pos(rng), width)
+    }
+
+    #[test]
+    fn test_window_arithmetic() {
+        let mut rng = StdRng::seed_from_u64(1008);
+        for _ in 0..100000 {
+            let (lhs, rhs, width) = extreme_values(&mut rng);
+            let w = Window::new(width);
+            let exact = (lhs as i128 - rhs as i128).abs();
+
+            assert_eq!(w.width(), width);
+            assert_eq!(Window::distance(lhs, rhs) as i128, exact);
+            assert_eq!(Window::distance(lhs, rhs), Window::distance(rhs, lhs));
+            assert_eq!(w.contains(lhs, rhs), exact <= width as i128);
+            assert_eq!(
+                w.passed(lhs, rhs),
+                lhs as i128 + (width as i128) < rhs as i128
+            );
+            assert_eq!(
+                w.contains(lhs, rhs),
+                !w.passed(lhs, rhs) && !w.passed(rhs, lhs)
+            );
+        }
+    }
+
+    #[test]
+    fn test_window_bounds() {
+        let w = Window::new(u32::MAX);
+        assert!(w.contains(0, i32::MAX));
+        assert!(w.contains(i32::MIN, i32::MAX));
+        assert!(!w.passed(i32::MIN, i32::MAX));
+        assert!(!w.passed(i32::MAX, i32::MIN));
+
+        let w = Window::new(u32::MAX - 1);
+        assert!(!w.contains(i32::MIN, i32::MAX));
+        assert!(w.passed(i32::MIN, i32::MAX));
+
+        let w = Window::new(0);
+        assert!(w.contains(i32::MAX, i32::MAX));
+        assert!(!w.contains(i32::MAX - 1, i32::MAX));
+        assert!(w.passed(i32::MAX - 1, i32::MAX));
+    }
+}
@@ -0,0 +1,186 @@
+//! End-to-end regression test on a public structural variant dataset.
+//!
+//! This is only built with `cargo test --features big-tests`, since the
+//! inputs listed in `tests/data/big/manifest.tsv` are downloaded (with `curl`)
+//! the first time it runs, and checked against the SHA-256 checksums there
+//! (with `sha256sum`), so that a changed upstream file fails as such rather
+//! than as a change in behaviour. Downloads are kept in `target/big-tests`,
+//! or in `SVELT_BIG_TESTS_CACHE` if it is set. The inputs are merged with the
+//! options in `tests/data/big/args`, and a summary of the output (the numbers
+//! of records, of each SVTYPE, and merged by each criterion) is compared with
+//! `tests/data/big/expected.tsv`. As for the quirk tests, run with
+//! `SVELT_BLESS=1` to record the summary after an intentional change in
+//! behaviour, and review the differences.
+
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+    process::Command,
+    rc::Rc,
+};
+
+use clap::Parser;
+use svelt::{
+    merge::merge_vcfs,
+    options::{CommonOptions, MergeOptions},
+};
+
+#[derive(Debug, Parser)]
+struct TestCli {
+    #[command(flatten)]
+    options: MergeOptions,
+
+    #[command(flatten)]
+    common: CommonOptions,
+}
+
+fn big_dir() -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/data/big")
+}
+
+fn cache_dir() -> PathBuf {
+    match std::env::var_os("SVELT_BIG_TESTS_CACHE") {
+        Some(dir) => PathBuf::from(dir),
+        None => PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("target/big-tests"),
+    }
+}
+
+/// The SHA-256 checksum of a file.
+fn sha256(path: &Path) -> String {
+    let output = Command::new("sha256sum")
+        .arg(path)
+        .output()
+        .expect("couldn't run sha256sum");
+    assert!(
+        output.status.success(),
+        "couldn't checksum {}",
+        path.display()
+    );
+    let output = String::from_utf8(output.stdout).unwrap();
+    String::from(output.split_whitespace().next().unwrap())
+}
+
+/// Fetch the inputs in the manifest that aren't already cached, checking
+/// their checksums, and return the paths of all of them.
+fn fetch_inputs() -> Vec<String> {
+    let manifest = std::fs::read_to_string(big_dir().join("manifest.tsv")).unwrap();
+    let cache = cache_dir();
+    std::fs::create_dir_all(&cache).unwrap();
+
+    let mut paths = Vec::new();
+    for line in manifest.lines() {
+        if line.starts_with('#') || line.trim().is_empty() {
+            continue;
+        }
+        let fields: Vec<&str> = line.split('\t').collect();
+        let [name, checksum, url] = fields[..] else {
+            panic!("manifest lines are NAME<TAB>SHA256<TAB>URL: {}", line);
+        };
+        let path = cache.join(name);
+        if !path.exists() {
+            let partial = cache.join(format!("{}.part", name));
+            let status = Command::new("curl")
+                .args([
+                    "--fail",
+                    "--location",
+                    "--silent",
+                    "--show-error",
+                    "--output",
+                ])
+                .arg(&partial)
+                .arg(url)
+                .status()
+                .expect("couldn't run curl");
+            assert!(status.success(), "couldn't download {}", url);
+            let actual = sha256(&partial);
+            if checksum == "-" {
+                panic!(
+                    "the manifest has no checksum for {}: its SHA-256 is {} (check it, and record it)",
+                    name, actual
+                );
+            }
+            if actual != checksum {
+                std::fs::remove_file(&partial).unwrap();
+                panic!(
+                    "{} has changed upstream: its SHA-256 is {}, where the manifest has {}",
+                    url, actual, checksum
+                );
+            }
+            std::fs::rename(&partial, &path).unwrap();
+        }
+        paths.push(String::from(path.to_str().unwrap()));
+    }
+    paths
+}
+
+/// Summarise a merged VCF: the numbers of records, of each SVTYPE, and of
+/// records merged by each criterion.
+fn summarise(vcf: &str) -> BTreeMap<String, usize> {
+    let mut summary = BTreeMap::new();
+    for line in vcf.lines().filter(|line| !line.starts_with('#')) {
+        *summary.entry(String::from("records")).or_default() += 1;
+        let info = line.split('\t').nth(7).unwrap();
+        for item in info.split(';') {
+            if let Some(svtype) = item.strip_prefix("SVTYPE=") {
+                *summary.entry(format!("svtype.{}", svtype)).or_default() += 1;
+            }
+            if let Some(criteria) = item.strip_prefix("SVELT_CRITERIA=") {
+                *summary.entry(String::from("merged")).or_default() += 1;
+                for criterion in criteria.split(',') {
+                    *summary
+                        .entry(format!("criterion.{}", criterion))
+                        .or_default() += 1;
+                }
+            }
+        }
+    }
+    summary
+}
+
+#[tokio::test]
+async fn big_merge() {
+    let vcfs = fetch_inputs();
+    assert!(
+        vcfs.len() >= 2,
+        "the manifest should list at least two inputs, so that merging across inputs is tested"
+    );
+
+    let mut args = vec![String::from("svelt")];
+    if let Ok(extra) = std::fs::read_to_string(big_dir().join("args")) {
+        args.extend(extra.split_whitespace().map(String::from));
+    }
+    let cli = TestCli::parse_from(args);
+
+    let out = cache_dir().join(format!("merged-{}.vcf", std::process::id()));
+    merge_vcfs(
+        out.to_str().unwrap(),
+        &vcfs,
+        Rc::new(cli.options),
+        &cli.common,
+    )
+    .await
+    .unwrap();
+    let merged = std::fs::read_to_string(&out).unwrap();
+    std::fs::remove_file(&out).unwrap();
+
+    let actual: String = summarise(&merged)
+        .iter()
+        .map(|(key, count)| format!("{}\t{}\n", key, count))
+        .collect();
+
+    let expected_path = big_dir().join("expected.tsv");
+    if std::env::var_os("SVELT_BLESS").is_some() {
+        std::fs::write(&expected_path, &actual).unwrap();
+        return;
+    }
+    let expected = std::fs::read_to_string(&expected_path).unwrap_or_else(|_| {
+        panic!(
+            "no expectations in {} (run with SVELT_BLESS=1 to record them)",
+            expected_path.display()
+        )
+    });
+    assert_eq!(
+        actual, expected,
+        "summary of the merged dataset has changed"
+    );
+}
@@ -0,0 +1,1642 @@
+//! Golden output tests for a corpus of caller quirks.
+//!
+//! Each directory under `tests/data/quirks` holds a pair of input VCFs
+//! (`a.vcf` and `b.vcf`) exhibiting a quirk seen in the wild, along with the
+//! merged output we expect (`expected.vcf`). An optional `args` file gives
+//! extra merge options for the case. To regenerate the expected outputs
+//! after an intentional change in behaviour, run the tests with `SVELT_BLESS=1`
+//! and review the differences.
+
+use std::{cell::RefCell, path::PathBuf, rc::Rc};
+
+use clap::Parser;
+use datafusion::{
+    arrow::datatypes::DataType,
+    prelude::{DataFrame, SessionContext, lit},
+};
+use noodles::vcf::{
+    self, Header, Record,
+    header::record::value::map::info::{Number, Type},
+    variant::{Record as _, RecordBuf, io::Write as _, record::Ids as _},
+};
+use svelt::{
+    annotate::annotate_vcf,
+    bundle::{BUNDLE_CONFIG, BUNDLE_MERGE_TABLE, BUNDLE_SUMMARY},
+    chroms::ChromSet,
+    determinism,
+    errors::{FailureClass, SveltError},
+    features::FeatureIndex,
+    merge::{MergeInput, Merger, merge_inputs, merge_inputs_with_observer, merge_vcfs},
+    observer::MergeObserver,
+    options::{
+        AgreementMatrixFormat, CommonOptions, DuplicateNames, IndexFormat, IndexingOptions,
+        MergeOptions,
+    },
+    output_format::{STANDARD_INFO_FIELDS, SVELT_INFO_FIELDS},
+    record_seeker::MemoryRecordStore,
+    report::read_merge_table,
+    tables::load_vcf_core,
+    vcf_reader::VcfReader,
+};
+
+#[derive(Debug, Parser)]
+struct TestCli {
+    #[command(flatten)]
+    options: MergeOptions,
+
+    #[command(flatten)]
+    common: CommonOptions,
+}
+
+fn quirks_dir() -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/data/quirks")
+}
+
+fn parse_cli(name: &str) -> TestCli {
+    let dir = quirks_dir();
+    let case = dir.join(name);
+    let reference = dir.join("ref.fa");
+    let mut args = vec![
+        String::from("svelt"),
+        String::from("--reference"),
+        String::from(reference.to_str().unwrap()),
+    ];
+    if let Ok(extra) = std::fs::read_to_string(case.join("args")) {
+        args.extend(extra.split_whitespace().map(String::from));
+    }
+    TestCli::parse_from(args)
+}
+
+async fn check_quirk(name: &str) {
+    let case = quirks_dir().join(name);
+    let cli = parse_cli(name);
+
+    let out = std::env::temp_dir().join(format!("svelt-quirk-{}-{}.vcf", name, std::process::id()));
+    let vcfs = vec![
+        String::from(case.join("a.vcf").to_str().unwrap()),
+        String::from(case.join("b.vcf").to_str().unwrap()),
+    ];
+    merge_vcfs(
+        out.to_str().unwrap(),
+        &vcfs,
+        Rc::new(cli.options),
+        &cli.common,
+    )
+    .await
+    .unwrap();
+
+    let actual = std::fs::read_to_string(&out).unwrap();
+    std::fs::remove_file(&out).unwrap();
+
+    let expected_path = case.join("expected.vcf");
+    if std::env::var_os("SVELT_BLESS").is_some() {
+        std::fs::write(&expected_path, &actual).unwrap();
+        return;
+    }
+    let expected = std::fs::read_to_string(&expected_path).unwrap();
+    assert_eq!(actual, expected, "merged output for '{}' has changed", name);
+}
+
+macro_rules! quirk {
+    ($name:ident) => {
+        #[tokio::test]
+        async fn $name() {
+            check_quirk(stringify!($name)).await;
+        }
+    };
+}
+
+/// Check a VCF against its header, as a validator would: svelt's fields are
+/// declared as svelt defines them, and each INFO field of each record is
+/// declared, with as many values, of the type, as the declaration says.
+fn check_conformance(vcf: &str) -> Vec<String> {
+    let header: String = vcf
+        .lines()
+        .take_while(|line| line.starts_with('#'))
+        .map(|line| format!("{}\n", line))
+        .collect();
+    let header: Header = header.parse().unwrap();
+    let mut problems = Vec::new();
+    for def in SVELT_INFO_FIELDS.iter() {
+        // The inputs' declarations of the standard fields are kept.
+        if STANDARD_INFO_FIELDS.contains(&def.id) {
+            continue;
+        }
+        if let Some(info) = header.infos().get(def.id)
+            && (info.number() != def.number || info.ty() != def.ty)
+        {
+            problems.push(format!("{} isn't declared as svelt defines it", def.id));
+        }
+    }
+    for line in vcf.lines().filter(|line| !line.starts_with('#')) {
+        let fields: Vec<&str> = line.split('\t').collect();
+        let (id, alts) = (fields[2], fields[4].split(',').count());
+        for item in fields[7].split(';').filter(|item| *item != ".") {
+            let (key, value) = match item.split_once('=') {
+                Some((key, value)) => (key, Some(value)),
+                None => (item, None),
+            };
+            let Some(info) = header.infos().get(key) else {
+                problems.push(format!("{}: {} isn't declared", id, key));
+                continue;
+            };
+            if info.ty() == Type::Flag {
+                if value.is_some() {
+                    problems.push(format!("{}: the flag {} has a value", id, key));
+                }
+                continue;
+            }
+            let values: Vec<&str> = value.map_or(Vec::new(), |value| value.split(',').collect());
+            let expected = match info.number() {
+                Number::Count(n) => Some(n),
+                Number::AlternateBases => Some(alts),
+                Number::ReferenceAlternateBases => Some(alts + 1),
+                _ => None,
+            };
+            if let Some(n) = expected
+                && values.len() != n
+            {
+                problems.push(format!(
+                    "{}: {} has {} values, not {}",
+                    id,
+                    key,
+                    values.len(),
+                    n
+                ));
+            }
+            let typed = values.iter().all(|value| {
+                *value == "."
+                    || match info.ty() {
+                        Type::Integer => value.parse::<i32>().is_ok(),
+                        Type::Float => value.parse::<f32>().is_ok(),
+                        Type::Character => value.chars().count() == 1,
+                        _ => true,
+                    }
+            });
+            if !typed {
+                problems.push(format!("{}: {} isn't of Type={}", id, item, info.ty()));
+            }
+        }
+    }
+    problems
+}
+
+/// Every expected output conforms to its header.
+#[test]
+fn header_conformance() {
+    for entry in std::fs::read_dir(quirks_dir()).unwrap() {
+        let expected = entry.unwrap().path().join("expected.vcf");
+        let Ok(vcf) = std::fs::read_to_string(&expected) else {
+            continue;
+        };
+        let problems = check_conformance(&vcf);
+        assert!(
+            problems.is_empty(),
+            "{}:\n{}",
+            expected.display(),
+            problems.join("\n")
+        );
+    }
+
+    let bad = "##fileformat=VCFv4.2\n\
+               ##INFO=<ID=CHR2,Number=1,Type=String,Description=\"Mate contig\">\n\
+               ##INFO=<ID=END2,Number=1,Type=Integer,Description=\"Mate position\">\n\
+               #CHROM\tPOS\tID\tREF\tALT\tQUAL\tFILTER\tINFO\n\
+               chr1\t100\tx\tA\t<TRA>\t.\t.\tCHR2=chr2,chr3;END2=x;SVLEN=5\n";
+    assert_eq!(
+        check_conformance(bad),
+        vec![
+            "x: CHR2 has 2 values, not 1",
+            "x: END2=x isn't of Type=Integer",
+            "x: SVLEN isn't declared",
+        ]
+    );
+}
+
+quirk!(balanced_translocation);
+quirk!(beyond_contig_end);
+quirk!(best_partner);
+quirk!(bnd_insertion_length);
+quirk!(consensus_samples);
+quirk!(dedup_within_inputs);
+quirk!(drop_uncalled);
+quirk!(depth_consistency);
+quirk!(duplicate_ids);
+quirk!(force_keep);
+quirk!(format_map);
+quirk!(header_only_input);
+quirk!(id_format);
+quirk!(id_format_original);
+quirk!(hom_ref_genotypes);
+quirk!(info_breakends);
+quirk!(info_type_variance);
+quirk!(inconsistent_end_svlen);
+quirk!(info_rule);
+quirk!(input_labels);
+quirk!(insertion_identity);
+quirk!(intra_input_merge);
+quirk!(kind_specific_criteria);
+quirk!(kind_windows);
+quirk!(legacy_tra);
+quirk!(malformed_records);
+quirk!(missing_chr2);
+quirk!(n_reference_bases);
+quirk!(reciprocal_overlap);
+quirk!(reconcile_annotations);
+quirk!(reconcile_contigs);
+quirk!(regions);
+quirk!(repeat_context);
+quirk!(soft_mate_contigs);
+quirk!(support_fields);
+quirk!(svelt_inputs);
+quirk!(symbolic_subtypes);
+quirk!(targets);
+quirk!(unknown_svtypes);
+quirk!(unpaired_bnd);
+quirk!(unsorted_input);
+quirk!(unwanted_fields);
+
+/// Merging pre-loaded inputs must give the same result as merging the files.
+#[tokio::test]
+async fn in_memory_inputs() {
+    let name = "duplicate_ids";
+    let case = quirks_dir().join(name);
+    let cli = parse_cli(name);
+
+    let header = vcf::io::reader::Builder::default()
+        .build_from_path(case.join("a.vcf"))
+        .unwrap()
+        .read_header()
+        .unwrap();
+    let names: Vec<&str> = header.contigs().keys().map(|name| name.as_str()).collect();
+    let chroms = Rc::new(ChromSet::from(names.as_ref()));
+
+    let mut inputs = Vec::new();
+    for vcf in ["a.vcf", "b.vcf"] {
+        let path = case.join(vcf);
+        let path = path.to_str().unwrap();
+        let mut reader = VcfReader::new(path, chroms.clone()).unwrap();
+        let records =
+            load_vcf_core(&mut reader, &cli.options, None, None, None, None, None).unwrap();
+        let recs: Vec<Record> = std::fs::read_to_string(path)
+            .unwrap()
+            .lines()
+            .filter(|line| !line.starts_with('#'))
+            .map(|line| Record::try_from(line.as_bytes()).unwrap())
+            .collect();
+        let header = reader.header.clone();
+        let store = MemoryRecordStore::new(Rc::new(header.clone()), recs);
+        inputs.push(MergeInput {
+            name: String::from(vcf),
+            header,
+            records,
+            store: Box::new(store),
+            rejected: Vec::new(),
+        });
+    }
+
+    let out = std::env::temp_dir().join(format!("svelt-in-memory-{}.vcf", std::process::id()));
+    merge_inputs(
+        out.to_str().unwrap(),
+        inputs,
+        Rc::new(cli.options),
+        &cli.common,
+    )
+    .await
+    .unwrap();
+
+    let actual = std::fs::read_to_string(&out).unwrap();
+    std::fs::remove_file(&out).unwrap();
+    let expected = std::fs::read_to_string(case.join("expected.vcf")).unwrap();
+    assert_eq!(actual, expected);
+}
+
+/// Records the criteria it sees, rejects the candidates of `near`, and
+/// leaves out records at position 2000.
+#[derive(Default)]
+struct TestObserver {
+    criteria: Vec<String>,
+    records: usize,
+}
+
+impl MergeObserver for TestObserver {
+    fn on_candidates(
+        &mut self,
+        criterion: &str,
+        candidates: DataFrame,
+    ) -> std::io::Result<DataFrame> {
+        self.criteria.push(String::from(criterion));
+        if criterion == "near" {
+            return Ok(candidates.filter(lit(false))?);
+        }
+        Ok(candidates)
+    }
+
+    fn on_record(&mut self, _header: &Header, record: &mut RecordBuf) -> std::io::Result<bool> {
+        self.records += 1;
+        Ok(record.variant_start().map(|p| p.get()) != Some(2000))
+    }
+}
+
+/// Observers see every criterion, and can veto merges and records.
+#[tokio::test]
+async fn merge_observer() {
+    let name = "duplicate_ids";
+    let case = quirks_dir().join(name);
+    let cli = parse_cli(name);
+
+    let header = vcf::io::reader::Builder::default()
+        .build_from_path(case.join("a.vcf"))
+        .unwrap()
+        .read_header()
+        .unwrap();
+    let names: Vec<&str> = header.contigs().keys().map(|name| name.as_str()).collect();
+    let chroms = Rc::new(ChromSet::from(names.as_ref()));
+    let inputs = ["a.vcf", "b.vcf"]
+        .iter()
+        .map(|vcf| {
+            MergeInput::from_path(
+                case.join(vcf).to_str().unwrap(),
+                chroms.clone(),
+                &cli.options,
+            )
+            .unwrap()
+        })
+        .collect();
+
+    let observer = Rc::new(RefCell::new(TestObserver::default()));
+    let out = std::env::temp_dir().join(format!("svelt-observer-{}.vcf", std::process::id()));
+    merge_inputs_with_observer(
+        out.to_str().unwrap(),
+        inputs,
+        Rc::new(cli.options),
+        &cli.common,
+        observer.clone(),
+    )
+    .await
+    .unwrap();
+
+    let actual = std::fs::read_to_string(&out).unwrap();
+    std::fs::remove_file(&out).unwrap();
+    let positions: Vec<&str> = actual
+        .lines()
+        .filter(|line| !line.starts_with('#'))
+        .map(|line| line.split('\t').nth(1).unwrap())
+        .collect();
+    assert_eq!(positions, vec!["1000", "1005"]);
+
+    let observer = observer.borrow();
+    assert_eq!(
+        observer.criteria,
+        vec!["exact", "exact", "locus", "exact", "here", "there", "near"]
+    );
+    assert_eq!(observer.records, 3);
+}
+
+/// The builder gives the same merge as the command line, and says what it
+/// wrote, counting the records after the observer has had its say.
+#[tokio::test]
+async fn merger_builder() {
+    let case = quirks_dir().join("duplicate_ids");
+    let dir = std::env::temp_dir().join(format!("svelt-merger-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let out = dir.join("merged.vcf");
+    let table = dir.join("merge-table.tsv");
+
+    let inputs = [case.join("a.vcf"), case.join("b.vcf")].map(|p| p.to_string_lossy().into_owned());
+
+    let outcome = Merger::builder()
+        .inputs(inputs.clone())
+        .output(out.to_str().unwrap())
+        .reference(quirks_dir().join("ref.fa").to_str().unwrap())
+        .position_window(25)
+        .write_merge_table(table.to_str().unwrap())
+        .run()
+        .await
+        .unwrap();
+
+    let actual = std::fs::read_to_string(&out).unwrap();
+    let expected = std::fs::read_to_string(case.join("expected.vcf")).unwrap();
+    assert_eq!(actual, expected);
+    assert_eq!(
+        outcome.paths,
+        vec![
+            String::from(out.to_str().unwrap()),
+            String::from(table.to_str().unwrap())
+        ]
+    );
+    assert!(table.exists());
+    let stats = &outcome.statistics;
+    let records: Vec<usize> = stats.inputs.iter().map(|input| input.records).collect();
+    assert_eq!(records, vec![2, 1]);
+    assert_eq!((stats.records, stats.merged), (2, 1));
+    assert_eq!(stats.criteria.get("near"), Some(&1));
+
+    let observer = Rc::new(RefCell::new(TestObserver::default()));
+    let outcome = Merger::builder()
+        .inputs(inputs)
+        .output(out.to_str().unwrap())
+        .reference(quirks_dir().join("ref.fa").to_str().unwrap())
+        .observer(observer.clone())
+        .run()
+        .await
+        .unwrap();
+    assert_eq!(observer.borrow().records, 3);
+    assert_eq!(
+        (outcome.statistics.records, outcome.statistics.merged),
+        (2, 0)
+    );
+
+    // Without an output.
+    assert!(
+        Merger::builder()
+            .inputs([case.join("a.vcf").to_str().unwrap()])
+            .run()
+            .await
+            .is_err()
+    );
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+/// The merge table's offsets are relative to the records actually emitted,
+/// which can differ from the table's coordinates (here, END disagrees with SVLEN).
+#[tokio::test]
+async fn merge_table_offsets() {
+    let name = "inconsistent_end_svlen";
+    let case = quirks_dir().join(name);
+    let mut cli = parse_cli(name);
+
+    let stem = format!("svelt-merge-table-{}", std::process::id());
+    let out = std::env::temp_dir().join(format!("{}.vcf", stem));
+    let table = std::env::temp_dir().join(format!("{}.tsv", stem));
+    cli.options.write_merge_table = Some(String::from(table.to_str().unwrap()));
+    let vcfs = vec![
+        String::from(case.join("a.vcf").to_str().unwrap()),
+        String::from(case.join("b.vcf").to_str().unwrap()),
+    ];
+    merge_vcfs(
+        out.to_str().unwrap(),
+        &vcfs,
+        Rc::new(cli.options),
+        &cli.common,
+    )
+    .await
+    .unwrap();
+
+    let vcf = std::fs::read_to_string(&out).unwrap();
+    let rows = read_merge_table(table.to_str().unwrap()).await.unwrap();
+    std::fs::remove_file(&out).unwrap();
+    std::fs::remove_file(&table).unwrap();
+
+    let emitted: Vec<(String, String, String)> = vcf
+        .lines()
+        .filter(|line| !line.starts_with('#'))
+        .map(|line| {
+            let fields: Vec<&str> = line.split('\t').collect();
+            let end = fields[7]
+                .split(';')
+                .find_map(|item| item.strip_prefix("END="))
+                .unwrap();
+            (String::from(fields[2]), String::from(fields[1]), String::from(end))
+        })
+        .collect();
+
+    let mut offsets = Vec::new();
+    for row in rows.iter() {
+        let emitted_row = (
+            row.emitted_id.clone().unwrap(),
+            row.emitted_start.unwrap().to_string(),
+            row.emitted_end.unwrap().to_string(),
+        );
+        assert!(
+            emitted.contains(&emitted_row),
+            "{:?} not in the output",
+            emitted_row
+        );
+        offsets.push(row.end_offset.unwrap());
+    }
+    offsets.sort();
+    // Against the primary table values, both of these would be 0.
+    assert_eq!(offsets, vec![100, 100]);
+}
+
+/// Groups left out by `--drop-uncalled` are left out of the merge table too,
+/// so every row has an emitted record.
+#[tokio::test]
+async fn merge_table_drop_uncalled() {
+    let name = "drop_uncalled";
+    let case = quirks_dir().join(name);
+    let mut cli = parse_cli(name);
+
+    let stem = format!("svelt-merge-table-uncalled-{}", std::process::id());
+    let out = std::env::temp_dir().join(format!("{}.vcf", stem));
+    let table = std::env::temp_dir().join(format!("{}.tsv", stem));
+    cli.options.write_merge_table = Some(String::from(table.to_str().unwrap()));
+    let vcfs = vec![
+        String::from(case.join("a.vcf").to_str().unwrap()),
+        String::from(case.join("b.vcf").to_str().unwrap()),
+    ];
+    merge_vcfs(
+        out.to_str().unwrap(),
+        &vcfs,
+        Rc::new(cli.options),
+        &cli.common,
+    )
+    .await
+    .unwrap();
+
+    let rows = read_merge_table(table.to_str().unwrap()).await.unwrap();
+    std::fs::remove_file(&out).unwrap();
+    std::fs::remove_file(&table).unwrap();
+
+    let mut kept: Vec<(u32, i32)> = rows
+        .iter()
+        .map(|row| {
+            assert!(row.emitted_id.is_some(), "{:?} was not emitted", row);
+            (row.vix, row.start)
+        })
+        .collect();
+    kept.sort();
+    assert_eq!(kept, vec![(0, 1000), (0, 1000), (1, 1000), (1, 2500)]);
+}
+
+/// Each row of the merge table gives its input's label and the samples whose
+/// genotypes it contributes to the merged record.
+#[tokio::test]
+async fn merge_table_provenance() {
+    let name = "input_labels";
+    let case = quirks_dir().join(name);
+    let mut cli = parse_cli(name);
+
+    let stem = format!("svelt-merge-provenance-{}", std::process::id());
+    let out = std::env::temp_dir().join(format!("{}.vcf", stem));
+    let table = std::env::temp_dir().join(format!("{}.tsv", stem));
+    cli.options.write_merge_table = Some(String::from(table.to_str().unwrap()));
+    let vcfs = vec![
+        String::from(case.join("a.vcf").to_str().unwrap()),
+        String::from(case.join("b.vcf").to_str().unwrap()),
+    ];
+    merge_vcfs(
+        out.to_str().unwrap(),
+        &vcfs,
+        Rc::new(cli.options),
+        &cli.common,
+    )
+    .await
+    .unwrap();
+
+    let rows = read_merge_table(table.to_str().unwrap()).await.unwrap();
+    std::fs::remove_file(&out).unwrap();
+    std::fs::remove_file(&table).unwrap();
+
+    assert!(!rows.is_empty());
+    for row in rows.iter() {
+        let (label, samples) = match row.vix {
+            0 => ("delly:HG002", "HG002_delly"),
+            _ => ("manta:HG002", "HG002_manta"),
+        };
+        assert_eq!(row.input_label.as_deref(), Some(label));
+        assert_eq!(row.samples.as_deref(), Some(samples));
+    }
+}
+
+/// A bundle holds a copy of the output, along with the merge table, the QC
+/// summary, and the configuration.
+#[tokio::test]
+async fn bundle_output() {
+    let name = "bnd_insertion_length";
+    let case = quirks_dir().join(name);
+    let mut cli = parse_cli(name);
+
+    let stem = format!("svelt-bundle-{}", std::process::id());
+    let out = std::env::temp_dir().join(format!("{}.vcf", stem));
+    let tsv = std::env::temp_dir().join(format!("{}.tsv", stem));
+    let dir = std::env::temp_dir().join(stem);
+    cli.options.bundle_output = Some(String::from(dir.to_str().unwrap()));
+    cli.options.write_merge_table = Some(String::from(tsv.to_str().unwrap()));
+    let vcfs = vec![
+        String::from(case.join("a.vcf").to_str().unwrap()),
+        String::from(case.join("b.vcf").to_str().unwrap()),
+    ];
+    merge_vcfs(
+        out.to_str().unwrap(),
+        &vcfs,
+        Rc::new(cli.options),
+        &cli.common,
+    )
+    .await
+    .unwrap();
+
+    let vcf = std::fs::read_to_string(&out).unwrap();
+    let copy = std::fs::read_to_string(dir.join(out.file_name().unwrap())).unwrap();
+    let config: serde_json::Value =
+        serde_json::from_str(&std::fs::read_to_string(dir.join(BUNDLE_CONFIG)).unwrap()).unwrap();
+    let summary: serde_json::Value =
+        serde_json::from_str(&std::fs::read_to_string(dir.join(BUNDLE_SUMMARY)).unwrap()).unwrap();
+    let table = read_merge_table(dir.join(BUNDLE_MERGE_TABLE).to_str().unwrap())
+        .await
+        .unwrap();
+    let tsv_table = read_merge_table(tsv.to_str().unwrap()).await.unwrap();
+    std::fs::remove_file(&out).unwrap();
+    std::fs::remove_file(&tsv).unwrap();
+    std::fs::remove_dir_all(&dir).unwrap();
+
+    assert_eq!(copy, vcf);
+    assert_eq!(config["inputs"], serde_json::json!(vcfs));
+    assert_eq!(config["options"]["bnd_insertion_window"], 10);
+    assert_eq!(summary["inputs"][1]["records"], 4);
+    assert_eq!(table.len(), 8);
+    assert_eq!(tsv_table, table);
+}
+
+/// More inputs than fit in a 64 bit mask (or in two decimal digits of the row key).
+#[tokio::test]
+async fn many_inputs() {
+    let cli = parse_cli("depth_consistency");
+    let n = 120;
+
+    let dir = std::env::temp_dir().join(format!("svelt-many-inputs-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let mut vcfs = Vec::new();
+    for i in 0..n {
+        let vcf = dir.join(format!("in{}.vcf", i));
+        let start = 1000 + i % 5;
+        let text = format!(
+            "##fileformat=VCFv4.2\n\
+             ##contig=<ID=chr1,length=5000>\n\
+             ##contig=<ID=chr2,length=5000>\n\
+             ##INFO=<ID=SVTYPE,Number=1,Type=String,Description=\"Type\">\n\
+             ##INFO=<ID=SVLEN,Number=1,Type=Integer,Description=\"Length\">\n\
+             ##INFO=<ID=END,Number=1,Type=Integer,Description=\"End\">\n\
+             ##FORMAT=<ID=GT,Number=1,Type=String,Description=\"Genotype\">\n\
+             #CHROM\tPOS\tID\tREF\tALT\tQUAL\tFILTER\tINFO\tFORMAT\tS{i}\n\
+             chr1\t{start}\tnear{i}\tA\t<DEL>\t20\tPASS\tSVTYPE=DEL;SVLEN=-500;END={end}\tGT\t0/1\n\
+             chr1\t3000\texact{i}\tA\t<DEL>\t20\tPASS\tSVTYPE=DEL;SVLEN=-500;END=3500\tGT\t0/1\n",
+            end = start + 500,
+        );
+        std::fs::write(&vcf, text).unwrap();
+        vcfs.push(String::from(vcf.to_str().unwrap()));
+    }
+
+    let out = dir.join("merged.vcf");
+    merge_vcfs(
+        out.to_str().unwrap(),
+        &vcfs,
+        Rc::new(cli.options),
+        &cli.common,
+    )
+    .await
+    .unwrap();
+    let merged = std::fs::read_to_string(&out).unwrap();
+    std::fs::remove_dir_all(&dir).unwrap();
+
+    let records: Vec<Vec<&str>> = merged
+        .lines()
+        .filter(|line| !line.starts_with('#'))
+        .map(|line| line.split('\t').collect())
+        .collect();
+    assert_eq!(records.len(), 2);
+    for fields in records.iter() {
+        assert_eq!(fields.len(), 9 + n);
+        assert!(fields[9..].iter().all(|gt| *gt == "0/1"));
+        let ids = fields[7]
+            .split(';')
+            .find_map(|item| item.strip_prefix("ORIGINAL_IDS="))
+            .unwrap();
+        assert_eq!(ids.split(',').count(), n);
+    }
+}
+
+/// With `--require-genotype-concordance`, records whose genotypes disagree in
+/// a sample common to both inputs aren't merged, but missing genotypes don't
+/// stand in the way. Only the groups are compared, since the inputs share a
+/// sample name.
+#[tokio::test]
+async fn genotype_concordance() {
+    let name = "genotype_concordance";
+    let case = quirks_dir().join(name);
+    let cli = parse_cli(name);
+
+    let out = std::env::temp_dir().join(format!("svelt-quirk-{}-{}.vcf", name, std::process::id()));
+    let vcfs = vec![
+        String::from(case.join("a.vcf").to_str().unwrap()),
+        String::from(case.join("b.vcf").to_str().unwrap()),
+    ];
+    merge_vcfs(
+        out.to_str().unwrap(),
+        &vcfs,
+        Rc::new(cli.options),
+        &cli.common,
+    )
+    .await
+    .unwrap();
+    let merged = std::fs::read_to_string(&out).unwrap();
+    std::fs::remove_file(&out).unwrap();
+
+    let groups: Vec<&str> = merged
+        .lines()
+        .filter(|line| !line.starts_with('#'))
+        .map(|line| {
+            line.split('\t')
+                .nth(7)
+                .unwrap()
+                .split(';')
+                .find_map(|item| item.strip_prefix("ORIGINAL_IDS="))
+                .unwrap()
+        })
+        .collect();
+    assert_eq!(groups, vec!["a1,b1", "a2", "b2", "a3,b3", "a4", "b4", "a5"]);
+}
+
+/// With `--write-index`, BGZF output compressed by several threads comes with
+/// a tabix or CSI index that finds the same records as a scan.
+#[tokio::test]
+async fn indexed_output() {
+    let dir = std::env::temp_dir().join(format!("svelt-indexed-output-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let mut vcfs = Vec::new();
+    for i in 0..2 {
+        let vcf = dir.join(format!("in{}.vcf", i));
+        let mut text = String::from(
+            "##fileformat=VCFv4.2\n\
+             ##contig=<ID=chr1,length=5000000>\n\
+             ##contig=<ID=chr2,length=5000000>\n\
+             ##INFO=<ID=SVTYPE,Number=1,Type=String,Description=\"Type\">\n\
+             ##INFO=<ID=SVLEN,Number=1,Type=Integer,Description=\"Length\">\n\
+             ##INFO=<ID=END,Number=1,Type=Integer,Description=\"End\">\n\
+             #CHROM\tPOS\tID\tREF\tALT\tQUAL\tFILTER\tINFO\n",
+        );
+        for chrom in ["chr1", "chr2"] {
+            for j in 0..1500 {
+                let start = 1000 + 2000 * j + 700 * i;
+                text.push_str(&format!(
+                    "{chrom}\t{start}\t{chrom}_{i}_{j}\tA\t<DEL>\t20\tPASS\tSVTYPE=DEL;SVLEN=-300;END={end}\n",
+                    end = start + 300,
+                ));
+            }
+        }
+        std::fs::write(&vcf, text).unwrap();
+        vcfs.push(String::from(vcf.to_str().unwrap()));
+    }
+
+    let region: noodles::core::Region = "chr2:1000000-1100000".parse().unwrap();
+    for format in [IndexFormat::Tbi, IndexFormat::Csi] {
+        let mut cli = parse_cli("depth_consistency");
+        cli.options.output_threads = 3;
+        cli.options.write_index = Some(format);
+        let out = dir.join(format!("merged-{:?}.vcf.gz", format));
+        merge_vcfs(
+            out.to_str().unwrap(),
+            &vcfs,
+            Rc::new(cli.options),
+            &cli.common,
+        )
+        .await
+        .unwrap();
+
+        let mut reader = vcf::io::indexed_reader::Builder::default()
+            .build_from_path(&out)
+            .unwrap();
+        let header = reader.read_header().unwrap();
+        let mut scanned = Vec::new();
+        for rec in reader.records() {
+            let rec = rec.unwrap();
+            let start = rec.variant_start().unwrap().unwrap();
+            let end = rec.variant_end(&header).unwrap();
+            if rec.reference_sequence_name() == "chr2"
+                && start <= region.interval().end().unwrap()
+                && end >= region.interval().start().unwrap()
+            {
+                scanned.push(String::from(rec.ids().iter().next().unwrap()));
+            }
+        }
+        let queried: Vec<String> = reader
+            .query(&header, &region)
+            .unwrap()
+            .map(|rec| String::from(rec.unwrap().ids().iter().next().unwrap()))
+            .collect();
+        assert_eq!(scanned.len(), 101);
+        assert_eq!(queried, scanned, "querying the {:?} index", format);
+    }
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+/// BCF output (inferred from the `.bcf` extension) reads back (with noodles'
+/// BCF reader) as the same records as the VCF output, and can only have a
+/// CSI index.
+#[tokio::test]
+async fn bcf_output() {
+    let name = "depth_consistency";
+    let case = quirks_dir().join(name);
+    let vcfs = vec![
+        String::from(case.join("a.vcf").to_str().unwrap()),
+        String::from(case.join("b.vcf").to_str().unwrap()),
+    ];
+    let stem = format!("svelt-quirk-bcf-{}", std::process::id());
+    let out = std::env::temp_dir().join(format!("{}.bcf", stem));
+    let vcf_out = std::env::temp_dir().join(format!("{}.vcf", stem));
+
+    let mut cli = parse_cli(name);
+    cli.options.write_index = Some(IndexFormat::Tbi);
+    assert!(
+        merge_vcfs(
+            out.to_str().unwrap(),
+            &vcfs,
+            Rc::new(cli.options),
+            &cli.common,
+        )
+        .await
+        .is_err()
+    );
+
+    let mut cli = parse_cli(name);
+    cli.options.write_index = Some(IndexFormat::Csi);
+    merge_vcfs(
+        out.to_str().unwrap(),
+        &vcfs,
+        Rc::new(cli.options),
+        &cli.common,
+    )
+    .await
+    .unwrap();
+    let cli = parse_cli(name);
+    merge_vcfs(
+        vcf_out.to_str().unwrap(),
+        &vcfs,
+        Rc::new(cli.options),
+        &cli.common,
+    )
+    .await
+    .unwrap();
+
+    // The records, as VCF lines.
+    let as_lines = |header: &Header, recs: Vec<RecordBuf>| -> Vec<String> {
+        let mut writer = vcf::io::Writer::new(Vec::new());
+        for rec in recs.iter() {
+            writer.write_variant_record(header, rec).unwrap();
+        }
+        String::from_utf8(writer.into_inner())
+            .unwrap()
+            .lines()
+            .map(String::from)
+            .collect()
+    };
+
+    let mut reader = noodles::bcf::io::Reader::new(std::fs::File::open(&out).unwrap());
+    let bcf_header = reader.read_header().unwrap();
+    let bcf_recs = reader
+        .record_bufs(&bcf_header)
+        .collect::<std::io::Result<Vec<RecordBuf>>>()
+        .unwrap();
+
+    let mut reader = vcf::io::reader::Builder::default()
+        .build_from_path(&vcf_out)
+        .unwrap();
+    let vcf_header = reader.read_header().unwrap();
+    let vcf_recs = reader
+        .record_bufs(&vcf_header)
+        .collect::<std::io::Result<Vec<RecordBuf>>>()
+        .unwrap();
+
+    assert_eq!(bcf_header.infos(), vcf_header.infos());
+    assert_eq!(bcf_header.contigs(), vcf_header.contigs());
+    assert!(!vcf_recs.is_empty());
+    assert_eq!(
+        as_lines(&bcf_header, bcf_recs),
+        as_lines(&vcf_header, vcf_recs)
+    );
+
+    let csi = out.with_extension("bcf.csi");
+    assert!(csi.exists());
+    std::fs::remove_file(&csi).unwrap();
+    std::fs::remove_file(&out).unwrap();
+    std::fs::remove_file(&vcf_out).unwrap();
+}
+
+/// The agreement matrix counts the groups shared by each pair of inputs, and
+/// how far apart their breakpoints are.
+#[tokio::test]
+async fn agreement_matrix() {
+    let name = "depth_consistency";
+    let case = quirks_dir().join(name);
+    let mut cli = parse_cli(name);
+
+    let stem = format!("svelt-agreement-{}", std::process::id());
+    let out = std::env::temp_dir().join(format!("{}.vcf", stem));
+    let matrix = std::env::temp_dir().join(format!("{}.json", stem));
+    cli.options.agreement_matrix = Some(String::from(matrix.to_str().unwrap()));
+    cli.options.agreement_matrix_format = AgreementMatrixFormat::Json;
+    let vcfs = vec![
+        String::from(case.join("a.vcf").to_str().unwrap()),
+        String::from(case.join("b.vcf").to_str().unwrap()),
+    ];
+    merge_vcfs(
+        out.to_str().unwrap(),
+        &vcfs,
+        Rc::new(cli.options),
+        &cli.common,
+    )
+    .await
+    .unwrap();
+    let agreement: serde_json::Value =
+        serde_json::from_str(&std::fs::read_to_string(&matrix).unwrap()).unwrap();
+    std::fs::remove_file(&out).unwrap();
+    std::fs::remove_file(&matrix).unwrap();
+
+    assert_eq!(agreement["inputs"], serde_json::json!(vcfs));
+    assert_eq!(
+        agreement["shared_groups"],
+        serde_json::json!([[3, 2], [2, 3]])
+    );
+    assert_eq!(
+        agreement["jaccard"],
+        serde_json::json!([[1.0, 0.5], [0.5, 1.0]])
+    );
+    assert_eq!(
+        agreement["median_offset"],
+        serde_json::json!([[0.0, 7.5], [7.5, 0.0]])
+    );
+}
+
+/// The IGV batch script visits the best supported records, with a view of
+/// each end of a breakend.
+#[tokio::test]
+async fn igv_batch() {
+    let name = "balanced_translocation";
+    let case = quirks_dir().join(name);
+    let mut cli = parse_cli(name);
+
+    let dir = std::env::temp_dir().join(format!("svelt-igv-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let out = dir.join("merged.vcf");
+    let batch = dir.join("review.bat");
+    cli.options.igv_batch = Some(String::from(batch.to_str().unwrap()));
+    cli.options.igv_top = 2;
+    cli.options.igv_padding = 100;
+    cli.options.igv_min_support = 2;
+    let vcfs = vec![
+        String::from(case.join("a.vcf").to_str().unwrap()),
+        String::from(case.join("b.vcf").to_str().unwrap()),
+    ];
+    merge_vcfs(
+        out.to_str().unwrap(),
+        &vcfs,
+        Rc::new(cli.options),
+        &cli.common,
+    )
+    .await
+    .unwrap();
+    let script = std::fs::read_to_string(&batch).unwrap();
+    let dir = std::fs::canonicalize(&dir).unwrap();
+    std::fs::remove_dir_all(&dir).unwrap();
+
+    let lines: Vec<&str> = script.lines().collect();
+    assert_eq!(lines[0], "# The 2 best supported of 4 merged records");
+    assert!(lines.contains(&format!("load {}", dir.join("merged.vcf").display()).as_str()));
+    assert!(lines.contains(&format!("snapshotDirectory {}", dir.display()).as_str()));
+    let gotos: Vec<&str> = lines
+        .iter()
+        .filter(|line| line.starts_with("goto "))
+        .cloned()
+        .collect();
+    assert_eq!(
+        gotos,
+        vec![
+            "goto chr1:1900-2100 chr2:2901-3101",
+            "goto chr1:1901-2101 chr2:2900-3100"
+        ]
+    );
+    let snapshots = lines
+        .iter()
+        .filter(|line| line.starts_with("snapshot "))
+        .count();
+    assert_eq!(snapshots, 2);
+}
+
+/// Merging the same inputs again, with other numbers of threads, or in the
+/// other order, makes the same records.
+#[tokio::test]
+async fn audit_determinism() {
+    let name = "format_map";
+    let case = quirks_dir().join(name);
+    let mut cli = parse_cli(name);
+
+    let dir = std::env::temp_dir().join(format!("svelt-determinism-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let report = dir.join("report.tsv");
+    cli.common.temp_dir = Some(String::from(dir.to_str().unwrap()));
+    let vcfs = vec![
+        String::from(case.join("a.vcf").to_str().unwrap()),
+        String::from(case.join("b.vcf").to_str().unwrap()),
+    ];
+    determinism::audit_determinism(
+        Some(report.to_str().unwrap()),
+        &vcfs,
+        true,
+        &[1],
+        &cli.options,
+        &cli.common,
+    )
+    .await
+    .unwrap();
+    let report = std::fs::read_to_string(&report).unwrap();
+    std::fs::remove_dir_all(&dir).unwrap();
+
+    let lines: Vec<&str> = report.lines().collect();
+    assert_eq!(
+        lines,
+        vec![
+            "run\tinput_order\tthreads\tresult\tstage\tdetail",
+            "repeat\t0,1\tdefault\tsame\t.\t.",
+            "threads=1\t0,1\t1\tsame\t.\t.",
+            "permuted\t1,0\tdefault\tsame\t.\t.",
+        ]
+    );
+}
+
+#[derive(Debug, Parser)]
+struct IndexCli {
+    #[command(flatten)]
+    options: IndexingOptions,
+}
+
+fn reverse_complement(seq: &str) -> String {
+    seq.chars()
+        .rev()
+        .map(|c| match c {
+            'A' => 'T',
+            'C' => 'G',
+            'G' => 'C',
+            _ => 'A',
+        })
+        .collect()
+}
+
+/// Inputs with svelt's own INFO fields (here, svelt's output) are refused
+/// without `--allow-svelt-inputs`, as their values would collide with this
+/// run's.
+#[tokio::test]
+async fn svelt_inputs_refused() {
+    let name = "svelt_inputs";
+    let case = quirks_dir().join(name);
+    let mut cli = parse_cli(name);
+    cli.options.allow_svelt_inputs = false;
+
+    let out = std::env::temp_dir().join(format!("svelt-refused-{}.vcf", std::process::id()));
+    let vcfs = vec![
+        String::from(case.join("a.vcf").to_str().unwrap()),
+        String::from(case.join("b.vcf").to_str().unwrap()),
+    ];
+    let e = merge_vcfs(
+        out.to_str().unwrap(),
+        &vcfs,
+        Rc::new(cli.options),
+        &cli.common,
+    )
+    .await
+    .err()
+    .unwrap();
+
+    assert_eq!(FailureClass::of(&e), FailureClass::InputValidation);
+    assert!(matches!(
+        SveltError::cause_of(&e),
+        Some(SveltError::SveltOwnedFields(_, fields)) if fields.contains("ORIGINAL_IDS")
+    ));
+}
+
+/// Features with the same name are renamed (or refused), so that
+/// classifications by them aren't ambiguous.
+#[tokio::test]
+async fn duplicate_feature_names() {
+    let dir = std::env::temp_dir().join(format!("svelt-duplicates-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let fasta = dir.join("repeats.fa");
+    std::fs::write(
+        &fasta,
+        ">SINE/Test/Rep1\nACGTTGCAAGGCTTAC\n>SINE/Test/Rep2\nTTGACCATGGACTTAG\n>SINE/Test/Rep1\nGGATCCTTAGCAATCG\n",
+    )
+    .unwrap();
+
+    let mut index_cli = IndexCli::parse_from(["svelt"]);
+    let ctx = SessionContext::new();
+    let idx = FeatureIndex::build(fasta.to_str().unwrap(), &index_cli.options, &ctx)
+        .await
+        .unwrap();
+    let features = dir.join("repeats");
+    idx.save(features.to_str().unwrap(), &ctx).await.unwrap();
+    let loaded = FeatureIndex::load(features.to_str().unwrap(), &ctx)
+        .await
+        .unwrap();
+
+    index_cli.options.duplicate_names = DuplicateNames::Error;
+    let e = FeatureIndex::build(fasta.to_str().unwrap(), &index_cli.options, &ctx)
+        .await
+        .err()
+        .unwrap();
+    std::fs::remove_dir_all(&dir).unwrap();
+
+    for idx in [&idx, &loaded] {
+        assert_eq!(idx.name(0), "SINE/Test/Rep1");
+        assert_eq!(idx.name(2), "SINE/Test/Rep1#2");
+        assert_eq!(idx.renamed(), 1);
+    }
+    assert_eq!(FailureClass::of(&e), FailureClass::InputValidation);
+    assert!(matches!(
+        SveltError::cause_of(&e),
+        Some(SveltError::DuplicateFeatureName(name)) if name == "SINE/Test/Rep1"
+    ));
+}
+
+#[tokio::test]
+async fn orient_insertions() {
+    let dir = std::env::temp_dir().join(format!("svelt-orient-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+
+    // A made up repeat family, inserted in both orientations.
+    let mut state: u64 = 1015;
+    let feature: String = (0..300)
+        .map(|_| {
+            state = state
+                .wrapping_mul(6364136223846793005)
+                .wrapping_add(1442695040888963407);
+            b"ACGT"[(state >> 62) as usize] as char
+        })
+        .collect();
+    let fasta = dir.join("repeats.fa");
+    std::fs::write(&fasta, format!(">SINE/Test/Rep1\n{}\n", feature)).unwrap();
+    let index_cli = IndexCli::parse_from(["svelt"]);
+    let ctx = SessionContext::new();
+    let idx = FeatureIndex::build(fasta.to_str().unwrap(), &index_cli.options, &ctx)
+        .await
+        .unwrap();
+    let features = dir.join("repeats");
+    idx.save(features.to_str().unwrap(), &ctx).await.unwrap();
+
+    let reversed = reverse_complement(&feature);
+    let mut vcfs = Vec::new();
+    for name in ["a", "b"] {
+        let vcf = dir.join(format!("{}.vcf", name));
+        let text = format!(
+            "##fileformat=VCFv4.2\n\
+             ##contig=<ID=chr1,length=5000>\n\
+             ##contig=<ID=chr2,length=5000>\n\
+             ##INFO=<ID=SVTYPE,Number=1,Type=String,Description=\"Type\">\n\
+             ##INFO=<ID=SVLEN,Number=1,Type=Integer,Description=\"Length\">\n\
+             #CHROM\tPOS\tID\tREF\tALT\tQUAL\tFILTER\tINFO\n\
+             chr1\t1000\t{name}_fwd\tA\tA{feature}\t20\tPASS\tSVTYPE=INS;SVLEN=300\n\
+             chr1\t3000\t{name}_rev\tA\tA{reversed}\t20\tPASS\tSVTYPE=INS;SVLEN=300\n",
+        );
+        std::fs::write(&vcf, text).unwrap();
+        vcfs.push(String::from(vcf.to_str().unwrap()));
+    }
+
+    let mut cli = parse_cli("orient_insertions");
+    cli.options.annotate_insertions = Some(String::from(features.to_str().unwrap()));
+    cli.options.orient_insertions = true;
+    cli.options.force_alt_tags = false;
+    let out = dir.join("merged.vcf");
+    merge_vcfs(
+        out.to_str().unwrap(),
+        &vcfs,
+        Rc::new(cli.options),
+        &cli.common,
+    )
+    .await
+    .unwrap();
+    let merged = std::fs::read_to_string(&out).unwrap();
+    std::fs::remove_dir_all(&dir).unwrap();
+
+    assert!(merged.contains("##INFO=<ID=SVELT_ALT_REVERSED,"));
+    let records: Vec<Vec<&str>> = merged
+        .lines()
+        .filter(|line| !line.starts_with('#'))
+        .map(|line| line.split('\t').collect())
+        .collect();
+    assert_eq!(records.len(), 2);
+
+    // Both are given in the orientation of the feature.
+    let expected_alt = format!("A{}", feature);
+    for rec in records.iter() {
+        assert_eq!(rec[4], expected_alt);
+        let alt_seq = format!("SVELT_ALT_SEQ={},{}", feature, feature);
+        assert!(rec[7].split(';').any(|item| item == alt_seq));
+    }
+    let info = |rec: &Vec<&str>, name: &str| rec[7].split(';').any(|item| item == name);
+    assert!(!info(&records[0], "SVELT_ALT_REVERSED"));
+    assert!(info(&records[1], "SVELT_ALT_REVERSED"));
+}
+
+/// `svelt annotate` classifies the insertions of a single VCF, replacing any
+/// classification it already has, and leaves the other records alone.
+#[tokio::test]
+async fn annotate_single_vcf() {
+    let dir = std::env::temp_dir().join(format!("svelt-annotate-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+
+    let mut state: u64 = 1253;
+    let mut bases = |n: usize| -> String {
+        (0..n)
+            .map(|_| {
+                state = state
+                    .wrapping_mul(6364136223846793005)
+                    .wrapping_add(1442695040888963407);
+                b"ACGT"[(state >> 62) as usize] as char
+            })
+            .collect()
+    };
+    let feature = bases(300);
+    let unrelated = bases(300);
+    let fasta = dir.join("repeats.fa");
+    std::fs::write(&fasta, format!(">SINE/Test/Rep1\n{}\n", feature)).unwrap();
+    let index_cli = IndexCli::parse_from(["svelt"]);
+    let ctx = SessionContext::new();
+    let idx = FeatureIndex::build(fasta.to_str().unwrap(), &index_cli.options, &ctx)
+        .await
+        .unwrap();
+    let features = dir.join("repeats");
+    idx.save(features.to_str().unwrap(), &ctx).await.unwrap();
+
+    let reversed = reverse_complement(&feature);
+    let vcf = dir.join("calls.vcf");
+    let text = format!(
+        "##fileformat=VCFv4.2\n\
+         ##contig=<ID=chr1,length=5000>\n\
+         ##INFO=<ID=SVTYPE,Number=1,Type=String,Description=\"Type\">\n\
+         ##INFO=<ID=SVLEN,Number=1,Type=Integer,Description=\"Length\">\n\
+         ##INFO=<ID=SVELT_ALT_CLASS,Number=1,Type=String,Description=\"Class\">\n\
+         #CHROM\tPOS\tID\tREF\tALT\tQUAL\tFILTER\tINFO\n\
+         chr1\t1000\tfwd\tA\tA{feature}\t20\tPASS\tSVTYPE=INS;SVLEN=300\n\
+         chr1\t2000\tdel\tA\t<DEL>\t20\tPASS\tSVTYPE=DEL;SVLEN=-300\n\
+         chr1\t3000\trev\tA\tA{reversed}\t20\tPASS\tSVTYPE=INS;SVLEN=300\n\
+         chr1\t4000\tstale\tA\tA{unrelated}\t20\tPASS\tSVTYPE=INS;SVLEN=300;SVELT_ALT_CLASS=Old+\n",
+    );
+    std::fs::write(&vcf, text).unwrap();
+
+    let out = dir.join("annotated.vcf");
+    annotate_vcf(
+        vcf.to_str().unwrap(),
+        out.to_str().unwrap(),
+        features.to_str().unwrap(),
+        100,
+        &ctx,
+    )
+    .await
+    .unwrap();
+    let annotated = std::fs::read_to_string(&out).unwrap();
+    std::fs::remove_dir_all(&dir).unwrap();
+
+    let records: Vec<Vec<&str>> = annotated
+        .lines()
+        .filter(|line| !line.starts_with('#'))
+        .map(|line| line.split('\t').collect())
+        .collect();
+    let ids: Vec<&str> = records.iter().map(|rec| rec[2]).collect();
+    assert_eq!(ids, vec!["fwd", "del", "rev", "stale"]);
+    let infos: Vec<&str> = records.iter().map(|rec| rec[7]).collect();
+    assert_eq!(
+        infos,
+        vec![
+            "SVTYPE=INS;SVLEN=300;SVELT_ALT_CLASS=SINE/Test/Rep1+",
+            "SVTYPE=DEL;SVLEN=-300",
+            "SVTYPE=INS;SVLEN=300;SVELT_ALT_CLASS=SINE/Test/Rep1-",
+            "SVTYPE=INS;SVLEN=300",
+        ]
+    );
+}
+
+#[tokio::test]
+async fn annotate_deletions() {
+    let dir = std::env::temp_dir().join(format!("svelt-annotate-del-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+
+    let mut state: u64 = 1030;
+    let mut random = |n: usize| -> String {
+        (0..n)
+            .map(|_| {
+                state = state
+                    .wrapping_mul(6364136223846793005)
+                    .wrapping_add(1442695040888963407);
+                b"ACGT"[(state >> 62) as usize] as char
+            })
+            .collect()
+    };
+
+    // A made up repeat family, with a (soft masked) copy on the reverse
+    // strand of the reference, just after position 1000.
+    let feature = random(300);
+    let fasta = dir.join("repeats.fa");
+    std::fs::write(&fasta, format!(">LINE/Test/Rep1\n{}\n", feature)).unwrap();
+    let index_cli = IndexCli::parse_from(["svelt"]);
+    let ctx = SessionContext::new();
+    let idx = FeatureIndex::build(fasta.to_str().unwrap(), &index_cli.options, &ctx)
+        .await
+        .unwrap();
+    let features = dir.join("repeats");
+    idx.save(features.to_str().unwrap(), &ctx).await.unwrap();
+
+    let chr1 = format!(
+        "{}{}{}",
+        random(1000),
+        reverse_complement(&feature).to_lowercase(),
+        random(3700)
+    );
+    let chr2 = random(5000);
+    let reference = dir.join("ref.fa");
+    std::fs::write(&reference, format!(">chr1\n{}\n>chr2\n{}\n", chr1, chr2)).unwrap();
+    std::fs::write(
+        dir.join("ref.fa.fai"),
+        "chr1\t5000\t6\t5000\t5001\nchr2\t5000\t5013\t5000\t5001\n",
+    )
+    .unwrap();
+
+    let mut vcfs = Vec::new();
+    for name in ["a", "b"] {
+        let vcf = dir.join(format!("{}.vcf", name));
+        let text = format!(
+            "##fileformat=VCFv4.2\n\
+             ##contig=<ID=chr1,length=5000>\n\
+             ##contig=<ID=chr2,length=5000>\n\
+             ##INFO=<ID=SVTYPE,Number=1,Type=String,Description=\"Type\">\n\
+             ##INFO=<ID=SVLEN,Number=1,Type=Integer,Description=\"Length\">\n\
+             ##INFO=<ID=END,Number=1,Type=Integer,Description=\"End\">\n\
+             #CHROM\tPOS\tID\tREF\tALT\tQUAL\tFILTER\tINFO\n\
+             chr1\t1000\t{name}_del\tN\t<DEL>\t20\tPASS\tSVTYPE=DEL;SVLEN=-300;END=1300\n\
+             chr1\t2000\t{name}_ins\tA\tA{feature}\t20\tPASS\tSVTYPE=INS;SVLEN=300\n\
+             chr1\t3000\t{name}_other\tN\t<DEL>\t20\tPASS\tSVTYPE=DEL;SVLEN=-300;END=3300\n",
+        );
+        std::fs::write(&vcf, text).unwrap();
+        vcfs.push(String::from(vcf.to_str().unwrap()));
+    }
+
+    let mut cli = parse_cli("annotate_deletions");
+    cli.options.reference = Some(String::from(reference.to_str().unwrap()));
+    cli.options.annotate_insertions = Some(String::from(features.to_str().unwrap()));
+    cli.options.annotate_deletions = true;
+    cli.options.orient_insertions = true;
+    let out = dir.join("merged.vcf");
+    merge_vcfs(
+        out.to_str().unwrap(),
+        &vcfs,
+        Rc::new(cli.options),
+        &cli.common,
+    )
+    .await
+    .unwrap();
+    let merged = std::fs::read_to_string(&out).unwrap();
+    std::fs::remove_dir_all(&dir).unwrap();
+
+    assert!(merged.contains("##INFO=<ID=SVELT_DEL_CLASS,"));
+    let records: Vec<Vec<&str>> = merged
+        .lines()
+        .filter(|line| !line.starts_with('#'))
+        .map(|line| line.split('\t').collect())
+        .collect();
+    assert_eq!(records.len(), 3);
+    let info = |rec: &Vec<&str>, name: &str| rec[7].split(';').any(|item| item == name);
+
+    // The deletion removing the copy of the repeat is tagged with it (in the
+    // orientation of the copy), and isn't mistaken for a reversed insertion.
+    assert!(info(&records[0], "SVELT_DEL_CLASS=LINE/Test/Rep1-"));
+    assert!(!info(&records[0], "SVELT_ALT_REVERSED"));
+    assert!(info(&records[1], "SVELT_ALT_CLASS=LINE/Test/Rep1+"));
+    assert!(!records[2][7].contains("_CLASS="));
+}
+
+#[tokio::test]
+async fn long_insertions() {
+    let dir = std::env::temp_dir().join(format!("svelt-long-ins-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+
+    // Insertions of a few hundred kb, as from assembly based callers. The
+    // alt_seq column holds them with 64 bit offsets, so that the many in a
+    // large batch can't overflow them.
+    let lengths = [200_000, 350_000, 500_000];
+    let mut state: u64 = 1016;
+    let sequences: Vec<String> = lengths
+        .iter()
+        .map(|len| {
+            (0..*len)
+                .map(|_| {
+                    state = state
+                        .wrapping_mul(6364136223846793005)
+                        .wrapping_add(1442695040888963407);
+                    b"ACGT"[(state >> 62) as usize] as char
+                })
+                .collect()
+        })
+        .collect();
+    let mut vcfs = Vec::new();
+    for name in ["a", "b"] {
+        let vcf = dir.join(format!("{}.vcf", name));
+        let mut text = String::from(
+            "##fileformat=VCFv4.2\n\
+             ##contig=<ID=chr1,length=5000>\n\
+             ##contig=<ID=chr2,length=5000>\n\
+             ##INFO=<ID=SVTYPE,Number=1,Type=String,Description=\"Type\">\n\
+             ##INFO=<ID=SVLEN,Number=1,Type=Integer,Description=\"Length\">\n\
+             #CHROM\tPOS\tID\tREF\tALT\tQUAL\tFILTER\tINFO\n",
+        );
+        for (i, seq) in sequences.iter().enumerate() {
+            text.push_str(&format!(
+                "chr1\t{}\t{}_{}\tA\tA{}\t20\tPASS\tSVTYPE=INS;SVLEN={}\n",
+                1000 * (i + 1),
+                name,
+                i,
+                seq,
+                seq.len()
+            ));
+        }
+        std::fs::write(&vcf, text).unwrap();
+        vcfs.push(String::from(vcf.to_str().unwrap()));
+    }
+
+    let cli = parse_cli("long_insertions");
+    let chroms = Rc::new(ChromSet::from(["chr1", "chr2"].as_ref()));
+    let mut reader = VcfReader::new(&vcfs[0], chroms).unwrap();
+    let records = load_vcf_core(&mut reader, &cli.options, None, None, None, None, None).unwrap();
+    assert_eq!(
+        records
+            .schema()
+            .field_with_name("alt_seq")
+            .unwrap()
+            .data_type(),
+        &DataType::LargeUtf8
+    );
+
+    let out = dir.join("merged.vcf");
+    merge_vcfs(
+        out.to_str().unwrap(),
+        &vcfs,
+        Rc::new(cli.options),
+        &cli.common,
+    )
+    .await
+    .unwrap();
+    let merged = std::fs::read_to_string(&out).unwrap();
+    std::fs::remove_dir_all(&dir).unwrap();
+
+    let records: Vec<Vec<&str>> = merged
+        .lines()
+        .filter(|line| !line.starts_with('#'))
+        .map(|line| line.split('\t').collect())
+        .collect();
+    assert_eq!(records.len(), sequences.len());
+    for (rec, seq) in records.iter().zip(sequences.iter()) {
+        assert!(rec[7].contains("SVELT_CRITERIA=exact"));
+        let alt_seq = format!("SVELT_ALT_SEQ={},{}", seq, seq);
+        assert!(rec[7].split(';').any(|item| item == alt_seq));
+    }
+}
+
+/// `svelt demo` makes its cohort and merges it, classifying the insertions.
+#[tokio::test]
+async fn demo() {
+    let dir = std::env::temp_dir().join(format!("svelt-demo-{}", std::process::id()));
+    svelt::demo::demo(dir.to_str().unwrap(), &CommonOptions::default())
+        .await
+        .unwrap();
+    let merged = std::fs::read_to_string(dir.join("merged.vcf")).unwrap();
+    let readme = dir.join("README.txt").exists();
+    std::fs::remove_dir_all(&dir).unwrap();
+
+    assert!(readme);
+    assert!(merged.contains("ORIGINAL_IDS=manta_S1_del,manta_S2_del,cutesv_S1_del,cutesv_S2_del;"));
+    assert!(merged.contains("SVELT_ALT_CLASS=SINE/Alu/DemoAlu+"));
+    assert!(merged.contains("SVELT_ALT_CLASS=LINE/L1/DemoL1-"));
+}
+
+/// Unsorted inputs are rejected unless they may be sorted, when they give the
+/// same result as the sorted inputs.
+#[tokio::test]
+async fn unsorted_input_rejected() {
+    let case = quirks_dir().join("unsorted_input");
+    let mut cli = parse_cli("unsorted_input");
+    cli.options.sort_inputs = false;
+    let out = std::env::temp_dir().join(format!("svelt-unsorted-{}.vcf", std::process::id()));
+    let vcfs = vec![
+        String::from(case.join("a.vcf").to_str().unwrap()),
+        String::from(case.join("b.vcf").to_str().unwrap()),
+    ];
+    let e = merge_vcfs(
+        out.to_str().unwrap(),
+        &vcfs,
+        Rc::new(cli.options),
+        &cli.common,
+    )
+    .await
+    .unwrap_err();
+    assert_eq!(FailureClass::of(&e), FailureClass::InputValidation);
+    assert!(matches!(
+        SveltError::cause_of(&e),
+        Some(SveltError::UnsortedInput(here, previous))
+            if here == "chr1:1000" && previous == "chr1:4000"
+    ));
+
+    let sorted = quirks_dir().join("dedup_within_inputs/expected.vcf");
+    assert_eq!(
+        std::fs::read_to_string(case.join("expected.vcf")).unwrap(),
+        std::fs::read_to_string(sorted).unwrap()
+    );
+}
+
+/// Without `--reconcile-contigs`, inputs must declare the same contigs in the
+/// same order, and even with it, a contig can't have different lengths.
+#[tokio::test]
+async fn contig_mismatch_rejected() {
+    let case = quirks_dir().join("reconcile_contigs");
+    let out = std::env::temp_dir().join(format!("svelt-contigs-{}.vcf", std::process::id()));
+    let mut vcfs = vec![
+        String::from(case.join("a.vcf").to_str().unwrap()),
+        String::from(case.join("b.vcf").to_str().unwrap()),
+    ];
+    let mut cli = parse_cli("reconcile_contigs");
+    cli.options.reconcile_contigs = false;
+    let e = merge_vcfs(
+        out.to_str().unwrap(),
+        &vcfs,
+        Rc::new(cli.options),
+        &cli.common,
+    )
+    .await
+    .unwrap_err();
+    assert_eq!(FailureClass::of(&e), FailureClass::ContigMismatch);
+
+    let longer = std::env::temp_dir().join(format!("svelt-contigs-{}-b.vcf", std::process::id()));
+    let text = std::fs::read_to_string(case.join("b.vcf")).unwrap();
+    std::fs::write(
+        &longer,
+        text.replace("<ID=chr1,length=5000>", "<ID=chr1,length=6000>"),
+    )
+    .unwrap();
+    vcfs[1] = String::from(longer.to_str().unwrap());
+    let cli = parse_cli("reconcile_contigs");
+    let e = merge_vcfs(
+        out.to_str().unwrap(),
+        &vcfs,
+        Rc::new(cli.options),
+        &cli.common,
+    )
+    .await
+    .unwrap_err();
+    std::fs::remove_file(&longer).unwrap();
+    assert_eq!(FailureClass::of(&e), FailureClass::ContigMismatch);
+    assert!(matches!(
+        SveltError::cause_of(&e),
+        Some(SveltError::ContigLength(chrom, 5000, 6000)) if chrom == "chr1"
+    ));
+}
+
+/// When all the inputs are header-only, the output is too, and the merge
+/// table and bundle are written, empty.
+#[tokio::test]
+async fn header_only_inputs() {
+    let case = quirks_dir().join("header_only_input");
+    let mut cli = parse_cli("header_only_input");
+
+    let stem = format!("svelt-header-only-{}", std::process::id());
+    let dir = std::env::temp_dir().join(&stem);
+    std::fs::create_dir_all(&dir).unwrap();
+    let b = dir.join("b.vcf");
+    let header: String = std::fs::read_to_string(case.join("b.vcf"))
+        .unwrap()
+        .lines()
+        .filter(|line| line.starts_with('#'))
+        .map(|line| format!("{}\n", line))
+        .collect();
+    std::fs::write(&b, header).unwrap();
+
+    let out = dir.join("out.vcf");
+    let tsv = dir.join("table.tsv");
+    let bundle = dir.join("bundle");
+    cli.options.write_merge_table = Some(String::from(tsv.to_str().unwrap()));
+    cli.options.bundle_output = Some(String::from(bundle.to_str().unwrap()));
+    let vcfs = vec![
+        String::from(case.join("a.vcf").to_str().unwrap()),
+        String::from(b.to_str().unwrap()),
+    ];
+    merge_vcfs(
+        out.to_str().unwrap(),
+        &vcfs,
+        Rc::new(cli.options),
+        &cli.common,
+    )
+    .await
+    .unwrap();
+
+    let vcf = std::fs::read_to_string(&out).unwrap();
+    let tsv_table = read_merge_table(tsv.to_str().unwrap()).await.unwrap();
+    let table = read_merge_table(bundle.join(BUNDLE_MERGE_TABLE).to_str().unwrap())
+        .await
+        .unwrap();
+    let summary: serde_json::Value =
+        serde_json::from_str(&std::fs::read_to_string(bundle.join(BUNDLE_SUMMARY)).unwrap())
+            .unwrap();
+    std::fs::remove_dir_all(&dir).unwrap();
+
+    let last = vcf.lines().last().unwrap();
+    assert!(last.starts_with("#CHROM\t"), "{}", last);
+    assert!(last.ends_with("\tA\tB"), "{}", last);
+    assert!(tsv_table.is_empty());
+    assert!(table.is_empty());
+    assert_eq!(summary["inputs"][1]["records"], 0);
+}